@@ -3,9 +3,11 @@
 //! なお、クラスタ構成の動的変更に関する詳細は、
 //! [Raftの論文](https://raft.github.io/raft.pdf)の「6 Cluster membership changes」を参照のこと.
 use std::cmp;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
+use crate::log::{LogHistory, LogIndex};
 use crate::node::NodeId;
+use crate::{ErrorKind, Result};
 
 /// クラスタに属するメンバ群.
 pub type ClusterMembers = BTreeSet<NodeId>;
@@ -36,15 +38,46 @@ impl ClusterState {
     }
 }
 
+/// `Joint`状態での合意値の算出方法.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JointQuorum {
+    /// 新旧メンバ群のそれぞれで過半数の承認を要求する(i.e., 両者の合意値の小さい方を採用する).
+    ///
+    /// Raftの論文の通りのjoint consensusであり、安全性が保証される.
+    #[default]
+    Both,
+
+    /// 新旧メンバ群の和集合の過半数の承認を合意値とする.
+    ///
+    /// 新旧で共通するメンバが多い対称的な構成変更では、`Both`よりも早くコミットが進む.
+    ///
+    /// 一般には、和集合の過半数が新旧のそれぞれの過半数と交わるとは限らず、
+    /// 例えば「構成変更を知らない旧メンバ群の過半数」と「和集合の過半数」が互いに素な合意を形成し得る.
+    /// そのため、和集合の任意の過半数が、新旧のそれぞれの任意の過半数と必ず交わる構成変更
+    /// (e.g., 一台の追加・削除・置換)でのみ使用可能であり、それ以外の構成変更は拒否される
+    /// (`ClusterConfig::validate_transition`を参照).
+    Union,
+}
+
 /// クラスタ構成.
 ///
 /// クラスタに属するメンバの集合に加えて、
 /// 動的構成変更用の状態を管理する.
+///
+/// また、投票権を持たずにログの複製のみを受け取るメンバ(ラーナー)の集合も保持する.
+/// ラーナーは、リーダ選出やログのコミットの判定には一切関与しない.
+///
+/// 逆に、投票権を有するメンバの内で、エントリのメタデータのみを受け取るもの(ウィットネス)の集合も保持する.
+/// ウィットネスは、リーダ選出やログのコミットの判定に参加するが、
+/// `Command`のペイロードやスナップショットの内容は受け取らず、自身がリーダになることもない.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClusterConfig {
     new: ClusterMembers,
     old: ClusterMembers,
+    learners: ClusterMembers,
+    witnesses: ClusterMembers,
     state: ClusterState,
+    joint_quorum: JointQuorum,
 }
 impl ClusterConfig {
     /// 現在のクラスタ状態を返す.
@@ -67,6 +100,21 @@ impl ClusterConfig {
         &self.old
     }
 
+    /// ラーナー(投票権を持たないメンバ)の集合が返される.
+    pub fn learners(&self) -> &ClusterMembers {
+        &self.learners
+    }
+
+    /// ウィットネス(投票権は有するが、ログのメタデータのみを保持するメンバ)の集合が返される.
+    pub fn witnesses(&self) -> &ClusterMembers {
+        &self.witnesses
+    }
+
+    /// `Joint`状態での合意値の算出方法を返す.
+    pub fn joint_quorum(&self) -> JointQuorum {
+        self.joint_quorum
+    }
+
     /// プライマリなメンバ集合が返される.
     ///
     /// "プライマリな集合"とは、それに属するメンバーの過半数以上の合意が得られれば、
@@ -86,13 +134,70 @@ impl ClusterConfig {
     /// クラスタに属するメンバ群を返す.
     ///
     /// 構成変更中の場合には、新旧両方のメンバの和集合が返される.
+    ///
+    /// ラーナーも含まれる.
     pub fn members(&self) -> impl Iterator<Item = &NodeId> {
-        self.new.union(&self.old)
+        self.new.union(&self.old).chain(self.learners.iter())
     }
 
     /// このクラスタ構成に含まれるノードかどうかを判定する.
+    ///
+    /// ラーナーの場合にも`true`が返される.
     pub fn is_known_node(&self, node: &NodeId) -> bool {
-        self.new.contains(node) || self.old.contains(node)
+        self.new.contains(node) || self.old.contains(node) || self.learners.contains(node)
+    }
+
+    /// ラーナーかどうかを判定する.
+    pub fn is_learner(&self, node: &NodeId) -> bool {
+        self.learners.contains(node)
+    }
+
+    /// ウィットネスかどうかを判定する.
+    pub fn is_witness(&self, node: &NodeId) -> bool {
+        self.witnesses.contains(node)
+    }
+
+    /// 選挙に立候補可能な(i.e., リーダになり得る)ノードかどうかを判定する.
+    ///
+    /// クラスタ構成に含まれるノードの内で、ラーナーでもウィットネスでもないものが該当する.
+    pub fn is_electable(&self, node: &NodeId) -> bool {
+        self.is_known_node(node) && !self.is_learner(node) && !self.is_witness(node)
+    }
+
+    /// 構成変更によって、投票権を失いつつあるノードかどうかを判定する.
+    ///
+    /// 構成変更中で、`node`が旧メンバ群には含まれるが、新メンバ群には含まれない
+    /// (i.e., 新構成ではラーナーとなるか、クラスタから取り除かれる)場合に`true`が返される.
+    ///
+    /// このようなノードは、構成変更が完了するまでは旧構成のメンバとして合意に参加するが、
+    /// 新構成の合意値の算出には数えられない.
+    /// 安定状態では、常に`false`が返される.
+    pub fn is_being_demoted(&self, node: &NodeId) -> bool {
+        !self.state.is_stable() && self.old.contains(node) && !self.new.contains(node)
+    }
+
+    /// 構成変更で新たに追加されたメンバ群のログ同期が完了したかどうかを判定する.
+    ///
+    /// `synced_new_members`は、ログ同期が完了したメンバの集合.
+    ///
+    /// 新メンバ群の内で旧メンバ群に含まれないもの全てが`synced_new_members`に含まれる場合に`true`が返される.
+    /// その場合には、`CatchUp`状態から`Joint`状態に遷移可能となる.
+    pub fn catchup_complete(&self, synced_new_members: &BTreeSet<NodeId>) -> bool {
+        self.new
+            .difference(&self.old)
+            .all(|n| synced_new_members.contains(n))
+    }
+
+    /// `acked`に含まれるノード群が、現在の構成における過半数(quorum)を形成するかどうかを判定する.
+    ///
+    /// `Joint`状態では(`JointQuorum::Both`の場合)、新旧両方のメンバ群で過半数が必要となる.
+    ///
+    /// # Errors
+    ///
+    /// 合意の判定に使用するメンバ群が空の場合には`ErrorKind::InconsistentState`が返される
+    /// (`consensus_value`を参照).
+    pub fn would_commit(&self, acked: &BTreeSet<NodeId>) -> Result<bool> {
+        track!(self.consensus_value(|n| acked.contains(n)))
     }
 
     /// 新しい安定状態の`ClusterConfig`インスタンスを生成する.
@@ -100,31 +205,222 @@ impl ClusterConfig {
         ClusterConfig {
             new: members,
             old: ClusterMembers::default(),
+            learners: ClusterMembers::default(),
+            witnesses: ClusterMembers::default(),
             state: ClusterState::Stable,
+            joint_quorum: JointQuorum::default(),
         }
     }
 
     /// 構成変更中の`ClusterConfig`インスタンスを生成する.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には`ErrorKind::InvalidInput`が返される:
+    ///
+    /// - `new_members`が空
+    /// - `state`が`Stable`なのに、`old_members`が空ではない
+    /// - `state`が`CatchUp`ないし`Joint`なのに、`old_members`が空
     pub fn with_state(
         new_members: ClusterMembers,
         old_members: ClusterMembers,
         state: ClusterState,
+    ) -> Result<Self> {
+        track_assert!(
+            !new_members.is_empty(),
+            ErrorKind::InvalidInput,
+            "New members must not be empty"
+        );
+        track_assert!(
+            state.is_stable() == old_members.is_empty(),
+            ErrorKind::InvalidInput,
+            "Old members must be empty if and only if the state is stable: state={:?}, old={:?}",
+            state,
+            old_members
+        );
+        Ok(Self::with_state_unchecked(
+            new_members,
+            old_members,
+            ClusterMembers::default(),
+            ClusterMembers::default(),
+            state,
+            JointQuorum::default(),
+        ))
+    }
+
+    /// 引数の組み合わせの妥当性を検証せずに、`ClusterConfig`インスタンスを生成する.
+    ///
+    /// 永続化済みの構成情報のデシリアライズ等、
+    /// 入力が既に妥当であることが保証されている場合に使用される.
+    /// それ以外の場合には`with_state`を使用すること.
+    ///
+    /// 各引数は、同名のアクセサ(e.g., `learners`, `joint_quorum`)が返す値にそのまま対応する.
+    /// そのため、全てのアクセサの値を保存しておけば、それらを渡すことで元の構成情報を復元できる.
+    pub fn with_state_unchecked(
+        new_members: ClusterMembers,
+        old_members: ClusterMembers,
+        learners: ClusterMembers,
+        witnesses: ClusterMembers,
+        state: ClusterState,
+        joint_quorum: JointQuorum,
     ) -> Self {
         ClusterConfig {
             new: new_members,
             old: old_members,
+            learners,
+            witnesses,
             state,
+            joint_quorum,
+        }
+    }
+
+    /// ラーナー群を`learners`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// 投票権を有するメンバに含まれるノードは、ラーナーからは除外される.
+    pub fn with_learners(mut self, learners: ClusterMembers) -> Self {
+        self.learners = learners
+            .into_iter()
+            .filter(|n| !self.new.contains(n) && !self.old.contains(n))
+            .collect();
+        self
+    }
+
+    /// ウィットネス群を`witnesses`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// ウィットネスは投票権を有するメンバとして扱われ、合意値の算出にも(他のメンバと同様に)参加する.
+    /// ただし、リーダはウィットネスに対して、`Command`エントリのペイロードを取り除いたエントリ群と、
+    /// 内容を空にしたスナップショットのみを送信する.
+    /// またウィットネスは、選挙に立候補することも、リーダシップの移譲先となることもない.
+    ///
+    /// 投票権を有するメンバに含まれないノードは無視される.
+    /// 以後の構成変更では、引き続き投票権を有するメンバである限り、ウィットネスとして扱われる.
+    ///
+    /// 一度削除したウィットネスを、データを保持するメンバとして再追加する場合には、
+    /// 事前にそのノードのストレージを初期化しておく必要がある
+    /// (ペイロードを持たないエントリが残っている場合には、そのノードはエラーを返して停止する).
+    pub fn with_witnesses(mut self, witnesses: ClusterMembers) -> Self {
+        self.witnesses = witnesses
+            .into_iter()
+            .filter(|n| self.new.contains(n) || self.old.contains(n))
+            .collect();
+        self
+    }
+
+    /// `Joint`状態での合意値の算出方法を`joint_quorum`に置き換えた`ClusterConfig`インスタンスを返す.
+    ///
+    /// 指定した方法は、以後の構成変更でも引き継がれる.
+    /// 安全性に関するトレードオフについては`JointQuorum`のドキュメントを参照のこと.
+    ///
+    /// # Errors
+    ///
+    /// 構成変更中に`JointQuorum::Union`が指定され、かつ、現在の新旧メンバ群に対して
+    /// それが安全ではない場合には`ErrorKind::InvalidInput`が返される.
+    pub fn with_joint_quorum(mut self, joint_quorum: JointQuorum) -> Result<Self> {
+        if joint_quorum == JointQuorum::Union && !self.state.is_stable() {
+            track!(validate_union_quorum(&self.new, &self.old))?;
         }
+        self.joint_quorum = joint_quorum;
+        Ok(self)
+    }
+
+    /// 現在の構成から、`new`をメンバ群とする構成への変更が可能かどうかを検証する.
+    ///
+    /// 現在のメンバ群と`new`に共通するメンバが存在しない場合(i.e., メンバの総入れ替え)には、
+    /// 新旧の構成で互いに素な過半数が成立し得るので、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    ///
+    /// また`new`が空の場合には、合意値が定まらなくなるので、同様にエラーが返される.
+    ///
+    /// `joint_quorum`が`JointQuorum::Union`の場合には、さらに、和集合の過半数が
+    /// 新旧のそれぞれの過半数と必ず交わることが要求される.
+    pub fn validate_transition(&self, new: &ClusterMembers) -> Result<()> {
+        track_assert!(
+            !new.is_empty(),
+            ErrorKind::InvalidInput,
+            "New members must not be empty"
+        );
+        let old = self.primary_members();
+        track_assert!(
+            old.is_empty() || !old.is_disjoint(new),
+            ErrorKind::InvalidInput,
+            "New members must overlap with current ones: old={:?}, new={:?}",
+            old,
+            new
+        );
+        if self.joint_quorum == JointQuorum::Union {
+            track!(validate_union_quorum(new, old))?;
+        }
+        Ok(())
     }
 
     /// 構成変更を開始するために、`new`を構成変更後のメンバ群として設定し、
     /// `CatchUp`状態に遷移した`ClusterConfig`インスタンスを返す.
-    pub(crate) fn start_config_change(&self, new: ClusterMembers) -> Self {
-        ClusterConfig {
+    ///
+    /// `new`に含まれるラーナーは、投票権を有するメンバに昇格する.
+    ///
+    /// なお、これは構成情報の値を計算するのみで、実際の構成変更は`ReplicatedLog::propose_config`を通して行われる.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には`ErrorKind::InvalidInput`が返される(`validate_transition`を参照):
+    ///
+    /// - `new`が空
+    /// - `new`が現在のメンバ群と共通部分を持たない
+    /// - `JointQuorum::Union`が指定されているが、`new`への変更に対しては安全ではない
+    pub fn start_config_change(&self, new: ClusterMembers) -> Result<Self> {
+        track!(self.validate_transition(&new))?;
+        let learners = self.learners.difference(&new).cloned().collect();
+        let old = self.primary_members().clone();
+        let witnesses = self
+            .witnesses
+            .iter()
+            .filter(|n| new.contains(*n) || old.contains(*n))
+            .cloned()
+            .collect();
+        Ok(ClusterConfig {
             new,
-            old: self.primary_members().clone(),
+            old,
+            learners,
+            witnesses,
             state: ClusterState::CatchUp,
+            joint_quorum: self.joint_quorum,
+        })
+    }
+
+    /// `learner`をラーナーとしてクラスタに追加した`ClusterConfig`インスタンスを返す.
+    ///
+    /// クラスタの状態は変化しない.
+    pub(crate) fn add_learner(&self, learner: NodeId) -> Self {
+        let mut next = self.clone();
+        if !self.is_known_node(&learner) {
+            next.learners.insert(learner);
         }
+        next
+    }
+
+    /// ラーナーである`learner`を新メンバに加えて、構成変更を開始する.
+    ///
+    /// 通常の構成変更と同様に`CatchUp`状態に遷移するので、
+    /// `learner`のログが旧メンバに追い付いた後に、投票権を有するメンバへの移行が完了する.
+    pub(crate) fn promote_learner(&self, learner: &NodeId) -> Result<Self> {
+        let mut new = self.new.clone();
+        new.insert(learner.clone());
+        track!(self.start_config_change(new))
+    }
+
+    /// `CatchUp`状態の構成変更を中止して、旧メンバ群のみからなる安定状態の構成を返す.
+    ///
+    /// `CatchUp`以外の状態では、単に自身の複製を返す
+    /// (`Joint`状態では、既に新メンバ群も合意に参加しているので、中止することはできない).
+    pub(crate) fn abort_catchup(&self) -> Self {
+        if self.state != ClusterState::CatchUp {
+            return self.clone();
+        }
+        let mut next = self.clone();
+        next.new = self.old.clone();
+        next.old = ClusterMembers::new();
+        next.witnesses = self.witnesses.intersection(&self.old).cloned().collect();
+        next.state = ClusterState::Stable;
+        next
     }
 
     /// 次の状態に遷移する.
@@ -145,12 +441,26 @@ impl ClusterConfig {
             ClusterState::Joint => {
                 let mut next = self.clone();
                 next.old = ClusterMembers::new();
+                next.witnesses = self.witnesses.intersection(&self.new).cloned().collect();
                 next.state = ClusterState::Stable;
                 next
             }
         }
     }
 
+    /// ログの`index`の地点までをコミットする際に、合意の判定に使用すべきクラスタ構成を返す.
+    ///
+    /// 構成変更中であっても、最新の構成ではなく、`index`の直前のエントリの時点で有効な構成が返される.
+    /// そのため、旧構成の下で提案されたエントリは、旧構成の規則に従ってコミットされることになる.
+    ///
+    /// `index`が`history`から既に削除された(i.e., コミット済みの)領域を指している場合には、
+    /// `LogHistory::committed_config`が返される.
+    pub fn effective_for_commit(history: &LogHistory, index: LogIndex) -> &ClusterConfig {
+        history
+            .config_at(index)
+            .unwrap_or_else(|| history.committed_config())
+    }
+
     /// 現在の構成での最新の合意値を返す.
     //
     /// `f`は、各メンバの現在の承認値を返す関数.
@@ -160,50 +470,741 @@ impl ClusterConfig {
     ///
     /// 最終的な合意値は「メンバの過半数が承認した値集合の中で
     /// 最も大きな値」となる.
-    pub(crate) fn consensus_value<F, T>(&self, f: F) -> T
+    ///
+    /// `Joint`状態での算出方法は`joint_quorum`に従う.
+    ///
+    /// メンバが一つだけの場合には、そのメンバの承認値がそのまま合意値となる.
+    ///
+    /// # Errors
+    ///
+    /// 合意の判定に使用するメンバ群が空の場合には、合意値が定まらないので
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
+    ///
+    /// `validate_transition`により、構成変更によってメンバが空になることはないが、
+    /// 初期構成(`ClusterConfig::new`)や`with_state_unchecked`では、空の構成も生成可能である.
+    pub(crate) fn consensus_value<F, T>(&self, f: F) -> Result<T>
     where
         F: Fn(&NodeId) -> T,
-        T: Ord + Copy + Default,
+        T: Ord + Copy,
     {
         match self.state {
-            ClusterState::Stable => median(&self.new, &f),
-            ClusterState::CatchUp => median(&self.old, &f),
-            ClusterState::Joint => {
+            ClusterState::Stable => track!(median(&self.new, &f)),
+            // 新メンバ群はログの同期中であり、まだ投票権を持たない.
+            // そのため(`primary_members`と同様に)旧メンバ群のみで合意値を決定する.
+            ClusterState::CatchUp => track!(median(&self.old, &f)),
+            ClusterState::Joint => match self.joint_quorum {
                 // joint consensus
-                cmp::min(median(&self.new, &f), median(&self.old, &f))
-            }
+                JointQuorum::Both => {
+                    let new = track!(median(&self.new, &f))?;
+                    let old = track!(median(&self.old, &f))?;
+                    Ok(cmp::min(new, old))
+                }
+                JointQuorum::Union => {
+                    track!(median(&self.new.union(&self.old).cloned().collect(), &f))
+                }
+            },
         }
     }
 
+    /// `consensus_value`メソッドによる合意値に加えて、その内訳を返す.
+    ///
+    /// 返り値には、投票権を有する各メンバの承認値や、過半数の判定に必要なメンバ数、
+    /// および、実際に過半数を形成したメンバ群が含まれる.
+    /// コミットが進まない原因を調査する際などに使用する.
+    ///
+    /// # Errors
+    ///
+    /// `consensus_value`と同様に、合意の判定に使用するメンバ群が空の場合には
+    /// `ErrorKind::InconsistentState`が返される.
+    pub fn agreement_detail<F, T>(&self, f: F) -> Result<AgreementReport<T>>
+    where
+        F: Fn(&NodeId) -> T,
+        T: Ord + Copy,
+    {
+        let groups = match self.state {
+            ClusterState::Stable => vec![track!(agreement(&self.new, &f))?],
+            ClusterState::CatchUp => vec![track!(agreement(&self.old, &f))?],
+            ClusterState::Joint => match self.joint_quorum {
+                JointQuorum::Both => vec![
+                    track!(agreement(&self.new, &f))?,
+                    track!(agreement(&self.old, &f))?,
+                ],
+                JointQuorum::Union => vec![track!(agreement(
+                    &self.new.union(&self.old).cloned().collect(),
+                    &f
+                ))?],
+            },
+        };
+        Ok(AgreementReport {
+            value: track!(self.consensus_value(&f))?,
+            groups,
+        })
+    }
+
     /// 基本的には`consensus_value`メソッドと同様.
     ///
     /// ただし構成変更中には、常に新旧メンバ群の両方から、
-    /// 過半数の承認を要求するところが異なる.
-    pub(crate) fn full_consensus_value<F, T>(&self, f: F) -> T
+    /// 過半数の承認を要求するところが異なる(`joint_quorum`の値は参照されない).
+    ///
+    /// # Errors
+    ///
+    /// 新旧いずれかのメンバ群が空の場合には`ErrorKind::InconsistentState`が返される.
+    pub(crate) fn full_consensus_value<F, T>(&self, f: F) -> Result<T>
     where
         F: Fn(&NodeId) -> T,
-        T: Ord + Copy + Default,
+        T: Ord + Copy,
     {
+        let new = track!(median(&self.new, &f))?;
         if self.state.is_stable() {
-            median(&self.new, &f)
+            Ok(new)
         } else {
             // joint consensus
-            cmp::min(median(&self.new, &f), median(&self.old, &f))
+            let old = track!(median(&self.old, &f))?;
+            Ok(cmp::min(new, old))
         }
     }
 }
 
-fn median<F, T>(members: &ClusterMembers, f: F) -> T
+/// `ClusterConfig::agreement_detail`が返す、合意値とその内訳.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgreementReport<T> {
+    /// 合意値 (i.e., `ClusterConfig::consensus_value`の結果).
+    pub value: T,
+
+    /// 合意の判定に使用されたメンバ群毎の内訳.
+    ///
+    /// 通常は一つだけだが、`JointQuorum::Both`での`Joint`状態では、新旧メンバ群の順に二つとなる.
+    pub groups: Vec<GroupAgreement<T>>,
+}
+
+/// あるメンバ群における、合意値の内訳.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupAgreement<T> {
+    /// 各メンバの承認値.
+    pub values: BTreeMap<NodeId, T>,
+
+    /// 過半数の判定に必要なメンバ数.
+    pub threshold: usize,
+
+    /// 過半数を形成したメンバ群 (i.e., 承認値が大きい順に`threshold`個選んだもの).
+    ///
+    /// 同じ承認値のメンバ間では、IDが小さい方が優先される.
+    pub quorum: ClusterMembers,
+
+    /// このメンバ群での合意値.
+    pub value: T,
+}
+
+fn agreement<F, T>(members: &ClusterMembers, f: F) -> Result<GroupAgreement<T>>
 where
     F: Fn(&NodeId) -> T,
-    T: Ord + Copy + Default,
+    T: Ord + Copy,
 {
-    let mut values = members.iter().map(|n| f(n)).collect::<Vec<_>>();
+    let values = members
+        .iter()
+        .map(|n| (n.clone(), f(n)))
+        .collect::<BTreeMap<_, _>>();
+    let mut sorted = values.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let threshold = members.len() / 2 + 1;
+    let quorum = sorted
+        .iter()
+        .take(threshold)
+        .map(|(n, _)| (*n).clone())
+        .collect();
+    let value = track!(median(members, &f))?;
+    Ok(GroupAgreement {
+        values,
+        threshold,
+        quorum,
+        value,
+    })
+}
+
+/// `JointQuorum::Union`を、`new`と`old`の間の構成変更に使用しても安全かどうかを検証する.
+///
+/// 和集合の過半数(`quorum`)に含まれる`X`(新旧いずれかのメンバ群)のメンバ数は、
+/// 最小でも`quorum - |和集合 - X|`となる.
+/// これと`X`の過半数の和が`|X|`を超えていれば、両者は必ず交わる.
+fn validate_union_quorum(new: &ClusterMembers, old: &ClusterMembers) -> Result<()> {
+    let union = new.union(old).count();
+    let quorum = union / 2 + 1;
+    for members in &[new, old] {
+        let overlap = quorum.saturating_sub(union - members.len());
+        track_assert!(
+            overlap + members.len() / 2 + 1 > members.len(),
+            ErrorKind::InvalidInput,
+            "A majority of the union may not intersect a majority of {:?}: new={:?}, old={:?}",
+            members,
+            new,
+            old
+        );
+    }
+    Ok(())
+}
+
+fn median<F, T>(members: &ClusterMembers, f: F) -> Result<T>
+where
+    F: Fn(&NodeId) -> T,
+    T: Ord + Copy,
+{
+    track_assert!(
+        !members.is_empty(),
+        ErrorKind::InconsistentState,
+        "No voting members to agree on a value"
+    );
+    let mut values = members.iter().map(f).collect::<Vec<_>>();
     values.sort();
     values.reverse();
-    if values.is_empty() {
-        T::default()
-    } else {
-        values[members.len() / 2]
+    Ok(values[members.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Async, Stream};
+
+    use super::*;
+    use crate::election::Role;
+    use crate::log::{LogEntry, LogPosition};
+    use crate::testing::MockCluster;
+    use crate::ReplicatedLogOptions;
+
+    fn members(ids: &[&str]) -> ClusterMembers {
+        ids.iter().map(|&id| NodeId::from(id)).collect()
+    }
+
+    fn complete_config_change(cluster: &mut MockCluster, leader: &str) {
+        cluster.run();
+        for _ in 0..10 {
+            if cluster.node(leader).cluster_config().state().is_stable() {
+                return;
+            }
+            cluster.fire_timeout(leader);
+            cluster.run();
+        }
+        panic!("The configuration change did not complete");
+    }
+
+    #[test]
+    fn would_commit_requires_quorum_of_each_half() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(stable.would_commit(&members(&["a", "b"])).unwrap());
+        assert!(!stable.would_commit(&members(&["c"])).unwrap());
+
+        let joint = ClusterConfig::with_state(
+            members(&["c", "d", "e"]),
+            members(&["a", "b", "c"]),
+            ClusterState::Joint,
+        )
+        .unwrap();
+
+        // 新メンバ群のみで過半数を満たしても、旧メンバ群の過半数がなければ不十分
+        assert!(!joint.would_commit(&members(&["c", "d", "e"])).unwrap());
+
+        // 新旧両方で過半数を満たす
+        assert!(joint.would_commit(&members(&["b", "c", "d"])).unwrap());
+    }
+
+    #[test]
+    fn learners_are_not_counted_in_consensus() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"])).add_learner("d".into());
+        assert!(config.is_learner(&"d".into()));
+        assert!(config.is_known_node(&"d".into()));
+        assert_eq!(config.members().count(), 4);
+        assert_eq!(*config.primary_members(), members(&["a", "b", "c"]));
+
+        // ラーナーの値は、どのような値であっても合意値に影響しない
+        let value = |n: &NodeId| match n.as_str() {
+            "a" => 10,
+            "d" => 100,
+            _ => 0,
+        };
+        assert_eq!(config.consensus_value(value).unwrap(), 0);
+        assert_eq!(config.full_consensus_value(value).unwrap(), 0);
+    }
+
+    #[test]
+    fn agreement_detail_names_quorum() {
+        let config = ClusterConfig::new(members(&["a", "b", "c", "d", "e"]));
+        let value = |n: &NodeId| match n.as_str() {
+            "a" => 12,
+            "b" => 10,
+            "c" => 11,
+            _ => 3, // "d"と"e"は遅れている
+        };
+        let report = config.agreement_detail(value).unwrap();
+        assert_eq!(report.value, config.consensus_value(value).unwrap());
+        assert_eq!(report.value, 10);
+        assert_eq!(report.groups.len(), 1);
+
+        let group = &report.groups[0];
+        assert_eq!(group.threshold, 3);
+        assert_eq!(group.quorum, members(&["a", "b", "c"]));
+        assert_eq!(group.values.len(), 5);
+        assert_eq!(group.values[&"d".into()], 3);
+
+        // `Joint`状態では、新旧のそれぞれについて内訳が返される
+        let joint = config
+            .start_config_change(members(&["a", "b", "f"]))
+            .unwrap()
+            .to_next_state();
+        let report = joint.agreement_detail(value).unwrap();
+        assert_eq!(report.value, joint.consensus_value(value).unwrap());
+        assert_eq!(report.groups.len(), 2);
+        assert_eq!(report.groups[0].threshold, 2);
+        assert_eq!(report.groups[0].quorum, members(&["a", "b"]));
+        assert_eq!(report.groups[1].threshold, 3);
+        assert_eq!(report.groups[1].quorum, members(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn witness_forms_majority_without_storing_commands() {
+        let options = ReplicatedLogOptions {
+            witnesses: members(&["w"]),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "w"], options);
+        assert!(cluster.node("a").cluster_config().is_witness(&"w".into()));
+        cluster.elect("a");
+
+        // データを保持するノードの一方が停止していても、ウィットネスと合わせて過半数となる
+        cluster.isolate("b");
+        let proposal = cluster.node_mut("a").propose_command(vec![1; 8]).unwrap();
+        cluster.run();
+        assert!(cluster
+            .node("a")
+            .local_history()
+            .committed_tail()
+            .is_newer_or_equal_than(LogPosition {
+                prev_term: proposal.term,
+                index: proposal.index + 1,
+            }));
+
+        // ウィットネスは、エントリの位置は保持するが、コマンドの内容は保持しない
+        let witness = cluster.log_suffix("w");
+        assert_eq!(witness.tail(), cluster.log_suffix("a").tail());
+        assert!(witness
+            .entries
+            .iter()
+            .all(|e| !matches!(e, LogEntry::Command { .. })));
+        assert!(witness
+            .entries
+            .iter()
+            .any(|e| matches!(e, LogEntry::StrippedCommand { .. })));
+        assert!(cluster.log_suffix("a").entries.iter().any(|e| match e {
+            LogEntry::Command { command, .. } => command[..] == [1; 8],
+            _ => false,
+        }));
+
+        // ウィットネスは立候補せず、リーダシップの移譲先にもならない
+        cluster.fire_timeout("w");
+        cluster.run();
+        assert_eq!(cluster.node("w").local_node().role, Role::Follower);
+        assert_eq!(cluster.leader(), Some("a".into()));
+        let e = cluster.node_mut("a").transfer_leadership("w".into()).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn former_witness_cannot_rejoin_as_data_node_without_wiping() {
+        let options = ReplicatedLogOptions {
+            witnesses: members(&["w"]),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "w"], options);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1; 8]).unwrap();
+        cluster.run();
+
+        // ウィットネスを削除する
+        cluster
+            .node_mut("a")
+            .propose_config(members(&["a", "b"]))
+            .unwrap();
+        complete_config_change(&mut cluster, "a");
+        let mut witness = cluster.take_node("w");
+
+        // データを保持するメンバとして再追加しても、ペイロードを持たないエントリが残っているので参加を拒否する
+        let new_members = members(&["a", "b", "w"]);
+        cluster
+            .node_mut("a")
+            .propose_config(new_members.clone())
+            .unwrap();
+        // (取り外したノードへのメッセージが残り続けるので、`run`ではなく`step`で駆動する)
+        let error = (0..100)
+            .find_map(|i| {
+                if i % 10 == 0 {
+                    cluster.fire_timeout("a");
+                }
+                cluster.step();
+                loop {
+                    match witness.poll() {
+                        Ok(Async::Ready(_)) => {}
+                        Ok(Async::NotReady) => return None,
+                        Err(e) => return Some(e),
+                    }
+                }
+            })
+            .expect("The former witness was not rejected");
+        assert_eq!(*error.kind(), ErrorKind::InconsistentState);
+        assert!(!cluster.node("a").cluster_config().is_witness(&"w".into()));
+
+        // ストレージを初期化すれば、データを保持するメンバとして参加できる
+        cluster.restart_from_storage("w", Default::default());
+        complete_config_change(&mut cluster, "a");
+        assert_eq!(
+            *cluster.node("a").cluster_config().primary_members(),
+            new_members
+        );
+        assert!(cluster.log_suffix("w").entries.iter().any(|e| match e {
+            LogEntry::Command { command, .. } => command[..] == [1; 8],
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn demoted_node_is_reported() {
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        assert!(!stable.is_being_demoted(&"c".into()));
+
+        let catchup = stable
+            .start_config_change(members(&["a", "b", "d"]))
+            .unwrap();
+        let joint = catchup.to_next_state();
+        for config in &[&catchup, &joint] {
+            assert!(config.is_being_demoted(&"c".into()));
+            assert!(!config.is_being_demoted(&"a".into()));
+            assert!(!config.is_being_demoted(&"d".into()));
+        }
+
+        // 新構成では合意に数えられない
+        let value = |n: &NodeId| match n.as_str() {
+            "c" => 10,
+            _ => 0,
+        };
+        assert_eq!(median(joint.new_members(), value).unwrap(), 0);
+
+        let stable = joint.to_next_state();
+        assert!(!stable.is_being_demoted(&"c".into()));
+        assert!(!stable.is_known_node(&"c".into()));
+    }
+
+    #[test]
+    fn joint_quorum_modes() {
+        let config = ClusterConfig::new(members(&["a", "b"]))
+            .start_config_change(members(&["a", "b", "c"]))
+            .unwrap()
+            .to_next_state();
+        assert_eq!(config.state(), ClusterState::Joint);
+        assert_eq!(config.joint_quorum(), JointQuorum::Both);
+
+        // 旧メンバの`b`が遅れている
+        let value = |n: &NodeId| match n.as_str() {
+            "a" | "c" => 10,
+            _ => 0,
+        };
+        assert_eq!(config.consensus_value(value).unwrap(), 0);
+        assert_eq!(config.full_consensus_value(value).unwrap(), 0);
+
+        let config = config.with_joint_quorum(JointQuorum::Union).unwrap();
+        assert_eq!(config.consensus_value(value).unwrap(), 10);
+        assert_eq!(config.full_consensus_value(value).unwrap(), 0);
+
+        // 和集合でも過半数に満たない場合には進まない
+        let value = |n: &NodeId| match n.as_str() {
+            "c" => 10,
+            _ => 0,
+        };
+        assert_eq!(config.consensus_value(value).unwrap(), 0);
+
+        // 指定は以後の構成変更にも引き継がれる
+        let config = config
+            .to_next_state()
+            .start_config_change(members(&["a", "c"]))
+            .unwrap();
+        assert_eq!(config.joint_quorum(), JointQuorum::Union);
+    }
+
+    #[test]
+    fn unsafe_union_quorum_is_rejected() {
+        // 和集合`{a,b,c,d,e}`の過半数`{c,d,e}`は、旧メンバ群の過半数`{a,b}`と交わらない
+        let stable = ClusterConfig::new(members(&["a", "b", "c"]));
+        let union = stable
+            .clone()
+            .with_joint_quorum(JointQuorum::Union)
+            .unwrap();
+        let e = union.start_config_change(members(&["c", "d", "e"])).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        // 構成変更中に`Union`に切り替えることもできない
+        let joint = stable
+            .start_config_change(members(&["c", "d", "e"]))
+            .unwrap()
+            .to_next_state();
+        let e = joint.clone().with_joint_quorum(JointQuorum::Union).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        assert!(joint.with_joint_quorum(JointQuorum::Both).is_ok());
+    }
+
+    #[test]
+    fn union_quorum_never_forms_disjoint_commits() {
+        let nodes = ["a", "b", "c", "d", "e"];
+        let subset = |mask: usize| -> ClusterMembers {
+            nodes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, n)| NodeId::from(*n))
+                .collect()
+        };
+        for old_mask in 1..(1 << nodes.len()) {
+            for new_mask in 1..(1 << nodes.len()) {
+                let old = subset(old_mask);
+                let new = subset(new_mask);
+                let stable = ClusterConfig::new(old.clone());
+                if stable.validate_transition(&new).is_err() {
+                    continue;
+                }
+                let accepted = stable
+                    .with_joint_quorum(JointQuorum::Union)
+                    .unwrap()
+                    .start_config_change(new.clone())
+                    .is_ok();
+
+                // 和集合の過半数`acked`に対して、その補集合が新旧いずれかの過半数を含むなら、
+                // 互いに素なコミットが成立し得る
+                let joint = ClusterConfig::with_state_unchecked(
+                    new.clone(),
+                    old.clone(),
+                    members(&[]),
+                    members(&[]),
+                    ClusterState::Joint,
+                    JointQuorum::Union,
+                );
+                let disjoint = (0..(1 << nodes.len()))
+                    .map(|mask| subset(mask & (old_mask | new_mask)))
+                    .filter(|acked| joint.would_commit(acked).unwrap())
+                    .any(|acked| {
+                        [&old, &new].iter().any(|&x| {
+                            let rest = x.difference(&acked).cloned().collect();
+                            ClusterConfig::new(x.clone()).would_commit(&rest).unwrap()
+                        })
+                    });
+                assert_eq!(accepted, !disjoint, "old={:?}, new={:?}", old, new);
+            }
+        }
+    }
+
+    #[test]
+    fn promote_learner_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"])).add_learner("d".into());
+        let config = config.promote_learner(&"d".into()).unwrap();
+        assert_eq!(config.state(), ClusterState::CatchUp);
+        assert!(!config.is_learner(&"d".into()));
+        assert_eq!(*config.new_members(), members(&["a", "b", "c", "d"]));
+        assert_eq!(*config.old_members(), members(&["a", "b", "c"]));
+
+        let config = config.to_next_state().to_next_state();
+        assert_eq!(config.state(), ClusterState::Stable);
+        assert_eq!(*config.primary_members(), members(&["a", "b", "c", "d"]));
+        assert!(config.learners().is_empty());
+    }
+
+    #[test]
+    fn catchup_complete_works() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d"]))
+            .unwrap();
+        assert!(!config.catchup_complete(&members(&[])));
+        assert!(!config.catchup_complete(&members(&["a", "b", "c"])));
+        assert!(config.catchup_complete(&members(&["d"])));
+    }
+
+    #[test]
+    fn start_config_change_validates_target() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+
+        // メンバの追加
+        let next = config
+            .start_config_change(members(&["a", "b", "c", "d"]))
+            .unwrap();
+        assert_eq!(next.state(), ClusterState::CatchUp);
+        assert_eq!(*next.new_members(), members(&["a", "b", "c", "d"]));
+        assert_eq!(*next.old_members(), members(&["a", "b", "c"]));
+
+        // メンバの削除
+        let next = config.start_config_change(members(&["a", "b"])).unwrap();
+        assert_eq!(*next.new_members(), members(&["a", "b"]));
+        assert_eq!(*next.old_members(), members(&["a", "b", "c"]));
+
+        // 空のメンバ群への変更は拒否される
+        let e = config.start_config_change(members(&[])).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn disjoint_config_change_is_rejected() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]));
+        let e = config.start_config_change(members(&["d", "e", "f"])).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        let config = config
+            .start_config_change(members(&["a", "d", "e"]))
+            .unwrap();
+        assert_eq!(*config.new_members(), members(&["a", "d", "e"]));
+        assert_eq!(*config.old_members(), members(&["a", "b", "c"]));
+
+        // 初期構成(メンバなし)からの変更は常に許可される
+        let config = ClusterConfig::new(members(&[]));
+        assert!(config.start_config_change(members(&["a"])).is_ok());
+    }
+
+    #[test]
+    fn single_member_consensus_works() {
+        let config = ClusterConfig::new(members(&["a"]));
+        assert_eq!(config.consensus_value(|_| 10).unwrap(), 10);
+        assert_eq!(config.full_consensus_value(|_| 10).unwrap(), 10);
+
+        let e = config.start_config_change(members(&[])).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        // 投票権を持つメンバが存在しない場合には、合意値は定まらない
+        let empty = ClusterConfig::new(members(&[]));
+        let e = empty.consensus_value(|_| 10).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+        let e = empty.full_consensus_value(|_| 10).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+        let e = empty.agreement_detail(|_| 10).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+        let e = empty.would_commit(&members(&["a"])).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+    }
+
+    #[test]
+    fn single_node_cluster_commits_proposal() {
+        let mut cluster = MockCluster::new(&["a"]);
+        cluster.elect("a");
+        let proposal = cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let history = cluster.node("a").local_history();
+        assert!(history.committed_tail().index > proposal.index);
+        assert_eq!(history.committed_tail(), history.tail());
+    }
+
+    #[test]
+    fn only_old_members_vote_during_catchup() {
+        let config = ClusterConfig::new(members(&["a", "b", "c"]))
+            .start_config_change(members(&["a", "b", "c", "d"]))
+            .unwrap();
+        assert_eq!(config.state(), ClusterState::CatchUp);
+        assert_eq!(*config.primary_members(), *config.old_members());
+
+        // 同期中の新メンバ`d`の承認値は、合意値に影響しない
+        let value = |n: &NodeId| match n.as_str() {
+            "a" | "d" => 10,
+            _ => 0,
+        };
+        assert_eq!(config.consensus_value(value).unwrap(), 0);
+        let value = |n: &NodeId| match n.as_str() {
+            "a" | "b" => 10,
+            _ => 0,
+        };
+        assert_eq!(config.consensus_value(value).unwrap(), 10);
+    }
+
+    #[test]
+    fn catching_up_node_does_not_advance_commit() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.add_node("d");
+        cluster.elect("a");
+        let committed = cluster.node("a").local_history().committed_tail();
+
+        // 旧メンバの`b`と`c`が孤立している間は、`d`が追い付いてもコミットは進まない
+        cluster.isolate("b");
+        cluster.isolate("c");
+        let members = members(&["a", "b", "c", "d"]);
+        cluster
+            .node_mut("a")
+            .propose_config(members.clone())
+            .unwrap();
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let history = cluster.node("a").local_history();
+        assert_eq!(history.config().state(), ClusterState::CatchUp);
+        assert_eq!(cluster.log_suffix("d").tail(), history.tail());
+        assert_eq!(history.committed_tail(), committed);
+
+        // 旧メンバとの疎通が回復すると、構成変更が完了して、`d`も投票権を有するメンバとなる
+        cluster.heal();
+        cluster.fire_timeout("a");
+        cluster.run();
+        let history = cluster.node("a").local_history();
+        assert_eq!(history.config().state(), ClusterState::Stable);
+        assert_eq!(*history.config().primary_members(), members);
+        assert_eq!(history.committed_tail(), history.tail());
+    }
+
+    #[test]
+    fn with_state_rejects_inconsistent_combinations() {
+        let kind = |new: &[&str], old: &[&str], state| {
+            ClusterConfig::with_state(members(new), members(old), state)
+                .err()
+                .map(|e| *e.kind())
+        };
+        let invalid = Some(ErrorKind::InvalidInput);
+        assert_eq!(kind(&["a"], &["b"], ClusterState::Stable), invalid);
+        assert_eq!(kind(&["a"], &[], ClusterState::CatchUp), invalid);
+        assert_eq!(kind(&["a"], &[], ClusterState::Joint), invalid);
+        assert_eq!(kind(&[], &["a"], ClusterState::Joint), invalid);
+        assert_eq!(kind(&[], &[], ClusterState::Stable), invalid);
+
+        let joint = ClusterConfig::with_state(
+            members(&["b", "c"]),
+            members(&["a", "b"]),
+            ClusterState::Joint,
+        )
+        .unwrap();
+        assert!(joint.state().is_joint());
+        assert_eq!(*joint.old_members(), members(&["a", "b"]));
+
+        // 検証を行わないコンストラクタは、そのまま値を保持する
+        let raw = ClusterConfig::with_state_unchecked(
+            members(&["a"]),
+            members(&["b"]),
+            members(&[]),
+            members(&[]),
+            ClusterState::Stable,
+            JointQuorum::default(),
+        );
+        assert_eq!(*raw.old_members(), members(&["b"]));
+    }
+
+    #[test]
+    fn with_state_unchecked_round_trips_through_accessors() {
+        let original = ClusterConfig::with_state(
+            members(&["a", "b", "c"]),
+            members(&["a", "b"]),
+            ClusterState::Joint,
+        )
+        .unwrap()
+        .with_learners(members(&["d"]))
+        .with_witnesses(members(&["c"]))
+        .with_joint_quorum(JointQuorum::Union)
+        .unwrap();
+
+        // 永続化と復元を模擬する
+        let restored = ClusterConfig::with_state_unchecked(
+            original.new_members().clone(),
+            original.old_members().clone(),
+            original.learners().clone(),
+            original.witnesses().clone(),
+            original.state(),
+            original.joint_quorum(),
+        );
+        assert_eq!(restored, original);
+        assert_eq!(*restored.learners(), members(&["d"]));
+        assert_eq!(*restored.witnesses(), members(&["c"]));
+        assert_eq!(restored.joint_quorum(), JointQuorum::Union);
+        assert!(!restored.primary_members().contains(&NodeId::from("d")));
     }
 }
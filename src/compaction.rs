@@ -0,0 +1,313 @@
+//! ローカルログのコンパクション(スナップショット取得)関連.
+//!
+//! `ReplicatedLog::set_compaction_policy`で`CompactionPolicy`を設定しておくことで、
+//! `ReplicatedLog::maybe_compact`を呼び出した際に、
+//! 必要に応じて自動でスナップショットが取得・インストールされるようになる.
+//!
+//! また`ReplicatedLogOptions::snapshot_policy`に`SnapshotPolicy`を指定しておくことで、
+//! スナップショットを取得すべき時点で`Event::SnapshotRequested`が発行されるようになる.
+use crate::log::LogHistory;
+
+/// 複製ログのコマンド群を適用する状態機械.
+///
+/// コンパクションの際に、スナップショットを取得するために使用される.
+pub trait StateMachine {
+    /// 現在の状態機械のスナップショットを返す.
+    ///
+    /// スナップショットは、ローカルログの消費済み終端
+    /// (i.e., `LogHistory::consumed_tail`)までのコマンド群が適用された状態を表している必要がある.
+    fn snapshot(&self) -> Vec<u8>;
+}
+
+/// ローカルログのコンパクションを行うべきかどうかを判断するためのポリシー.
+pub trait CompactionPolicy {
+    /// コンパクションを行うべき場合には`true`を返す.
+    ///
+    /// `history`はローカルログの現在の履歴.
+    /// コンパクションが行われた場合には、`history.consumed_tail()`の地点が新しいログの先頭となる.
+    fn should_compact(&self, history: &LogHistory) -> bool;
+}
+
+/// `Event::SnapshotRequested`を発行するタイミングを決定するためのポリシー.
+///
+/// 閾値は消費済みのログ領域(i.e., `LogHistory::head`から`LogHistory::consumed_tail`まで)に対して判定され、
+/// 一度発行された後は、スナップショットがインストールされて、ログの先頭位置が変わるまでは再発行されない.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SnapshotPolicy {
+    /// スナップショットの取得は利用者に委ねられ、`Event::SnapshotRequested`は発行されない.
+    #[default]
+    Manual,
+
+    /// 消費済みのエントリ数が指定値以上になった時点で、スナップショットを要求する.
+    ConsumedEntries(usize),
+}
+impl CompactionPolicy for SnapshotPolicy {
+    fn should_compact(&self, history: &LogHistory) -> bool {
+        match *self {
+            SnapshotPolicy::Manual => false,
+            SnapshotPolicy::ConsumedEntries(n) => {
+                history.consumed_tail().index - history.head().index >= n
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterConfig;
+    use crate::testing::MockCluster;
+    use crate::{ErrorKind, Event, ReplicatedLogOptions};
+    use futures::{Async, Stream};
+    use std::sync::Arc;
+
+    struct EntryCountPolicy(usize);
+    impl CompactionPolicy for EntryCountPolicy {
+        fn should_compact(&self, history: &LogHistory) -> bool {
+            history.consumed_tail().index - history.head().index >= self.0
+        }
+    }
+
+    struct Machine(Vec<u8>);
+    impl StateMachine for Machine {
+        fn snapshot(&self) -> Vec<u8> {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn maybe_compact_works() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let machine = Machine(vec![1, 2, 3]);
+
+        // ポリシー未設定
+        assert_eq!(cluster.node_mut("a").maybe_compact(&machine).unwrap(), None);
+
+        cluster
+            .node_mut("a")
+            .set_compaction_policy(EntryCountPolicy(5));
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        assert_eq!(cluster.node_mut("a").maybe_compact(&machine).unwrap(), None);
+
+        for i in 0..2 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let new_head = cluster.node_mut("a").maybe_compact(&machine).unwrap();
+        assert!(new_head.is_some());
+        cluster.run();
+        assert_eq!(
+            Some(cluster.node("a").local_history().head().index),
+            new_head
+        );
+        let storage = cluster.node("a").io().storage().lock().unwrap();
+        assert_eq!(
+            storage.prefix.as_ref().map(|p| &p.snapshot),
+            Some(&machine.0)
+        );
+        drop(storage);
+
+        assert_eq!(cluster.node_mut("a").maybe_compact(&machine).unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_is_requested_once_per_head() {
+        let options = ReplicatedLogOptions {
+            snapshot_policy: SnapshotPolicy::ConsumedEntries(5),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        let requests = |cluster: &MockCluster| {
+            cluster
+                .events("a")
+                .iter()
+                .filter_map(|e| match e {
+                    Event::SnapshotRequested { up_to } => Some(*up_to),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for i in 0..10 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let up_to = requests(&cluster);
+        assert_eq!(up_to.len(), 1);
+        assert!(up_to[0].as_u64() >= 5);
+        assert!(up_to[0] <= cluster.node("a").local_history().consumed_tail().index);
+
+        // スナップショットがインストールされると、再び要求されるようになる
+        cluster
+            .node_mut("a")
+            .install_snapshot(up_to[0], vec![1])
+            .unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("a").local_history().head().index, up_to[0]);
+        for i in 0..10 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        assert_eq!(requests(&cluster).len(), 2);
+    }
+
+    #[test]
+    fn complete_snapshot_works() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let consumed_tail = cluster.node("a").local_history().consumed_tail().index;
+        let up_to = consumed_tail - 2;
+        cluster
+            .node_mut("a")
+            .complete_snapshot(up_to, vec![1, 2, 3])
+            .unwrap();
+        cluster.run();
+
+        let history = cluster.node("a").local_history();
+        assert_eq!(history.head().index, up_to);
+        let storage = cluster.node("a").io().storage().lock().unwrap();
+        let prefix = storage.prefix.clone().unwrap();
+        assert_eq!(prefix.tail, history.head());
+        assert_eq!(prefix.snapshot, [1, 2, 3]);
+        assert_eq!(&prefix.config, history.config_at(up_to).unwrap());
+        assert_eq!(storage.suffix.head.index, up_to);
+    }
+
+    #[test]
+    fn stale_snapshot_completion_is_ignored() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let consumed_tail = cluster.node("a").local_history().consumed_tail().index;
+        cluster
+            .node_mut("a")
+            .complete_snapshot(consumed_tail, vec![1])
+            .unwrap();
+        cluster.run();
+
+        // 既にスナップショットが取得済みの地点
+        for &up_to in &[consumed_tail, consumed_tail - 1] {
+            cluster
+                .node_mut("a")
+                .complete_snapshot(up_to, vec![2])
+                .unwrap();
+            cluster.run();
+        }
+        assert_eq!(
+            cluster.node("a").local_history().head().index,
+            consumed_tail
+        );
+        let storage = cluster.node("a").io().storage().lock().unwrap();
+        assert_eq!(
+            storage.prefix.as_ref().map(|p| &p.snapshot[..]),
+            Some(&[1][..])
+        );
+    }
+
+    #[test]
+    fn snapshot_completion_beyond_consumed_tail_is_rejected() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![0]).unwrap();
+        cluster.run();
+
+        let consumed_tail = cluster.node("a").local_history().consumed_tail().index;
+        let e = cluster
+            .node_mut("a")
+            .complete_snapshot(consumed_tail + 1, vec![1])
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert!(!cluster.node("a").is_snapshot_installing());
+    }
+
+    #[test]
+    fn install_snapshot_validates_against_history() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let consumed_tail = cluster.node("a").local_history().consumed_tail().index;
+        let config = cluster.node("a").cluster_config().clone();
+
+        // 記録されている構成と一致する
+        cluster
+            .node_mut("a")
+            .install_snapshot_with_config(consumed_tail - 2, config.clone(), vec![1])
+            .unwrap();
+        cluster.run();
+        let head = cluster.node("a").local_history().head();
+        assert_eq!(head.index, consumed_tail - 2);
+
+        // 現在の先頭よりも古い地点
+        let e = cluster
+            .node_mut("a")
+            .install_snapshot(head.index - 1, vec![2])
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+        let e = cluster
+            .node_mut("a")
+            .install_snapshot_with_config(head.index - 1, config.clone(), vec![2])
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+
+        // 記録されている構成と異なる
+        let other = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let e = cluster
+            .node_mut("a")
+            .install_snapshot_with_config(consumed_tail, other, vec![2])
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+
+        assert!(!cluster.node("a").is_snapshot_installing());
+        assert_eq!(cluster.node("a").local_history().head(), head);
+    }
+
+    #[test]
+    fn corrupted_snapshot_is_detected_on_load() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let consumed_tail = cluster.node("a").local_history().consumed_tail().index;
+        cluster
+            .node_mut("a")
+            .install_snapshot(consumed_tail, vec![1, 2, 3])
+            .unwrap();
+        cluster.run();
+
+        // 保存済みのスナップショットの1バイトを反転させてから再起動する
+        let storage = Arc::clone(cluster.take_node("a").io().storage());
+        {
+            let mut storage = storage.lock().unwrap();
+            let prefix = storage.prefix.as_mut().unwrap();
+            assert!(prefix.checksum.is_some());
+            prefix.snapshot[1] ^= 0xFF;
+        }
+        cluster.restart_from_storage("a", storage);
+
+        let e = loop {
+            match cluster.node_mut("a").poll() {
+                Err(e) => break e,
+                Ok(Async::Ready(Some(_))) => {}
+                Ok(_) => panic!("Corrupted snapshot was loaded"),
+            }
+        };
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+    }
+}
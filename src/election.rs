@@ -1,5 +1,12 @@
 //! リーダ選出関連の構成要素群.
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+
 use crate::node::NodeId;
+use crate::{Error, ErrorKind, Result};
 
 /// ある選挙ないしリーダの任期期間を識別するための番号.
 ///
@@ -18,12 +25,28 @@ impl Term {
     pub fn as_u64(self) -> u64 {
         self.0
     }
+
+    /// `usize`から`Term`インスタンスを生成する.
+    ///
+    /// `n`が`u64`の範囲に収まらない場合には`u64::MAX`に丸められる.
+    pub fn from_usize_saturating(n: usize) -> Self {
+        Term(u64::try_from(n).unwrap_or(u64::MAX))
+    }
 }
 impl From<u64> for Term {
     fn from(f: u64) -> Self {
         Term::new(f)
     }
 }
+impl TryFrom<usize> for Term {
+    type Error = Error;
+
+    /// `n`が`u64`の範囲に収まらない場合には`ErrorKind::InvalidInput`を理由としたエラーを返す.
+    fn try_from(n: usize) -> Result<Self> {
+        let n = track!(u64::try_from(n).map_err(Error::from))?;
+        Ok(Term(n))
+    }
+}
 
 /// 選挙でのノードの投票内容.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -34,6 +57,33 @@ pub struct Ballot {
     /// 投票先.
     pub voted_for: NodeId,
 }
+impl fmt::Display for Ballot {
+    /// `{term}:{voted_for}`形式で出力する.
+    ///
+    /// この形式の文字列は`Ballot::from_str`で元に戻すことができる.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.term.as_u64(), self.voted_for.as_str())
+    }
+}
+impl FromStr for Ballot {
+    type Err = Error;
+
+    /// `Display`の出力形式(i.e., `{term}:{voted_for}`)の文字列を解析する.
+    ///
+    /// 形式が不正な場合には`ErrorKind::InvalidInput`を理由としたエラーを返す.
+    fn from_str(s: &str) -> Result<Self> {
+        let (term, voted_for) = track!(s
+            .split_once(':')
+            .ok_or_else(|| ErrorKind::InvalidInput.cause(format!("Malformed ballot: {:?}", s))))?;
+        let term = track!(term
+            .parse::<u64>()
+            .map_err(|e| ErrorKind::InvalidInput.cause(e)))?;
+        Ok(Ballot {
+            term: term.into(),
+            voted_for: voted_for.into(),
+        })
+    }
+}
 
 /// 選挙におけるノードの役割.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -47,3 +97,178 @@ pub enum Role {
     /// 過半数以上の投票を集めて選出されたリーダ.
     Leader,
 }
+impl Role {
+    /// 役割の名前(`"follower"`, `"candidate"`, `"leader"`のいずれか)を返す.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Follower => "follower",
+            Role::Candidate => "candidate",
+            Role::Leader => "leader",
+        }
+    }
+}
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl FromStr for Role {
+    type Err = Error;
+
+    /// 役割の名前を解析する (大文字・小文字は区別しない).
+    ///
+    /// 未知の名前の場合には`ErrorKind::InvalidInput`を理由としたエラーを返す.
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "follower" => Ok(Role::Follower),
+            "candidate" => Ok(Role::Candidate),
+            "leader" => Ok(Role::Leader),
+            _ => track_panic!(ErrorKind::InvalidInput, "Unknown role: {:?}", s),
+        }
+    }
+}
+
+/// 選挙における役割毎のタイムアウト時間を決定するためのポリシー.
+///
+/// `ReplicatedLogOptions::timeout_policy`として指定され、
+/// 決定された時間は`Io::create_timeout_with`メソッドに渡される.
+///
+/// 複数の候補者が同時に立候補し続けて票が割れることを避けるために、
+/// 候補者のタイムアウトには`0`から`candidate_jitter`までのランダムな時間が加算される.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimeoutPolicy {
+    /// リーダのタイムアウト時間(i.e., ハートビートの送信間隔).
+    ///
+    /// デフォルト値は`100ms`.
+    pub leader: Duration,
+
+    /// フォロワーのタイムアウト時間(i.e., リーダとの疎通が途絶えたと判断するまでの時間).
+    ///
+    /// デフォルト値は`1s`.
+    pub follower: Duration,
+
+    /// 候補者のタイムアウト時間(i.e., 選挙をやり直すまでの時間)の下限.
+    ///
+    /// デフォルト値は`1s`.
+    pub candidate: Duration,
+
+    /// 候補者のタイムアウト時間に加算されるランダムな時間の上限.
+    ///
+    /// デフォルト値は`500ms`.
+    pub candidate_jitter: Duration,
+}
+impl TimeoutPolicy {
+    /// `role`用のタイムアウト時間を返す.
+    ///
+    /// `seed`は候補者のタイムアウト時間に加算するランダムな時間を決定するために使用される.
+    /// 同じ`seed`に対しては、常に同じ時間が返される.
+    pub fn timeout(&self, role: Role, seed: u64) -> Duration {
+        self.timeout_with_rank(role, seed, 0)
+    }
+
+    /// 選挙における優先順位を考慮して、`role`用のタイムアウト時間を返す.
+    ///
+    /// `rank`は、ローカルノードよりも優先度の高い立候補可能なメンバの数.
+    /// フォロワーおよび候補者のタイムアウト時間には`candidate_jitter`の`rank`倍が加算されるので、
+    /// 優先度の高いノードの方が、常に先に立候補することになる.
+    pub fn timeout_with_rank(&self, role: Role, seed: u64, rank: usize) -> Duration {
+        let deferral = self.candidate_jitter * rank as u32;
+        match role {
+            Role::Leader => self.leader,
+            Role::Follower => self.follower + deferral,
+            Role::Candidate => {
+                let jitter = self.candidate_jitter.as_nanos() * u128::from(seed % 1024) / 1024;
+                self.candidate + Duration::from_nanos(jitter as u64) + deferral
+            }
+        }
+    }
+}
+impl Default for TimeoutPolicy {
+    fn default() -> Self {
+        TimeoutPolicy {
+            leader: Duration::from_millis(100),
+            follower: Duration::from_secs(1),
+            candidate: Duration::from_secs(1),
+            candidate_jitter: Duration::from_millis(500),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn term_from_usize() {
+        assert_eq!(Term::try_from(3usize).unwrap(), Term::new(3));
+        assert_eq!(Term::from_usize_saturating(3), Term::new(3));
+
+        // `usize`が`u64`よりも広いプラットフォームでの桁溢れを模擬する
+        let e = Error::from(u64::try_from(u128::MAX).unwrap_err());
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn role_round_trip() {
+        for &role in &[Role::Follower, Role::Candidate, Role::Leader] {
+            assert_eq!(role.to_string().parse::<Role>().unwrap(), role);
+            assert_eq!(role.as_str().to_uppercase().parse::<Role>().unwrap(), role);
+        }
+        assert_eq!("Leader".parse::<Role>().unwrap(), Role::Leader);
+
+        let e = "learner".parse::<Role>().unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn candidate_timeout_is_jittered() {
+        let policy = TimeoutPolicy::default();
+        assert_eq!(policy.timeout(Role::Leader, 3), policy.leader);
+        assert_eq!(policy.timeout(Role::Follower, 3), policy.follower);
+
+        let timeouts = (0..1024)
+            .map(|seed| policy.timeout(Role::Candidate, seed))
+            .collect::<Vec<_>>();
+        assert!(timeouts
+            .iter()
+            .all(|&t| policy.candidate <= t && t < policy.candidate + policy.candidate_jitter));
+        assert_ne!(timeouts[0], timeouts[1]);
+        assert_eq!(policy.timeout(Role::Candidate, 1024 + 1), timeouts[1]);
+    }
+
+    #[test]
+    fn lower_ranked_timeouts_are_deferred() {
+        let policy = TimeoutPolicy::default();
+        assert_eq!(policy.timeout_with_rank(Role::Leader, 3, 2), policy.leader);
+        assert_eq!(
+            policy.timeout_with_rank(Role::Follower, 3, 2),
+            policy.follower + policy.candidate_jitter * 2
+        );
+
+        // 優先順位が下がる毎に、候補者のタイムアウト時間の範囲は重ならない
+        let max_rank0 = (0..1024)
+            .map(|seed| policy.timeout_with_rank(Role::Candidate, seed, 0))
+            .max()
+            .unwrap();
+        let min_rank1 = (0..1024)
+            .map(|seed| policy.timeout_with_rank(Role::Candidate, seed, 1))
+            .min()
+            .unwrap();
+        assert!(max_rank0 < min_rank1);
+    }
+
+    #[test]
+    fn ballot_round_trip() {
+        let ballot = Ballot {
+            term: 10.into(),
+            voted_for: "127.0.0.1:4000".into(),
+        };
+        assert_eq!(ballot.to_string(), "10:127.0.0.1:4000");
+        assert_eq!(ballot.to_string().parse::<Ballot>().unwrap(), ballot);
+
+        for s in &["", "foo", "x:node", "-1:node"] {
+            let e = s.parse::<Ballot>().unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput, "{:?}", s);
+        }
+    }
+}
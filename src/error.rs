@@ -9,6 +9,11 @@ impl From<std::io::Error> for Error {
         ErrorKind::Other.cause(f).into()
     }
 }
+impl From<std::num::TryFromIntError> for Error {
+    fn from(f: std::num::TryFromIntError) -> Self {
+        ErrorKind::InvalidInput.cause(f).into()
+    }
+}
 impl From<prometrics::Error> for Error {
     fn from(f: prometrics::Error) -> Self {
         ErrorKind::Other.cause(f).into()
@@ -56,6 +61,9 @@ pub enum ErrorKind {
     ///
     /// 主に`Io`トレイトの実装のために設けられたエラー区分.
     ///
+    /// なお、ローカルログへの追記処理がこのエラーで失敗した場合には、
+    /// 一時的なエラーとして扱われ、内部で一定回数までリトライされる.
+    ///
     /// このエラーを受け取った場合、利用者はそのノードの使用を停止して、
     /// どのような問題が発生しているかを詳細に調査すべきである.
     Other,
@@ -1,8 +1,9 @@
-use futures::Future;
+use futures::{Async, Future, Poll};
+use std::time::Duration;
 
 use crate::election::{Ballot, Role};
-use crate::log::{Log, LogIndex, LogPrefix, LogSuffix};
-use crate::message::Message;
+use crate::log::{Log, LogIndex, LogPosition, LogPrefix, LogSuffix};
+use crate::message::{GroupId, Message};
 use crate::{Error, Result};
 
 /// Raftの実行に必要なI/O機能を提供するためのトレイト.
@@ -46,11 +47,30 @@ pub trait Io {
     /// 致命的なものを除いては、`Err`は返さないことが望ましい.
     fn try_recv_message(&mut self) -> Result<Option<Message>>;
 
+    /// Raftグループ`group_id`のローカルノードに対して送信されたメッセージの受信を試みる.
+    ///
+    /// 一つの`Io`実装を複数のグループ(`ReplicatedLog`インスタンス)で共有する場合には、
+    /// このメソッドを上書きして、`(group_id, destination)`の組に一致するメッセージのみを返すこと.
+    /// `ReplicatedLog`は、`ReplicatedLogOptions::group_id`を引数にして、このメソッド経由でメッセージを受信する.
+    ///
+    /// デフォルト実装は、単一グループ用で、`group_id`を無視して`try_recv_message`メソッドを呼び出す.
+    fn try_recv_group_message(&mut self, group_id: GroupId) -> Result<Option<Message>> {
+        let _ = group_id;
+        self.try_recv_message()
+    }
+
     /// メッセージを送信する.
     ///
     /// もしメッセージ送信に何らかの理由で失敗した場合でも、単に無視される.
     /// 仮にチャンネルの致命的な問題が発生している場合には、次の`try_recv_message`メソッドの
     /// 呼び出しで`Err`を返すこと.
+    ///
+    /// 宛先が未知ないし切断済みのノード(e.g., 構成変更によって削除された直後のメンバ)の場合も同様で、
+    /// パニックせずにメッセージを破棄すること.
+    /// Raftはメッセージの消失を前提としているので、破棄されたメッセージは後続の再送や同期によって補われる.
+    ///
+    /// 複数のグループで共有される実装では、`message.header().group_id`と宛先の組に基づいて、
+    /// 宛先グループのノードにメッセージを届けること.
     fn send_message(&mut self, message: Message);
 
     /// ローカルノードの投票状況を保存する.
@@ -69,8 +89,69 @@ pub trait Io {
     /// `suffix`の開始位置が、現在のログの末尾よりも前方の場合は、
     /// 新しい開始位置よりも後ろの古いエントリは削除してしまって構わない.
     /// (リーダの入れ替えにより、ログの未コミット部分で競合が発生したことを示している)
+    ///
+    /// # Errors
+    ///
+    /// `suffix.head.prev_term`が、保存済みのログの該当位置の`Term`と一致しない場合には、
+    /// 何も保存せずに`ErrorKind::InconsistentState`を理由としたエラーを返すこと.
     fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog;
 
+    /// `save_log_suffix`メソッドと同様だが、`suffix`の所有権を受け取る.
+    ///
+    /// 実装によっては、`suffix`(に含まれるコマンド群)の複製を省略することができる.
+    ///
+    /// デフォルト実装は、単に`save_log_suffix`メソッドを呼び出す.
+    fn save_log_suffix_owned(&mut self, suffix: LogSuffix) -> Self::SaveLog {
+        self.save_log_suffix(&suffix)
+    }
+
+    /// ローカルノードの投票状況とローカルログの末尾部分を、まとめて保存する.
+    ///
+    /// 両者を別々に保存した場合には、その間でクラッシュすると、
+    /// 投票状況とログの内容が食い違った状態で再起動してしまう可能性がある.
+    /// トランザクションを有する実装(e.g., 単一のWALへの追記)では、このメソッドを上書きして、
+    /// `SaveBallotAndLog::atomic`を用いて、両者をアトミックに保存することが望ましい.
+    ///
+    /// デフォルト実装は、`save_ballot`と`save_log_suffix`を順に呼び出して、
+    /// その両方の完了を待つ(アトミック性は保証されない).
+    fn save_ballot_and_log(&mut self, ballot: Ballot, suffix: &LogSuffix) -> SaveBallotAndLog<Self>
+    where
+        Self: Sized,
+    {
+        let ballot = self.save_ballot(ballot);
+        let log = self.save_log_suffix(suffix);
+        SaveBallotAndLog::chained(ballot, log)
+    }
+
+    /// ローカルノードの投票状況を保存し、併せてローカルログを`new_tail`の位置まで切り詰める.
+    ///
+    /// `new_tail`以降のエントリは削除される(`new_tail`が現在の末尾と等しい場合には何も削除されない).
+    /// 例えば、投票を行うと同時にログの未コミット部分を破棄するような場合に使用される.
+    ///
+    /// デフォルト実装は、`new_tail`から始まる空のサフィックスを引数にして、
+    /// `save_ballot_and_log`メソッドを呼び出す.
+    /// そのため、アトミック性は`save_ballot_and_log`メソッドの実装に従う.
+    ///
+    /// # Errors
+    ///
+    /// `new_tail.prev_term`が保存済みのログの`new_tail.index`直前のエントリの`Term`と一致しない場合には、
+    /// `save_log_suffix`メソッドと同様に`ErrorKind::InconsistentState`を理由としたエラーを返す.
+    /// `save_ballot_and_log`がアトミックな実装では、この場合に投票状況も保存されない.
+    fn save_ballot_and_truncate(
+        &mut self,
+        ballot: Ballot,
+        new_tail: LogPosition,
+    ) -> SaveBallotAndLog<Self>
+    where
+        Self: Sized,
+    {
+        let suffix = LogSuffix {
+            head: new_tail,
+            entries: Vec::new(),
+        };
+        self.save_ballot_and_log(ballot, &suffix)
+    }
+
     /// ローカルログの指定範囲のエントリを取得する.
     ///
     /// 範囲は`start`から始まり、`end`を含まない最後のエントリまでを取得する.
@@ -81,11 +162,85 @@ pub trait Io {
     /// どちらの挙動も許容される.
     ///
     /// ただし、`start`とは異なる位置から、エントリの取得を開始することは許可されない.
+    ///
+    /// # スナップショットとの関係
+    ///
+    /// `start`のエントリがローカルログの後半部分に残っている場合には、
+    /// 範囲の一部ないし全体がスナップショット済みの領域に含まれていても、`Log::Suffix`を返すこと.
+    /// 小さな範囲の取得のために、スナップショット全体が返されて(転送されて)しまうことを避けるため.
+    ///
+    /// `Log::Prefix`(スナップショット)を返して良いのは、`start`のエントリが既に破棄されている場合のみで、
+    /// その際のスナップショットの終端は`start`以降でなければならない.
+    /// 範囲全体がスナップショットに含まれるか(`LogPrefix::covers`)、境界を跨ぐかに関わらず、
+    /// 呼び出し側はスナップショットの終端以降のエントリを、必要に応じて改めて取得する.
     fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog;
 
+    /// ローカルログの`start`から始まるエントリを、最大で`max_entries`個取得する.
+    ///
+    /// ログの末端までのエントリ数が`max_entries`よりも少ない場合には、末端までのエントリが返される.
+    /// `start`がスナップショット済みの領域を指している場合の挙動は`load_log`メソッドと同様.
+    ///
+    /// デフォルト実装は、単に`load_log(start, Some(start + max_entries))`を呼び出す.
+    /// そのため`load_log`が指定範囲に満たないログに対してエラーを返す実装の場合には、
+    /// このメソッドも上書きする必要がある.
+    fn load_log_limited(&mut self, start: LogIndex, max_entries: usize) -> Self::LoadLog {
+        self.load_log(start, Some(start + max_entries))
+    }
+
+    /// `load_log_limited`と同様だが、`Command`ペイロードの合計サイズが`max_bytes`を超えた時点で、
+    /// それ以降のエントリの取得を打ち切る.
+    ///
+    /// ただし、単体で`max_bytes`を超えるエントリであっても、先頭のエントリは常に返すこと.
+    /// `ReplicatedLogOptions::max_append_bytes`が指定されている場合に、
+    /// リーダがフォロワーへの差分送信用のエントリ群を読み込むために使用する.
+    ///
+    /// 上限を超えたエントリを含めて返すことも許容される(呼び出し側で切り詰められる).
+    /// デフォルト実装は、`max_bytes`を無視して、単に`load_log_limited(start, max_entries)`を呼び出すので、
+    /// ストレージからの読み込み量も制限したい場合には、このメソッドを上書きすること.
+    fn load_log_limited_bytes(
+        &mut self,
+        start: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Self::LoadLog {
+        let _ = max_bytes;
+        self.load_log_limited(start, max_entries)
+    }
+
     /// 選挙における役割に応じた時間のタイムアウトオブジェクトを生成する.
+    ///
+    /// タイムアウト時間は実装に委ねられる.
+    /// `ReplicatedLog`は`create_timeout_with`メソッド経由でのみタイムアウトを生成するので、
+    /// `ReplicatedLogOptions::timeout_policy`に従わせたい場合には、そちらを上書きすること.
     fn create_timeout(&mut self, role: Role) -> Self::Timeout;
 
+    /// `ReplicatedLogOptions::timeout_policy`に従って決定された`duration`後に、
+    /// 発火するタイムアウトオブジェクトを生成する.
+    ///
+    /// 実装は`duration`に従うことが期待される.
+    ///
+    /// デフォルト実装は、`duration`を無視して、単に`create_timeout`メソッドを呼び出す.
+    fn create_timeout_with(&mut self, role: Role, duration: Duration) -> Self::Timeout {
+        let _ = duration;
+        self.create_timeout(role)
+    }
+
+    /// 直前に`create_timeout`(ないし`create_timeout_with`)で生成したタイムアウトを取り消す.
+    ///
+    /// `ReplicatedLog`は、役割の遷移等で新しいタイムアウトを生成する直前に、このメソッドを呼び出す.
+    /// 取り消されたタイムアウトは、以後は発火しないことが期待される.
+    ///
+    /// フォロワーの選挙タイムアウトのリセットも、この取り消しと再生成によって表現される.
+    /// フォロワーはリーダからの(現在の`Term`の)`AppendEntriesCall`を受信する度に、
+    /// このメソッドを呼び出した上で、新しいタイムアウトを生成する.
+    /// そのため、リーダから定期的にハートビートを受信している限りは、フォロワーが立候補することはない.
+    ///
+    /// 古いタイムアウトオブジェクトは呼び出し後に単に破棄されるので、
+    /// 破棄によってタイマーが解放される実装の場合には、何もする必要はない.
+    ///
+    /// デフォルト実装は何も行わない.
+    fn cancel_timeout(&mut self) {}
+
     /// I/O処理を行う余裕があるかどうかを返す.
     ///
     /// これが`true`を返している間は、フォロワーの同期処理は実施されない.
@@ -93,3 +248,47 @@ pub trait Io {
         false
     }
 }
+
+/// `Io::save_ballot_and_log`および`Io::save_ballot_and_truncate`メソッドが返す`Future`.
+pub struct SaveBallotAndLog<IO: Io> {
+    ballot: Option<IO::SaveBallot>,
+    log: Option<IO::SaveLog>,
+}
+impl<IO: Io> SaveBallotAndLog<IO> {
+    /// 投票状況とログの保存を個別に行う`Future`群から、インスタンスを生成する.
+    ///
+    /// 投票状況の保存が完了した後に、ログの保存の完了を待つ.
+    pub fn chained(ballot: IO::SaveBallot, log: IO::SaveLog) -> Self {
+        SaveBallotAndLog {
+            ballot: Some(ballot),
+            log: Some(log),
+        }
+    }
+
+    /// 投票状況とログの両方をアトミックに保存する`Future`から、インスタンスを生成する.
+    pub fn atomic(future: IO::SaveLog) -> Self {
+        SaveBallotAndLog {
+            ballot: None,
+            log: Some(future),
+        }
+    }
+}
+impl<IO: Io> Future for SaveBallotAndLog<IO> {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut f) = self.ballot {
+            if track!(f.poll())?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            self.ballot = None;
+        }
+        if let Some(ref mut f) = self.log {
+            if track!(f.poll())?.is_not_ready() {
+                return Ok(Async::NotReady);
+            }
+            self.log = None;
+        }
+        Ok(Async::Ready(()))
+    }
+}
@@ -3,6 +3,8 @@
 //! このクレート自体は、アルゴリズム実装のみに専念しており、
 //! 実際に動作するシステムで利用するためには、`Io`トレイトの
 //! 実装を別個用意する必要がある.
+//! ただし、単一プロセス内で完結する構成であれば、全ての状態をメモリ上に保持する
+//! 参照実装(`memory::MemoryIo`)をそのまま利用することもできる(`memory`フィーチャが必要).
 //!
 //! [Raft]: https://raft.github.io/
 #![warn(missing_docs)]
@@ -14,15 +16,23 @@ extern crate prometrics;
 extern crate trackable;
 
 pub use crate::error::{Error, ErrorKind};
-pub use crate::io::Io;
-pub use crate::replicated_log::{Event, ReplicatedLog};
+pub use crate::io::{Io, SaveBallotAndLog};
+pub use crate::replicated_log::{
+    Drain, ElectionStats, Event, FollowerProgress, HeartbeatQuorum, NodeStatus, PendingCommitted,
+    ReadIndex, ReplicatedLog, ReplicatedLogOptions, ShutdownFuture, SnapshotProgress,
+};
 
 pub mod cluster;
+pub mod compaction;
 pub mod election;
 pub mod log;
+#[cfg(any(test, feature = "memory"))]
+pub mod memory;
 pub mod message;
 pub mod metrics;
 pub mod node;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 mod error;
 mod io;
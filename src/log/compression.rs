@@ -0,0 +1,232 @@
+use crate::Result;
+
+/// スナップショット(`LogPrefix::snapshot`)の保存時に使用する圧縮方式.
+///
+/// `LogPrefix::compression`として、(圧縮後の)バイト列と一緒に保持される.
+///
+/// `None`以外の方式は、対応するフィーチャーを有効にした場合にのみ使用可能.
+/// 全ての方式は常に定義されているので、フィーチャーの有無によって型の定義が変わることはないが、
+/// 無効なフィーチャーの方式で圧縮・展開を行おうとした場合には`ErrorKind::InvalidInput`が返される.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Compression {
+    /// 圧縮を行わない.
+    ///
+    /// バイト列はそのまま保存されるので、保存内容は圧縮方式の導入前と全く同じとなる.
+    #[default]
+    None,
+
+    /// [LZ4](https://lz4.github.io/lz4/)のブロック形式で圧縮する.
+    ///
+    /// 圧縮後のバイト列の先頭には、展開後のサイズが4バイトのリトルエンディアンで付与される.
+    /// そのため、展開後のサイズが`u32`の範囲を超えるスナップショットは圧縮できない.
+    ///
+    /// `lz4`フィーチャーが有効な場合にのみ使用可能.
+    Lz4,
+
+    /// [Zstandard](https://facebook.github.io/zstd/)のフレーム形式で圧縮する.
+    ///
+    /// LZ4よりも圧縮・展開は遅いが、圧縮率は高い.
+    ///
+    /// `zstd`フィーチャーが有効な場合にのみ使用可能.
+    Zstd,
+}
+impl Compression {
+    /// `bytes`を圧縮する.
+    ///
+    /// 対応するフィーチャーが無効な場合には`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn compress(self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes),
+            Compression::Lz4 => track!(lz4::compress(&bytes)),
+            Compression::Zstd => track!(zstd::compress(&bytes)),
+        }
+    }
+
+    /// `compress`で圧縮されたバイト列を展開する.
+    ///
+    /// バイト列が壊れている場合には`ErrorKind::InconsistentState`を理由としたエラーが返される.
+    /// 対応するフィーチャーが無効な場合には`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn decompress(self, bytes: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes),
+            Compression::Lz4 => track!(lz4::decompress(&bytes)),
+            Compression::Zstd => track!(zstd::decompress(&bytes)),
+        }
+    }
+}
+
+#[cfg(feature = "lz4")]
+mod lz4 {
+    use std::convert::TryFrom;
+    use trackable::error::ErrorKindExt;
+
+    use crate::{ErrorKind, Result};
+
+    // 一つのシーケンスで展開可能なバイト数の、入力バイト数に対する上限(おおよそ).
+    const MAX_EXPANSION: usize = 255;
+
+    pub fn compress(input: &[u8]) -> Result<Vec<u8>> {
+        track_assert!(
+            u32::try_from(input.len()).is_ok(),
+            ErrorKind::InvalidInput,
+            "Too large input for LZ4: {} bytes",
+            input.len()
+        );
+        Ok(lz4_flex::block::compress_prepend_size(input))
+    }
+
+    pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+        let (size, block) = track!(lz4_flex::block::uncompressed_size(input)
+            .map_err(|e| ErrorKind::InconsistentState.cause(e.to_string())))?;
+
+        // 壊れたサイズ情報によって、巨大な領域を確保してしまわないようにする
+        let capacity = size.min(block.len().saturating_mul(MAX_EXPANSION));
+        let output = track!(lz4_flex::block::decompress(block, capacity)
+            .map_err(|e| ErrorKind::InconsistentState.cause(e.to_string())))?;
+        track_assert_eq!(output.len(), size, ErrorKind::InconsistentState);
+        Ok(output)
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+mod lz4 {
+    use crate::{ErrorKind, Result};
+
+    pub fn compress(_input: &[u8]) -> Result<Vec<u8>> {
+        track_panic!(ErrorKind::InvalidInput, "The `lz4` feature is not enabled");
+    }
+
+    pub fn decompress(_input: &[u8]) -> Result<Vec<u8>> {
+        track_panic!(ErrorKind::InvalidInput, "The `lz4` feature is not enabled");
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    use trackable::error::ErrorKindExt;
+
+    use crate::{ErrorKind, Result};
+
+    pub fn compress(input: &[u8]) -> Result<Vec<u8>> {
+        // 圧縮レベルには、ライブラリのデフォルト値を使用する
+        let compressed =
+            track!(zstd::stream::encode_all(input, 0).map_err(|e| ErrorKind::Other.cause(e)))?;
+        Ok(compressed)
+    }
+
+    pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+        // フレームヘッダのサイズ情報は信用せずに、実際に展開できた分だけ領域を確保する
+        let decompressed = track!(
+            zstd::stream::decode_all(input).map_err(|e| ErrorKind::InconsistentState.cause(e))
+        )?;
+        Ok(decompressed)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd {
+    use crate::{ErrorKind, Result};
+
+    pub fn compress(_input: &[u8]) -> Result<Vec<u8>> {
+        track_panic!(ErrorKind::InvalidInput, "The `zstd` feature is not enabled");
+    }
+
+    pub fn decompress(_input: &[u8]) -> Result<Vec<u8>> {
+        track_panic!(ErrorKind::InvalidInput, "The `zstd` feature is not enabled");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+
+    #[test]
+    fn none_is_identity() {
+        let bytes = (0..100).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        let compressed = Compression::None.compress(bytes.clone()).unwrap();
+        assert_eq!(compressed, bytes);
+        assert_eq!(Compression::None.decompress(compressed).unwrap(), bytes);
+    }
+
+    fn enabled() -> Vec<Compression> {
+        let mut methods = Vec::new();
+        if cfg!(feature = "lz4") {
+            methods.push(Compression::Lz4);
+        }
+        if cfg!(feature = "zstd") {
+            methods.push(Compression::Zstd);
+        }
+        methods
+    }
+
+    #[test]
+    fn compressed_snapshot_decodes_to_identical_bytes() {
+        let bytes = (0..10_000).map(|i| (i % 7) as u8).collect::<Vec<_>>();
+        for method in enabled() {
+            let compressed = method.compress(bytes.clone()).unwrap();
+            assert!(compressed.len() < bytes.len(), "method={:?}", method);
+            assert_ne!(compressed, bytes);
+            assert_eq!(method.decompress(compressed).unwrap(), bytes);
+            assert_eq!(
+                method
+                    .decompress(method.compress(Vec::new()).unwrap())
+                    .unwrap(),
+                b""
+            );
+        }
+    }
+
+    #[test]
+    fn corrupted_bytes_are_rejected() {
+        let bytes = b"0123456789".repeat(10);
+        for method in enabled() {
+            let compressed = method.compress(bytes.clone()).unwrap();
+            for len in 0..compressed.len() {
+                let e = method.decompress(compressed[..len].to_vec()).err();
+                assert_eq!(
+                    e.map(|e| *e.kind()),
+                    Some(ErrorKind::InconsistentState),
+                    "method={:?}, len={}",
+                    method,
+                    len
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_does_not_trust_declared_size() {
+        // リテラル"a"に続いて、オフセット1・長さ5の一致、最後に空のリテラル
+        let block = [6, 0, 0, 0, 0x11, b'a', 1, 0, 0x00];
+        assert_eq!(
+            Compression::Lz4.decompress(block.to_vec()).unwrap(),
+            b"aaaaaa"
+        );
+
+        // 展開後のサイズとして`u32::MAX`が宣言されていても、その分の領域は確保しない
+        let mut bomb = block;
+        bomb[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let e = Compression::Lz4.decompress(bomb.to_vec()).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+    }
+
+    #[test]
+    fn disabled_method_is_rejected() {
+        let mut disabled = Vec::new();
+        if !cfg!(feature = "lz4") {
+            disabled.push(Compression::Lz4);
+        }
+        if !cfg!(feature = "zstd") {
+            disabled.push(Compression::Zstd);
+        }
+        for method in disabled {
+            let e = method.compress(vec![1, 2, 3]).err();
+            assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+            let e = method.decompress(vec![1, 2, 3]).err();
+            assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        }
+    }
+}
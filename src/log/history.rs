@@ -1,7 +1,9 @@
 use std::collections::VecDeque;
+use std::sync::Arc;
 use trackable::error::ErrorKindExt;
 
 use crate::cluster::ClusterConfig;
+use crate::election::Term;
 use crate::log::{LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix};
 use crate::{ErrorKind, Result};
 
@@ -14,8 +16,11 @@ use crate::{ErrorKind, Result};
 /// 「ログの消費済み末尾(log_consumed_tail)」の三つの地点を保持している.
 ///
 /// それらの関しては`log_consumed_tail <= log_committed_tail <= log_tail`の不変項が維持される.
+///
+/// なお、消費済み終端よりも前の記録は不要なので、消費済み終端が進む度に破棄される.
 #[derive(Debug, Clone)]
 pub struct LogHistory {
+    head: LogPosition,
     appended_tail: LogPosition,
     committed_tail: LogPosition,
     consumed_tail: LogPosition,
@@ -26,6 +31,7 @@ impl LogHistory {
     pub fn new(config: ClusterConfig) -> Self {
         let initial = HistoryRecord::new(LogPosition::default(), config);
         LogHistory {
+            head: LogPosition::default(),
             appended_tail: LogPosition::default(),
             committed_tail: LogPosition::default(),
             consumed_tail: LogPosition::default(),
@@ -33,8 +39,34 @@ impl LogHistory {
         }
     }
 
+    /// 外部から取得したスナップショット`prefix`の地点から始まる`LogHistory`インスタンスを生成する.
+    ///
+    /// ログの複製を経由せずに、スナップショットから直接起動する新規ノード向けのコンストラクタ.
+    ///
+    /// 生成されたインスタンスでは、先端位置および三つの終端位置の全てが`prefix.tail`となり、
+    /// クラスタ構成は`prefix.config`となる.
+    pub fn restore_from_snapshot(prefix: &LogPrefix) -> Self {
+        let initial = HistoryRecord::new(prefix.tail, prefix.config.clone());
+        LogHistory {
+            head: prefix.tail,
+            appended_tail: prefix.tail,
+            committed_tail: prefix.tail,
+            consumed_tail: prefix.tail,
+            records: vec![initial].into(),
+        }
+    }
+
     /// ローカルログの先端位置を返す.
     pub fn head(&self) -> LogPosition {
+        self.head
+    }
+
+    /// 歴史が保持されている最初の位置を返す.
+    ///
+    /// 消費済みの領域の記録は破棄されるため、
+    /// この位置はローカルログの先端位置(i.e., `head`)よりも後ろになることがある.
+    /// この位置よりも前のインデックスに対しては`get_record`は`None`を返す.
+    pub fn history_head(&self) -> LogPosition {
         self.records[0].head
     }
 
@@ -60,25 +92,221 @@ impl LogHistory {
         &self.last_record().config
     }
 
+    /// ローカルログに記録されたコミット済みのクラスタ構成の内で、最新のものを返す.
+    ///
+    /// `config`メソッドとは異なり、未コミットの構成変更は反映されない.
+    pub fn committed_config(&self) -> &ClusterConfig {
+        // NOTE: 保持されているレコード群の先頭は、常に消費済み(i.e., コミット済み)の地点以前となる
+        self.config_at(self.committed_tail.index)
+            .unwrap_or_else(|| &self.records[0].config)
+    }
+
+    /// 現時点の状態を要約した`LogHistorySnapshot`を返す.
+    ///
+    /// 返り値は`self`とは独立しており、以後の`self`の変更の影響を受けない.
+    pub fn capture(&self) -> LogHistorySnapshot {
+        LogHistorySnapshot {
+            head: self.head,
+            consumed_tail: self.consumed_tail,
+            committed_tail: self.committed_tail,
+            tail: self.appended_tail,
+            config: Arc::new(self.config().clone()),
+            record_count: self.records.len(),
+        }
+    }
+
+    /// 永続化用の`LogHistorySummary`を返す.
+    ///
+    /// 正常停止時に保存しておけば、次回起動時に`ReplicatedLogOptions::restored_history`経由で、
+    /// ローカルログの再走査を省略して`LogHistory`を復元することができる.
+    pub fn summarize(&self) -> LogHistorySummary {
+        LogHistorySummary {
+            head: self.head,
+            appended_tail: self.appended_tail,
+            committed_tail: self.committed_tail,
+            consumed_tail: self.consumed_tail,
+            records: self.records.iter().cloned().collect(),
+        }
+    }
+
+    /// `summary`の内容を用いて、ローカルログの`suffix`が追記された状態を復元する.
+    ///
+    /// `record_appended(suffix)`の代わりに起動時に使われ、エントリ群の走査を省略できる.
+    ///
+    /// `summary`が現在の先端位置(i.e., 読み込まれたスナップショットの終端)や`suffix`の終端と整合しない場合には、
+    /// 何もせずに`false`を返すので、その場合には呼び出し側で`record_appended`を使って再構築すること.
+    ///
+    /// なお、コミット済みおよび消費済みの終端は永続化されないので、
+    /// `summary`のそれらの値は使わず、現在の値が維持される.
+    pub fn restore_summary(&mut self, summary: &LogHistorySummary, suffix: &LogSuffix) -> bool {
+        if summary.head != self.head
+            || self.appended_tail != self.head
+            || summary.appended_tail != suffix.tail()
+        {
+            return false;
+        }
+
+        // 先端位置を含むレコードが、現在の先頭レコードと一致している必要がある
+        // (消費済み領域の記録が破棄されている場合には、ここで不一致となる)
+        let i = summary
+            .records
+            .partition_point(|r| r.head.index <= self.head.index);
+        if i == 0 || summary.records[i - 1].config != self.last_record().config {
+            return false;
+        }
+        let rest = &summary.records[i..];
+        let is_ordered = rest.windows(2).all(|w| w[0].head.index < w[1].head.index);
+        if !is_ordered
+            || rest
+                .last()
+                .is_some_and(|r| summary.appended_tail.index < r.head.index)
+        {
+            return false;
+        }
+
+        self.records.extend(rest.iter().cloned());
+        self.appended_tail = summary.appended_tail;
+        true
+    }
+
     /// 最後に追加された`HistoryRecord`を返す.
     pub fn last_record(&self) -> &HistoryRecord {
         self.records.back().expect("Never fails")
     }
 
+    /// 保持されている`HistoryRecord`群を、古いものから順に走査するためのイテレータを返す.
+    ///
+    /// クラスタ構成や`Term`の変遷を確認するためのもの.
+    /// 消費済みの領域の記録は破棄されるので、最初の要素は`history_head`の地点のものとなる.
+    pub fn records(&self) -> impl Iterator<Item = &HistoryRecord> {
+        self.records.iter()
+    }
+
+    /// 保持されている`HistoryRecord`の数を返す.
+    pub fn record_count(&self) -> usize {
+        self.records.len()
+    }
+
     /// 指定されたインデックスが属するレコードを返す.
     ///
     /// 既に削除された領域が指定された場合には`None`が返される.
     pub fn get_record(&self, index: LogIndex) -> Option<&HistoryRecord> {
-        for r in self.records.iter().rev() {
-            if r.head.index <= index {
-                return Some(&r);
-            }
+        // `records`は`head.index`の昇順に並んでいるので二分探索が可能
+        let i = self.records.partition_point(|r| r.head.index <= index);
+        if i == 0 {
+            None
+        } else {
+            self.records.get(i - 1)
+        }
+    }
+
+    /// 指定されたインデックスの時点でのクラスタ構成を返す.
+    ///
+    /// 既に削除された領域が指定された場合には`None`が返される.
+    pub fn config_at(&self, index: LogIndex) -> Option<&ClusterConfig> {
+        self.get_record(index).map(|r| &r.config)
+    }
+
+    /// 指定されたインデックスの地点の`Term`を返す.
+    ///
+    /// 返り値は`LogPosition::prev_term`と同様に、`index`の直前のエントリの`Term`を表している.
+    ///
+    /// 既に削除された領域が指定された場合には`None`が返される.
+    pub fn term_at(&self, index: LogIndex) -> Option<Term> {
+        self.get_record(index).map(|r| r.head.prev_term)
+    }
+
+    /// 内部状態の不変項が維持されているかどうかを検査する.
+    ///
+    /// 以下を確認する:
+    ///
+    /// - `consumed_tail <= committed_tail <= tail`および`head <= tail`
+    /// - 保持されているレコード群が、インデックスおよび`Term`について単調非減少であり、
+    ///   最後のレコードが`tail`以前に位置する
+    /// - コミット済みの終端の地点のレコード(i.e., クラスタ構成)が保持されており、
+    ///   そこから得られる`Term`が`committed_tail.prev_term`と一致する
+    ///
+    /// # Errors
+    ///
+    /// いずれかの不変項が破られている場合には、
+    /// 破られた不変項を示すメッセージと共に`ErrorKind::InconsistentState`が返される.
+    pub fn check_invariants(&self) -> Result<()> {
+        track_assert!(
+            self.consumed_tail.index <= self.committed_tail.index,
+            ErrorKind::InconsistentState,
+            "invariant `consumed_tail <= committed_tail` is violated: consumed={:?}, committed={:?}",
+            self.consumed_tail,
+            self.committed_tail
+        );
+        track_assert!(
+            self.committed_tail.index <= self.appended_tail.index,
+            ErrorKind::InconsistentState,
+            "invariant `committed_tail <= tail` is violated: committed={:?}, tail={:?}",
+            self.committed_tail,
+            self.appended_tail
+        );
+        track_assert!(
+            self.head.index <= self.appended_tail.index,
+            ErrorKind::InconsistentState,
+            "invariant `head <= tail` is violated: head={:?}, tail={:?}",
+            self.head,
+            self.appended_tail
+        );
+        for (a, b) in self.records.iter().zip(self.records.iter().skip(1)) {
+            track_assert!(
+                a.head.index <= b.head.index && a.head.prev_term <= b.head.prev_term,
+                ErrorKind::InconsistentState,
+                "invariant `records are monotone` is violated: prev={:?}, next={:?}",
+                a.head,
+                b.head
+            );
+        }
+        track_assert!(
+            self.last_record().head.index <= self.appended_tail.index,
+            ErrorKind::InconsistentState,
+            "invariant `last record <= tail` is violated: last_record={:?}, tail={:?}",
+            self.last_record().head,
+            self.appended_tail
+        );
+        let committed_term = self.term_at(self.committed_tail.index);
+        track_assert_eq!(
+            committed_term,
+            Some(self.committed_tail.prev_term),
+            ErrorKind::InconsistentState,
+            "invariant `config and term at committed_tail are known` is violated: committed={:?}",
+            self.committed_tail
+        );
+        Ok(())
+    }
+
+    /// ローカルログに、未コミットのクラスタ構成の変更が存在するかどうかを判定する.
+    ///
+    /// 最新のクラスタ構成が構成変更中(i.e., 安定状態以外)の場合や、
+    /// 最新のクラスタ構成を記録したエントリがまだコミットされていない場合には`true`が返される.
+    pub fn has_uncommitted_config_change(&self) -> bool {
+        let config = self.config();
+        if !config.state().is_stable() {
+            return true;
         }
-        None
+
+        // 最新の構成が記録された位置 (`Term`の変更によるレコードは飛ばす)
+        let changed_at = self
+            .records
+            .iter()
+            .rev()
+            .take_while(|r| r.config == *config)
+            .last()
+            .map(|r| r.head.index)
+            .expect("Never fails");
+        self.committed_tail.index < changed_at
     }
 
     /// `suffix`がローカルログに追記されたことを記録する.
     pub fn record_appended(&mut self, suffix: &LogSuffix) -> Result<()> {
+        if suffix.entries.is_empty() && suffix.tail() == self.appended_tail {
+            // 何も追記されていない
+            return Ok(());
+        }
         let entries_offset = if self.appended_tail.index <= suffix.head.index {
             0
         } else {
@@ -87,17 +315,24 @@ impl LogHistory {
             // 両者の先頭位置がズレることがあるので調整する
             self.appended_tail.index - suffix.head.index
         };
-        for (i, e) in suffix.entries.iter().enumerate().skip(entries_offset) {
-            let tail = LogPosition {
-                prev_term: e.term(),
-                index: suffix.head.index + i + 1,
-            };
+
+        // 追加され得るレコード数(i.e., 構成変更と`Term`の切り替わりの数)分の領域を、事前に確保しておく
+        let mut boundaries = 0;
+        let mut last_term = self.last_record().head.prev_term;
+        for e in suffix.entries.iter().skip(entries_offset) {
+            if let LogEntry::Config { .. } = *e {
+                boundaries += 1;
+            }
+            if e.term() != last_term {
+                boundaries += 1;
+                last_term = e.term();
+            }
+        }
+        self.records.reserve(boundaries);
+
+        for (tail, e) in suffix.iter_with_positions().skip(entries_offset) {
             if let LogEntry::Config { ref config, .. } = *e {
-                if self.last_record().config != *config {
-                    // クラスタ構成が変更された
-                    let record = HistoryRecord::new(tail, config.clone());
-                    self.records.push_back(record);
-                }
+                self.apply_config_change(tail, config);
             }
             if tail.prev_term != self.last_record().head.prev_term {
                 // 新しい選挙期間(`Term`)に移った
@@ -116,6 +351,17 @@ impl LogHistory {
         Ok(())
     }
 
+    /// `at`の地点に`config`を含むエントリが追記されたことを記録する.
+    ///
+    /// 最新のレコードのクラスタ構成と異なる場合にのみ、新しいレコードが追加される.
+    fn apply_config_change(&mut self, at: LogPosition, config: &ClusterConfig) {
+        if self.last_record().config != *config {
+            // クラスタ構成が変更された
+            let record = HistoryRecord::new(at, config.clone());
+            self.records.push_back(record);
+        }
+    }
+
     /// `new_tail_index`までコミット済み地点が進んだことを記録する.
     pub fn record_committed(&mut self, new_tail_index: LogIndex) -> Result<()> {
         track_assert!(
@@ -144,6 +390,9 @@ impl LogHistory {
     /// `new_tail`までのログに含まれるコマンドが消費されたことを記録する.
     ///
     /// ここでの"消費"とは「状態機械に入力として渡されて実行された」ことを意味する.
+    ///
+    /// 複数のエントリ分を一度に進めることが可能で、その場合でも`new_tail_index`のレコード検索は一度のみ行われる.
+    /// ただし`new_tail_index`はコミット済みの終端以前である必要がある.
     pub fn record_consumed(&mut self, new_tail_index: LogIndex) -> Result<()> {
         track_assert!(self.consumed_tail.index <= new_tail_index, ErrorKind::Other);
         track_assert!(
@@ -161,6 +410,7 @@ impl LogHistory {
             prev_term,
             index: new_tail_index,
         };
+        self.trim_consumed_records();
         Ok(())
     }
 
@@ -176,6 +426,14 @@ impl LogHistory {
             self.committed_tail,
             new_tail
         );
+        if new_tail.index == self.committed_tail.index {
+            // コミット済み地点へのロールバックは、その地点の`Term`も一致している必要がある
+            track_assert_eq!(
+                new_tail.prev_term,
+                self.committed_tail.prev_term,
+                ErrorKind::InconsistentState
+            );
+        }
         track_assert_eq!(
             self.get_record(new_tail.index).map(|r| r.head.prev_term),
             Some(new_tail.prev_term),
@@ -203,6 +461,13 @@ impl LogHistory {
     /// (スナップショット地点から現在までの歴史が消失してしまうため)
     ///
     /// なお、`head`以前の記録は歴史から削除される.
+    ///
+    /// # Errors
+    ///
+    /// `new_head`が現在のログの範囲内で、かつ、その地点の`Term`が歴史と一致するにも関わらず、
+    /// `config`がその地点で有効なクラスタ構成(`config_at`)と異なる場合には、
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
+    /// (古い構成を含むスナップショットによって、構成の歴史が書き換えられてしまうことを防ぐため)
     pub fn record_snapshot_installed(
         &mut self,
         new_head: LogPosition,
@@ -215,12 +480,25 @@ impl LogHistory {
             self.head(),
             new_head
         );
+        if new_head.index <= self.appended_tail.index
+            && self.term_at(new_head.index) == Some(new_head.prev_term)
+        {
+            // 分岐していない範囲では、構成の歴史と一致していなければならない
+            let expected = self.config_at(new_head.index).expect("Never fails");
+            track_assert_eq!(
+                &config,
+                expected,
+                ErrorKind::InconsistentState,
+                "new_head={:?}",
+                new_head
+            );
+        }
 
         // スナップショット地点までの歴史は捨てる
         while self
             .records
             .front()
-            .map_or(false, |r| r.head.index <= new_head.index)
+            .is_some_and(|r| r.head.index <= new_head.index)
         {
             self.records.pop_front();
         }
@@ -228,6 +506,7 @@ impl LogHistory {
         // 新しいログの先頭をセット
         let record = HistoryRecord::new(new_head, config);
         self.records.push_front(record);
+        self.head = new_head;
 
         if self.appended_tail.index < new_head.index {
             self.appended_tail = new_head;
@@ -251,13 +530,26 @@ impl LogHistory {
                 self.committed_tail.index
             );
             self.consumed_tail = snapshot.tail;
+            self.trim_consumed_records();
         }
         Ok(())
     }
+
+    /// 消費済み終端よりも前の記録を破棄する.
+    ///
+    /// 消費済み終端を含むレコードは、`get_record`のために残される.
+    fn trim_consumed_records(&mut self) {
+        let i = self
+            .records
+            .partition_point(|r| r.head.index <= self.consumed_tail.index);
+        if i > 1 {
+            self.records.drain(..i - 1);
+        }
+    }
 }
 
 /// `LogHistory`に保持されるレコード.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HistoryRecord {
     /// 記録地点.
     pub head: LogPosition,
@@ -270,3 +562,548 @@ impl HistoryRecord {
         HistoryRecord { head, config }
     }
 }
+
+/// ある時点での`LogHistory`の要約.
+///
+/// `LogHistory`全体を複製する代わりに、監視用のスレッド等に状態を受け渡す際に利用可能.
+/// クラスタ構成は`Arc`で共有されるので、複製は安価に行える.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogHistorySnapshot {
+    /// ローカルログの先端位置.
+    pub head: LogPosition,
+
+    /// ローカルログの適用済みの終端位置.
+    pub consumed_tail: LogPosition,
+
+    /// ローカルログのコミット済みの終端位置.
+    pub committed_tail: LogPosition,
+
+    /// ローカルログの終端位置.
+    pub tail: LogPosition,
+
+    /// ローカルログに記録された最新のクラスタ構成.
+    pub config: Arc<ClusterConfig>,
+
+    /// 保持されていた`HistoryRecord`の数.
+    pub record_count: usize,
+}
+
+/// 永続化用の`LogHistory`の要約.
+///
+/// `LogHistory::summarize`で生成され、全てのフィールドが公開されているので、
+/// 利用者は任意の形式でシリアライズして保存することができる.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogHistorySummary {
+    /// ローカルログの先端位置.
+    pub head: LogPosition,
+
+    /// ローカルログの終端位置.
+    pub appended_tail: LogPosition,
+
+    /// ローカルログのコミット済みの終端位置.
+    pub committed_tail: LogPosition,
+
+    /// ローカルログの適用済みの終端位置.
+    pub consumed_tail: LogPosition,
+
+    /// 保持されていた`HistoryRecord`群 (`head.index`の昇順).
+    pub records: Vec<HistoryRecord>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterMembers;
+
+    // 外部クレートに依存しないための簡易な疑似乱数生成器 (xorshift)
+    struct Rng(u64);
+    impl Rng {
+        fn next(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    #[test]
+    fn get_record_matches_linear_scan() {
+        let config = ClusterConfig::new(ClusterMembers::new());
+        let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+        for _ in 0..200 {
+            let mut history = LogHistory::new(config.clone());
+            history.records.clear();
+
+            // 同じ位置に複数のレコードが存在するケースも含めて、昇順のレコード列を生成する
+            let mut index = rng.next(10);
+            for term in 0..=rng.next(20) {
+                let head = LogPosition {
+                    prev_term: term.into(),
+                    index: index.into(),
+                };
+                history
+                    .records
+                    .push_back(HistoryRecord::new(head, config.clone()));
+                index += rng.next(5);
+            }
+
+            for i in 0..index + 5 {
+                let i = LogIndex::new(i);
+                let expected = history.records.iter().rev().find(|r| r.head.index <= i);
+                assert_eq!(
+                    history.get_record(i).map(|r| r.head),
+                    expected.map(|r| r.head)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn apply_config_change_pushes_only_changed_config() {
+        let members = |n: u64| (0..n).map(|i| i.to_string().into()).collect();
+        let config = ClusterConfig::new(members(1));
+        let mut history = LogHistory::new(config.clone());
+        let at = LogPosition {
+            prev_term: 1.into(),
+            index: 3.into(),
+        };
+
+        // 同じ構成: レコードは追加されない
+        history.apply_config_change(at, &config);
+        assert_eq!(history.records.len(), 1);
+        assert_eq!(history.last_record().head, LogPosition::default());
+
+        // 異なる構成: レコードが追加される
+        let new_config = ClusterConfig::new(members(3));
+        history.apply_config_change(at, &new_config);
+        assert_eq!(history.records.len(), 2);
+        assert_eq!(history.last_record().head, at);
+        assert_eq!(history.config(), &new_config);
+    }
+
+    #[test]
+    fn large_committed_range_is_consumed_at_once() {
+        let config = ClusterConfig::new(ClusterMembers::new());
+        let mut history = LogHistory::new(config);
+        let suffix = LogSuffix {
+            head: history.tail(),
+            entries: vec![LogEntry::Noop { term: 1.into() }; 10_000],
+        };
+        history.record_appended(&suffix).unwrap();
+        history.record_committed(LogIndex::new(9_000)).unwrap();
+
+        assert!(history.record_consumed(LogIndex::new(9_001)).is_err());
+        history.record_consumed(LogIndex::new(9_000)).unwrap();
+        assert_eq!(history.consumed_tail().index, LogIndex::new(9_000));
+        assert_eq!(history.consumed_tail(), history.committed_tail());
+    }
+
+    #[test]
+    fn check_invariants_reports_violated_invariant() {
+        let members = |n: u64| (0..n).map(|i| i.to_string().into()).collect();
+        let mut history = LogHistory::new(ClusterConfig::new(members(1)));
+        let suffix = LogSuffix {
+            head: history.tail(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: ClusterConfig::new(members(2)),
+                },
+                LogEntry::Noop { term: 2.into() },
+            ],
+        };
+        history.record_appended(&suffix).unwrap();
+        history.record_committed(LogIndex::new(2)).unwrap();
+        history.record_consumed(LogIndex::new(1)).unwrap();
+        assert!(history.check_invariants().is_ok());
+
+        // 消費済みの終端がコミット済みの終端を追い越している
+        let mut broken = history.clone();
+        broken.consumed_tail = broken.appended_tail;
+        let e = broken.check_invariants().unwrap_err();
+        assert!(e.to_string().contains("consumed_tail <= committed_tail"));
+
+        // レコードの並び順が壊れている
+        let mut broken = history.clone();
+        broken.records.swap(0, 1);
+        let e = broken.check_invariants().unwrap_err();
+        assert!(e.to_string().contains("records are monotone"));
+
+        // レコードが終端よりも先を指している (i.e., インデックスに空白がある)
+        let mut broken = history.clone();
+        broken.records.back_mut().unwrap().head.index = broken.appended_tail.index + 1;
+        let e = broken.check_invariants().unwrap_err();
+        assert!(e.to_string().contains("last record <= tail"));
+
+        // コミット済みの終端の`Term`が履歴と一致しない
+        let mut broken = history.clone();
+        broken.committed_tail.prev_term = 5.into();
+        let e = broken.check_invariants().unwrap_err();
+        assert!(e.to_string().contains("config and term at committed_tail"));
+    }
+
+    #[test]
+    fn consumed_records_are_trimmed() {
+        let members = |n: u64| (0..n).map(|i| i.to_string().into()).collect();
+        let mut history = LogHistory::new(ClusterConfig::new(members(1)));
+
+        let term = 1.into();
+        for i in 0..100u64 {
+            // 毎回異なる構成を追記する
+            let config = ClusterConfig::new(members(i % 3 + 1));
+            let suffix = LogSuffix {
+                head: history.tail(),
+                entries: vec![LogEntry::Config { term, config }],
+            };
+            history.record_appended(&suffix).unwrap();
+
+            let tail = history.tail().index;
+            history.record_committed(tail).unwrap();
+            history.record_consumed(tail).unwrap();
+            assert!(history.records.len() <= 2, "{}", history.records.len());
+        }
+        assert_eq!(history.head().index, LogIndex::new(0));
+        assert_eq!(history.history_head().index, LogIndex::new(100));
+        assert!(history.get_record(LogIndex::new(99)).is_none());
+
+        // 消費済み終端以降は、引き続き参照可能
+        let tail = history.tail().index;
+        let record = history.get_record(tail).unwrap();
+        assert_eq!(record.head.prev_term, term);
+        assert_eq!(history.config_at(tail), Some(history.config()));
+
+        // 未消費の領域の記録は残る
+        for i in 1..=10u64 {
+            let config = ClusterConfig::new(members(i % 3 + 1));
+            let suffix = LogSuffix {
+                head: history.tail(),
+                entries: vec![LogEntry::Config { term, config }],
+            };
+            history.record_appended(&suffix).unwrap();
+        }
+        assert_eq!(history.records.len(), 11);
+        assert_eq!(
+            history.config_at(tail + 1),
+            Some(&ClusterConfig::new(members(2)))
+        );
+    }
+
+    #[test]
+    fn captured_snapshot_is_not_affected_by_appends() {
+        let config = ClusterConfig::new(ClusterMembers::new());
+        let new_config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut history = LogHistory::new(config.clone());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: 1.into() }; 2],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+        track_try_unwrap!(history.record_committed(1.into()));
+
+        let snapshot = history.capture();
+        assert_eq!(snapshot.head, history.head());
+        assert_eq!(snapshot.committed_tail, history.committed_tail());
+        assert_eq!(snapshot.consumed_tail, history.consumed_tail());
+        assert_eq!(snapshot.tail, history.tail());
+        assert_eq!(snapshot.record_count, 2);
+        assert_eq!(*snapshot.config, config);
+
+        let cloned = snapshot.clone();
+        assert!(Arc::ptr_eq(&cloned.config, &snapshot.config));
+
+        let suffix = LogSuffix {
+            head: history.tail(),
+            entries: vec![LogEntry::Config {
+                term: 2.into(),
+                config: new_config.clone(),
+            }],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+        assert_eq!(history.config(), &new_config);
+        assert_eq!(snapshot, cloned);
+        assert_eq!(snapshot.tail.index, LogIndex::new(2));
+        assert_eq!(*snapshot.config, config);
+    }
+
+    #[test]
+    fn record_appended_aligns_with_positions() {
+        let config = ClusterConfig::new(ClusterMembers::new());
+        let new_config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut history = LogHistory::new(config.clone());
+
+        // 空の追記は何もしない
+        let suffix = LogSuffix::default();
+        track_try_unwrap!(history.record_appended(&suffix));
+        assert_eq!(history.tail(), LogPosition::default());
+        assert_eq!(history.records.len(), 1);
+
+        let entries = vec![
+            LogEntry::Noop { term: 1.into() },
+            LogEntry::Config {
+                term: 2.into(),
+                config: new_config.clone(),
+            },
+        ];
+        let mut log = LogSuffix::default();
+        for e in entries {
+            let suffix = LogSuffix {
+                head: log.tail(),
+                entries: vec![e.clone()],
+            };
+            track_try_unwrap!(history.record_appended(&suffix));
+            log.entries.push(e);
+            assert_eq!(history.tail(), log.tail());
+
+            // 末尾への空の追記も何もしない
+            let records = history.records.len();
+            let suffix = LogSuffix {
+                head: log.tail(),
+                entries: Vec::new(),
+            };
+            track_try_unwrap!(history.record_appended(&suffix));
+            assert_eq!(history.tail(), log.tail());
+            assert_eq!(history.records.len(), records);
+        }
+
+        for p in log.positions() {
+            let record = history.get_record(p.index).expect("Never fails");
+            assert_eq!(record.head.prev_term, p.prev_term, "position={:?}", p);
+        }
+        assert_eq!(history.last_record().config, new_config);
+        assert_eq!(history.last_record().head, log.tail());
+    }
+
+    #[test]
+    fn config_at_and_term_at_work() {
+        let old_config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let new_config = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let mut history = LogHistory::new(old_config.clone());
+
+        // [0: Noop(1), 1: Command(1), 2: Config(1), 3: Noop(2), 4: Command(2)]
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Command {
+                    term: 1.into(),
+                    command: Vec::new().into(),
+                },
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: new_config.clone(),
+                },
+                LogEntry::Noop { term: 2.into() },
+                LogEntry::Command {
+                    term: 2.into(),
+                    command: Vec::new().into(),
+                },
+            ],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+
+        let expected = [
+            (0, &old_config, 0),
+            (1, &old_config, 1),
+            (2, &old_config, 1),
+            (3, &new_config, 1),
+            (4, &new_config, 2),
+            (5, &new_config, 2),
+        ];
+        for &(index, config, term) in &expected {
+            let index = LogIndex::new(index);
+            assert_eq!(history.config_at(index), Some(config), "index={:?}", index);
+            assert_eq!(
+                history.term_at(index),
+                Some(term.into()),
+                "index={:?}",
+                index
+            );
+        }
+
+        // 保持されている先頭より前の地点
+        track_try_unwrap!(history.record_committed(LogIndex::new(4)));
+        track_try_unwrap!(history.record_consumed(LogIndex::new(4)));
+        assert_eq!(history.config_at(LogIndex::new(3)), None);
+        assert_eq!(history.term_at(LogIndex::new(3)), None);
+        assert_eq!(history.config_at(LogIndex::new(4)), Some(&new_config));
+        assert_eq!(history.term_at(LogIndex::new(4)), Some(2.into()));
+    }
+
+    #[test]
+    fn records_are_iterated_from_oldest() {
+        let config0 = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let config1 = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let config2 = ClusterConfig::new(vec!["b".into()].into_iter().collect());
+        let mut history = LogHistory::new(config0.clone());
+
+        // [0: Noop(1), 1: Config(1), 2: Noop(2), 3: Config(2)]
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: config1.clone(),
+                },
+                LogEntry::Noop { term: 2.into() },
+                LogEntry::Config {
+                    term: 2.into(),
+                    config: config2.clone(),
+                },
+            ],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+
+        let records = history
+            .records()
+            .map(|r| (r.head.index.as_u64(), r.head.prev_term.as_u64(), &r.config))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            [
+                (0, 0, &config0),
+                (1, 1, &config0),
+                (2, 1, &config1),
+                (3, 2, &config1),
+                (4, 2, &config2),
+            ]
+        );
+        assert_eq!(history.record_count(), records.len());
+    }
+
+    #[test]
+    fn rollback_to_committed_tail_requires_same_term() {
+        let config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let mut history = LogHistory::new(config);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 2.into() },
+                LogEntry::Noop { term: 2.into() },
+            ],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+        track_try_unwrap!(history.record_committed(LogIndex::new(3)));
+        let committed = history.committed_tail();
+        assert_eq!(committed.prev_term, 2.into());
+
+        let wrong = LogPosition {
+            prev_term: 1.into(),
+            index: committed.index,
+        };
+        let e = history.record_rollback(wrong).err().map(|e| *e.kind());
+        assert_eq!(e, Some(ErrorKind::InconsistentState));
+        assert_eq!(history.tail(), suffix.tail());
+
+        track_try_unwrap!(history.record_rollback(committed));
+        assert_eq!(history.tail(), committed);
+    }
+
+    #[test]
+    fn restore_from_snapshot_works() {
+        let config = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let prefix = LogPrefix::new(
+            LogPosition {
+                prev_term: 3.into(),
+                index: LogIndex::new(10),
+            },
+            config.clone(),
+            vec![1, 2, 3],
+        );
+        let mut history = LogHistory::restore_from_snapshot(&prefix);
+        assert_eq!(history.head(), prefix.tail);
+        assert_eq!(history.tail(), prefix.tail);
+        assert_eq!(history.committed_tail(), prefix.tail);
+        assert_eq!(history.consumed_tail(), prefix.tail);
+        assert_eq!(history.config(), &config);
+        assert_eq!(history.config_at(LogIndex::new(10)), Some(&config));
+        assert_eq!(history.term_at(LogIndex::new(10)), Some(3.into()));
+        assert!(history.get_record(LogIndex::new(9)).is_none());
+
+        // スナップショットの地点から追記可能
+        let suffix = LogSuffix {
+            head: prefix.tail,
+            entries: vec![LogEntry::Noop { term: 4.into() }],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+        assert_eq!(history.tail(), suffix.tail());
+    }
+
+    #[test]
+    fn committed_config_ignores_uncommitted_change() {
+        let old_config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let new_config = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let mut history = LogHistory::new(old_config.clone());
+
+        // [0: Noop(1), 1: Config(1)]
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: new_config.clone(),
+                },
+            ],
+        };
+        track_try_unwrap!(history.record_appended(&suffix));
+        track_try_unwrap!(history.record_committed(LogIndex::new(1)));
+        assert_eq!(history.config(), &new_config);
+        assert_eq!(history.committed_config(), &old_config);
+
+        track_try_unwrap!(history.record_committed(LogIndex::new(2)));
+        assert_eq!(history.committed_config(), &new_config);
+    }
+
+    #[test]
+    fn snapshot_config_must_match_history() {
+        let old_config = ClusterConfig::new(vec!["a".into()].into_iter().collect());
+        let new_config = ClusterConfig::new(vec!["a".into(), "b".into()].into_iter().collect());
+        let history = || {
+            // [0: Noop(1), 1: Config(1), 2: Noop(1)]
+            let mut history = LogHistory::new(old_config.clone());
+            let suffix = LogSuffix {
+                head: LogPosition::default(),
+                entries: vec![
+                    LogEntry::Noop { term: 1.into() },
+                    LogEntry::Config {
+                        term: 1.into(),
+                        config: new_config.clone(),
+                    },
+                    LogEntry::Noop { term: 1.into() },
+                ],
+            };
+            track_try_unwrap!(history.record_appended(&suffix));
+            history
+        };
+        let position = |prev_term: u64, index: u64| LogPosition {
+            prev_term: prev_term.into(),
+            index: index.into(),
+        };
+
+        // 範囲内で、構成が歴史と一致する
+        let mut h = history();
+        track_try_unwrap!(h.record_snapshot_installed(position(1, 2), new_config.clone()));
+        assert_eq!(h.head(), position(1, 2));
+        assert_eq!(h.config(), &new_config);
+
+        // 範囲内で、構成が歴史と異なる (構成変更前の古い構成)
+        let mut h = history();
+        let e = h
+            .record_snapshot_installed(position(1, 2), old_config.clone())
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+        assert_eq!(h.head(), LogPosition::default());
+        assert_eq!(h.config(), &new_config);
+
+        // ログの末尾よりも先のスナップショットは検査されない
+        let mut h = history();
+        track_try_unwrap!(h.record_snapshot_installed(position(2, 10), old_config.clone()));
+        assert_eq!(h.head(), position(2, 10));
+        assert_eq!(h.tail(), position(2, 10));
+        assert_eq!(h.config(), &old_config);
+    }
+}
@@ -1,12 +1,19 @@
 //! ノードローカルなログ関連の構成要素群.
+use std::cmp::{self, Ordering};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::mem;
 use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::sync::Arc;
 
-pub use self::history::{HistoryRecord, LogHistory};
+pub use self::compression::Compression;
+pub use self::history::{HistoryRecord, LogHistory, LogHistorySnapshot, LogHistorySummary};
 
 use crate::cluster::ClusterConfig;
 use crate::election::Term;
-use crate::{ErrorKind, Result};
+use crate::{Error, ErrorKind, Result};
 
+mod compression;
 mod history;
 
 /// ローカルログ.
@@ -30,7 +37,13 @@ impl From<LogSuffix> for Log {
 }
 
 /// ログの前半部分 (i.e., スナップショット).
+///
+/// 今後フィールドが追加されても利用者のコードが壊れないように、`#[non_exhaustive]`が指定されている.
+/// クレート外でインスタンスを生成する場合には`LogPrefix::new`を使用し、
+/// 必要に応じて、その後に`compression`や`checksum`を設定すること
+/// (e.g., 永続化済みのスナップショットを復元する場合).
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct LogPrefix {
     /// 前半部分の終端位置.
     ///
@@ -41,7 +54,157 @@ pub struct LogPrefix {
     pub config: ClusterConfig,
 
     /// 前半部分に含まれるコマンド群の適用後の状態機械のスナップショット.
+    ///
+    /// `compression`が`Compression::None`以外の場合には、圧縮後のバイト列となる.
     pub snapshot: Vec<u8>,
+
+    /// `snapshot`の圧縮方式.
+    pub compression: Compression,
+
+    /// `snapshot`(圧縮後のバイト列)のCRC32チェックサム.
+    ///
+    /// ローカルログへの保存時(`Io::save_log_prefix`の呼び出し前)に計算され、
+    /// ロード後の展開時(`LogPrefix::decompress`)に検証される.
+    ///
+    /// `None`の場合には検証は行われない(e.g., チェックサムの導入前に保存されたスナップショット).
+    pub checksum: Option<u32>,
+}
+impl LogPrefix {
+    /// 新しい`LogPrefix`インスタンスを生成する.
+    ///
+    /// `snapshot`は展開済みのバイト列として扱われ(i.e., `compression`は`Compression::None`)、
+    /// チェックサムは設定されない.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use raftlog::cluster::ClusterConfig;
+    /// use raftlog::log::{Compression, LogPosition, LogPrefix};
+    ///
+    /// let config = ClusterConfig::new(Default::default());
+    /// let prefix = LogPrefix::new(LogPosition::default(), config, b"foo".to_vec());
+    /// assert_eq!(prefix.compression, Compression::None);
+    /// assert_eq!(prefix.checksum, None);
+    ///
+    /// // 永続化済みのチェックサムを復元する
+    /// let mut restored = prefix.clone();
+    /// restored.checksum = prefix.with_checksum().checksum;
+    /// assert!(restored.verify_checksum().is_ok());
+    /// ```
+    pub fn new(tail: LogPosition, config: ClusterConfig, snapshot: Vec<u8>) -> Self {
+        LogPrefix {
+            tail,
+            config,
+            snapshot,
+            compression: Compression::None,
+            checksum: None,
+        }
+    }
+
+    /// `[start, end)`の範囲のエントリが、全てこのスナップショットに含まれているかどうかを判定する.
+    ///
+    /// 範囲の先頭のみがスナップショットに含まれる場合(i.e., スナップショットの境界を跨ぐ場合)には`false`が返される.
+    /// その場合、`tail`以降の残りのエントリは、ログの後半部分から別途取得する必要がある.
+    ///
+    /// 空の範囲(`start == end`)は、`end <= tail.index`であれば含まれているものとして扱われる.
+    ///
+    /// 不正な範囲(`start > end`)に対しても単に`false`が返されるので、
+    /// それを区別する必要がある場合には、事前に`validate_range`で検証すること.
+    pub fn covers(&self, start: LogIndex, end: LogIndex) -> bool {
+        start <= end && end <= self.tail.index
+    }
+
+    /// `[start, end)`が、ログの範囲として妥当かどうかを検証する.
+    ///
+    /// スナップショットの終端よりも後ろの範囲は、ログの後半部分に含まれ得るので妥当なものとして扱われる.
+    ///
+    /// # Errors
+    ///
+    /// `start > end`の場合には`ErrorKind::InvalidInput`が返される.
+    pub fn validate_range(&self, start: LogIndex, end: LogIndex) -> Result<()> {
+        track_assert!(
+            start <= end,
+            ErrorKind::InvalidInput,
+            "Invalid log range: start={:?}, end={:?}, snapshot_tail={:?}",
+            start,
+            end,
+            self.tail.index
+        );
+        Ok(())
+    }
+
+    /// `snapshot`を指定の方式で圧縮した`LogPrefix`を返す.
+    ///
+    /// 既に圧縮済みの場合には、一度展開してから圧縮し直す.
+    /// チェックサムが設定されている場合には、圧縮後のバイト列に対して再計算される.
+    pub fn compress(self, compression: Compression) -> Result<Self> {
+        if self.compression == compression {
+            return Ok(self);
+        }
+        let mut prefix = track!(self.decompress())?;
+        prefix.snapshot = track!(compression.compress(prefix.snapshot))?;
+        prefix.compression = compression;
+        prefix.checksum = prefix.checksum.map(|_| crc32(&prefix.snapshot));
+        Ok(prefix)
+    }
+
+    /// `snapshot`を展開した(i.e., `Compression::None`の)`LogPrefix`を返す.
+    ///
+    /// 展開の前に`verify_checksum`による検証が行われる.
+    /// チェックサムが設定されている場合には、展開後のバイト列に対して再計算される.
+    pub fn decompress(self) -> Result<Self> {
+        track!(self.verify_checksum())?;
+        if self.compression == Compression::None {
+            return Ok(self);
+        }
+        let snapshot = track!(self.compression.decompress(self.snapshot))?;
+        let checksum = self.checksum.map(|_| crc32(&snapshot));
+        Ok(LogPrefix {
+            tail: self.tail,
+            config: self.config,
+            snapshot,
+            compression: Compression::None,
+            checksum,
+        })
+    }
+
+    /// 現在の`snapshot`のチェックサムを計算して設定した`LogPrefix`を返す.
+    pub fn with_checksum(mut self) -> Self {
+        self.checksum = Some(crc32(&self.snapshot));
+        self
+    }
+
+    /// `snapshot`が、設定されているチェックサムと一致するかどうかを検証する.
+    ///
+    /// # Errors
+    ///
+    /// チェックサムが一致しない場合には`ErrorKind::InconsistentState`が返される.
+    pub fn verify_checksum(&self) -> Result<()> {
+        if let Some(expected) = self.checksum {
+            let actual = crc32(&self.snapshot);
+            track_assert_eq!(
+                actual,
+                expected,
+                ErrorKind::InconsistentState,
+                "Snapshot checksum mismatch: tail={:?}",
+                self.tail
+            );
+        }
+        Ok(())
+    }
+}
+
+// CRC32 (IEEE 802.3)を計算する.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= u32::from(b);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
 }
 
 /// ログの後半部分.
@@ -52,7 +215,7 @@ pub struct LogPrefix {
 /// ただし、このデータ構造自体は、常に追記的なアクセスのために利用され、
 /// "ログの途中の一部だけを更新する"といった操作は発生しないので、
 /// "常にログの末尾に対して適用される"的な意味合いで`Suffix`と付けている.
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct LogSuffix {
     /// ログの開始位置.
     ///
@@ -64,6 +227,60 @@ pub struct LogSuffix {
     pub entries: Vec<LogEntry>,
 }
 impl LogSuffix {
+    /// `byte_len`で、エントリ毎に加算される固定のオーバーヘッドの推定値(バイト数).
+    ///
+    /// エントリの`Term`や種別、ペイロード長の表現に必要な領域を見込んだもの.
+    pub const ENTRY_OVERHEAD_BYTES: usize = 16;
+
+    /// 後半部分に含まれるエントリの数を返す.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// 後半部分にエントリが一つも含まれていないかどうかを判定する.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// 後半部分のおおよそのサイズ(バイト数)を返す.
+    ///
+    /// `Command`エントリのペイロードの合計サイズに、
+    /// エントリ毎に`LogSuffix::ENTRY_OVERHEAD_BYTES`を加えたものとなる.
+    ///
+    /// これはメッセージの分割や、メトリクスへの報告などの判断に使うための推定値であり、
+    /// シリアライズ後の正確なサイズではない
+    /// (e.g., `Config`エントリのメンバ群のサイズは考慮されない).
+    pub fn byte_len(&self) -> usize {
+        let payload: usize = self
+            .entries
+            .iter()
+            .map(|e| match e {
+                LogEntry::Command { command, .. } => command.len(),
+                _ => 0,
+            })
+            .sum();
+        payload + self.entries.len() * Self::ENTRY_OVERHEAD_BYTES
+    }
+
+    /// `Command`エントリを`StrippedCommand`エントリに置き換えた`LogSuffix`を返す.
+    ///
+    /// 各エントリの位置や`Term`、クラスタ構成は維持されるので、
+    /// ログのメタデータのみを保持するウィットネス(`ClusterConfig::witnesses`)への送信に使用される.
+    pub fn metadata_only(&self) -> LogSuffix {
+        let entries = self
+            .entries
+            .iter()
+            .map(|e| match e {
+                LogEntry::Command { term, .. } => LogEntry::StrippedCommand { term: *term },
+                e => e.clone(),
+            })
+            .collect();
+        LogSuffix {
+            head: self.head,
+            entries,
+        }
+    }
+
     /// ログの終端位置を返す.
     ///
     /// "終端位置" = "entriesに含まれない最初のエントリの位置".
@@ -80,13 +297,37 @@ impl LogSuffix {
     }
 
     /// 後半部分に含まれるエントリの位置を走査するためのイテレータを返す.
-    pub fn positions(&self) -> LogPositions {
+    pub fn positions(&self) -> LogPositions<'_> {
         LogPositions {
             suffix: self,
             offset: 0,
         }
     }
 
+    /// 後半部分に含まれるエントリと、その位置の組を走査するためのイテレータを返す.
+    ///
+    /// 各エントリには、そのエントリの直後の位置
+    /// (i.e., `prev_term`がエントリの`Term`と等しくなる位置)が対応付けられる.
+    /// `entries[i]`の場合には、その位置のインデックスは`head.index + i + 1`となる.
+    pub fn iter_with_positions(&self) -> impl Iterator<Item = (LogPosition, &LogEntry)> {
+        self.positions().skip(1).zip(self.entries.iter())
+    }
+
+    /// 後半部分に含まれる`Command`エントリのインデックスとペイロードの組を走査するためのイテレータを返す.
+    ///
+    /// `Command`以外のエントリは読み飛ばされる.
+    /// コマンドのみを必要とする状態機械に、エントリを供給する際に利用可能.
+    pub fn commands(&self) -> impl Iterator<Item = (LogIndex, &[u8])> {
+        let head = self.head.index;
+        self.entries
+            .iter()
+            .enumerate()
+            .filter_map(move |(i, e)| match e {
+                LogEntry::Command { command, .. } => Some((head + i, &command[..])),
+                _ => None,
+            })
+    }
+
     /// `new_head`のまでスキップする.
     ///
     /// 現在の先頭から`new_head`までのエントリは破棄され、`new_head`が新しい先頭になる.
@@ -107,7 +348,7 @@ impl LogSuffix {
         let prev_term = self
             .entries
             .drain(0..count)
-            .last()
+            .next_back()
             .expect("Never fails")
             .term();
         self.head.prev_term = prev_term;
@@ -115,19 +356,152 @@ impl LogSuffix {
         Ok(())
     }
 
+    /// 後半部分の構造上の不変項が維持されているかどうかを検査する.
+    ///
+    /// `validate`による検査に加えて、以下を確認する:
+    ///
+    /// - 各エントリの`Term`が、`head.prev_term`から始まって単調非減少である
+    ///   (`Config`エントリの`Term`も、前後のエントリと同様に扱われる)
+    ///
+    /// `validate`は型の構造上の前提(インデックスが桁溢れしないこと)のみを確認する安価なもので、
+    /// ログの読み込み時に常に実行される.
+    /// 一方でこのメソッドは、エントリの内容にまで踏み込んだ検査を行うもので、
+    /// ファジングや障害調査の際に使用することを想定している.
+    /// いずれもエントリ数に対して線形の時間で完了するため、デバッグ時に頻繁に呼び出しても問題ない.
+    ///
+    /// # Errors
+    ///
+    /// 不変項が破られている場合には、最初に違反が見つかったインデックスを示すメッセージと共に
+    /// `ErrorKind::InconsistentState`が返される.
+    pub fn verify(&self) -> Result<()> {
+        track!(self.validate())?;
+        let mut prev_term = self.head.prev_term;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let index = self.head.index + i;
+            track_assert!(
+                prev_term <= entry.term(),
+                ErrorKind::InconsistentState,
+                "Term regression at index {:?}: prev_term={:?}, term={:?}",
+                index,
+                prev_term,
+                entry.term()
+            );
+            prev_term = entry.term();
+        }
+        Ok(())
+    }
+
     /// 終端を`new_tail`の位置まで切り詰める.
     ///
+    /// 返り値は、切り詰めによって取り除かれたエントリ群(`new_tail`から元の終端まで)で、
+    /// 元の並び順が維持される.
+    /// `new_tail`が現在の終端と等しい場合には、空の`Vec`が返される.
+    ///
     /// # Errors
     ///
     /// `new_tail`が`LogSuffix`が保持する範囲の外の場合には、
     /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
-    pub fn truncate(&mut self, new_tail: LogIndex) -> Result<()> {
+    pub fn truncate(&mut self, new_tail: LogIndex) -> Result<Vec<LogEntry>> {
         track_assert!(self.head.index <= new_tail, ErrorKind::InvalidInput);
         track_assert!(new_tail <= self.tail().index, ErrorKind::InvalidInput);
         let delta = self.tail().index - new_tail;
         let new_len = self.entries.len() - delta;
-        self.entries.truncate(new_len);
-        Ok(())
+        Ok(self.entries.split_off(new_len))
+    }
+
+    /// `at`の位置で二つに分割する.
+    ///
+    /// `self`には`[head, at)`の範囲が残り、`[at, tail)`の範囲が返される.
+    /// 返される後半部分の`head.prev_term`は、`at`の直前のエントリの`Term`となる
+    /// (`at`が先頭の場合には`self.head.prev_term`).
+    ///
+    /// `slice`と異なり、エントリ群の複製は行わない.
+    ///
+    /// # Errors
+    ///
+    /// `at`が`LogSuffix`が保持する範囲の外の場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// その場合、`self`は変更されない.
+    pub fn split_off(&mut self, at: LogIndex) -> Result<LogSuffix> {
+        track_assert!(self.head.index <= at, ErrorKind::InvalidInput);
+        track_assert!(at <= self.tail().index, ErrorKind::InvalidInput);
+        let offset = at - self.head.index;
+        let prev_term = if offset == 0 {
+            self.head.prev_term
+        } else {
+            self.entries[offset - 1].term()
+        };
+        Ok(LogSuffix {
+            head: LogPosition {
+                prev_term,
+                index: at,
+            },
+            entries: self.entries.split_off(offset),
+        })
+    }
+
+    /// スナップショットに取り込んでも安全な領域(`[head, consumed)`)を切り出して返す.
+    ///
+    /// `committed`と`consumed`には、`LogHistory`のコミット済みおよび消費済みの終端を渡す.
+    /// `self`には、残りの`[consumed, tail)`の範囲が残される.
+    /// `consumed`が先頭と等しい場合には、空の`LogSuffix`が返される.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には`ErrorKind::InvalidInput`が返され、`self`は変更されない:
+    ///
+    /// - `consumed < self.head.index`
+    /// - `committed < consumed`
+    /// - `self.tail().index < committed`
+    pub fn trim_to_committed(
+        &mut self,
+        committed: LogIndex,
+        consumed: LogIndex,
+    ) -> Result<LogSuffix> {
+        track_assert!(
+            self.head.index <= consumed && consumed <= committed && committed <= self.tail().index,
+            ErrorKind::InvalidInput,
+            "Out of range: head={:?}, consumed={:?}, committed={:?}, tail={:?}",
+            self.head.index,
+            consumed,
+            committed,
+            self.tail().index
+        );
+        let rest = track!(self.split_off(consumed))?;
+        Ok(mem::replace(self, rest))
+    }
+
+    /// `other`との間で、内容が最初に食い違う地点を返す.
+    ///
+    /// 両者が共に保持する範囲のエントリ群を先頭から比較し、
+    /// `Term`ないしペイロードが異なる最初のエントリのインデックスを返す.
+    /// 共通範囲が全て一致する場合(e.g., 一方が他方の接頭辞となっている場合)には`None`が返される.
+    ///
+    /// 両者の範囲が重ならず、間に欠落した領域が存在する場合には、その欠落領域の先頭が返される.
+    /// なお、一方の終端が他方の先頭と丁度一致する場合には、食い違いは無いものとして扱われる.
+    ///
+    /// ウィットネスのログに含まれる`StrippedCommand`エントリは、
+    /// 同じ`Term`の`Command`エントリとは一致するものとして扱われる.
+    ///
+    /// ノード間でのログの分岐の調査を補助するためのもの.
+    pub fn first_divergence(&self, other: &LogSuffix) -> Option<LogIndex> {
+        let start = cmp::max(self.head.index, other.head.index);
+        let end = cmp::min(self.tail().index, other.tail().index);
+        if end < start {
+            return Some(end);
+        }
+        let a = &self.entries[start - self.head.index..end - self.head.index];
+        let b = &other.entries[start - other.head.index..end - other.head.index];
+        a.iter()
+            .zip(b)
+            .position(|(a, b)| match (a, b) {
+                (LogEntry::StrippedCommand { term }, LogEntry::Command { term: t, .. })
+                | (LogEntry::Command { term: t, .. }, LogEntry::StrippedCommand { term }) => {
+                    term != t
+                }
+                _ => a != b,
+            })
+            .map(|i| start + i)
     }
 
     /// 指定された範囲のログ領域を切り出して返す.
@@ -157,16 +531,207 @@ impl LogSuffix {
             entries: slice_entries,
         })
     }
-}
-impl Default for LogSuffix {
-    fn default() -> Self {
-        LogSuffix {
-            head: LogPosition::default(),
-            entries: Vec::new(),
+
+    /// 末尾に`entry`を追加する.
+    ///
+    /// # Errors
+    ///
+    /// `entry`の`Term`が、現在の終端位置の`prev_term`(i.e., 最後のエントリの`Term`)よりも小さい場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// その場合、`self`は変更されない.
+    pub fn push(&mut self, entry: LogEntry) -> Result<()> {
+        let last_term = self.tail().prev_term;
+        track_assert!(
+            last_term <= entry.term(),
+            ErrorKind::InvalidInput,
+            "last_term={:?}, entry_term={:?}",
+            last_term,
+            entry.term()
+        );
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// 末尾に`other`のエントリ群を追加する.
+    ///
+    /// `other`が空(e.g., ハートビートの`AppendEntriesCall`)で、その`head`が`self.tail()`と一致する場合には、
+    /// 何も変更せずに成功する.
+    /// また`self`が空で`self.head`と`other.head`が一致する場合には、`self`は`other`と同じ内容になる.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には`ErrorKind::InvalidInput`が返される:
+    ///
+    /// - `other.head`が`self.tail()`と一致しない (i.e., 接続地点のインデックスないし`prev_term`が異なる)
+    /// - `other`のエントリ群の`Term`が単調増加ではない
+    ///
+    /// エラーの場合、`self`は変更されない.
+    ///
+    /// 追加されるエントリ数分の領域は事前に一度に確保されるので、
+    /// 長大な`other`を追加する場合でも、途中で再割り当てが繰り返されることはない.
+    pub fn extend_from_suffix(&mut self, other: &LogSuffix) -> Result<()> {
+        track!(self.check_extendable_by(other))?;
+        self.entries.reserve(other.entries.len());
+        self.entries.extend_from_slice(&other.entries);
+        Ok(())
+    }
+
+    /// 連続する複数の後半部分を、先頭から順に連結した一つの後半部分を生成する.
+    ///
+    /// スナップショット境界の前後を別々に読み込んだ場合のように、
+    /// 複数回に分けて取得したログ領域を一つにまとめるために使用される.
+    /// エントリ群は複製されずに、結果の後半部分へと移動される.
+    ///
+    /// # Errors
+    ///
+    /// 以下のいずれかの場合には`ErrorKind::InvalidInput`が返される:
+    ///
+    /// - `parts`が空である
+    /// - 各要素の`head`が、直前の要素の`tail()`と一致しない (i.e., 欠落ないし`prev_term`の不一致がある)
+    /// - いずれかの要素のエントリ群の`Term`が単調増加ではない
+    pub fn concat(parts: Vec<LogSuffix>) -> Result<LogSuffix> {
+        let head = track_assert_some!(
+            parts.first().map(|p| p.head),
+            ErrorKind::InvalidInput,
+            "No parts"
+        );
+        let len = parts.iter().map(|p| p.entries.len()).sum();
+        let mut suffix = LogSuffix {
+            head,
+            entries: Vec::with_capacity(len),
+        };
+        for (i, part) in parts.into_iter().enumerate() {
+            track!(suffix.check_extendable_by(&part), "part={}", i)?;
+            suffix.entries.extend(part.entries);
+        }
+        Ok(suffix)
+    }
+
+    fn check_extendable_by(&self, other: &LogSuffix) -> Result<()> {
+        track_assert_eq!(other.head, self.tail(), ErrorKind::InvalidInput);
+        let mut last_term = other.head.prev_term;
+        for e in &other.entries {
+            track_assert!(
+                last_term <= e.term(),
+                ErrorKind::InvalidInput,
+                "last_term={:?}, entry_term={:?}",
+                last_term,
+                e.term()
+            );
+            last_term = e.term();
         }
+        Ok(())
+    }
+
+    /// 少なくとも`additional`個のエントリを、再割り当てなしに追加できるように領域を確保する.
+    ///
+    /// 大量のエントリをまとめて追加する前に呼び出すことで、途中での再割り当てを避けることができる.
+    pub fn reserve(&mut self, additional: usize) {
+        self.entries.reserve(additional);
+    }
+
+    /// 後半部分の構造が壊れていないかを検査する.
+    ///
+    /// `tail().index`が`head.index + entries.len()`と一致する(i.e., インデックスが桁溢れしない)ことを確認する.
+    ///
+    /// `positions()`が返す位置群は`head`から導出されるので、
+    /// インデックスが桁溢れしない限り、それらは常に`head`から連続した狭義単調増加の列となる.
+    /// そのため、この検査のみで、壊れたストレージから読み込まれたログを使用前に安価に検査できる.
+    ///
+    /// エントリの`Term`の単調性等の、内容にまで踏み込んだ検査が必要な場合には`verify`を使用すること.
+    ///
+    /// # Errors
+    ///
+    /// 検査に失敗した場合には`ErrorKind::InconsistentState`が返される.
+    /// 例えば`head.index`が`u64::MAX`に近く、エントリ群の末尾のインデックスが桁溢れする場合が該当する.
+    pub fn validate(&self) -> Result<()> {
+        let head = self.head.index.as_u64();
+        let tail = track_assert_some!(
+            head.checked_add(self.entries.len() as u64),
+            ErrorKind::InconsistentState,
+            "head={:?}, entries={}",
+            self.head,
+            self.entries.len()
+        );
+        track_assert_eq!(
+            self.tail().index,
+            LogIndex::new(tail),
+            ErrorKind::InconsistentState
+        );
+        Ok(())
+    }
+
+    /// 終端が`follower_tail`のフォロワーに対して、ログの後半部分の代わりにスナップショットを送るべきかどうかを判定する.
+    ///
+    /// 送信が必要な`follower_tail`から`tail()`までの領域が、ログ全体(i.e., 先頭から`tail()`まで)に占める割合が
+    /// `snapshot_savings_ratio`を超える場合には、ログの大半を送り直すことになるので`true`を返す.
+    ///
+    /// `follower_tail`が`head`よりも前の場合には、そもそも後半部分では同期できないので常に`true`となり、
+    /// `tail()`以降の場合には送信すべきエントリが存在しないので常に`false`となる.
+    pub fn prefer_snapshot_over(
+        &self,
+        follower_tail: LogIndex,
+        snapshot_savings_ratio: f64,
+    ) -> bool {
+        if follower_tail < self.head.index {
+            return true;
+        }
+        let tail = self.tail().index;
+        if tail <= follower_tail {
+            return false;
+        }
+        let missing = (tail - follower_tail) as f64;
+        missing > tail.as_u64() as f64 * snapshot_savings_ratio
+    }
+
+    /// ログの内容を要約した`SuffixDescription`を返す.
+    ///
+    /// 主にテストやデバッグ時に、ログの内容を検査するために使用される.
+    pub fn describe(&self) -> SuffixDescription {
+        let mut terms: Vec<(LogIndex, Term)> = Vec::new();
+        for (i, e) in self.entries.iter().enumerate() {
+            if terms.last().map(|t| t.1) != Some(e.term()) {
+                terms.push((self.head.index + i, e.term()));
+            }
+        }
+        SuffixDescription {
+            head: self.head,
+            tail: self.tail(),
+            entry_count: self.entries.len(),
+            terms,
+        }
+    }
+
+    /// `Term`毎の、このログに含まれるエントリの数を返す.
+    ///
+    /// リーダの交代が頻発していないかどうかを調査する際に使用される.
+    pub fn term_histogram(&self) -> BTreeMap<Term, usize> {
+        let mut histogram = BTreeMap::new();
+        for e in &self.entries {
+            *histogram.entry(e.term()).or_insert(0) += 1;
+        }
+        histogram
     }
 }
 
+/// `LogSuffix::describe`が返す、ログの後半部分の要約.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuffixDescription {
+    /// ログの開始位置.
+    pub head: LogPosition,
+
+    /// ログの終端位置.
+    pub tail: LogPosition,
+
+    /// ログに含まれるエントリの数.
+    pub entry_count: usize,
+
+    /// `Term`の境界.
+    ///
+    /// 各要素は「その`Term`に属する最初のエントリのインデックス」と「`Term`」のペア.
+    pub terms: Vec<(LogIndex, Term)>,
+}
+
 /// `LogSuffix`に含まれるログの位置を走査するための`Iterator`実装.
 #[derive(Debug)]
 pub struct LogPositions<'a> {
@@ -207,7 +772,27 @@ pub enum LogEntry {
     Config { term: Term, config: ClusterConfig },
 
     /// 状態機械の入力となるコマンドを格納したエントリ.
-    Command { term: Term, command: Vec<u8> },
+    ///
+    /// `command`は`Arc`で共有されているので、`LogSuffix`の複製や分割(e.g., フォロワー群への送信)の際に、
+    /// ペイロードのバイト列自体が複製されることはない.
+    Command { term: Term, command: Arc<[u8]> },
+
+    /// リーダのリース期限を共有するための、コマンドを持たないエントリ.
+    ///
+    /// `ReplicatedLogOptions::lease_duration`が指定されている場合に、リーダによって定期的に追加される.
+    ///
+    /// `lease_until`はリーダが発行した論理タイムスタンプ(UNIXエポックからのミリ秒)で、
+    /// このエントリがコミットされると、各ノードの読み込みリースがその時刻まで延長される.
+    LeaseNoop { term: Term, lease_until: u64 },
+
+    /// `Command`エントリのペイロードを取り除いたエントリ.
+    ///
+    /// ログのメタデータのみを保持するウィットネス(`ClusterConfig::witnesses`)への送信時に、
+    /// `Command`エントリの代わりに使用される.
+    ///
+    /// 空のコマンドを持つ`Command`エントリとは区別されるので、このエントリを保持するノードは、
+    /// ストレージを初期化しない限り、データを保持するノードとしてクラスタに参加することはできない.
+    StrippedCommand { term: Term },
 }
 impl LogEntry {
     /// このエントリが発行された`Term`を返す.
@@ -216,12 +801,17 @@ impl LogEntry {
             LogEntry::Noop { term } => term,
             LogEntry::Config { term, .. } => term,
             LogEntry::Command { term, .. } => term,
+            LogEntry::LeaseNoop { term, .. } => term,
+            LogEntry::StrippedCommand { term } => term,
         }
     }
 }
 
 /// 提案ID.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+///
+/// `term`、`index`の順で比較される.
+/// 一つのリーダの任期内では、この順序は提案が発行された順序と一致する.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct ProposalId {
     /// 提案が発行された時の`Term`.
     pub term: Term,
@@ -229,6 +819,25 @@ pub struct ProposalId {
     /// 提案を保持するエントリのログ内でのインデックス.
     pub index: LogIndex,
 }
+impl ProposalId {
+    /// プロセスや実行環境に依らず、常に同じ値となるハッシュ値を返す.
+    ///
+    /// `term`と`index`を(リトルエンディアンのバイト列として)順にFNV-1aで処理した値.
+    /// 標準の`Hash`トレイトとは異なり、アルゴリズムが固定されているので、
+    /// 複数のプロセス間で提案IDを分散(シャーディング)させる用途に利用可能.
+    pub fn stable_hash(&self) -> u64 {
+        const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+        let term = self.term.as_u64().to_le_bytes();
+        let index = self.index.as_u64().to_le_bytes();
+        term.iter()
+            .chain(index.iter())
+            .fold(FNV_OFFSET_BASIS, |hash, &b| {
+                (hash ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+            })
+    }
+}
 
 /// ログの特定位置を識別するためのデータ構造.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
@@ -240,10 +849,48 @@ pub struct LogPosition {
     pub index: LogIndex,
 }
 impl LogPosition {
-    /// `self`がログ上で、`other`と等しい、あるいは、より後方に位置している場合に`true`が返る.
+    /// `self`と`other`のログ上での前後関係を返す.
+    ///
+    /// `prev_term`が等しい場合には、インデックスの大小によって順序が決まる.
+    /// `prev_term`が異なる場合には、`Term`がより大きい方が後方となる.
+    ///
+    /// ただし`Term`が大きい方のインデックスの方が小さい場合には、
+    /// 二つの位置はそれぞれ分岐したログ上にあるため、`None`が返される.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::cmp::Ordering;
+    /// use raftlog::log::LogPosition;
+    ///
+    /// let a = LogPosition { prev_term: 10.into(), index: 5.into() };
+    /// let b = LogPosition { prev_term: 10.into(), index: 3.into() };
+    /// assert_eq!(a.cmp_position(b), Some(Ordering::Greater));
+    /// assert_eq!(b.cmp_position(a), Some(Ordering::Less));
+    /// assert_eq!(a.cmp_position(a), Some(Ordering::Equal));
+    ///
+    /// let a = LogPosition { prev_term: 5.into(), index: 10.into() };
+    /// let b = LogPosition { prev_term: 10.into(), index: 3.into() };
+    /// assert_eq!(a.cmp_position(b), None);
+    /// ```
+    pub fn cmp_position(&self, other: LogPosition) -> Option<Ordering> {
+        let term = self.prev_term.cmp(&other.prev_term);
+        let index = self.index.cmp(&other.index);
+        match (term, index) {
+            (Ordering::Equal, _) => Some(index),
+            (_, Ordering::Equal) => Some(term),
+            _ if term == index => Some(term),
+            _ => None,
+        }
+    }
+
+    /// `self`がログ上で、`other`と等しい、あるいは、より新しい場合に`true`が返る.
     ///
-    /// なお`self`と`other`が、それぞれ分岐したログ上に位置しており、
-    /// 前後関係が判断できない場合には`false`が返される.
+    /// Raftのログの新しさの規則に従い、`prev_term`が大きい方がより新しく、
+    /// `prev_term`が等しい場合にはインデックスが大きい方がより新しいものとして扱われる.
+    ///
+    /// `self`と`other`がそれぞれ分岐したログ上に位置している場合(i.e., `cmp_position`が`None`を返す場合)も、
+    /// 同様に`prev_term`が大きい方が新しいと判定される.
     ///
     /// # Examples
     ///
@@ -263,14 +910,62 @@ impl LogPosition {
     /// assert!(!b.is_newer_or_equal_than(a));
     ///
     /// // `a`の方がインデックスは大きいが、`b`の方が`Term`は大きい
-    /// // => 順序が確定できない
+    /// // => 分岐しているが、`Term`が大きい`b`の方が新しい
     /// let a = LogPosition { prev_term: 5.into(), index: 10.into() };
     /// let b = LogPosition { prev_term: 10.into(), index: 3.into() };
     /// assert!(!a.is_newer_or_equal_than(b));
-    /// assert!(!b.is_newer_or_equal_than(a));
+    /// assert!(b.is_newer_or_equal_than(a));
     /// ```
     pub fn is_newer_or_equal_than(&self, other: LogPosition) -> bool {
-        self.prev_term >= other.prev_term && self.index >= other.index
+        match self.cmp_position(other) {
+            Some(order) => order != Ordering::Less,
+            None => self.prev_term > other.prev_term,
+        }
+    }
+
+    /// `self`の後ろに、`new_term`のエントリを`entries`個追加した場合の終端位置を返す.
+    ///
+    /// `entries`が`0`の場合には、`self`がそのまま返される.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use raftlog::log::LogPosition;
+    ///
+    /// let p = LogPosition { prev_term: 1.into(), index: 30.into() };
+    /// assert_eq!(p.advanced(3, 2.into()), LogPosition { prev_term: 2.into(), index: 33.into() });
+    /// assert_eq!(p.advanced(0, 2.into()), p);
+    /// ```
+    pub fn advanced(self, entries: usize, new_term: Term) -> LogPosition {
+        if entries == 0 {
+            self
+        } else {
+            LogPosition {
+                prev_term: new_term,
+                index: self.index + entries,
+            }
+        }
+    }
+
+    /// `idx`が、`range_head`から`range_tail`までの半開区間`[range_head.index, range_tail.index)`に含まれるかどうかを判定する.
+    ///
+    /// 区間の終端(`range_tail.index`)自体は含まれない.
+    /// 位置の`prev_term`は考慮されない.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use raftlog::log::LogPosition;
+    ///
+    /// let head = LogPosition { prev_term: 1.into(), index: 10.into() };
+    /// let tail = LogPosition { prev_term: 2.into(), index: 20.into() };
+    /// assert!(LogPosition::contains_index(head, tail, 10.into()));
+    /// assert!(LogPosition::contains_index(head, tail, 19.into()));
+    /// assert!(!LogPosition::contains_index(head, tail, 20.into()));
+    /// assert!(!LogPosition::contains_index(head, tail, 9.into()));
+    /// ```
+    pub fn contains_index(range_head: LogPosition, range_tail: LogPosition, idx: LogIndex) -> bool {
+        range_head.index <= idx && idx < range_tail.index
     }
 }
 
@@ -287,12 +982,47 @@ impl LogIndex {
     pub fn as_u64(self) -> u64 {
         self.0
     }
+
+    /// `usize`から`LogIndex`インスタンスを生成する.
+    ///
+    /// `n`が`u64`の範囲に収まらない場合には`u64::MAX`に丸められる.
+    pub fn from_usize_saturating(n: usize) -> Self {
+        LogIndex(u64::try_from(n).unwrap_or(u64::MAX))
+    }
+
+    /// `other`から`self`までのエントリ数(i.e., `self - other`)を返す.
+    ///
+    /// `other`の方が後方にある場合や、結果が`usize`に収まらない場合には`None`が返される.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use raftlog::log::LogIndex;
+    ///
+    /// assert_eq!(LogIndex::new(10).distance(LogIndex::new(3)), Some(7));
+    /// assert_eq!(LogIndex::new(10).distance(LogIndex::new(10)), Some(0));
+    /// assert_eq!(LogIndex::new(3).distance(LogIndex::new(10)), None);
+    /// ```
+    pub fn distance(self, other: LogIndex) -> Option<usize> {
+        self.0
+            .checked_sub(other.0)
+            .and_then(|n| usize::try_from(n).ok())
+    }
 }
 impl From<u64> for LogIndex {
     fn from(f: u64) -> Self {
         LogIndex::new(f)
     }
 }
+impl TryFrom<usize> for LogIndex {
+    type Error = Error;
+
+    /// `n`が`u64`の範囲に収まらない場合には`ErrorKind::InvalidInput`を理由としたエラーを返す.
+    fn try_from(n: usize) -> Result<Self> {
+        let n = track!(u64::try_from(n).map_err(Error::from))?;
+        Ok(LogIndex(n))
+    }
+}
 impl Add<usize> for LogIndex {
     type Output = Self;
     fn add(self, rhs: usize) -> Self::Output {
@@ -348,6 +1078,17 @@ mod test {
         assert_eq!(suffix.tail().index.as_u64(), 2);
     }
     #[test]
+    fn log_position_advanced() {
+        assert_eq!(id(1, 30).advanced(3, 2.into()), id(2, 33));
+        assert_eq!(id(1, 30).advanced(0, 2.into()), id(1, 30));
+
+        let suffix = LogSuffix {
+            head: id(1, 30),
+            entries: vec![noop(2), noop(2), noop(2)],
+        };
+        assert_eq!(suffix.head.advanced(3, 2.into()), suffix.tail());
+    }
+    #[test]
     fn log_suffix_positions() {
         let suffix = LogSuffix::default();
         assert_eq!(suffix.positions().collect::<Vec<_>>(), [id(0, 0)]);
@@ -365,6 +1106,61 @@ mod test {
         );
     }
     #[test]
+    fn log_suffix_iter_with_positions() {
+        let suffix = LogSuffix::default();
+        assert_eq!(suffix.iter_with_positions().count(), 0);
+
+        let suffix = LogSuffix {
+            head: id(1, 30),
+            entries: vec![noop(1), noop(1), noop(3), noop(3), noop(4)],
+        };
+        let pairs = suffix.iter_with_positions().collect::<Vec<_>>();
+        assert_eq!(pairs.len(), suffix.entries.len());
+        for (i, (p, e)) in pairs.iter().enumerate() {
+            assert_eq!(p.index, suffix.head.index + i + 1);
+            assert_eq!(p.prev_term, e.term());
+            assert_eq!(*e, &suffix.entries[i]);
+        }
+        assert_eq!(pairs[1].0, id(1, 32));
+        assert_eq!(pairs[2].0, id(3, 33));
+        assert_eq!(pairs.last().map(|p| p.0), Some(suffix.tail()));
+    }
+    #[test]
+    fn log_suffix_describe() {
+        let suffix = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), noop(2), noop(2), noop(2), noop(5)],
+        };
+        let d = suffix.describe();
+        assert_eq!(d.head, id(1, 10));
+        assert_eq!(d.tail, id(5, 15));
+        assert_eq!(d.entry_count, 5);
+        assert_eq!(
+            d.terms,
+            [
+                (10.into(), 1.into()),
+                (11.into(), 2.into()),
+                (14.into(), 5.into())
+            ]
+        );
+
+        let d = LogSuffix::default().describe();
+        assert_eq!(d.entry_count, 0);
+        assert!(d.terms.is_empty());
+    }
+    #[test]
+    fn log_suffix_term_histogram() {
+        let suffix = LogSuffix {
+            head: id(0, 0),
+            entries: vec![noop(1), noop(1), noop(2), noop(3), noop(3), noop(3)],
+        };
+        let histogram = suffix.term_histogram();
+        let expected = vec![(1.into(), 2), (2.into(), 1), (3.into(), 3)];
+        assert_eq!(histogram.into_iter().collect::<Vec<_>>(), expected);
+
+        assert!(LogSuffix::default().term_histogram().is_empty());
+    }
+    #[test]
     fn log_suffix_skip_to() {
         let mut suffix = LogSuffix {
             head: LogPosition {
@@ -395,6 +1191,69 @@ mod test {
         assert_eq!(suffix.entries.len(), 0);
     }
     #[test]
+    fn log_suffix_len_and_byte_len() {
+        let empty = LogSuffix::default();
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+        assert_eq!(empty.byte_len(), 0);
+
+        let suffix = LogSuffix {
+            head: id(0, 0),
+            entries: vec![
+                noop(1),
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: ClusterConfig::new(Default::default()),
+                },
+                LogEntry::Command {
+                    term: 1.into(),
+                    command: vec![0; 10].into(),
+                },
+                LogEntry::Command {
+                    term: 2.into(),
+                    command: vec![0; 5].into(),
+                },
+            ],
+        };
+        assert_eq!(suffix.len(), 4);
+        assert!(!suffix.is_empty());
+        assert_eq!(suffix.byte_len(), 15 + 4 * LogSuffix::ENTRY_OVERHEAD_BYTES);
+    }
+
+    #[test]
+    fn log_suffix_verify() {
+        let suffix = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), noop(2), noop(2), noop(3)],
+        };
+        assert!(suffix.verify().is_ok());
+        assert!(LogSuffix::default().verify().is_ok());
+
+        // エントリ間での`Term`の後退
+        let mut broken = suffix.clone();
+        broken.entries[2] = noop(1);
+        let e = broken.verify().unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("Term regression at index LogIndex(12)"));
+
+        // `head`に対する`Term`の後退
+        let mut broken = suffix.clone();
+        broken.head.prev_term = 2.into();
+        let e = broken.verify().unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("Term regression at index LogIndex(10)"));
+
+        // `validate`で検出される不整合も報告される
+        let broken = LogSuffix {
+            head: id(1, u64::MAX),
+            entries: vec![noop(1)],
+        };
+        let e = broken.verify().err().map(|e| *e.kind());
+        assert_eq!(e, Some(ErrorKind::InconsistentState));
+    }
+    #[test]
     fn log_suffix_truncate() {
         let mut suffix = LogSuffix {
             head: LogPosition {
@@ -409,12 +1268,156 @@ mod test {
         );
         assert_eq!(suffix.entries.len(), 3);
 
-        suffix.truncate(31.into()).unwrap();
+        let removed = suffix.truncate(31.into()).unwrap();
         assert_eq!(
             suffix.positions().collect::<Vec<_>>(),
             [id(0, 30), id(0, 31)]
         );
         assert_eq!(suffix.entries.len(), 1);
+
+        // 取り除かれたエントリ群を戻せば、元のログが復元できる
+        assert_eq!(removed, [noop(2), noop(2)]);
+        let mut restored = suffix.clone();
+        for e in removed {
+            restored.push(e).unwrap();
+        }
+        assert_eq!(
+            restored.positions().collect::<Vec<_>>(),
+            [id(0, 30), id(0, 31), id(2, 32), id(2, 33)]
+        );
+
+        assert_eq!(suffix.truncate(31.into()).unwrap(), []);
+        assert!(suffix.truncate(32.into()).is_err());
+        assert_eq!(suffix.entries.len(), 1);
+    }
+    #[test]
+    fn log_suffix_extend_allocates_once() {
+        let mut suffix = LogSuffix {
+            head: id(1, 0),
+            entries: Vec::new(),
+        };
+        let other = LogSuffix {
+            head: id(1, 0),
+            entries: (0..10_000).map(|i| noop(1 + i / 1000)).collect(),
+        };
+        suffix.extend_from_suffix(&other).unwrap();
+        assert_eq!(suffix.len(), 10_000);
+
+        // 一度の確保で、丁度必要な分の領域が割り当てられている
+        assert_eq!(suffix.entries.capacity(), 10_000);
+    }
+
+    #[test]
+    fn log_suffix_split_off() {
+        let suffix = LogSuffix {
+            head: id(1, 30),
+            entries: vec![noop(1), noop(2), noop(2), noop(3)],
+        };
+        for &(at, prev_term) in &[(30, 1), (32, 2), (34, 3)] {
+            let mut front = suffix.clone();
+            let back = front.split_off(at.into()).unwrap();
+            assert_eq!(front.head, suffix.head);
+            assert_eq!(front.tail(), id(prev_term, at));
+            assert_eq!(back.head, id(prev_term, at));
+            assert_eq!(back.tail(), suffix.tail());
+
+            // 二つを繋げれば、元のログが復元できる
+            front.extend_from_suffix(&back).unwrap();
+            assert_eq!(front.head, suffix.head);
+            assert_eq!(front.entries, suffix.entries);
+        }
+
+        let mut unchanged = suffix.clone();
+        let e = unchanged.split_off(29.into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        let e = unchanged.split_off(35.into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        assert_eq!(unchanged.entries, suffix.entries);
+    }
+
+    #[test]
+    fn log_suffix_trim_to_committed() {
+        let suffix = LogSuffix {
+            head: id(1, 30),
+            entries: vec![noop(1), noop(2), noop(2), noop(3)],
+        };
+
+        // 消費済みの終端が先頭 => 空
+        let mut rest = suffix.clone();
+        let trimmed = rest.trim_to_committed(32.into(), 30.into()).unwrap();
+        assert_eq!(trimmed.head, suffix.head);
+        assert!(trimmed.entries.is_empty());
+        assert_eq!((rest.head, &rest.entries), (suffix.head, &suffix.entries));
+
+        // 途中まで消費済み
+        let mut rest = suffix.clone();
+        let trimmed = rest.trim_to_committed(33.into(), 32.into()).unwrap();
+        assert_eq!(trimmed.head, suffix.head);
+        assert_eq!(trimmed.tail(), id(2, 32));
+        assert_eq!(rest.head, id(2, 32));
+        assert_eq!(rest.tail(), suffix.tail());
+
+        // 終端まで消費済み
+        let mut rest = suffix.clone();
+        let trimmed = rest.trim_to_committed(34.into(), 34.into()).unwrap();
+        assert_eq!(
+            (trimmed.head, &trimmed.entries),
+            (suffix.head, &suffix.entries)
+        );
+        assert_eq!(rest.head, suffix.tail());
+        assert!(rest.entries.is_empty());
+
+        // 範囲外
+        for &(committed, consumed) in &[(32, 29), (31, 32), (35, 32)] {
+            let mut unchanged = suffix.clone();
+            let e = unchanged
+                .trim_to_committed(committed.into(), consumed.into())
+                .unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+            assert_eq!(unchanged.entries, suffix.entries);
+        }
+    }
+
+    #[test]
+    fn log_suffix_first_divergence() {
+        let command = |term: u64, c: u8| LogEntry::Command {
+            term: term.into(),
+            command: vec![c].into(),
+        };
+        let suffix = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), command(1, 0), command(2, 1), command(2, 2)],
+        };
+
+        // 同一、あるいは、一方が他方の接頭辞
+        assert_eq!(suffix.first_divergence(&suffix), None);
+        let prefix = suffix.slice(10.into(), 12.into()).unwrap();
+        assert_eq!(suffix.first_divergence(&prefix), None);
+        assert_eq!(prefix.first_divergence(&suffix), None);
+
+        // `Term`のみが異なる
+        let mut other = suffix.slice(11.into(), 14.into()).unwrap();
+        other.entries[1] = command(1, 1);
+        assert_eq!(suffix.first_divergence(&other), Some(12.into()));
+        assert_eq!(other.first_divergence(&suffix), Some(12.into()));
+
+        // ペイロードのみが異なる
+        let mut other = suffix.clone();
+        other.entries[3] = command(2, 9);
+        assert_eq!(suffix.first_divergence(&other), Some(13.into()));
+
+        // 範囲が重ならない
+        let disjoint = LogSuffix {
+            head: id(3, 20),
+            entries: vec![noop(3)],
+        };
+        assert_eq!(suffix.first_divergence(&disjoint), Some(14.into()));
+        assert_eq!(disjoint.first_divergence(&suffix), Some(14.into()));
+        let adjacent = LogSuffix {
+            head: suffix.tail(),
+            entries: vec![noop(3)],
+        };
+        assert_eq!(suffix.first_divergence(&adjacent), None);
     }
     #[test]
     fn log_suffix_slice() {
@@ -438,4 +1441,468 @@ mod test {
         );
         assert_eq!(slice.entries.len(), 2);
     }
+    #[test]
+    fn log_index_from_usize() {
+        assert_eq!(LogIndex::try_from(10usize).unwrap(), LogIndex::new(10));
+        assert_eq!(LogIndex::from_usize_saturating(10), LogIndex::new(10));
+
+        // `usize`が`u64`よりも広いプラットフォームでの桁溢れを模擬する
+        let e = Error::from(u64::try_from(u128::MAX).unwrap_err());
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn log_index_distance() {
+        assert_eq!(LogIndex::new(10).distance(LogIndex::new(3)), Some(7));
+        assert_eq!(LogIndex::new(10).distance(LogIndex::new(10)), Some(0));
+        assert_eq!(LogIndex::new(3).distance(LogIndex::new(10)), None);
+        assert_eq!(
+            LogIndex::new(u64::MAX).distance(LogIndex::new(0)),
+            usize::try_from(u64::MAX).ok()
+        );
+    }
+
+    #[test]
+    fn log_position_contains_index() {
+        let (head, tail) = (id(1, 10), id(2, 13));
+        assert!(!LogPosition::contains_index(head, tail, 9.into()));
+        assert!(LogPosition::contains_index(head, tail, 10.into()));
+        assert!(LogPosition::contains_index(head, tail, 12.into()));
+        assert!(!LogPosition::contains_index(head, tail, 13.into()));
+        assert!(!LogPosition::contains_index(head, tail, 14.into()));
+
+        // 空の区間には、いずれのインデックスも含まれない
+        assert!(!LogPosition::contains_index(head, head, 10.into()));
+
+        // `LogSuffix`の範囲判定と一致する
+        let suffix = LogSuffix {
+            head,
+            entries: vec![noop(1), noop(2), noop(2)],
+        };
+        for i in 8..16 {
+            let idx = LogIndex::new(i);
+            let contained = LogPosition::contains_index(suffix.head, suffix.tail(), idx);
+            assert_eq!(contained, suffix.slice(idx, idx + 1).is_ok());
+        }
+    }
+
+    #[test]
+    fn log_position_cmp_position() {
+        // `Term`が等しく、インデックスが異なる
+        assert_eq!(id(10, 5).cmp_position(id(10, 3)), Some(Ordering::Greater));
+        assert_eq!(id(10, 3).cmp_position(id(10, 5)), Some(Ordering::Less));
+        assert!(id(10, 5).is_newer_or_equal_than(id(10, 3)));
+        assert!(!id(10, 3).is_newer_or_equal_than(id(10, 5)));
+
+        // インデックスが等しく、`Term`が異なる
+        assert_eq!(id(20, 3).cmp_position(id(10, 3)), Some(Ordering::Greater));
+        assert_eq!(id(10, 3).cmp_position(id(20, 3)), Some(Ordering::Less));
+        assert!(id(20, 3).is_newer_or_equal_than(id(10, 3)));
+        assert!(!id(10, 3).is_newer_or_equal_than(id(20, 3)));
+
+        // `Term`もインデックスも大きい
+        assert_eq!(id(20, 5).cmp_position(id(10, 3)), Some(Ordering::Greater));
+        assert_eq!(id(10, 3).cmp_position(id(20, 5)), Some(Ordering::Less));
+
+        // 分岐している
+        assert_eq!(id(5, 10).cmp_position(id(10, 3)), None);
+        assert_eq!(id(10, 3).cmp_position(id(5, 10)), None);
+        assert!(!id(5, 10).is_newer_or_equal_than(id(10, 3)));
+        assert!(id(10, 3).is_newer_or_equal_than(id(5, 10)));
+
+        // 等しい
+        assert_eq!(id(10, 3).cmp_position(id(10, 3)), Some(Ordering::Equal));
+        assert!(id(10, 3).is_newer_or_equal_than(id(10, 3)));
+    }
+
+    #[test]
+    fn proposal_id_ordering() {
+        let id = |term: u64, index: u64| ProposalId {
+            term: term.into(),
+            index: index.into(),
+        };
+        let mut ids = vec![id(2, 3), id(1, 5), id(2, 1), id(1, 2), id(3, 0)];
+        ids.sort();
+        assert_eq!(ids, [id(1, 2), id(1, 5), id(2, 1), id(2, 3), id(3, 0)]);
+    }
+
+    #[test]
+    fn log_suffix_reserve() {
+        let mut suffix = LogSuffix::default();
+        suffix.reserve(100);
+        let capacity = suffix.entries.capacity();
+        assert!(capacity >= 100);
+
+        let ptr = suffix.entries.as_ptr();
+        suffix.entries.extend((0..100).map(noop));
+        assert_eq!(suffix.entries.capacity(), capacity);
+        assert_eq!(suffix.entries.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn proposal_id_stable_hash() {
+        let id = |term: u64, index: u64| ProposalId {
+            term: term.into(),
+            index: index.into(),
+        };
+        assert_eq!(id(3, 10).stable_hash(), id(3, 10).stable_hash());
+        assert_ne!(id(3, 10).stable_hash(), id(3, 11).stable_hash());
+        assert_ne!(id(3, 10).stable_hash(), id(10, 3).stable_hash());
+
+        // 値はアルゴリズムのみで決まるので、固定値と比較できる
+        assert_eq!(id(0, 0).stable_hash(), 0x8820_1fb9_60ff_6465);
+    }
+
+    #[test]
+    fn log_suffix_validate() {
+        let suffix = LogSuffix {
+            head: LogPosition {
+                prev_term: 1.into(),
+                index: 3.into(),
+            },
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 2.into() },
+            ],
+        };
+        assert!(suffix.validate().is_ok());
+        assert!(LogSuffix::default().validate().is_ok());
+
+        // 末尾のインデックスが桁溢れする
+        let suffix = LogSuffix {
+            head: LogPosition {
+                prev_term: 1.into(),
+                index: u64::MAX.into(),
+            },
+            entries: vec![LogEntry::Noop { term: 1.into() }],
+        };
+        let e = suffix.validate().err().map(|e| *e.kind());
+        assert_eq!(e, Some(ErrorKind::InconsistentState));
+    }
+
+    #[test]
+    fn log_suffix_prefer_snapshot_over() {
+        let suffix = LogSuffix {
+            head: LogPosition {
+                prev_term: 1.into(),
+                index: 5.into(),
+            },
+            entries: vec![LogEntry::Noop { term: 1.into() }; 95],
+        };
+
+        // 90%のエントリが欠けている
+        assert!(suffix.prefer_snapshot_over(10.into(), 0.5));
+
+        // 5%のエントリが欠けている
+        assert!(!suffix.prefer_snapshot_over(95.into(), 0.5));
+
+        // 欠けているエントリが無い / 後半部分の先頭よりも前
+        assert!(!suffix.prefer_snapshot_over(100.into(), 0.0));
+        assert!(suffix.prefer_snapshot_over(4.into(), 1.0));
+    }
+
+    #[test]
+    fn log_suffix_commands() {
+        let command = |term: u64, bytes: &[u8]| LogEntry::Command {
+            term: term.into(),
+            command: bytes.into(),
+        };
+        let suffix = LogSuffix {
+            head: id(1, 20),
+            entries: vec![
+                noop(1),
+                command(1, b"foo"),
+                LogEntry::Config {
+                    term: 1.into(),
+                    config: ClusterConfig::new(Default::default()),
+                },
+                command(1, b""),
+                LogEntry::LeaseNoop {
+                    term: 2.into(),
+                    lease_until: 0,
+                },
+                command(2, b"bar"),
+            ],
+        };
+        let commands = suffix.commands().collect::<Vec<_>>();
+        assert_eq!(
+            commands,
+            [
+                (LogIndex::new(21), &b"foo"[..]),
+                (LogIndex::new(23), &b""[..]),
+                (LogIndex::new(25), &b"bar"[..]),
+            ]
+        );
+        assert_eq!(LogSuffix::default().commands().count(), 0);
+    }
+
+    #[test]
+    fn log_suffix_clone_shares_command_payloads() {
+        let payload: Arc<[u8]> = vec![0; 1024].into();
+        let suffix = LogSuffix {
+            head: id(1, 0),
+            entries: vec![
+                LogEntry::Command {
+                    term: 1.into(),
+                    command: Arc::clone(&payload),
+                },
+                noop(1),
+            ],
+        };
+        assert_eq!(Arc::strong_count(&payload), 2);
+
+        // 複製や分割では、ペイロードのバイト列は共有される
+        let cloned = suffix.clone();
+        let sliced = suffix.slice(0.into(), 1.into()).unwrap();
+        assert_eq!(Arc::strong_count(&payload), 4);
+        for s in &[&cloned, &sliced] {
+            match s.entries[0] {
+                LogEntry::Command { ref command, .. } => assert!(Arc::ptr_eq(command, &payload)),
+                _ => panic!(),
+            }
+        }
+    }
+    #[test]
+    fn log_suffix_push() {
+        let mut suffix = LogSuffix::default();
+        suffix.push(noop(1)).unwrap();
+        suffix.push(noop(1)).unwrap();
+        suffix.push(noop(3)).unwrap();
+        assert_eq!(suffix.tail(), id(3, 3));
+
+        // `Term`の逆行
+        let e = suffix.push(noop(2)).err().map(|e| *e.kind());
+        assert_eq!(e, Some(ErrorKind::InvalidInput));
+        assert_eq!(suffix.tail(), id(3, 3));
+
+        let mut suffix = LogSuffix {
+            head: id(2, 10),
+            entries: Vec::new(),
+        };
+        assert!(suffix.push(noop(1)).is_err());
+        suffix.push(noop(2)).unwrap();
+        assert_eq!(suffix.tail(), id(2, 11));
+    }
+    #[test]
+    fn log_suffix_extend_from_suffix() {
+        let mut suffix = LogSuffix::default();
+        let other = LogSuffix {
+            head: id(0, 0),
+            entries: vec![noop(1), noop(2)],
+        };
+        suffix.extend_from_suffix(&other).unwrap();
+        assert_eq!(suffix.tail(), id(2, 2));
+
+        // 接続地点の`prev_term`が異なる
+        let other = LogSuffix {
+            head: id(1, 2),
+            entries: vec![noop(2)],
+        };
+        assert!(suffix.extend_from_suffix(&other).is_err());
+
+        // 接続地点のインデックスが異なる
+        let other = LogSuffix {
+            head: id(2, 3),
+            entries: vec![noop(2)],
+        };
+        assert!(suffix.extend_from_suffix(&other).is_err());
+
+        // エントリ群の`Term`が逆行している
+        let other = LogSuffix {
+            head: id(2, 2),
+            entries: vec![noop(3), noop(2)],
+        };
+        assert!(suffix.extend_from_suffix(&other).is_err());
+        assert_eq!(suffix.tail(), id(2, 2));
+
+        let other = LogSuffix {
+            head: id(2, 2),
+            entries: vec![noop(2), noop(4)],
+        };
+        suffix.extend_from_suffix(&other).unwrap();
+        assert_eq!(suffix.tail(), id(4, 4));
+    }
+    #[test]
+    fn log_prefix_covers() {
+        let prefix = LogPrefix::new(
+            id(1, 10),
+            ClusterConfig::new(Default::default()),
+            Vec::new(),
+        );
+
+        // 範囲全体がスナップショットに含まれる
+        assert!(prefix.covers(0.into(), 10.into()));
+        assert!(prefix.covers(3.into(), 7.into()));
+        assert!(prefix.covers(10.into(), 10.into()));
+
+        // スナップショットの境界を跨ぐ
+        assert!(!prefix.covers(3.into(), 11.into()));
+
+        // 範囲全体が後半部分に含まれる
+        assert!(!prefix.covers(10.into(), 12.into()));
+        assert!(!prefix.covers(11.into(), 12.into()));
+
+        // 不正な範囲
+        assert!(!prefix.covers(7.into(), 3.into()));
+    }
+
+    #[test]
+    fn log_prefix_validate_range() {
+        let prefix = LogPrefix::new(
+            id(1, 10),
+            ClusterConfig::new(Default::default()),
+            Vec::new(),
+        );
+
+        // スナップショットの範囲内
+        assert!(prefix.validate_range(3.into(), 7.into()).is_ok());
+        assert!(prefix.covers(3.into(), 7.into()));
+
+        // スナップショットよりも後ろの範囲
+        assert!(prefix.validate_range(11.into(), 12.into()).is_ok());
+        assert!(!prefix.covers(11.into(), 12.into()));
+
+        // 不正な範囲
+        let e = prefix.validate_range(7.into(), 3.into()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+    #[test]
+    fn log_suffix_concat() {
+        // 二つの連続する後半部分
+        let a = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), noop(2)],
+        };
+        let b = LogSuffix {
+            head: id(2, 12),
+            entries: vec![noop(2), noop(3)],
+        };
+        let suffix = LogSuffix::concat(vec![a.clone(), b.clone()]).unwrap();
+        assert_eq!(suffix.head, id(1, 10));
+        assert_eq!(suffix.entries, [noop(1), noop(2), noop(2), noop(3)]);
+
+        // 三つの連続する後半部分 (空のものを含む)
+        let c = LogSuffix {
+            head: id(3, 14),
+            entries: Vec::new(),
+        };
+        let d = LogSuffix {
+            head: id(3, 14),
+            entries: vec![noop(4)],
+        };
+        let suffix = LogSuffix::concat(vec![a.clone(), b, c, d]).unwrap();
+        assert_eq!(suffix.head, id(1, 10));
+        assert_eq!(suffix.tail(), id(4, 15));
+        assert_eq!(suffix.slice(12.into(), 15.into()).unwrap().entries.len(), 3);
+
+        // 間に欠落がある
+        let gap = LogSuffix {
+            head: id(2, 13),
+            entries: vec![noop(3)],
+        };
+        let e = LogSuffix::concat(vec![a.clone(), gap]).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        // 接続地点の`prev_term`が異なる
+        let mismatch = LogSuffix {
+            head: id(1, 12),
+            entries: vec![noop(3)],
+        };
+        let e = LogSuffix::concat(vec![a, mismatch]).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+
+        let e = LogSuffix::concat(Vec::new()).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+    }
+    #[test]
+    fn log_suffix_extend_from_empty_or_into_empty_suffix() {
+        // 空の後半部分(ハートビート)の追加は、何も変更しない
+        let mut suffix = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), noop(2)],
+        };
+        let heartbeat = LogSuffix {
+            head: suffix.tail(),
+            entries: Vec::new(),
+        };
+        suffix.extend_from_suffix(&heartbeat).unwrap();
+        assert_eq!(suffix.head, id(1, 10));
+        assert_eq!(suffix.entries, [noop(1), noop(2)]);
+
+        let heartbeat = LogSuffix {
+            head: id(2, 11),
+            entries: Vec::new(),
+        };
+        assert!(suffix.extend_from_suffix(&heartbeat).is_err());
+        assert_eq!(suffix.entries, [noop(1), noop(2)]);
+
+        // 空の後半部分への追加は、追加対象の内容をそのまま採用する
+        let mut suffix = LogSuffix {
+            head: id(1, 10),
+            entries: Vec::new(),
+        };
+        let next = LogSuffix {
+            head: id(1, 10),
+            entries: vec![noop(1), noop(3)],
+        };
+        suffix.extend_from_suffix(&next).unwrap();
+        assert_eq!(suffix.head, next.head);
+        assert_eq!(suffix.entries, next.entries);
+        assert_eq!(suffix.tail(), id(3, 12));
+    }
+    #[test]
+    fn log_prefix_checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let prefix = LogPrefix::new(
+            id(1, 10),
+            ClusterConfig::new(Default::default()),
+            b"123456789".to_vec(),
+        )
+        .with_checksum();
+        assert_eq!(prefix.checksum, Some(0xCBF4_3926));
+        assert!(prefix.verify_checksum().is_ok());
+
+        let mut corrupted = prefix.clone();
+        corrupted.snapshot[0] ^= 1;
+        let e = corrupted.decompress().err().map(|e| *e.kind());
+        assert_eq!(e, Some(ErrorKind::InconsistentState));
+    }
+    #[test]
+    fn log_prefix_compression() {
+        let prefix = LogPrefix::new(
+            id(1, 10),
+            ClusterConfig::new(Default::default()),
+            (0..100).collect(),
+        );
+
+        // `Compression::None`の場合には、バイト列は一切変更されない
+        let compressed = prefix.clone().compress(Compression::None).unwrap();
+        assert_eq!(compressed.snapshot, prefix.snapshot);
+        assert_eq!(compressed.compression, Compression::None);
+
+        let decompressed = compressed.decompress().unwrap();
+        assert_eq!(decompressed.tail, prefix.tail);
+        assert_eq!(decompressed.snapshot, prefix.snapshot);
+    }
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn log_prefix_lz4_round_trip() {
+        let prefix = LogPrefix::new(
+            id(1, 10),
+            ClusterConfig::new(Default::default()),
+            b"snapshot".repeat(100),
+        )
+        .with_checksum();
+
+        let compressed = prefix.clone().compress(Compression::Lz4).unwrap();
+        assert_eq!(compressed.compression, Compression::Lz4);
+        assert!(compressed.snapshot.len() < prefix.snapshot.len());
+        assert!(compressed.verify_checksum().is_ok());
+
+        let decompressed = compressed.decompress().unwrap();
+        assert_eq!(decompressed.compression, Compression::None);
+        assert_eq!(decompressed.snapshot, prefix.snapshot);
+        assert_eq!(decompressed.checksum, prefix.checksum);
+    }
 }
@@ -0,0 +1,815 @@
+//! 全ての状態をメモリ上に保持する`Io`の参照実装.
+//!
+//! `MemoryIo`は、投票状況およびローカルログを`MemoryStorage`に保存する.
+//! ストレージは`Arc<Mutex<_>>`で共有されるので、`ReplicatedLog`を破棄した後に、
+//! 同じストレージを引き継いだ`MemoryIo`を用いてノードを再構築する(i.e., 再起動する)ことができる.
+//! ただし、プロセスが終了した場合には、当然ながら内容は失われる.
+//!
+//! ノード間の通信には、同一プロセス内で共有される`MemoryNetwork`を使用する.
+//! タイムアウトはプロセス内の全ての`MemoryIo`で共有される単一のタイマースレッドによって、実時間の経過に従って発火する.
+//! このスレッドは最初のタイムアウト生成時に起動され、プロセスの終了まで存続する.
+//!
+//! 内部の`Mutex`が(他のスレッドのパニックにより)ポイズニングされている場合には、
+//! 各操作は`ErrorKind::Other`を理由としたエラーを返す.
+//!
+//! `testing`モジュールの`MockIo`とは異なり、全ての非同期処理は通常のタスク(e.g., `futures::executor::spawn`)の中で
+//! 駆動されることを前提としており、完了可能になった時点でタスクに通知を行う.
+//! そのため、テストだけではなく、単一プロセス内で完結する構成(e.g., 単一ノードのクラスタ)の運用にも使用できる.
+//!
+//! # Examples
+//!
+//! ```
+//! use futures::executor;
+//! use prometrics::metrics::MetricBuilder;
+//! use raftlog::election::{Role, TimeoutPolicy};
+//! use raftlog::memory::MemoryIo;
+//! use raftlog::{Event, ReplicatedLog, ReplicatedLogOptions};
+//! use std::time::Duration;
+//!
+//! let mut options = ReplicatedLogOptions::default();
+//! options.timeout_policy = TimeoutPolicy {
+//!     leader: Duration::from_millis(5),
+//!     follower: Duration::from_millis(5),
+//!     candidate: Duration::from_millis(5),
+//!     candidate_jitter: Duration::from_millis(1),
+//! };
+//! let members = vec!["a".into()].into_iter().collect();
+//! let io = MemoryIo::new("a".into());
+//! let rlog =
+//!     ReplicatedLog::with_options("a".into(), members, io, &MetricBuilder::new(), options).unwrap();
+//! let mut rlog = executor::spawn(rlog);
+//! while let Some(event) = rlog.wait_stream() {
+//!     if event.unwrap() == (Event::RoleChanged { new_role: Role::Leader }) {
+//!         break;
+//!     }
+//! }
+//! ```
+use futures::task::{self, Task};
+use futures::{Async, Future, Poll};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use trackable::error::ErrorKindExt;
+
+use crate::election::{Ballot, Role, TimeoutPolicy};
+use crate::io::{Io, SaveBallotAndLog};
+use crate::log::{Log, LogEntry, LogIndex, LogPrefix, LogSuffix};
+use crate::message::{GroupId, Message};
+use crate::node::NodeId;
+use crate::{Error, ErrorKind, Result};
+
+/// ノードの永続ストレージ.
+///
+/// ノードを再起動した場合でも内容は維持される.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// 保存済みの投票状況.
+    pub ballot: Option<Ballot>,
+
+    /// 保存済みのスナップショット.
+    pub prefix: Option<LogPrefix>,
+
+    /// スナップショット以降のログ.
+    pub suffix: LogSuffix,
+}
+impl MemoryStorage {
+    pub(crate) fn save_log_prefix(&mut self, prefix: LogPrefix) {
+        if self.suffix.head.index < prefix.tail.index {
+            if self.suffix.skip_to(prefix.tail.index).is_err() {
+                // `prefix`がローカルログを完全に追い越している
+                self.suffix.head = prefix.tail;
+                self.suffix.entries.clear();
+            }
+            if prefix.tail.prev_term != self.suffix.head.prev_term {
+                self.suffix.head.prev_term = prefix.tail.prev_term;
+                self.suffix.entries.clear();
+            }
+        }
+        self.prefix = Some(prefix);
+    }
+
+    pub(crate) fn save_log_suffix_owned(&mut self, suffix: LogSuffix) -> Result<()> {
+        if self.suffix.entries.is_empty() && self.suffix.head == suffix.head {
+            // 空のログへの追記の場合は、複製せずにそのまま保持する
+            self.suffix = suffix;
+            Ok(())
+        } else {
+            // 既存のログとのマージが必要
+            track!(self.save_log_suffix(&suffix))
+        }
+    }
+
+    pub(crate) fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Result<()> {
+        let entries_offset = if self.suffix.head.index <= suffix.head.index {
+            0
+        } else {
+            // スナップショットのインストールタイミング次第で、こちらに入ることがある
+            self.suffix.head.index - suffix.head.index
+        };
+        track_assert!(
+            suffix.head.index <= self.suffix.tail().index,
+            ErrorKind::InconsistentState,
+            "suffix.head={:?}, self.tail={:?}",
+            suffix.head,
+            self.suffix.tail()
+        );
+        let offset = suffix.head.index + entries_offset - self.suffix.head.index;
+        let prev_term = if offset == 0 {
+            self.suffix.head.prev_term
+        } else {
+            self.suffix.entries[offset - 1].term()
+        };
+        track_assert_eq!(
+            suffix.positions().nth(entries_offset).map(|p| p.prev_term),
+            Some(prev_term),
+            ErrorKind::InconsistentState
+        );
+        self.suffix.entries.truncate(offset);
+        self.suffix.reserve(suffix.entries.len() - entries_offset);
+        self.suffix
+            .entries
+            .extend(suffix.entries.iter().skip(entries_offset).cloned());
+        Ok(())
+    }
+
+    pub(crate) fn load_log(&self, start: LogIndex, end: Option<LogIndex>) -> Result<Log> {
+        // `start`のエントリが後半部分に残っている限りは、スナップショットの範囲と重なっていても後半部分を返す
+        if start < self.suffix.head.index {
+            // スナップショット済み領域
+            let prefix = track!(self
+                .prefix
+                .clone()
+                .ok_or_else(|| ErrorKind::Other.cause("No snapshot installed")))?;
+            if let Some(end) = end {
+                track!(prefix.validate_range(start, end))?;
+            }
+            return Ok(Log::Prefix(prefix));
+        }
+        let end = end.unwrap_or_else(|| self.suffix.tail().index);
+        let suffix = track!(self.suffix.slice(start, end))?;
+        Ok(Log::Suffix(suffix))
+    }
+
+    pub(crate) fn load_log_limited(&self, start: LogIndex, max_entries: usize) -> Result<Log> {
+        let end = cmp::min(start + max_entries, self.suffix.tail().index);
+        track!(self.load_log(start, Some(cmp::max(start, end))))
+    }
+
+    pub(crate) fn load_log_limited_bytes(
+        &self,
+        start: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Result<Log> {
+        if start < self.suffix.head.index || self.suffix.tail().index <= start {
+            return track!(self.load_log_limited(start, max_entries));
+        }
+
+        // 合計サイズが`max_bytes`を超える直前のエントリまでを読み込む (ただし先頭のエントリは常に含める)
+        let offset = start - self.suffix.head.index;
+        let mut bytes = 0;
+        let mut len = 0;
+        for e in self.suffix.entries.iter().skip(offset).take(max_entries) {
+            if let LogEntry::Command { ref command, .. } = *e {
+                bytes += command.len();
+            }
+            if len > 0 && bytes > max_bytes {
+                break;
+            }
+            len += 1;
+        }
+        track!(self.load_log(start, Some(start + len)))
+    }
+}
+
+/// 同一プロセス内のノード間で共有されるメッセージ配送網.
+///
+/// メッセージは`(group_id, destination)`の組に基づいて、宛先のメールボックスに即座に配送される.
+/// 宛先が未登録(i.e., 対応する`MemoryIo`が生成されていない)のメッセージは破棄される.
+#[derive(Debug, Default)]
+pub struct MemoryNetwork {
+    mailboxes: BTreeMap<(GroupId, NodeId), Mailbox>,
+}
+impl MemoryNetwork {
+    /// 新しい`MemoryNetwork`インスタンスを生成する.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn send(&mut self, message: Message) {
+        let header = message.header();
+        let key = (header.group_id, header.destination.clone());
+        if let Some(mailbox) = self.mailboxes.get_mut(&key) {
+            mailbox.messages.push_back(message);
+            if let Some(task) = mailbox.task.take() {
+                task.notify();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Mailbox {
+    messages: VecDeque<Message>,
+
+    // メールボックスが空の時に受信を試みたタスク.
+    task: Option<Task>,
+}
+
+/// 全ての状態をメモリ上に保持する`Io`の実装.
+///
+/// 詳細はモジュールのドキュメントを参照のこと.
+#[derive(Debug)]
+pub struct MemoryIo {
+    node: NodeId,
+    group_id: GroupId,
+    network: Arc<Mutex<MemoryNetwork>>,
+    storage: Arc<Mutex<MemoryStorage>>,
+    last_timeout: Option<Arc<Mutex<TimeoutSlot>>>,
+}
+impl MemoryIo {
+    /// 他のノードとは通信しない、ノード`node`用の`MemoryIo`インスタンスを生成する.
+    ///
+    /// ストレージは空の状態から開始される.
+    pub fn new(node: NodeId) -> Self {
+        Self::with_storage(node, Arc::default())
+    }
+
+    /// `storage`の内容を引き継いだ、ノード`node`用の`MemoryIo`インスタンスを生成する.
+    ///
+    /// ノードを再起動する場合には、以前の`MemoryIo::storage`を渡すこと.
+    pub fn with_storage(node: NodeId, storage: Arc<Mutex<MemoryStorage>>) -> Self {
+        Self::with_network(node, GroupId::default(), Arc::default(), storage)
+    }
+
+    /// `network`に接続された、グループ`group_id`のノード`node`用の`MemoryIo`インスタンスを生成する.
+    ///
+    /// 同じ`(group_id, node)`の組のインスタンスが既に存在する場合には、
+    /// そのメールボックス(に届いている未受信のメッセージ群)を引き継ぐ.
+    pub fn with_network(
+        node: NodeId,
+        group_id: GroupId,
+        network: Arc<Mutex<MemoryNetwork>>,
+        storage: Arc<Mutex<MemoryStorage>>,
+    ) -> Self {
+        // ポイズニングされている場合でも、メールボックスは受信時に改めて作成されるので問題はない
+        if let Ok(mut network) = lock(&network) {
+            network
+                .mailboxes
+                .entry((group_id, node.clone()))
+                .or_default();
+        }
+        MemoryIo {
+            node,
+            group_id,
+            network,
+            storage,
+            last_timeout: None,
+        }
+    }
+
+    /// ノードの永続ストレージを返す.
+    pub fn storage(&self) -> &Arc<Mutex<MemoryStorage>> {
+        &self.storage
+    }
+
+    /// 接続しているメッセージ配送網を返す.
+    pub fn network(&self) -> &Arc<Mutex<MemoryNetwork>> {
+        &self.network
+    }
+
+    fn schedule(&mut self, duration: Duration) -> MemoryTimeout {
+        let deadline = Instant::now() + duration;
+        let slot = Arc::new(Mutex::new(TimeoutSlot::default()));
+        Timer::shared().schedule(deadline, Arc::clone(&slot));
+        self.last_timeout = Some(Arc::clone(&slot));
+        MemoryTimeout { deadline, slot }
+    }
+}
+impl Io for MemoryIo {
+    type SaveBallot = MemoryResult<()>;
+    type LoadBallot = MemoryResult<Option<Ballot>>;
+    type SaveLog = MemoryResult<()>;
+    type LoadLog = MemoryResult<Log>;
+    type Timeout = MemoryTimeout;
+
+    fn try_recv_message(&mut self) -> Result<Option<Message>> {
+        let group_id = self.group_id;
+        self.try_recv_group_message(group_id)
+    }
+
+    fn try_recv_group_message(&mut self, group_id: GroupId) -> Result<Option<Message>> {
+        let mut network = track!(lock(&self.network))?;
+        let mailbox = network
+            .mailboxes
+            .entry((group_id, self.node.clone()))
+            .or_default();
+        let message = mailbox.messages.pop_front();
+        if message.is_none() && task::is_in_task() {
+            // 次のメッセージが届いた時点で、受信を再試行させる
+            mailbox.task = Some(task::current());
+        }
+        Ok(message)
+    }
+
+    fn send_message(&mut self, message: Message) {
+        // メッセージの配送は元々保証されないので、配送網が使用不能な場合には単に破棄する
+        if let Ok(mut network) = lock(&self.network) {
+            network.send(message);
+        }
+    }
+
+    fn save_ballot(&mut self, ballot: Ballot) -> Self::SaveBallot {
+        MemoryResult::new(track!(lock(&self.storage)).map(|mut storage| {
+            storage.ballot = Some(ballot);
+        }))
+    }
+
+    fn load_ballot(&mut self) -> Self::LoadBallot {
+        MemoryResult::new(track!(lock(&self.storage)).map(|storage| storage.ballot.clone()))
+    }
+
+    fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog {
+        MemoryResult::new(
+            track!(lock(&self.storage)).map(|mut storage| storage.save_log_prefix(prefix)),
+        )
+    }
+
+    fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
+        MemoryResult::new(
+            track!(lock(&self.storage))
+                .and_then(|mut storage| track!(storage.save_log_suffix(suffix))),
+        )
+    }
+
+    fn save_log_suffix_owned(&mut self, suffix: LogSuffix) -> Self::SaveLog {
+        MemoryResult::new(
+            track!(lock(&self.storage))
+                .and_then(|mut storage| track!(storage.save_log_suffix_owned(suffix))),
+        )
+    }
+
+    fn save_ballot_and_log(
+        &mut self,
+        ballot: Ballot,
+        suffix: &LogSuffix,
+    ) -> SaveBallotAndLog<Self> {
+        // 同じロックの下で両方を更新するので、アトミックに保存される
+        let result = track!(lock(&self.storage)).and_then(|mut storage| {
+            track!(storage.save_log_suffix(suffix))?;
+            storage.ballot = Some(ballot);
+            Ok(())
+        });
+        SaveBallotAndLog::atomic(MemoryResult::new(result))
+    }
+
+    fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog {
+        MemoryResult::new(
+            track!(lock(&self.storage)).and_then(|storage| track!(storage.load_log(start, end))),
+        )
+    }
+
+    fn load_log_limited(&mut self, start: LogIndex, max_entries: usize) -> Self::LoadLog {
+        MemoryResult::new(
+            track!(lock(&self.storage))
+                .and_then(|storage| track!(storage.load_log_limited(start, max_entries))),
+        )
+    }
+
+    fn load_log_limited_bytes(
+        &mut self,
+        start: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Self::LoadLog {
+        MemoryResult::new(track!(lock(&self.storage)).and_then(|storage| {
+            track!(storage.load_log_limited_bytes(start, max_entries, max_bytes))
+        }))
+    }
+
+    fn create_timeout(&mut self, role: Role) -> Self::Timeout {
+        let duration = TimeoutPolicy::default().timeout(role, 0);
+        self.schedule(duration)
+    }
+
+    fn create_timeout_with(&mut self, _role: Role, duration: Duration) -> Self::Timeout {
+        self.schedule(duration)
+    }
+
+    fn cancel_timeout(&mut self) {
+        if let Some(slot) = self.last_timeout.take() {
+            slot.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .cancelled = true;
+            Timer::shared().unschedule(&slot);
+        }
+    }
+}
+impl Drop for MemoryIo {
+    fn drop(&mut self) {
+        // 共有タイマーに不要なタイムアウトが残り続けないようにする
+        if let Some(slot) = self.last_timeout.take() {
+            Timer::shared().unschedule(&slot);
+        }
+    }
+}
+
+/// `MemoryIo`が生成する、生成時点で結果が確定している非同期処理.
+///
+/// `futures::future::FutureResult`とは異なり、完了後に再度ポーリングされた場合でもパニックせず、
+/// `ErrorKind::InconsistentState`を理由としたエラーを返す.
+#[derive(Debug)]
+pub struct MemoryResult<T> {
+    result: Option<Result<T>>,
+}
+impl<T> MemoryResult<T> {
+    pub(crate) fn new(result: Result<T>) -> Self {
+        MemoryResult {
+            result: Some(result),
+        }
+    }
+}
+impl<T> Future for MemoryResult<T> {
+    type Item = T;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = track_assert_some!(
+            self.result.take(),
+            ErrorKind::InconsistentState,
+            "MemoryResult has already completed"
+        );
+        let item = track!(result)?;
+        Ok(Async::Ready(item))
+    }
+}
+
+/// `MemoryIo`が生成するタイムアウト.
+///
+/// 指定時間の経過後に完了する.
+/// ただし`Io::cancel_timeout`で取り消された後は、完了することはない.
+#[derive(Debug)]
+pub struct MemoryTimeout {
+    deadline: Instant,
+    slot: Arc<Mutex<TimeoutSlot>>,
+}
+impl Future for MemoryTimeout {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut slot = track!(lock(&self.slot))?;
+        if slot.cancelled {
+            return Ok(Async::NotReady);
+        }
+        if self.deadline <= Instant::now() {
+            return Ok(Async::Ready(()));
+        }
+        if task::is_in_task() {
+            // 期限到達時にタイマースレッドから通知してもらう
+            slot.task = Some(task::current());
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimeoutSlot {
+    cancelled: bool,
+    task: Option<Task>,
+}
+
+// プロセス内の全ての`MemoryIo`で共有されるタイマー.
+//
+// 登録されたタイムアウトの期限が到来したら、それを待機しているタスクに通知する.
+//
+// キューおよびスロットの内容は単純なので、ポイズニングされていても内部状態をそのまま使い続ける.
+#[derive(Debug, Default)]
+struct Timer {
+    queue: Mutex<TimerQueue>,
+    cond: Condvar,
+}
+impl Timer {
+    fn shared() -> &'static Timer {
+        static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            let timer: &'static Timer = Box::leak(Box::default());
+            thread::spawn(move || timer.run());
+            timer
+        })
+    }
+
+    fn schedule(&self, deadline: Instant, slot: Arc<Mutex<TimeoutSlot>>) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.pending.push((deadline, slot));
+        self.cond.notify_one();
+    }
+
+    fn unschedule(&self, slot: &Arc<Mutex<TimeoutSlot>>) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.pending.retain(|(_, s)| !Arc::ptr_eq(s, slot));
+    }
+
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let now = Instant::now();
+            queue.pending.retain(|(deadline, slot)| {
+                if now < *deadline {
+                    return true;
+                }
+                let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Some(task) = slot.task.take() {
+                    task.notify();
+                }
+                false
+            });
+            queue = match queue.pending.iter().map(|(deadline, _)| *deadline).min() {
+                None => self
+                    .cond
+                    .wait(queue)
+                    .unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    self.cond
+                        .wait_timeout(queue, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimerQueue {
+    pending: Vec<(Instant, Arc<Mutex<TimeoutSlot>>)>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    let guard = track!(mutex
+        .lock()
+        .map_err(|e| ErrorKind::Other.cause(e.to_string())))?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::{self, Spawn};
+    use prometrics::metrics::MetricBuilder;
+    use std::collections::BTreeSet;
+    use std::thread;
+
+    use super::*;
+    use crate::cluster::ClusterConfig;
+    use crate::election::Term;
+    use crate::log::{LogEntry, LogPosition};
+    use crate::message::{MessageHeader, SequenceNumber, TimeoutNowCast};
+    use crate::{Event, ReplicatedLog, ReplicatedLogOptions};
+
+    fn start(io: MemoryIo) -> Spawn<ReplicatedLog<MemoryIo>> {
+        let options = ReplicatedLogOptions {
+            timeout_policy: TimeoutPolicy {
+                leader: Duration::from_millis(5),
+                follower: Duration::from_millis(5),
+                candidate: Duration::from_millis(5),
+                candidate_jitter: Duration::from_millis(1),
+            },
+            ..ReplicatedLogOptions::default()
+        };
+        let members = vec![NodeId::from("a")].into_iter().collect();
+        let rlog =
+            ReplicatedLog::with_options("a".into(), members, io, &MetricBuilder::new(), options)
+                .unwrap();
+        executor::spawn(rlog)
+    }
+
+    fn wait_for<F>(rlog: &mut Spawn<ReplicatedLog<MemoryIo>>, mut f: F) -> Vec<Event>
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        let mut events = Vec::new();
+        while let Some(event) = rlog.wait_stream() {
+            let event = event.unwrap();
+            let done = f(&event);
+            events.push(event);
+            if done {
+                return events;
+            }
+        }
+        panic!("Unexpected termination: events={:?}", events);
+    }
+
+    fn is_committed(event: &Event, command: &[u8]) -> bool {
+        match event {
+            Event::Committed {
+                entry: LogEntry::Command { command: c, .. },
+                ..
+            } => &c[..] == command,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn load_log_returns_snapshot_then_rawlog() {
+        let mut io = MemoryIo::new("a".into());
+        let position = |index: u64| LogPosition {
+            prev_term: 1.into(),
+            index: index.into(),
+        };
+        let prefix = LogPrefix::new(
+            position(3),
+            ClusterConfig::new(BTreeSet::new()),
+            b"foo".to_vec(),
+        );
+        let entries = vec![LogEntry::Noop { term: 1.into() }; 5];
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries,
+        };
+        io.save_log_suffix(&suffix).wait().unwrap();
+        io.save_log_prefix(prefix).wait().unwrap();
+        assert_eq!(io.storage().lock().unwrap().suffix.head.index, 3.into());
+
+        // 先頭は破棄済みなので、まずはスナップショットが返される
+        let prefix = match io.load_log(0.into(), None).wait().unwrap() {
+            Log::Prefix(p) => p,
+            Log::Suffix(_) => panic!(),
+        };
+        assert_eq!(prefix.snapshot, b"foo");
+
+        // スナップショットの終端以降は、後半部分から取得する
+        match io.load_log(prefix.tail.index, None).wait().unwrap() {
+            Log::Suffix(s) => assert_eq!((s.head.index, s.tail().index), (3.into(), 5.into())),
+            Log::Prefix(_) => panic!(),
+        }
+        match io.load_log_limited(prefix.tail.index, 1).wait().unwrap() {
+            Log::Suffix(s) => assert_eq!((s.head.index, s.tail().index), (3.into(), 4.into())),
+            Log::Prefix(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn reboot_restores_snapshot_and_rawlog() {
+        let mut rlog = start(MemoryIo::new("a".into()));
+        wait_for(&mut rlog, |e| {
+            *e == Event::RoleChanged {
+                new_role: Role::Leader,
+            }
+        });
+        rlog.get_mut().propose_command(b"foo".to_vec()).unwrap();
+        rlog.get_mut().propose_command(b"bar".to_vec()).unwrap();
+        wait_for(&mut rlog, |e| is_committed(e, b"bar"));
+
+        let snapshot_head = rlog.get_ref().local_history().committed_tail();
+        rlog.get_mut()
+            .install_snapshot(snapshot_head.index, b"foobar".to_vec())
+            .unwrap();
+        wait_for(&mut rlog, |e| match e {
+            Event::SnapshotInstalled { new_head } => *new_head == snapshot_head,
+            _ => false,
+        });
+        rlog.get_mut().propose_command(b"baz".to_vec()).unwrap();
+        wait_for(&mut rlog, |e| is_committed(e, b"baz"));
+        let term = rlog.get_ref().local_node().ballot.term;
+
+        // 同じストレージを引き継いで再起動する
+        let storage = Arc::clone(rlog.get_ref().io().storage());
+        drop(rlog);
+        let mut rlog = start(MemoryIo::with_storage("a".into(), storage));
+        let events = wait_for(&mut rlog, |e| is_committed(e, b"baz"));
+
+        // スナップショットがロードされた後に、それ以降のエントリ群がコミットされる
+        let loaded = Event::SnapshotLoaded {
+            new_head: snapshot_head,
+            snapshot: b"foobar".to_vec(),
+        };
+        let loaded_at = events.iter().position(|e| *e == loaded).unwrap();
+        let first_commit = events
+            .iter()
+            .position(|e| matches!(e, Event::Committed { .. }))
+            .unwrap();
+        assert!(loaded_at < first_commit);
+        assert!(!events.iter().any(|e| is_committed(e, b"foo")));
+        assert!(rlog.get_ref().local_node().ballot.term > term);
+    }
+
+    #[test]
+    fn truncation_with_mismatched_prev_term_is_rejected() {
+        let mut io = MemoryIo::new("a".into());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: Term::new(1) },
+                LogEntry::Noop { term: Term::new(2) },
+            ],
+        };
+        io.save_log_suffix(&suffix).wait().unwrap();
+
+        let ballot = Ballot {
+            term: Term::new(3),
+            voted_for: "b".into(),
+        };
+        let mismatched = LogPosition {
+            prev_term: Term::new(2),
+            index: LogIndex::new(1),
+        };
+        let error = io
+            .save_ballot_and_truncate(ballot.clone(), mismatched)
+            .wait()
+            .err()
+            .unwrap();
+        assert_eq!(*error.kind(), ErrorKind::InconsistentState);
+        assert_eq!(io.storage().lock().unwrap().ballot, None);
+        assert_eq!(io.storage().lock().unwrap().suffix.tail(), suffix.tail());
+
+        let new_tail = LogPosition {
+            prev_term: Term::new(1),
+            index: LogIndex::new(1),
+        };
+        io.save_ballot_and_truncate(ballot.clone(), new_tail)
+            .wait()
+            .unwrap();
+        assert_eq!(io.storage().lock().unwrap().ballot, Some(ballot));
+        assert_eq!(io.storage().lock().unwrap().suffix.tail(), new_tail);
+    }
+
+    #[test]
+    fn timeout_fires_unless_cancelled() {
+        let mut io = MemoryIo::new("a".into());
+        let timeout = io.create_timeout_with(Role::Follower, Duration::from_millis(1));
+        executor::spawn(timeout).wait_future().unwrap();
+
+        let mut timeout = io.create_timeout_with(Role::Follower, Duration::from_millis(1));
+        io.cancel_timeout();
+        thread::sleep(Duration::from_millis(5));
+        assert_eq!(timeout.poll().unwrap(), Async::NotReady);
+    }
+
+    #[test]
+    fn timer_is_shared_among_instances() {
+        let mut a = MemoryIo::new("a".into());
+        let mut b = MemoryIo::new("b".into());
+        let timeout_a = a.create_timeout_with(Role::Follower, Duration::from_millis(1));
+        let timeout_b = b.create_timeout_with(Role::Follower, Duration::from_millis(1));
+
+        // 一方のインスタンスを破棄しても、他方のタイムアウトは発火し続ける
+        drop(a);
+        executor::spawn(timeout_b).wait_future().unwrap();
+        executor::spawn(timeout_a).wait_future().unwrap();
+    }
+
+    #[test]
+    fn poisoned_storage_is_reported_as_error() {
+        let mut io = MemoryIo::new("a".into());
+        let storage = Arc::clone(io.storage());
+        let _ = thread::spawn(move || {
+            let _guard = storage.lock().unwrap();
+            panic!("poison");
+        })
+        .join();
+
+        let error = io.load_ballot().wait().err().unwrap();
+        assert_eq!(*error.kind(), ErrorKind::Other);
+        let ballot = Ballot {
+            term: Term::new(1),
+            voted_for: "a".into(),
+        };
+        let error = io.save_ballot(ballot).wait().err().unwrap();
+        assert_eq!(*error.kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn messages_are_delivered_through_shared_network() {
+        let network = Arc::new(Mutex::new(MemoryNetwork::new()));
+        let mut a = MemoryIo::with_network(
+            "a".into(),
+            GroupId::default(),
+            Arc::clone(&network),
+            Arc::default(),
+        );
+        let mut b = MemoryIo::with_network(
+            "b".into(),
+            GroupId::default(),
+            Arc::clone(&network),
+            Arc::default(),
+        );
+        let message = |destination: &str| -> Message {
+            TimeoutNowCast {
+                header: MessageHeader {
+                    group_id: GroupId::default(),
+                    sender: "a".into(),
+                    destination: destination.into(),
+                    seq_no: SequenceNumber::new(0),
+                    term: Term::new(1),
+                },
+            }
+            .into()
+        };
+        a.send_message(message("b"));
+        a.send_message(message("unknown"));
+        assert!(a.try_recv_message().unwrap().is_none());
+        let received = b.try_recv_message().unwrap().unwrap();
+        assert_eq!(received.header().destination, NodeId::from("b"));
+        assert!(b.try_recv_message().unwrap().is_none());
+    }
+}
@@ -3,8 +3,9 @@
 //! なおRaftの論文に倣って"RPC"という呼称を採用しているが、
 //! 実際にここで想定されている通信モデルは、RPCではなく
 //! 非同期のメッセージ送受信モデル、となっている.
+use crate::cluster::ClusterConfig;
 use crate::election::Term;
-use crate::log::{LogIndex, LogPosition, LogPrefix, LogSuffix};
+use crate::log::{Compression, LogIndex, LogPosition, LogPrefix, LogSuffix};
 use crate::node::NodeId;
 
 /// RPC用のメッセージ全般.
@@ -16,6 +17,11 @@ pub enum Message {
     AppendEntriesCall(AppendEntriesCall),
     AppendEntriesReply(AppendEntriesReply),
     InstallSnapshotCast(InstallSnapshotCast),
+    InstallSnapshotChunkCast(InstallSnapshotChunkCast),
+    PreVoteCall(PreVoteCall),
+    PreVoteReply(PreVoteReply),
+    TimeoutNowCast(TimeoutNowCast),
+    DepartureCast(DepartureCast),
 }
 impl Message {
     /// メッセージのヘッダを返す.
@@ -26,6 +32,11 @@ impl Message {
             Message::AppendEntriesCall(m) => &m.header,
             Message::AppendEntriesReply(m) => &m.header,
             Message::InstallSnapshotCast(m) => &m.header,
+            Message::InstallSnapshotChunkCast(m) => &m.header,
+            Message::PreVoteCall(m) => &m.header,
+            Message::PreVoteReply(m) => &m.header,
+            Message::TimeoutNowCast(m) => &m.header,
+            Message::DepartureCast(m) => &m.header,
         }
     }
 
@@ -46,6 +57,21 @@ impl Message {
             Message::InstallSnapshotCast(m) => {
                 m.header.destination = dst.clone();
             }
+            Message::InstallSnapshotChunkCast(m) => {
+                m.header.destination = dst.clone();
+            }
+            Message::PreVoteCall(m) => {
+                m.header.destination = dst.clone();
+            }
+            Message::PreVoteReply(m) => {
+                m.header.destination = dst.clone();
+            }
+            Message::TimeoutNowCast(m) => {
+                m.header.destination = dst.clone();
+            }
+            Message::DepartureCast(m) => {
+                m.header.destination = dst.clone();
+            }
         }
     }
 }
@@ -74,10 +100,41 @@ impl From<InstallSnapshotCast> for Message {
         Message::InstallSnapshotCast(f)
     }
 }
+impl From<InstallSnapshotChunkCast> for Message {
+    fn from(f: InstallSnapshotChunkCast) -> Self {
+        Message::InstallSnapshotChunkCast(f)
+    }
+}
+impl From<PreVoteCall> for Message {
+    fn from(f: PreVoteCall) -> Self {
+        Message::PreVoteCall(f)
+    }
+}
+impl From<PreVoteReply> for Message {
+    fn from(f: PreVoteReply) -> Self {
+        Message::PreVoteReply(f)
+    }
+}
+impl From<TimeoutNowCast> for Message {
+    fn from(f: TimeoutNowCast) -> Self {
+        Message::TimeoutNowCast(f)
+    }
+}
+impl From<DepartureCast> for Message {
+    fn from(f: DepartureCast) -> Self {
+        Message::DepartureCast(f)
+    }
+}
 
 /// メッセージのヘッダ.
 #[derive(Debug, Clone)]
 pub struct MessageHeader {
+    /// メッセージが属するRaftグループ.
+    ///
+    /// 一つの`Io`実装を複数のグループで共有する場合には、
+    /// `Io`は`(group_id, destination)`の組に基づいてメッセージを振り分ける必要がある.
+    pub group_id: GroupId,
+
     /// メッセージの送信元.
     pub sender: NodeId,
 
@@ -100,6 +157,17 @@ pub struct RequestVoteCall {
 
     /// 送信者のログの終端位置.
     pub log_tail: LogPosition,
+
+    /// リーダシップの移譲によって開始された選挙かどうか.
+    ///
+    /// この値が`true`の場合には、受信者は現在のリーダをフォロー中であっても、
+    /// 新しい選挙を受け入れる.
+    pub leadership_transfer: bool,
+
+    /// 送信者の選挙における優先度(`ReplicatedLogOptions::election_priorities`を参照).
+    ///
+    /// 受信者は、自分よりも優先度の高い候補者からの要求を受け取った場合には、自身の立候補を遅らせる.
+    pub priority: u32,
 }
 
 /// `RequestVoteRPC`の応答メッセージ.
@@ -110,6 +178,55 @@ pub struct RequestVoteReply {
 
     /// 投票を行ったかどうか.
     pub voted: bool,
+
+    /// 投票を行わなかった理由.
+    ///
+    /// 診断用の情報で、選挙の結果には影響を与えない.
+    /// 投票を行った場合や、理由が特定されない場合には`None`となる.
+    pub reason: Option<VoteRejectionReason>,
+}
+
+/// `RequestVoteReply`で投票が拒否された理由.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteRejectionReason {
+    /// 投票者の`Term`(`their_term`)の方が、候補者のものよりも新しい.
+    StaleTerm { their_term: Term },
+
+    /// 投票者は、同じ`Term`内で既に`for_node`に投票済み.
+    AlreadyVoted { for_node: NodeId },
+
+    /// 投票者のログの終端(`their_tail`)の方が、候補者のものよりも新しい.
+    LogBehind { their_tail: LogPosition },
+}
+
+/// `PreVoteRPC`の要求メッセージ.
+///
+/// 実際に立候補する(i.e., `Term`を進める)前に、
+/// 過半数から投票を得られる見込みがあるかどうかを問い合わせるために使用される.
+///
+/// ヘッダの`term`には、送信者が立候補した場合の`Term`が設定される.
+/// なお、送信者のローカルな`Term`自体は変化しない.
+#[derive(Debug, Clone)]
+pub struct PreVoteCall {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+
+    /// 送信者のログの終端位置.
+    pub log_tail: LogPosition,
+
+    /// 送信者の選挙における優先度(`RequestVoteCall::priority`を参照).
+    pub priority: u32,
+}
+
+/// `PreVoteRPC`の応答メッセージ.
+#[derive(Debug, Clone)]
+pub struct PreVoteReply {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+
+    /// 立候補された場合に投票を行うかどうか.
+    pub voted: bool,
 }
 
 /// `AppendEntriesRPC`の要求メッセージ.
@@ -119,6 +236,11 @@ pub struct AppendEntriesCall {
     pub header: MessageHeader,
 
     /// コミット済みログの終端インデックス.
+    ///
+    /// エントリを含まないハートビートにも設定されるので、
+    /// フォロワーは新規エントリの受信が無くとも、コミット済みの地点を進めることができる.
+    /// ただしフォロワー側では、`suffix`によってリーダのログとの一致が確認できた範囲に切り詰めた上で反映される.
+    /// また(他のメッセージと同様に)古い`Term`のリーダからのものは無視される.
     pub committed_log_tail: LogIndex,
 
     /// 追記対象となるログの末尾部分.
@@ -139,12 +261,30 @@ pub struct AppendEntriesReply {
     /// それを考慮すると`next_head`といった名前の方が適切かもしれない.
     pub log_tail: LogPosition,
 
+    /// 応答者のログとリーダのログが分岐していた場合に、
+    /// 分岐を検出したエントリの(応答者のログ上での)`Term`.
+    ///
+    /// リーダはこの値と`conflict_index`を使って、
+    /// 一度の往復で、この`Term`に属するエントリ群の全体を飛び越えてログの同期位置を戻すことができる.
+    pub conflict_term: Option<Term>,
+
+    /// 応答者のログにおいて、`conflict_term`に属する最初のエントリのインデックス.
+    ///
+    /// `conflict_term`が`None`の場合には意味を持たない.
+    pub conflict_index: LogIndex,
+
     /// 応答者が忙しいかどうか.
     ///
     /// この値が`true`の場合には、
     /// followerの`log_tail`が遅れていたとしても、
     /// リーダはログの同期のための追加のメッセージ送信を行わない.
     pub busy: bool,
+
+    /// 応答者のノードの起動毎に替わる識別子.
+    ///
+    /// リーダはこの値の変化によって、フォロワーが再起動したことを検知し、
+    /// ログの同期位置を一から探索し直す.
+    pub epoch: u64,
 }
 
 /// `InstallSnapshotRPC`用のメッセージ.
@@ -161,6 +301,85 @@ pub struct InstallSnapshotCast {
     pub prefix: LogPrefix,
 }
 
+/// `InstallSnapshotCast`を分割送信するためのメッセージ.
+///
+/// スナップショットのバイト列を`offset`位置から切り出した断片を保持する.
+/// 受信側は、全ての断片が揃った時点で、それらを連結してスナップショットをインストールする.
+///
+/// 断片の到着順序は任意で、重複して届いても良い.
+/// 欠落した断片は、リーダによるスナップショットの再送時に補完される.
+///
+/// スナップショットが圧縮されている場合には、圧縮後のバイト列が分割され、
+/// 受信側で全ての断片を連結した後に展開される.
+#[derive(Debug, Clone)]
+pub struct InstallSnapshotChunkCast {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+
+    /// スナップショットの終端位置 (`LogPrefix::tail`).
+    pub tail: LogPosition,
+
+    /// スナップショット取得時のクラスタ構成 (`LogPrefix::config`).
+    pub config: ClusterConfig,
+
+    /// スナップショットのバイト列の圧縮方式 (`LogPrefix::compression`).
+    pub compression: Compression,
+
+    /// この断片の、スナップショットのバイト列内での開始位置.
+    pub offset: u64,
+
+    /// 断片のバイト列.
+    pub bytes: Vec<u8>,
+
+    /// 最後の断片かどうか.
+    pub done: bool,
+}
+
+/// リーダシップの移譲先に、即座に選挙を開始させるためのメッセージ.
+///
+/// リーダから、ログが追い付いたフォロワーに対して送信される.
+#[derive(Debug, Clone)]
+pub struct TimeoutNowCast {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+}
+
+/// ノードの停止をリーダに通知するためのメッセージ.
+///
+/// `ReplicatedLog::shutdown`を呼び出したフォロワーから、リーダに対して送信される.
+/// リーダは、送信元から再び応答を受け取るまでの間は、そのノードへのログ同期を行わない.
+#[derive(Debug, Clone)]
+pub struct DepartureCast {
+    /// メッセージヘッダ.
+    pub header: MessageHeader,
+}
+
+/// Raftグループの識別子.
+///
+/// 一つのノード上で複数のRaftグループ(`ReplicatedLog`インスタンス)を動かし、
+/// それらで一つの`Io`実装(i.e., ネットワーク層やストレージ層)を共有する場合に、
+/// メッセージをグループ毎に振り分けるために使用される.
+///
+/// 単一のグループのみを扱う場合には、`GroupId::default()`(i.e., `0`)を使用すれば良い.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId(u64);
+impl GroupId {
+    /// 新しい`GroupId`インスタンスを生成する.
+    pub fn new(id: u64) -> Self {
+        GroupId(id)
+    }
+
+    /// 識別子の値を返す.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+impl From<u64> for GroupId {
+    fn from(f: u64) -> Self {
+        GroupId(f)
+    }
+}
+
 /// メッセージのシーケンス番号.
 ///
 /// この番号はノード毎に管理され、要求系のメッセージ送信の度にインクリメントされる.
@@ -168,6 +387,9 @@ pub struct InstallSnapshotCast {
 ///
 /// シーケンス番号は、一つの`Term`内では単調増加することが保証されている.
 /// 逆に言えば、複数の`Term`を跨いだ場合には、シーケンス番号が増加する保証は無い.
+///
+/// 番号は`u64::MAX`の次に`0`へと巡回する.
+/// `Ord`の実装は単純な値の比較なので、発行順の比較には巡回を考慮した`is_newer_than`を使用すること.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SequenceNumber(u64);
 impl SequenceNumber {
@@ -176,6 +398,23 @@ impl SequenceNumber {
         SequenceNumber(num)
     }
 
+    /// 次に発行されるシーケンス番号を返す.
+    ///
+    /// `u64::MAX`の次は`0`となる.
+    pub fn next(self) -> Self {
+        SequenceNumber(self.0.wrapping_add(1))
+    }
+
+    /// `self`が`other`よりも後に発行されたものかどうかを判定する.
+    ///
+    /// 巡回を考慮した比較(RFC 1982のシリアル番号演算)が行われ、
+    /// `self`が`other`から`2^63`未満だけ先に進んでいる場合に`true`が返される.
+    /// そのため、比較対象の二つの番号の発行間隔が`2^63`未満であることを前提としている.
+    pub fn is_newer_than(self, other: Self) -> bool {
+        let diff = self.0.wrapping_sub(other.0);
+        diff != 0 && diff < 1 << 63
+    }
+
     /// シーケンス番号の値を返す.
     pub fn as_u64(self) -> u64 {
         self.0
@@ -1,7 +1,10 @@
 //! raftlog のメトリクス。
 
 use prometrics::metrics::{Counter, Gauge, Histogram, HistogramBuilder, MetricBuilder};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
+use crate::node::NodeId;
 use crate::{Error, Result};
 
 /// `raftlog` 全体に関するメトリクス。
@@ -27,10 +30,22 @@ pub struct NodeStateMetrics {
     pub(crate) candidate_to_leader_duration_seconds: Histogram,
     pub(crate) candidate_to_follower_duration_seconds: Histogram,
     pub(crate) loader_to_candidate_duration_seconds: Histogram,
+    pub(crate) cluster_state: Gauge,
+    pub(crate) uncommitted_entries: Gauge,
+    pub(crate) unconsumed_entries: Gauge,
+    pub(crate) append_entries_rejected_total: Counter,
+    pub(crate) elections_lost_total: Counter,
+    pub(crate) term_lifetime_seconds: Histogram,
+    follower_builder: MetricBuilder,
+    followers: Arc<Mutex<BTreeMap<NodeId, FollowerMetrics>>>,
 }
 impl NodeStateMetrics {
     pub(crate) fn new(builder: &MetricBuilder) -> Result<Self> {
         let mut builder: MetricBuilder = builder.clone();
+        let cluster_state = track!(builder
+            .gauge("cluster_state")
+            .help("State of the cluster configuration (0=Stable, 1=CatchUp, 2=Joint)")
+            .finish())?;
         builder.subsystem("node_state");
         let transit_to_candidate_total = track!(builder
             .counter("transit_to_candidate_total")
@@ -67,6 +82,28 @@ impl NodeStateMetrics {
                 .histogram("loader_to_candidate_duration_seconds")
                 .help("Elapsed time moving from loader to candidate")
         ))?;
+        let uncommitted_entries = track!(builder
+            .gauge("uncommitted_entries")
+            .help("Number of appended but uncommitted log entries")
+            .finish())?;
+        let unconsumed_entries = track!(builder
+            .gauge("unconsumed_entries")
+            .help("Number of committed but unconsumed log entries")
+            .finish())?;
+        let append_entries_rejected_total = track!(builder
+            .counter("append_entries_rejected_total")
+            .help("Number of rejected AppendEntries (i.e., local log rollbacks)")
+            .finish())?;
+        let elections_lost_total = track!(builder
+            .counter("elections_lost_total")
+            .help("Number of elections the local node lost as a candidate")
+            .finish())?;
+        let term_lifetime_seconds = track!(make_role_change_histogram(
+            builder
+                .histogram("term_lifetime_seconds")
+                .help("Elapsed time each term had a stable leader")
+        ))?;
+        let follower_builder = builder.clone();
         Ok(Self {
             transit_to_candidate_total,
             transit_to_follower_total,
@@ -76,8 +113,76 @@ impl NodeStateMetrics {
             candidate_to_leader_duration_seconds,
             candidate_to_follower_duration_seconds,
             loader_to_candidate_duration_seconds,
+            cluster_state,
+            uncommitted_entries,
+            unconsumed_entries,
+            append_entries_rejected_total,
+            elections_lost_total,
+            term_lifetime_seconds,
+            follower_builder,
+            followers: Arc::default(),
         })
     }
+
+    /// リーダから見た、フォロワー`follower`毎のメトリクスを返す.
+    ///
+    /// メトリクスは初回の呼び出し時に(`follower`ラベル付きで)生成され、以後は使い回される.
+    pub(crate) fn follower(&self, follower: &NodeId) -> Result<FollowerMetrics> {
+        let mut followers = self.followers.lock().expect("Never fails");
+        if let Some(m) = followers.get(follower) {
+            return Ok(m.clone());
+        }
+        let m = track!(FollowerMetrics::new(&self.follower_builder, follower))?;
+        followers.insert(follower.clone(), m.clone());
+        Ok(m)
+    }
+}
+
+/// リーダから見た、フォロワー毎のログ複製に関するメトリクス。
+#[derive(Clone)]
+pub struct FollowerMetrics {
+    pub(crate) appended_entries_total: Counter,
+    pub(crate) appended_bytes_total: Counter,
+    pub(crate) append_latency_seconds: Histogram,
+}
+impl FollowerMetrics {
+    fn new(builder: &MetricBuilder, follower: &NodeId) -> Result<Self> {
+        let mut builder = builder.clone();
+        builder.label("follower", follower.as_str());
+        let appended_entries_total = track!(builder
+            .counter("follower_appended_entries_total")
+            .help("Number of log entries appended to the follower")
+            .finish())?;
+        let appended_bytes_total = track!(builder
+            .counter("follower_appended_bytes_total")
+            .help("Total command bytes of log entries appended to the follower")
+            .finish())?;
+        let append_latency_seconds = track!(make_latency_histogram(
+            builder
+                .histogram("follower_append_latency_seconds")
+                .help("Round-trip time of AppendEntries to the follower")
+        ))?;
+        Ok(Self {
+            appended_entries_total,
+            appended_bytes_total,
+            append_latency_seconds,
+        })
+    }
+}
+
+fn make_latency_histogram(builder: &mut HistogramBuilder) -> Result<Histogram> {
+    builder
+        .bucket(0.0005)
+        .bucket(0.001)
+        .bucket(0.005)
+        .bucket(0.01)
+        .bucket(0.05)
+        .bucket(0.1)
+        .bucket(0.5)
+        .bucket(1.0)
+        .bucket(5.0)
+        .finish()
+        .map_err(|e| track!(Error::from(e)))
 }
 
 fn make_role_change_histogram(builder: &mut HistogramBuilder) -> Result<Histogram> {
@@ -105,3 +210,90 @@ fn make_role_change_histogram(builder: &mut HistogramBuilder) -> Result<Histogra
         .finish()
         .map_err(|e| track!(Error::from(e)))
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::MockCluster;
+
+    #[test]
+    fn cluster_state_gauge_follows_config_changes() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.add_node("d");
+        cluster.elect("a");
+        let gauge = cluster.node("a").metrics().node_state.cluster_state.clone();
+        assert_eq!(gauge.value(), 0.0);
+
+        let members = vec!["a", "b", "c", "d"];
+        let members = members.into_iter().map(Into::into).collect();
+        cluster.node_mut("a").propose_config(members).unwrap();
+        let mut observed = vec![gauge.value()];
+        while cluster.step() {
+            if observed.last() != Some(&gauge.value()) {
+                observed.push(gauge.value());
+            }
+        }
+        assert_eq!(observed, [0.0, 1.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn log_lag_gauges_become_zero_after_convergence() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+        }
+        let uncommitted = cluster
+            .node("a")
+            .metrics()
+            .node_state
+            .uncommitted_entries
+            .clone();
+        cluster.step();
+        assert!(uncommitted.value() > 0.0);
+
+        // コミット済み地点をフォロワーに伝える
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        for id in &["a", "b", "c"] {
+            let metrics = &cluster.node(id).metrics().node_state;
+            assert_eq!(metrics.uncommitted_entries.value(), 0.0);
+            assert_eq!(metrics.unconsumed_entries.value(), 0.0);
+            assert_eq!(metrics.append_entries_rejected_total.value(), 0.0);
+        }
+        let metrics = &cluster.node("a").metrics().node_state;
+        assert_eq!(metrics.transit_to_leader_total.value(), 1.0);
+    }
+
+    #[test]
+    fn follower_metrics_count_appended_entries() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let metrics = cluster
+            .node("a")
+            .metrics()
+            .node_state
+            .follower(&"b".into())
+            .unwrap();
+        let entries = metrics.appended_entries_total.value();
+        let bytes = metrics.appended_bytes_total.value();
+        let tail = cluster.log_suffix("b").tail().index;
+
+        for i in 0..4 {
+            let commands = (0..=i).map(|_| vec![0; 10]).collect();
+            cluster.node_mut("a").propose_commands(commands).unwrap();
+            cluster.run();
+        }
+        let appended = cluster.log_suffix("b").tail().index - tail;
+        assert_eq!(appended, 1 + 2 + 3 + 4);
+        assert_eq!(
+            metrics.appended_entries_total.value() - entries,
+            appended as f64
+        );
+        assert_eq!(
+            metrics.appended_bytes_total.value() - bytes,
+            (appended * 10) as f64
+        );
+        assert!(metrics.append_latency_seconds.count() >= 4);
+    }
+}
@@ -1,15 +1,37 @@
 //! ノード関連の構成要素.
 use crate::election::{Ballot, Role};
+use crate::{ErrorKind, Result};
 
 /// ノードのID.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct NodeId(String);
 impl NodeId {
     /// 新しい`NodeId`インスタンスを生成する.
+    ///
+    /// `id`の内容は検証されない.
+    /// 外部から与えられた文字列を使用する場合には、`NodeId::from_str_checked`を使うこと.
     pub fn new<T: Into<String>>(id: T) -> Self {
         NodeId(id.into())
     }
 
+    /// 内容を検証した上で、新しい`NodeId`インスタンスを生成する.
+    ///
+    /// # Errors
+    ///
+    /// `id`が空文字列の場合や、空白文字ないし制御文字を含む場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn from_str_checked<T: Into<String>>(id: T) -> Result<Self> {
+        let id = id.into();
+        track_assert!(!id.is_empty(), ErrorKind::InvalidInput, "Empty node ID");
+        track_assert!(
+            !id.chars().any(|c| c.is_whitespace() || c.is_control()),
+            ErrorKind::InvalidInput,
+            "Node ID contains whitespace or control characters: {:?}",
+            id
+        );
+        Ok(NodeId(id))
+    }
+
     /// IDに対応する文字列を返す.
     pub fn as_str(&self) -> &str {
         &self.0
@@ -36,6 +58,45 @@ impl From<NodeId> for String {
     }
 }
 
+/// リーダがフォロワー群を走査する順番を決定するための戦略.
+///
+/// 既定では、フォロワー群は`NodeId`の昇順で走査されるが、
+/// テスト等で特定の順番(e.g., 遅れているフォロワーを常に最後に処理する)を再現したい場合に使用する
+/// (`ReplicatedLogOptions::follower_order`を参照).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FollowerOrder {
+    /// `seed`から決定論的に生成される順列.
+    ///
+    /// 同じ`seed`とノード群に対しては、常に同じ順番となる.
+    Shuffled(u64),
+
+    /// 指定された順番.
+    ///
+    /// 含まれないノードは、含まれるノードの後ろに`NodeId`の昇順で並べられる.
+    Fixed(Vec<NodeId>),
+}
+impl FollowerOrder {
+    /// `node`の整列用のキーを返す.
+    ///
+    /// キーの昇順が、走査の順番となる.
+    pub fn sort_key<'a>(&self, node: &'a NodeId) -> (u64, &'a NodeId) {
+        match *self {
+            FollowerOrder::Shuffled(seed) => {
+                // FNV-1aでノードIDを`seed`と混ぜ合わせる
+                let hash = node.as_str().bytes().fold(
+                    (seed ^ 0xcbf2_9ce4_8422_2325).wrapping_mul(0x0100_0000_01b3),
+                    |h, b| (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3),
+                );
+                (hash, node)
+            }
+            FollowerOrder::Fixed(ref order) => {
+                let i = order.iter().position(|n| n == node).unwrap_or(order.len());
+                (i as u64, node)
+            }
+        }
+    }
+}
+
 /// ノードの各種情報を保持するためのデータ構造.
 #[derive(Debug, Clone)]
 pub struct Node {
@@ -74,3 +135,26 @@ impl Node {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_node_id() {
+        let id = NodeId::from_str_checked("node-1").unwrap();
+        assert_eq!(id, NodeId::new("node-1"));
+
+        let e = NodeId::from_str_checked("").err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        let e = NodeId::from_str_checked("node\n1").err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        let e = NodeId::from_str_checked("node 1").err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        // 検証しないコンストラクタは、任意の文字列を受け付ける
+        assert_eq!(NodeId::new("node\n1").as_str(), "node\n1");
+    }
+}
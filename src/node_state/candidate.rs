@@ -18,6 +18,7 @@ use crate::{Io, Result};
 pub struct Candidate<IO: Io> {
     followers: HashSet<NodeId>,
     init: Option<IO::SaveBallot>,
+    leadership_transfer: bool,
 }
 impl<IO: Io> Candidate<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -26,8 +27,20 @@ impl<IO: Io> Candidate<IO> {
         Candidate {
             init: Some(future),
             followers: HashSet::new(),
+            leadership_transfer: false,
         }
     }
+
+    /// リーダシップの移譲を受けて立候補する`Candidate`を生成する.
+    pub fn with_leadership_transfer(common: &mut Common<IO>) -> Self {
+        let mut this = Self::new(common);
+        this.leadership_transfer = true;
+        this
+    }
+    /// 投票状況の保存中かどうかを判定する.
+    pub fn has_pending_io(&self) -> bool {
+        self.init.is_some()
+    }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
         Ok(Some(common.transit_to_candidate()))
     }
@@ -38,9 +51,9 @@ impl<IO: Io> Candidate<IO> {
     ) -> Result<NextState<IO>> {
         if let Message::RequestVoteReply(RequestVoteReply { voted: true, .. }) = message {
             self.followers.insert(message.header().sender.clone());
-            let is_elected = common
+            let is_elected = track!(common
                 .config()
-                .consensus_value(|n| self.followers.contains(n));
+                .consensus_value(|n| self.followers.contains(n)))?;
             if is_elected {
                 return Ok(Some(common.transit_to_leader()));
             }
@@ -50,7 +63,9 @@ impl<IO: Io> Candidate<IO> {
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
         if let Async::Ready(Some(())) = track!(self.init.poll())? {
             self.init = None;
-            common.rpc_caller().broadcast_request_vote();
+            common
+                .rpc_caller()
+                .broadcast_request_vote(self.leadership_transfer);
         }
         Ok(None)
     }
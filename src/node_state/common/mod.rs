@@ -1,21 +1,93 @@
 use futures::{Async, Future, Poll};
-use std::collections::VecDeque;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{self, AtomicU64};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use self::rpc_builder::{RpcCallee, RpcCaller};
 use super::candidate::Candidate;
 use super::follower::Follower;
 use super::leader::Leader;
 use super::{NextState, RoleState};
-use crate::cluster::ClusterConfig;
+use crate::cluster::{ClusterConfig, ClusterMembers, ClusterState};
+use crate::compaction::CompactionPolicy;
 use crate::election::{Ballot, Role, Term};
-use crate::log::{Log, LogHistory, LogIndex, LogPosition, LogPrefix, LogSuffix};
-use crate::message::{Message, MessageHeader, SequenceNumber};
+use crate::log::{
+    Log, LogEntry, LogHistory, LogIndex, LogPosition, LogPrefix, LogSuffix, ProposalId,
+};
+use crate::message::{
+    Message, MessageHeader, PreVoteCall, RequestVoteCall, SequenceNumber, VoteRejectionReason,
+};
 use crate::metrics::NodeStateMetrics;
 use crate::node::{Node, NodeId};
-use crate::{Error, ErrorKind, Event, Io, Result};
+use crate::{Error, ErrorKind, Event, Io, ReplicatedLogOptions, Result, SnapshotProgress};
 
 mod rpc_builder;
 
+/// ストレージへの保存処理が一時的なエラーで失敗した場合に、リトライを行う最大回数.
+pub const MAX_SAVE_RETRIES: usize = 3;
+
+/// ストレージ操作で発生したエラーが、リトライによって回復し得る一時的なものかどうかを判定する.
+///
+/// `ErrorKind::Other`は`Io`実装由来の一時的なエラーとして扱い、
+/// それ以外(e.g., `ErrorKind::InconsistentState`)は致命的なものとして扱う.
+pub fn is_transient_error(e: &Error) -> bool {
+    *e.kind() == ErrorKind::Other
+}
+
+/// ノードの起動毎に替わる識別子を生成する.
+///
+/// 起動時刻と、プロセス内での生成回数を混ぜ合わせることで、
+/// 同一プロセス内で複数のノードが起動された場合でも、値が重複しないようにしている.
+fn generate_epoch() -> u64 {
+    static GENERATED: AtomicU64 = AtomicU64::new(0);
+    let count = GENERATED.fetch_add(1, atomic::Ordering::SeqCst);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    now ^ count.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+// タイムアウト時間の決定に使用する乱数列の初期値を、ノードIDと(指定されていれば)シードから導出する.
+fn initial_timeout_seed(node_id: &NodeId, random_seed: Option<u64>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node_id.hash(&mut hasher);
+    if let Some(seed) = random_seed {
+        seed.hash(&mut hasher);
+    }
+    hasher.finish() | 1
+}
+
+// ローカルノードよりも選挙における優先度が高い、立候補可能なメンバの数を返す.
+fn election_rank(options: &ReplicatedLogOptions, config: &ClusterConfig, local: &NodeId) -> usize {
+    let priority = |id: &NodeId| options.election_priorities.get(id).cloned().unwrap_or(0);
+    let local_priority = priority(local);
+    config
+        .members()
+        .filter(|&id| id != local && config.is_electable(id) && priority(id) > local_priority)
+        .count()
+}
+
+// `suffix`が、ウィットネス用にペイロードを取り除いたエントリを含んでいるかどうかを判定する.
+fn has_stripped_entries(suffix: &LogSuffix) -> bool {
+    suffix
+        .entries
+        .iter()
+        .any(|e| matches!(e, LogEntry::StrippedCommand { .. }))
+}
+
+// xorshiftで、タイムアウト時間の決定に使用する乱数列の次の値を求める.
+fn next_timeout_seed(mut seed: u64) -> u64 {
+    seed ^= seed << 13;
+    seed ^= seed >> 7;
+    seed ^= seed << 17;
+    seed
+}
+
 /// 全ての状態に共通する処理をまとめた構造体.
 pub struct Common<IO: Io> {
     local_node: Node,
@@ -25,9 +97,60 @@ pub struct Common<IO: Io> {
     io: IO,
     unread_message: Option<Message>,
     seq_no: SequenceNumber,
-    load_committed: Option<IO::LoadLog>,
+    load_committed: Option<CheckedLoadLog<IO>>,
     install_snapshot: Option<InstallSnapshot<IO>>,
     metrics: NodeStateMetrics,
+    options: ReplicatedLogOptions,
+
+    // PreVoteを実施中かどうか (i.e., フォロー中のリーダとの疎通が途絶えていると判断しているか).
+    pre_voting: bool,
+
+    // 送信元のリーダ毎の、受信済みの`AppendEntriesCall`の最新の`Term`とシーケンス番号.
+    last_received: BTreeMap<NodeId, (Term, SequenceNumber)>,
+
+    // フォロー中のリーダから、最後にメッセージを受信した時刻.
+    last_leader_contact: Option<Instant>,
+
+    // フォロー中のリーダから最後に通知された、リーダのコミット済みの終端.
+    leader_committed_tail: Option<LogIndex>,
+
+    // コミット済みの`LogEntry::LeaseNoop`の`lease_until`の最大値.
+    read_lease: Option<u64>,
+
+    // ノードの起動毎に替わる識別子.
+    //
+    // `AppendEntriesReply`に含めて送信され、リーダがフォロワーの再起動を検知するために使用される.
+    epoch: u64,
+
+    // タイムアウト時間(`TimeoutPolicy`のジッター)の決定に使用する乱数列の現在値.
+    timeout_seed: u64,
+
+    // 最後に`Event::SnapshotRequested`を発行した時点のログの先頭位置.
+    //
+    // ログの先頭位置が変わる(i.e., スナップショットがインストールされる)までは、再発行しない.
+    snapshot_requested_head: Option<LogIndex>,
+
+    // 起動時にストレージから復元した投票状況の`Term`.
+    //
+    // 現在の`Term`がこれを下回ることはない.
+    loaded_term: Term,
+
+    // ローカルノードがリーダとして提案した、未コミットのコマンドの提案ID群.
+    //
+    // コミットされた場合には`Event::ProposalCommitted`を、
+    // ロールバックによって破棄された場合には`Event::ProposalRejected`を発行するために使用される.
+    proposals: BTreeMap<LogIndex, ProposalId>,
+
+    // ローカルノードが保持している最新のスナップショット(展開済み).
+    //
+    // インストールの完了時とロード時に更新される.
+    latest_snapshot: Option<LogPrefix>,
+
+    // ウィットネスとして受信した、データを持たないエントリないしスナップショットを保持しているかどうか.
+    //
+    // `LogEntry::StrippedCommand`を追記ないしロードした場合や、
+    // ローカルノードをウィットネスとする構成のスナップショットをインストールした場合に`true`となる.
+    holds_stripped_data: bool,
 }
 impl<IO> Common<IO>
 where
@@ -39,10 +162,16 @@ where
         mut io: IO,
         config: ClusterConfig,
         metrics: NodeStateMetrics,
+        options: ReplicatedLogOptions,
     ) -> Self {
         // 最初は（仮に）フォロワーだとしておく
-        let timeout = io.create_timeout(Role::Follower);
-        Common {
+        let timeout_seed = initial_timeout_seed(&node_id, options.random_seed);
+        let rank = election_rank(&options, &config, &node_id);
+        let duration = options
+            .timeout_policy
+            .timeout_with_rank(Role::Follower, timeout_seed, rank);
+        let timeout = io.create_timeout_with(Role::Follower, duration);
+        let common = Common {
             local_node: Node::new(node_id),
             io,
             history: LogHistory::new(config),
@@ -53,7 +182,80 @@ where
             load_committed: None,
             install_snapshot: None,
             metrics,
-        }
+            options,
+            pre_voting: false,
+            last_received: BTreeMap::new(),
+            last_leader_contact: None,
+            leader_committed_tail: None,
+            read_lease: None,
+            epoch: generate_epoch(),
+            timeout_seed,
+            snapshot_requested_head: None,
+            loaded_term: Term::new(0),
+            proposals: BTreeMap::new(),
+            latest_snapshot: None,
+            holds_stripped_data: false,
+        };
+        common.update_log_metrics();
+        common
+    }
+
+    /// `ReplicatedLog`の生成時に指定されたオプションを返す.
+    pub fn options(&self) -> &ReplicatedLogOptions {
+        &self.options
+    }
+
+    /// ローカルノードの選挙における優先度を返す.
+    pub fn election_priority(&self) -> u32 {
+        self.options
+            .election_priorities
+            .get(&self.local_node.id)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// PreVoteを開始して、`PreVoteCall`をブロードキャストする.
+    ///
+    /// 返り値は、送信メッセージのシーケンス番号.
+    pub fn start_pre_vote(&mut self) -> SequenceNumber {
+        self.pre_voting = true;
+        let seq_no = self.next_seq_no();
+        self.rpc_caller().broadcast_pre_vote();
+        seq_no
+    }
+
+    /// 実施中のPreVoteを終了する.
+    pub fn stop_pre_vote(&mut self) {
+        self.pre_voting = false;
+    }
+
+    /// フォロー中のリーダとの疎通が取れているかどうかを判定する.
+    ///
+    /// リーダからのメッセージを受信済みで、かつ、その後に選挙のタイムアウトが発生していない場合に`true`が返される.
+    pub fn is_following_leader(&self) -> bool {
+        self.local_node.role == Role::Follower
+            && self.last_leader_contact.is_some()
+            && !self.pre_voting
+    }
+
+    /// フォロー中のリーダからメッセージを受信したことを記録する.
+    ///
+    /// `leader_committed_tail`は、そのメッセージで通知されたリーダのコミット済みの終端.
+    pub fn record_leader_contact(&mut self, leader_committed_tail: LogIndex) {
+        self.last_leader_contact = Some(Instant::now());
+        self.leader_committed_tail = Some(leader_committed_tail);
+    }
+
+    /// フォロー中のリーダから最後に通知された、リーダのコミット済みの終端を返す.
+    ///
+    /// 役割が遷移した時点で`None`にリセットされる.
+    pub fn leader_committed_tail(&self) -> Option<LogIndex> {
+        self.leader_committed_tail
+    }
+
+    /// コミット済みの`LogEntry::LeaseNoop`によって確立された、読み込みリースの期限を返す.
+    pub fn read_lease(&self) -> Option<u64> {
+        self.read_lease
     }
 
     /// 現在のクラスタの構成情報を返す.
@@ -76,6 +278,11 @@ where
         self.local_node.ballot.term
     }
 
+    /// ノード状態に関するメトリクスを返す.
+    pub fn metrics(&self) -> &NodeStateMetrics {
+        &self.metrics
+    }
+
     /// ローカルノードの情報を返す.
     pub fn local_node(&self) -> &Node {
         &self.local_node
@@ -83,17 +290,88 @@ where
 
     /// ローカルログへの追記イベントを処理する.
     pub fn handle_log_appended(&mut self, suffix: &LogSuffix) -> Result<()> {
-        track!(self.history.record_appended(suffix))
+        track!(self.history.record_appended(suffix))?;
+        self.update_log_metrics();
+        self.holds_stripped_data |= has_stripped_entries(suffix);
+        track!(self.check_stripped_data())
+    }
+
+    /// 起動時に読み込んだ`suffix`に対して、`ReplicatedLogOptions::restored_history`を用いた履歴の復元を試みる.
+    ///
+    /// 復元できなかった場合には`false`を返すので、呼び出し側で`handle_log_appended`を使うこと.
+    /// 保存された要約が使われるのは最初の一回のみ.
+    pub fn handle_log_restored(&mut self, suffix: &LogSuffix) -> Result<bool> {
+        let restored = self
+            .options
+            .restored_history
+            .take()
+            .is_some_and(|summary| self.history.restore_summary(&summary, suffix));
+        if restored {
+            self.update_log_metrics();
+            self.holds_stripped_data |= has_stripped_entries(suffix);
+            track!(self.check_stripped_data())?;
+        }
+        Ok(restored)
+    }
+
+    /// ウィットネスとして受信したデータを持たないエントリ(ないしスナップショット)を保持するノードが、
+    /// データを保持するメンバとして扱われていないかを検証する.
+    ///
+    /// ウィットネスがクラスタから削除された後に、通常のメンバとして再追加された場合には、
+    /// ローカルログのエントリの位置と`Term`はリーダのものと一致するため、ペイロードが再送されることはない.
+    /// そのまま投票やコミットに参加すると、コマンドの内容が失われてしまうので、
+    /// ストレージを初期化するまでは`ErrorKind::InconsistentState`を返して、参加を拒否する.
+    fn check_stripped_data(&self) -> Result<()> {
+        let local = &self.local_node.id;
+        let config = self.history.config();
+        track_assert!(
+            !self.holds_stripped_data || config.is_witness(local) || !config.is_known_node(local),
+            ErrorKind::InconsistentState,
+            "A former witness must wipe its storage before rejoining as a data node: node={:?}",
+            local
+        );
+        Ok(())
     }
 
     /// ログのコミットイベントを処理する.
+    ///
+    /// 新たにコミットされた領域に、ローカルノードが提案したコマンドが含まれていた場合には、
+    /// その提案毎に`Event::ProposalCommitted`を発行する.
     pub fn handle_log_committed(&mut self, new_tail: LogIndex) -> Result<()> {
-        track!(self.history.record_committed(new_tail))
+        track!(self.history.record_committed(new_tail))?;
+        let uncommitted = self.proposals.split_off(&new_tail);
+        let committed = mem::replace(&mut self.proposals, uncommitted);
+        self.events.extend(
+            committed
+                .into_values()
+                .map(|id| Event::ProposalCommitted { id }),
+        );
+        self.update_log_metrics();
+        Ok(())
     }
 
     /// ローカルログのロールバックイベントを処理する.
+    ///
+    /// 破棄された領域に、ローカルノードが提案したコマンドが含まれていた場合には、
+    /// その提案毎に`Event::ProposalRejected`を発行する.
     pub fn handle_log_rollbacked(&mut self, new_tail: LogPosition) -> Result<()> {
-        track!(self.history.record_rollback(new_tail))
+        track!(self.history.record_rollback(new_tail))?;
+        let rejected = self.proposals.split_off(&new_tail.index);
+        self.events.extend(
+            rejected
+                .into_values()
+                .map(|id| Event::ProposalRejected { id }),
+        );
+        self.update_log_metrics();
+        Ok(())
+    }
+
+    /// ローカルノードがリーダとして提案したコマンドの提案IDを記録する.
+    ///
+    /// 記録された提案は、コミットされた場合には`Event::ProposalCommitted`として、
+    /// コミットされるまでの間にロールバックによって破棄された場合には`Event::ProposalRejected`として通知される.
+    pub fn track_proposal(&mut self, id: ProposalId) {
+        self.proposals.insert(id.index, id);
     }
 
     /// ログのスナップショットインストール完了イベントを処理する.
@@ -102,11 +380,19 @@ where
         new_head: LogPosition,
         config: ClusterConfig,
     ) -> Result<()> {
-        track!(self.history.record_snapshot_installed(new_head, config))
+        if config.is_witness(&self.local_node.id) {
+            // ウィットネス宛てのスナップショットは、内容を持たない
+            self.holds_stripped_data = true;
+        }
+        track!(self.history.record_snapshot_installed(new_head, config))?;
+        self.proposals = self.proposals.split_off(&new_head.index);
+        self.update_log_metrics();
+        track!(self.check_stripped_data())
     }
 
     /// ログのスナップショットロードイベントを処理する.
     pub fn handle_log_snapshot_loaded(&mut self, prefix: LogPrefix) -> Result<()> {
+        let prefix = track!(prefix.decompress())?;
         if self.history.committed_tail().index < prefix.tail.index {
             // タイミング次第では、進行中のスナップショットインストールを追い越して、
             // ロードが発生してしまうことがあるので、その場合でも`LogHistory`の整合性が崩れないように、
@@ -126,6 +412,8 @@ where
                 .record_snapshot_installed(prefix.tail, prefix.config.clone(),))?;
         }
         track!(self.history.record_snapshot_loaded(&prefix))?;
+        self.update_log_metrics();
+        self.remember_snapshot(prefix.clone());
         let event = Event::SnapshotLoaded {
             new_head: prefix.tail,
             snapshot: prefix.snapshot,
@@ -135,12 +423,58 @@ where
         Ok(())
     }
 
+    /// ローカルノードが保持している最新のスナップショットを返す.
+    ///
+    /// インストールないしロードが完了したものの内で、最も新しいものが返される.
+    /// 返り値の`tail`は、ローカルログの先頭(`LogHistory::head`)と一致する.
+    pub fn latest_snapshot(&self) -> Option<&LogPrefix> {
+        self.latest_snapshot.as_ref()
+    }
+
+    fn remember_snapshot(&mut self, prefix: LogPrefix) {
+        let is_newer = self
+            .latest_snapshot
+            .as_ref()
+            .is_none_or(|s| s.tail.index <= prefix.tail.index);
+        if is_newer {
+            self.latest_snapshot = Some(prefix);
+        }
+    }
+
+    /// ストレージから読み込んだ投票状況を復元する.
+    pub fn restore_ballot(&mut self, ballot: Ballot) {
+        self.loaded_term = ballot.term;
+        self.set_ballot(ballot);
+    }
+
+    /// ローカルノードの状態が、安全性に関する不変項を満たしているかどうかを検査する.
+    ///
+    /// `LogHistory::check_invariants`による検査に加えて、
+    /// 現在の`Term`が起動時に復元したものから後退していないことを確認する.
+    pub fn check_invariants(&self) -> Result<()> {
+        track!(self.history.check_invariants())?;
+        track_assert!(
+            self.loaded_term <= self.term(),
+            ErrorKind::InconsistentState,
+            "invariant `term never regresses from the loaded ballot` is violated: loaded={:?}, current={:?}",
+            self.loaded_term,
+            self.term()
+        );
+        Ok(())
+    }
+
     /// ローカルノードの投票状況を更新する.
     pub fn set_ballot(&mut self, new_ballot: Ballot) {
         if self.local_node.ballot != new_ballot {
+            let old = self.local_node.ballot.term;
+            let new = new_ballot.term;
             self.local_node.ballot = new_ballot.clone();
             self.metrics.event_queue_len.increment();
             self.events.push_back(Event::TermChanged { new_ballot });
+            if old != new {
+                self.metrics.event_queue_len.increment();
+                self.events.push_back(Event::TermAdvanced { old, new });
+            }
         }
     }
 
@@ -152,6 +486,39 @@ where
         self.install_snapshot.is_some()
     }
 
+    /// 所有している`Io`を返す.
+    pub fn into_io(self) -> IO {
+        self.io
+    }
+
+    /// ローカルログへのスナップショットの保存中の場合には、その進捗を返す.
+    pub fn snapshot_install_progress(&self) -> Option<SnapshotProgress> {
+        self.install_snapshot.as_ref().map(|s| SnapshotProgress {
+            tail: s.summary.tail,
+            received_bytes: s.bytes,
+            total_bytes: Some(s.bytes),
+            saving: true,
+        })
+    }
+
+    /// スナップショットのインストールが停滞していることを通知する.
+    pub fn notify_snapshot_install_stalled(&mut self, tail: LogPosition) {
+        self.events
+            .push_back(Event::SnapshotInstallStalled { tail });
+    }
+
+    /// `Event::ConfigChangeAborted`を発行する.
+    pub fn notify_config_change_aborted(
+        &mut self,
+        new_members: ClusterMembers,
+        lagging: ClusterMembers,
+    ) {
+        self.events.push_back(Event::ConfigChangeAborted {
+            new_members,
+            lagging,
+        });
+    }
+
     /// Returns `true` if and only if a node is installing snapshot and should not do
     /// anything else until the running snapshot installation completes.
     /// This method should be used to determine the next state of a node.
@@ -180,16 +547,16 @@ where
 
     /// `Candidate`状態に遷移する.
     pub fn transit_to_candidate(&mut self) -> RoleState<IO> {
-        self.metrics.transit_to_candidate_total.increment();
-        let new_ballot = Ballot {
-            term: (self.local_node.ballot.term.as_u64() + 1).into(),
-            voted_for: self.local_node.id.clone(),
-        };
-        self.set_ballot(new_ballot);
-        self.set_role(Role::Candidate);
+        self.start_candidacy();
         RoleState::Candidate(Candidate::new(self))
     }
 
+    /// リーダシップの移譲を受けて`Candidate`状態に遷移する.
+    pub fn transit_to_candidate_by_transfer(&mut self) -> RoleState<IO> {
+        self.start_candidacy();
+        RoleState::Candidate(Candidate::with_leadership_transfer(self))
+    }
+
     /// `Follower`状態に遷移する.
     pub fn transit_to_follower(
         &mut self,
@@ -234,8 +601,35 @@ where
     }
 
     /// 指定範囲のローカルログをロードする.
-    pub fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> IO::LoadLog {
-        self.io.load_log(start, end)
+    ///
+    /// 読み込まれたログが要求範囲と整合しない場合には`ErrorKind::InconsistentState`で失敗する.
+    pub fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> CheckedLoadLog<IO> {
+        let future = self.io.load_log(start, end);
+        CheckedLoadLog::new(future, start, end)
+    }
+
+    /// `start`から始まるローカルログを、最大で`max_entries`個分ロードする.
+    ///
+    /// 読み込まれたログが要求範囲と整合しない場合には`ErrorKind::InconsistentState`で失敗する.
+    pub fn load_log_limited(&mut self, start: LogIndex, max_entries: usize) -> CheckedLoadLog<IO> {
+        let future = self.io.load_log_limited(start, max_entries);
+        CheckedLoadLog::new(future, start, Some(start + max_entries))
+    }
+
+    /// `start`から始まるローカルログを、最大で`max_entries`個分、
+    /// `Command`ペイロードの合計サイズが`max_bytes`を超えない範囲でロードする.
+    ///
+    /// 読み込まれたログが要求範囲と整合しない場合には`ErrorKind::InconsistentState`で失敗する.
+    pub fn load_log_limited_bytes(
+        &mut self,
+        start: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> CheckedLoadLog<IO> {
+        let future = self
+            .io
+            .load_log_limited_bytes(start, max_entries, max_bytes);
+        CheckedLoadLog::new(future, start, Some(start + max_entries))
     }
 
     /// ローカルログの末尾部分に`suffix`を追記する.
@@ -254,8 +648,17 @@ where
     }
 
     /// 指定されたロール用のタイムアウトを設定する.
+    ///
+    /// タイムアウト時間は`ReplicatedLogOptions::timeout_policy`と`election_priorities`に従って決定される.
     pub fn set_timeout(&mut self, role: Role) {
-        self.timeout = self.io.create_timeout(role);
+        self.timeout_seed = next_timeout_seed(self.timeout_seed);
+        let rank = election_rank(&self.options, self.config(), &self.local_node.id);
+        let duration = self
+            .options
+            .timeout_policy
+            .timeout_with_rank(role, self.timeout_seed, rank);
+        self.io.cancel_timeout();
+        self.timeout = self.io.create_timeout_with(role, duration);
     }
 
     /// タイムアウトに達していないかを確認する.
@@ -274,7 +677,7 @@ where
         if let Some(message) = self.unread_message.take() {
             Ok(Some(message))
         } else {
-            track!(self.io.try_recv_message())
+            track!(self.io.try_recv_group_message(self.options.group_id))
         }
     }
 
@@ -286,13 +689,26 @@ where
         );
         track_assert!(self.install_snapshot.is_none(), ErrorKind::Busy);
 
-        let future = InstallSnapshot::new(self, snapshot);
+        let snapshot = track!(snapshot.decompress())?.with_checksum();
+        let compressed = track!(snapshot.clone().compress(self.options.snapshot_compression))?;
+        let future = InstallSnapshot::new(self, compressed, snapshot);
         self.install_snapshot = Some(future);
         Ok(())
     }
 
     /// 受信メッセージに対する共通的な処理を実行する.
     pub fn handle_message(&mut self, message: Message) -> HandleMessageResult<IO> {
+        if message.header().group_id != self.options.group_id {
+            // 別のRaftグループ宛のメッセージ (`Io`による振り分けの誤り)は破棄する
+            return HandleMessageResult::Handled(None);
+        }
+        if self.is_replayed(&message) {
+            // 同じ`Term`内で、既に受信したものよりも古い`AppendEntriesCall`
+            // (i.e., 重複ないし再送されたメッセージ)は破棄する
+            return HandleMessageResult::Handled(None);
+        }
+        self.notify_vote_rejected(&message);
+
         if self.local_node.role == Role::Leader
             && !self.config().is_known_node(&message.header().sender)
         {
@@ -302,10 +718,28 @@ where
             //  停止時には知らなかった新構成を把握するために、
             //  不明なノードからもメッセージも受信する必要がある.
             HandleMessageResult::Handled(None)
+        } else if let Message::PreVoteCall(ref m) = message {
+            // PreVoteはローカルノードの`Term`には影響を与えないので、`Term`の比較よりも先に処理する
+            let voted = self.can_grant_pre_vote(m);
+            self.rpc_callee(&m.header).reply_pre_vote(voted);
+            if voted {
+                self.defer_to_preferred_candidate(m.priority);
+            }
+            HandleMessageResult::Handled(None)
+        } else if let Message::PreVoteReply(_) = message {
+            HandleMessageResult::Unhandled(message)
         } else if message.header().term > self.local_node.ballot.term {
             // b) 相手のtermの方が大きい => 新しい選挙が始まっているので追従する
             let is_follower = self.local_node.ballot.voted_for != self.local_node.id;
-            if is_follower && self.local_node.ballot.voted_for != message.header().sender {
+            let is_transfer = match message {
+                Message::RequestVoteCall(ref m) => m.leadership_transfer,
+                _ => false,
+            };
+            if is_follower
+                && !self.pre_voting
+                && !is_transfer
+                && self.local_node.ballot.voted_for != message.header().sender
+            {
                 // リーダをフォロー中(i.e., 定期的にハートビートを受信できている)の場合には、
                 // そのリーダを信じて、現在の選挙を維持する.
                 //
@@ -323,6 +757,12 @@ where
                     self.transit_to_follower(candidate, Some(m.header))
                 } else {
                     // ローカルログの方が新しいので、自分で立候補する
+                    //
+                    // NOTE: 候補者が拒否の理由を把握できるように、立候補前に(新しい`Term`で)返信しておく
+                    let reason = VoteRejectionReason::LogBehind {
+                        their_tail: self.history.tail(),
+                    };
+                    self.rpc_callee(&m.header).reject_request_vote(reason);
                     self.transit_to_candidate()
                 }
             } else if let Message::AppendEntriesCall { .. } = message {
@@ -341,14 +781,28 @@ where
             // c) 自分のtermの方が大きい => 選挙期間が古くなっていることを送信元の通知
 
             // NOTE: 返信メッセージの中身は重要ではないので、一番害の無さそうなものを送っておく
-            self.rpc_callee(message.header()).reply_request_vote(false);
+            let reason = VoteRejectionReason::StaleTerm {
+                their_term: self.local_node.ballot.term,
+            };
+            self.rpc_callee(message.header())
+                .reject_request_vote(reason);
             HandleMessageResult::Handled(None)
         } else {
             // d) 同じ選挙期間に属するノードからのメッセージ
             match message {
-                Message::RequestVoteCall { .. } if !self.is_following_sender(&message) => {
+                Message::RequestVoteCall(ref m) if !self.is_following_sender(&message) => {
                     // 別の人をフォロー中に投票依頼が来た場合ので拒否
-                    self.rpc_callee(message.header()).reply_request_vote(false);
+                    let reason = VoteRejectionReason::AlreadyVoted {
+                        for_node: self.local_node.ballot.voted_for.clone(),
+                    };
+                    self.rpc_callee(&m.header).reject_request_vote(reason);
+                    if self.should_yield_to_candidate(m) {
+                        // 競合する候補者に立候補を譲る (`ReplicatedLogOptions::election_tie_break`)
+                        let local = self.local_node.id.clone();
+                        let next = self.transit_to_follower(local, None);
+                        return HandleMessageResult::Handled(Some(next));
+                    }
+                    self.defer_to_preferred_candidate(m.priority);
                     HandleMessageResult::Handled(None)
                 }
                 Message::AppendEntriesCall { .. } if !self.is_following_sender(&message) => {
@@ -372,9 +826,11 @@ where
                     tail: new_head,
                     config,
                 } = summary;
-                self.install_snapshot = None;
+                let installed = self.install_snapshot.take().expect("Never fails").installed;
                 self.events.push_back(Event::SnapshotInstalled { new_head });
                 track!(self.history.record_snapshot_installed(new_head, config))?;
+                self.update_log_metrics();
+                self.remember_snapshot(installed);
             }
 
             // コミット済みログの処理.
@@ -402,32 +858,151 @@ where
     }
 
     /// RPCの要求用のインスタンスを返す.
-    pub fn rpc_caller(&mut self) -> RpcCaller<IO> {
+    pub fn rpc_caller(&mut self) -> RpcCaller<'_, IO> {
         RpcCaller::new(self)
     }
 
     /// RPCの応答用のインスタンスを返す.
-    pub fn rpc_callee<'a>(&'a mut self, caller: &'a MessageHeader) -> RpcCallee<IO> {
+    pub fn rpc_callee<'a>(&'a mut self, caller: &'a MessageHeader) -> RpcCallee<'a, IO> {
         RpcCallee::new(self, caller)
     }
 
     fn handle_committed(&mut self, suffix: LogSuffix) -> Result<()> {
         let new_tail = suffix.tail();
+        let range = (suffix.head.index, new_tail.index);
         for (index, entry) in (suffix.head.index.as_u64()..)
             .map(LogIndex::new)
-            .zip(suffix.entries.into_iter())
+            .zip(suffix.entries)
         {
+            if let LogEntry::LeaseNoop { lease_until, .. } = entry {
+                self.read_lease = cmp::max(self.read_lease, Some(lease_until));
+            }
             let event = Event::Committed { index, entry };
             self.events.push_back(event);
         }
+        if range.0 < range.1 {
+            self.events.push_back(Event::CommittedRange { range });
+        }
         if new_tail.index >= self.log().head().index {
             // 「ローカルログの終端よりも先の地点のスナップショット」をインストールした後、
             // そのスナップショットのロードが行われるまでの間には、上の条件が`false`になる可能性がある.
+            let old_tail = self.history.consumed_tail().index;
             track!(self.history.record_consumed(new_tail.index))?;
+            if old_tail < new_tail.index {
+                let range = (old_tail, new_tail.index);
+                self.events.push_back(Event::Consumed { range });
+            }
+            self.update_log_metrics();
+            self.maybe_request_snapshot();
         }
         Ok(())
     }
+    fn maybe_request_snapshot(&mut self) {
+        let head = self.history.head().index;
+        if self.snapshot_requested_head == Some(head) {
+            return;
+        }
+        if self.options.snapshot_policy.should_compact(&self.history) {
+            let up_to = self.history.consumed_tail().index;
+            self.snapshot_requested_head = Some(head);
+            self.events.push_back(Event::SnapshotRequested { up_to });
+        }
+    }
+    fn start_candidacy(&mut self) {
+        self.metrics.transit_to_candidate_total.increment();
+        let new_ballot = Ballot {
+            term: (self.local_node.ballot.term.as_u64() + 1).into(),
+            voted_for: self.local_node.id.clone(),
+        };
+        self.set_ballot(new_ballot);
+        self.set_role(Role::Candidate);
+    }
+    // 重複ないし再送された`AppendEntriesCall`かどうかを判定する.
+    //
+    // シーケンス番号は永続化されず、再起動の度に先頭から振り直される.
+    // そのため、同じ`Term`内で送信元が再起動し得るメッセージ(e.g., `Term`を進めずに`Term + 1`で送信される`PreVoteCall`の後に、
+    // 再起動して同じ`Term`で送信される`RequestVoteCall`)を対象にすると、正当なメッセージまで破棄してしまう.
+    // `AppendEntriesCall`の送信元は、その`Term`のリーダのみであり、リーダが再起動した場合には必ず新しい`Term`で立候補し直すので、
+    // 同じ`Term`内でシーケンス番号が巻き戻ることはない.
+    fn is_replayed(&mut self, message: &Message) -> bool {
+        if !matches!(message, Message::AppendEntriesCall(_)) {
+            return false;
+        }
+        let header = message.header();
+        let last = self
+            .last_received
+            .entry(header.sender.clone())
+            .or_insert((header.term, header.seq_no));
+        if header.term < last.0 {
+            // 古い`Term`のメッセージの処理は、通常のメッセージ処理に任せる
+            return false;
+        }
+        if header.term == last.0 && last.1.is_newer_than(header.seq_no) {
+            return true;
+        }
+        *last = (header.term, header.seq_no);
+        false
+    }
+    // 立候補中に、投票を拒否する応答を受信した場合には`Event::VoteRejected`を発行する.
+    //
+    // 診断目的のみのもので、選挙の結果には影響を与えない.
+    fn notify_vote_rejected(&mut self, message: &Message) {
+        if self.local_node.role != Role::Candidate {
+            return;
+        }
+        if let Message::RequestVoteReply(m) = message {
+            if m.header.term < self.local_node.ballot.term {
+                // 以前の立候補に対する応答
+                return;
+            }
+            if let Some(ref reason) = m.reason {
+                let event = Event::VoteRejected {
+                    voter: m.header.sender.clone(),
+                    reason: reason.clone(),
+                };
+                self.metrics.event_queue_len.increment();
+                self.events.push_back(event);
+            }
+        }
+    }
+    // 自分よりも優先度の高い候補者が存在する場合には、タイムアウトをリセットして、自身の立候補を遅らせる.
+    fn defer_to_preferred_candidate(&mut self, candidate_priority: u32) {
+        let role = self.local_node.role;
+        if role != Role::Leader && candidate_priority > self.election_priority() {
+            self.set_timeout(role);
+        }
+    }
+    // 同じ`Term`で競合する候補者に、自身の立候補を譲るべきかどうかを判定する.
+    //
+    // 両者のログが同じ位置で終わっている場合にのみ、`(優先度, NodeId)`の小さい方が譲る.
+    fn should_yield_to_candidate(&self, m: &RequestVoteCall) -> bool {
+        if !self.options.election_tie_break || self.local_node.role != Role::Candidate {
+            return false;
+        }
+        if m.log_tail != self.history.tail() {
+            return false;
+        }
+        let local = (self.election_priority(), &self.local_node.id);
+        local < (m.priority, &m.header.sender)
+    }
+    fn can_grant_pre_vote(&self, m: &PreVoteCall) -> bool {
+        let has_leader = match self.local_node.role {
+            Role::Leader => true,
+            Role::Candidate => false,
+            Role::Follower => {
+                !self.pre_voting && self.local_node.ballot.voted_for != self.local_node.id
+            }
+        };
+        !has_leader
+            && m.header.term > self.local_node.ballot.term
+            && m.log_tail.is_newer_or_equal_than(self.history.tail())
+    }
     fn set_role(&mut self, new_role: Role) {
+        // 役割が遷移した時点で、実施中のPreVoteは終了となる
+        self.pre_voting = false;
+        self.last_leader_contact = None;
+        self.leader_committed_tail = None;
+
         if self.local_node.role != new_role {
             self.local_node.role = new_role;
             self.events.push_back(Event::RoleChanged { new_role });
@@ -436,8 +1011,27 @@ where
     fn is_following_sender(&self, message: &Message) -> bool {
         self.local_node.ballot.voted_for == message.header().sender
     }
+    fn update_log_metrics(&self) {
+        let value = match self.history.config().state() {
+            ClusterState::Stable => 0.0,
+            ClusterState::CatchUp => 1.0,
+            ClusterState::Joint => 2.0,
+        };
+        self.metrics.cluster_state.set(value);
+
+        let appended = self.history.tail().index;
+        let committed = self.history.committed_tail().index;
+        let consumed = self.history.consumed_tail().index;
+        self.metrics
+            .uncommitted_entries
+            .set((appended - committed) as f64);
+        self.metrics
+            .unconsumed_entries
+            .set((committed - consumed) as f64);
+    }
 }
 
+#[allow(clippy::large_enum_variant)] // 一時的な戻り値に過ぎないので、サイズは問題とならない
 pub enum HandleMessageResult<IO: Io> {
     Handled(Option<RoleState<IO>>),
     Unhandled(Message),
@@ -452,15 +1046,25 @@ struct SnapshotSummary {
 struct InstallSnapshot<IO: Io> {
     future: IO::SaveLog,
     summary: SnapshotSummary,
+    bytes: u64,
+
+    // 保存完了後に`Common::latest_snapshot`に反映される、展開済みのスナップショット.
+    installed: LogPrefix,
 }
 impl<IO: Io> InstallSnapshot<IO> {
-    pub fn new(common: &mut Common<IO>, prefix: LogPrefix) -> Self {
+    pub fn new(common: &mut Common<IO>, prefix: LogPrefix, installed: LogPrefix) -> Self {
         let summary = SnapshotSummary {
             tail: prefix.tail,
             config: prefix.config.clone(),
         };
+        let bytes = prefix.snapshot.len() as u64;
         let future = common.io.save_log_prefix(prefix);
-        InstallSnapshot { future, summary }
+        InstallSnapshot {
+            future,
+            summary,
+            bytes,
+            installed,
+        }
     }
 }
 impl<IO: Io> Future for InstallSnapshot<IO> {
@@ -471,6 +1075,57 @@ impl<IO: Io> Future for InstallSnapshot<IO> {
     }
 }
 
+/// `Io::load_log`の結果が、要求された範囲と整合しているかを検証する`Future`.
+///
+/// 不正な範囲のログが返された場合に、後続の処理で分かり難いパニックが発生することを防ぐために使用される.
+pub struct CheckedLoadLog<IO: Io> {
+    future: IO::LoadLog,
+    start: LogIndex,
+    end: Option<LogIndex>,
+}
+impl<IO: Io> CheckedLoadLog<IO> {
+    fn new(future: IO::LoadLog, start: LogIndex, end: Option<LogIndex>) -> Self {
+        CheckedLoadLog { future, start, end }
+    }
+}
+impl<IO: Io> Future for CheckedLoadLog<IO> {
+    type Item = Log;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let log = match track!(self.future.poll())? {
+            Async::NotReady => return Ok(Async::NotReady),
+            Async::Ready(log) => log,
+        };
+        match log {
+            Log::Prefix(ref prefix) => {
+                // スナップショットは、要求範囲の先頭を含んでいなければならない
+                track_assert!(
+                    self.start <= prefix.tail.index,
+                    ErrorKind::InconsistentState,
+                    "Loaded snapshot does not cover the requested range: requested=[{:?}, {:?}), loaded=[_, {:?})",
+                    self.start,
+                    self.end,
+                    prefix.tail.index
+                );
+            }
+            Log::Suffix(ref suffix) => {
+                // 要求範囲よりも短いログは許容されるが、先頭位置のずれや範囲外のエントリは許容されない
+                let tail = suffix.tail().index;
+                track_assert!(
+                    suffix.head.index == self.start && self.end.is_none_or(|end| tail <= end),
+                    ErrorKind::InconsistentState,
+                    "Loaded log does not match the requested range: requested=[{:?}, {:?}), loaded=[{:?}, {:?})",
+                    self.start,
+                    self.end,
+                    suffix.head.index,
+                    tail
+                );
+            }
+        }
+        Ok(Async::Ready(log))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -491,12 +1146,8 @@ mod tests {
             .add_member("node3".into())
             .finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
-        let prefix = LogPrefix {
-            tail: LogPosition::default(),
-            config: cluster,
-            snapshot: Vec::default(),
-        };
+        let mut common = Common::new(node_id, io, cluster.clone(), metrics, Default::default());
+        let prefix = LogPrefix::new(LogPosition::default(), cluster, Vec::default());
 
         assert!(!common.is_snapshot_installing());
         common.install_snapshot(prefix)?;
@@ -505,6 +1156,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn term_regression_from_loaded_ballot_is_detected() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id.clone(), io, cluster, metrics, Default::default());
+
+        common.restore_ballot(Ballot {
+            term: Term::new(5),
+            voted_for: node_id.clone(),
+        });
+        track!(common.check_invariants())?;
+
+        common.set_ballot(Ballot {
+            term: Term::new(3),
+            voted_for: node_id,
+        });
+        let e = common.check_invariants().unwrap_err();
+        assert!(e.to_string().contains("term never regresses"));
+        Ok(())
+    }
+
     #[test]
     fn is_focusing_on_installing_snapshot_works() -> TestResult {
         let node_id: NodeId = "node1".into();
@@ -515,16 +1189,16 @@ mod tests {
             .add_member("node3".into())
             .finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), metrics, Default::default());
         let prev_term = Term::new(0);
-        let node_prefix = LogPrefix {
-            tail: LogPosition {
+        let node_prefix = LogPrefix::new(
+            LogPosition {
                 prev_term,
                 index: LogIndex::new(3),
             },
-            config: cluster.clone(),
-            snapshot: vec![0],
-        };
+            cluster.clone(),
+            vec![0],
+        );
         let log_suffix = LogSuffix {
             head: LogPosition {
                 prev_term,
@@ -533,27 +1207,27 @@ mod tests {
             entries: vec![
                 LogEntry::Command {
                     term: prev_term,
-                    command: Vec::default(),
+                    command: Vec::new().into(),
                 },
                 LogEntry::Command {
                     term: prev_term,
-                    command: Vec::default(),
+                    command: Vec::new().into(),
                 },
                 LogEntry::Command {
                     term: prev_term,
-                    command: Vec::default(),
+                    command: Vec::new().into(),
                 },
             ],
         };
         // The prefix of a leader is a bit ahead.
-        let leader_prefix = LogPrefix {
-            tail: LogPosition {
+        let leader_prefix = LogPrefix::new(
+            LogPosition {
                 prev_term,
                 index: LogIndex::new(5),
             },
-            config: cluster,
-            snapshot: vec![1],
-        };
+            cluster,
+            vec![1],
+        );
 
         assert!(!common.is_focusing_on_installing_snapshot());
         // Applies a prefix before tests.
@@ -576,4 +1250,208 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn consumed_range_is_reported_once_per_batch() {
+        use crate::testing::MockCluster;
+
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        let start = cluster.node("b").local_history().consumed_tail().index;
+        let commands = (0..500u32).map(|i| i.to_be_bytes().to_vec()).collect();
+        cluster.node_mut("a").propose_commands(commands).unwrap();
+        cluster.run();
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+
+        let history = cluster.node("b").local_history();
+        assert_eq!(history.consumed_tail().index, start + 500);
+        assert_eq!(history.consumed_tail(), history.committed_tail());
+        let ranges = cluster
+            .events("b")
+            .iter()
+            .filter_map(|e| match e {
+                Event::Consumed { range } if range.0 >= start => Some(*range),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(start, start + 500)]);
+    }
+
+    #[test]
+    fn committed_ranges_are_contiguous() {
+        use crate::testing::MockCluster;
+
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let term = cluster.node("a").local_node().ballot.term;
+        cluster
+            .node_mut("a")
+            .propose_commands(vec![vec![1], vec![2]])
+            .unwrap();
+        cluster.run();
+        cluster
+            .node_mut("a")
+            .propose_commands(vec![vec![3], vec![4], vec![5]])
+            .unwrap();
+        cluster.run();
+
+        for id in &["a", "b"] {
+            let mut committed = Vec::new();
+            let mut ranges = Vec::new();
+            for e in cluster.events(id) {
+                match e {
+                    Event::Committed { index, .. } => committed.push(*index),
+                    Event::CommittedRange { range } => ranges.push(*range),
+                    Event::TermAdvanced { old, new } => assert!(old < new),
+                    _ => {}
+                }
+            }
+            assert!(ranges.len() >= 2);
+            assert_eq!(ranges[0].0, committed[0]);
+            assert_eq!(ranges.last().unwrap().1, *committed.last().unwrap() + 1);
+            for w in ranges.windows(2) {
+                assert!(w[0].0 < w[0].1);
+                assert_eq!(w[0].1, w[1].0);
+            }
+        }
+        let last_advanced = cluster.events("a").iter().rev().find_map(|e| match e {
+            Event::TermAdvanced { new, .. } => Some(*new),
+            _ => None,
+        });
+        assert_eq!(last_advanced, Some(term));
+    }
+
+    #[test]
+    fn replayed_message_is_dropped() {
+        use crate::testing::MockCluster;
+
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let old = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .rev()
+            .find(|m| match m {
+                Message::AppendEntriesCall(m) => {
+                    m.header.destination.as_str() == "b" && !m.suffix.entries.is_empty()
+                }
+                _ => false,
+            })
+            .cloned()
+            .unwrap();
+
+        cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        cluster.run();
+        let tail = cluster.log_suffix("b").tail();
+
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.network().lock().unwrap().replay(old);
+        cluster.run();
+        assert!(cluster.network().lock().unwrap().sent_messages().is_empty());
+        assert_eq!(cluster.log_suffix("b").tail(), tail);
+    }
+
+    #[test]
+    fn mismatched_load_log_range_is_reported() -> TestResult {
+        let node_id: NodeId = "node1".into();
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
+        let mut handle = io.handle();
+        let cluster = io.cluster.clone();
+        let mut common = Common::new(node_id, io, cluster, metrics, Default::default());
+
+        // `[3, 5)`を要求したのに、`[2, 4)`が返される
+        let term = Term::new(1);
+        let wrong = LogSuffix {
+            head: LogPosition {
+                prev_term: term,
+                index: LogIndex::new(2),
+            },
+            entries: vec![LogEntry::Noop { term }, LogEntry::Noop { term }],
+        };
+        handle.append_log(LogIndex::new(3), LogIndex::new(5), Log::Suffix(wrong));
+        let e = common
+            .load_log(LogIndex::new(3), Some(LogIndex::new(5)))
+            .wait()
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+        assert!(e
+            .to_string()
+            .contains("requested=[LogIndex(3), Some(LogIndex(5)))"));
+        assert!(e.to_string().contains("loaded=[LogIndex(2), LogIndex(4))"));
+
+        // 要求範囲の末尾を超えるエントリも許容されない
+        let longer = LogSuffix {
+            head: LogPosition {
+                prev_term: term,
+                index: LogIndex::new(3),
+            },
+            entries: vec![LogEntry::Noop { term }; 3],
+        };
+        handle.append_log(LogIndex::new(3), LogIndex::new(5), Log::Suffix(longer));
+        let e = common
+            .load_log(LogIndex::new(3), Some(LogIndex::new(5)))
+            .wait()
+            .unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::InconsistentState);
+
+        // 要求範囲よりも短いログは許容される
+        let shorter = LogSuffix {
+            head: LogPosition {
+                prev_term: term,
+                index: LogIndex::new(3),
+            },
+            entries: vec![LogEntry::Noop { term }],
+        };
+        handle.append_log(LogIndex::new(3), LogIndex::new(5), Log::Suffix(shorter));
+        let log = track!(common
+            .load_log(LogIndex::new(3), Some(LogIndex::new(5)))
+            .wait())?;
+        assert!(matches!(log, Log::Suffix(ref s) if s.tail().index == LogIndex::new(4)));
+        Ok(())
+    }
+
+    #[test]
+    fn latest_snapshot_reflects_installed_one() -> TestResult {
+        use crate::testing::MockCluster;
+
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..3 {
+            track!(cluster.propose("a", vec![i]))?;
+        }
+        cluster.run();
+        assert!(cluster.node("a").latest_snapshot().is_none());
+
+        let new_head = cluster.node("a").local_history().consumed_tail().index;
+        track!(cluster
+            .node_mut("a")
+            .install_snapshot(new_head, b"state".to_vec()))?;
+        assert!(cluster.node("a").latest_snapshot().is_none());
+        cluster.run();
+
+        let node = cluster.node("a");
+        let snapshot = node.latest_snapshot().expect("Never fails");
+        assert_eq!(snapshot.tail, node.local_history().head());
+        assert_eq!(snapshot.tail.index, new_head);
+        assert_eq!(&snapshot.config, node.cluster_config());
+        assert_eq!(snapshot.snapshot, b"state");
+
+        // 再起動後は、ストレージからロードされたものが返される
+        cluster.restart("a");
+        cluster.run();
+        let node = cluster.node("a");
+        let snapshot = node.latest_snapshot().expect("Never fails");
+        assert_eq!(snapshot.tail, node.local_history().head());
+        assert_eq!(snapshot.snapshot, b"state");
+        Ok(())
+    }
 }
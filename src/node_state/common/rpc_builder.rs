@@ -1,6 +1,9 @@
+use std::cmp;
+
 use super::Common;
-use crate::log::{LogPosition, LogPrefix, LogSuffix};
-use crate::message::{self, AppendEntriesReply, Message, MessageHeader, SequenceNumber};
+use crate::election::Term;
+use crate::log::{LogIndex, LogPosition, LogPrefix, LogSuffix};
+use crate::message::{self, AppendEntriesReply, Message, MessageHeader, VoteRejectionReason};
 use crate::node::NodeId;
 use crate::Io;
 
@@ -12,17 +15,37 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
     pub fn new(common: &'a mut Common<IO>) -> Self {
         RpcCaller { common }
     }
-    pub fn broadcast_request_vote(mut self) {
+    pub fn broadcast_request_vote(mut self, leadership_transfer: bool) {
         let header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
         let log_tail = self.common.history.tail();
         let request = message::RequestVoteCall {
             header: header.clone(),
             log_tail,
+            leadership_transfer,
+            priority: self.common.election_priority(),
         }
         .into();
         let self_reply = message::RequestVoteReply {
             header,
             voted: true,
+            reason: None,
+        }
+        .into();
+        self.broadcast(request, self_reply);
+    }
+    pub fn broadcast_pre_vote(mut self) {
+        let mut header = self.make_header(&NodeId::new(String::new())); // ブロードキャストノード時に空文字列を宛先に指定
+        header.term = (header.term.as_u64() + 1).into(); // 立候補した場合の`Term`
+        let log_tail = self.common.history.tail();
+        let request = message::PreVoteCall {
+            header: header.clone(),
+            log_tail,
+            priority: self.common.election_priority(),
+        }
+        .into();
+        let self_reply = message::PreVoteReply {
+            header,
+            voted: true,
         }
         .into();
         self.broadcast(request, self_reply);
@@ -38,12 +61,18 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
         let self_reply = AppendEntriesReply {
             header,
             log_tail: self.common.history.tail(),
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
             busy: false,
+            epoch: self.common.epoch,
         }
         .into();
         self.broadcast(request, self_reply);
     }
-    pub fn send_append_entries(mut self, peer: &NodeId, suffix: LogSuffix) {
+    pub fn send_append_entries(mut self, peer: &NodeId, mut suffix: LogSuffix) {
+        if self.common.history.config().is_witness(peer) {
+            suffix = suffix.metadata_only();
+        }
         let message = message::AppendEntriesCall {
             header: self.make_header(peer),
             committed_log_tail: self.common.history.committed_tail().index,
@@ -52,16 +81,63 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
         .into();
         self.common.io.send_message(message);
     }
-    pub fn send_install_snapshot(mut self, peer: &NodeId, prefix: LogPrefix) {
+    pub fn send_timeout_now(mut self, peer: &NodeId) {
+        let header = self.make_header(peer);
+        let message = message::TimeoutNowCast { header }.into();
+        self.common.io.send_message(message);
+    }
+    pub fn send_departure(mut self, peer: &NodeId) {
+        let header = self.make_header(peer);
+        let message = message::DepartureCast { header }.into();
+        self.common.io.send_message(message);
+    }
+    pub fn send_install_snapshot(mut self, peer: &NodeId, mut prefix: LogPrefix) {
+        if self.common.history.config().is_witness(peer) {
+            // ウィットネスには、スナップショットの内容は送らない
+            // (空のバイト列は圧縮形式として不正なので、圧縮方式やチェックサムも破棄する)
+            prefix = LogPrefix::new(prefix.tail, prefix.config, Vec::new());
+        }
         let header = self.make_header(peer);
         let message = message::InstallSnapshotCast { header, prefix }.into();
         self.common.io.send_message(message);
     }
+    pub fn send_install_snapshot_chunks(
+        mut self,
+        peer: &NodeId,
+        mut prefix: LogPrefix,
+        chunk_size: usize,
+    ) {
+        if self.common.history.config().is_witness(peer) {
+            prefix = LogPrefix::new(prefix.tail, prefix.config, Vec::new());
+        }
+        let chunk_size = cmp::max(chunk_size, 1);
+        let mut offset = 0;
+        loop {
+            let end = cmp::min(offset + chunk_size, prefix.snapshot.len());
+            let done = end == prefix.snapshot.len();
+            let message = message::InstallSnapshotChunkCast {
+                header: self.make_header(peer),
+                tail: prefix.tail,
+                config: prefix.config.clone(),
+                compression: prefix.compression,
+                offset: offset as u64,
+                bytes: prefix.snapshot[offset..end].to_vec(),
+                done,
+            }
+            .into();
+            self.common.io.send_message(message);
+            if done {
+                break;
+            }
+            offset = end;
+        }
+    }
 
     fn make_header(&mut self, destination: &NodeId) -> MessageHeader {
         let seq_no = self.common.seq_no;
-        self.common.seq_no = SequenceNumber::new(seq_no.as_u64() + 1);
+        self.common.seq_no = seq_no.next();
         MessageHeader {
+            group_id: self.common.options.group_id,
             sender: self.common.local_node.id.clone(),
             destination: destination.clone(),
             seq_no,
@@ -70,9 +146,24 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
     }
     fn broadcast(&mut self, mut message: Message, self_reply: Message) {
         let mut do_self_reply = false;
-        for peer in self.common.history.config().members() {
+        let mut peers = self
+            .common
+            .history
+            .config()
+            .members()
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some(ref order) = self.common.options.follower_order {
+            peers.sort_by(|a, b| order.sort_key(a).cmp(&order.sort_key(b)));
+        }
+        for peer in &peers {
             if *peer == self.common.local_node.id {
                 do_self_reply = true;
+            } else if self.common.history.config().is_witness(peer) {
+                // ウィットネスには、ログのメタデータのみを送る
+                let mut message = metadata_only(&message);
+                message.set_destination(peer);
+                self.common.io.send_message(message);
             } else {
                 message.set_destination(peer);
                 self.common.io.send_message(message.clone());
@@ -84,6 +175,20 @@ impl<'a, IO: 'a + Io> RpcCaller<'a, IO> {
     }
 }
 
+/// `message`が`AppendEntriesCall`の場合には、`Command`エントリのペイロードを取り除いたものを返す.
+fn metadata_only(message: &Message) -> Message {
+    if let Message::AppendEntriesCall(m) = message {
+        message::AppendEntriesCall {
+            header: m.header.clone(),
+            committed_log_tail: m.committed_log_tail,
+            suffix: m.suffix.metadata_only(),
+        }
+        .into()
+    } else {
+        message.clone()
+    }
+}
+
 /// RPC応答メッセージの送信を補助するためのビルダ.
 pub struct RpcCallee<'a, IO: 'a + Io> {
     common: &'a mut Common<IO>,
@@ -95,14 +200,54 @@ impl<'a, IO: 'a + Io> RpcCallee<'a, IO> {
     }
     pub fn reply_request_vote(self, voted: bool) {
         let header = self.make_header();
-        let message = message::RequestVoteReply { header, voted }.into();
+        let message = message::RequestVoteReply {
+            header,
+            voted,
+            reason: None,
+        }
+        .into();
+        self.common.io.send_message(message);
+    }
+    pub fn reject_request_vote(self, reason: VoteRejectionReason) {
+        let header = self.make_header();
+        let message = message::RequestVoteReply {
+            header,
+            voted: false,
+            reason: Some(reason),
+        }
+        .into();
+        self.common.io.send_message(message);
+    }
+    pub fn reply_pre_vote(self, voted: bool) {
+        let header = self.make_header();
+        let message = message::PreVoteReply { header, voted }.into();
         self.common.io.send_message(message);
     }
     pub fn reply_append_entries(self, log_tail: LogPosition) {
         let message = AppendEntriesReply {
             header: self.make_header(),
             log_tail,
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch: self.common.epoch,
+        }
+        .into();
+        self.common.io.send_message(message);
+    }
+    pub fn reply_append_entries_conflict(
+        self,
+        log_tail: LogPosition,
+        conflict_term: Term,
+        conflict_index: LogIndex,
+    ) {
+        let message = AppendEntriesReply {
+            header: self.make_header(),
+            log_tail,
+            conflict_term: Some(conflict_term),
+            conflict_index,
             busy: false,
+            epoch: self.common.epoch,
         }
         .into();
         self.common.io.send_message(message);
@@ -111,7 +256,10 @@ impl<'a, IO: 'a + Io> RpcCallee<'a, IO> {
         let message = AppendEntriesReply {
             header: self.make_header(),
             log_tail: self.common.history.tail(),
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
             busy: true,
+            epoch: self.common.epoch,
         }
         .into();
         self.common.io.send_message(message);
@@ -119,6 +267,7 @@ impl<'a, IO: 'a + Io> RpcCallee<'a, IO> {
 
     fn make_header(&self) -> MessageHeader {
         MessageHeader {
+            group_id: self.common.options.group_id,
             sender: self.common.local_node.id.clone(),
             destination: self.caller.sender.clone(),
             seq_no: self.caller.seq_no,
@@ -1,6 +1,7 @@
 use futures::{Async, Future};
 
-use super::super::{Common, NextState, RoleState};
+use super::super::common::{self, Common};
+use super::super::{NextState, RoleState};
 use super::{Follower, FollowerIdle};
 use crate::log::LogPosition;
 use crate::message::{AppendEntriesCall, Message};
@@ -17,6 +18,7 @@ pub struct FollowerAppend<IO: Io> {
     future: Option<IO::SaveLog>,
     new_log_tail: LogPosition,
     message: AppendEntriesCall,
+    retries: usize,
 }
 impl<IO: Io> FollowerAppend<IO> {
     pub fn new(common: &mut Common<IO>, mut message: AppendEntriesCall) -> Self {
@@ -27,6 +29,7 @@ impl<IO: Io> FollowerAppend<IO> {
             new_log_tail = common.log().tail();
         }
         if message.suffix.tail().index < message.committed_log_tail {
+            // リーダのログとの一致が確認できている範囲を超えてコミットしないように、切り詰める
             message.committed_log_tail = message.suffix.tail().index;
         }
         if message.committed_log_tail < common.log_committed_tail().index {
@@ -36,6 +39,9 @@ impl<IO: Io> FollowerAppend<IO> {
         let future = if new_log_tail.index == common.log().tail().index {
             // 新規追加分がない場合は、保存処理を省略して最適化
             // (AppendEntriesCallは、単にハートビートの用途でも使用されるので、空のケースは珍しくない)
+            //
+            // NOTE: この場合でも`run_once`では即座に保存完了扱いとなり、
+            // ハートビートで通知されたコミット済み地点の反映(`handle_log_committed`)は行われる
             None
         } else {
             Some(common.save_log_suffix(&message.suffix))
@@ -44,6 +50,7 @@ impl<IO: Io> FollowerAppend<IO> {
             future,
             new_log_tail,
             message,
+            retries: 0,
         }
     }
     pub fn handle_message(
@@ -57,7 +64,20 @@ impl<IO: Io> FollowerAppend<IO> {
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        if let Async::Ready(_) = track!(self.future.poll())? {
+        let polled = loop {
+            match track!(self.future.poll()) {
+                Err(e)
+                    if common::is_transient_error(&e)
+                        && self.retries < common::MAX_SAVE_RETRIES =>
+                {
+                    // 一時的なエラーなので、同じ内容の保存をやり直す
+                    self.retries += 1;
+                    self.future = Some(common.save_log_suffix(&self.message.suffix));
+                }
+                polled => break track!(polled)?,
+            }
+        };
+        if let Async::Ready(_) = polled {
             if self.new_log_tail == self.message.suffix.tail() {
                 track!(common.handle_log_appended(&self.message.suffix))?;
             }
@@ -2,14 +2,15 @@ use std::marker::PhantomData;
 use trackable::error::ErrorKindExt;
 
 use super::super::{Common, NextState, RoleState};
-use super::{Follower, FollowerAppend, FollowerSnapshot};
-use crate::log::{LogPosition, LogSuffix};
+use super::{is_stale_snapshot, Follower, FollowerAppend, FollowerSnapshot};
+use crate::election::Term;
+use crate::log::{LogIndex, LogPosition, LogSuffix};
 use crate::message::{AppendEntriesCall, Message};
 use crate::{ErrorKind, Io, Result};
 
 /// 待機中(i.e., 受信メッセージ処理が可能)なフォロワーのサブ状態.
 ///
-/// リーダから送られてきた`AppendEntriesCall`および`InstallSnapshotCast`を処理する.
+/// リーダから送られてきた`AppendEntriesCall`および`InstallSnapshotCast`(`InstallSnapshotChunkCast`)を処理する.
 pub struct FollowerIdle<IO: Io> {
     _phantom: PhantomData<IO>,
 }
@@ -29,7 +30,7 @@ impl<IO: Io> FollowerIdle<IO> {
         match message {
             Message::AppendEntriesCall(m) => track!(self.handle_entries(common, m)),
             Message::InstallSnapshotCast(m) => {
-                if m.prefix.tail.index <= common.log_committed_tail().index {
+                if is_stale_snapshot(common, m.prefix.tail) {
                     // 既にコミット済みの地点のスナップショットは無視する
                     // (必要なら、ローカルノードで独自にスナップショットを取れば良い)
                     Ok(None)
@@ -45,6 +46,16 @@ impl<IO: Io> FollowerIdle<IO> {
                     Ok(Some(RoleState::Follower(Follower::Snapshot(next))))
                 }
             }
+            Message::InstallSnapshotChunkCast(m) => {
+                if is_stale_snapshot(common, m.tail) || common.is_snapshot_installing() {
+                    // `InstallSnapshotCast`の場合と同様
+                    Ok(None)
+                } else {
+                    // 分割されたスナップショットの受信を開始する
+                    let next = track!(FollowerSnapshot::receive(common, m))?;
+                    Ok(Some(RoleState::Follower(Follower::Snapshot(next))))
+                }
+            }
             _ => Ok(None),
         }
     }
@@ -56,8 +67,10 @@ impl<IO: Io> FollowerIdle<IO> {
     ) -> Result<NextState<IO>> {
         // `AppendEntriesCall`で受け取ったエントリ群が、ローカルログの末尾に追記可能になるように調整する
 
+        // NOTE: 歴史が破棄された消費済みの領域は、スナップショット地点以前と同様に扱う
         let local_tail = common.log().tail();
-        if message.suffix.tail().index < common.log().head().index {
+        let local_head = common.log().history_head();
+        if message.suffix.tail().index < local_head.index {
             // リーダのログが、ローカルログに比べて大幅に短い (i.e., スナップショット地点以前)
             // => チャンネルに任意のメッセージ遅延を許している以上発生し得る
             common
@@ -65,10 +78,10 @@ impl<IO: Io> FollowerIdle<IO> {
                 .reply_append_entries(local_tail); // 処理はせずに、自分のログ終端を通知するに留める
             return Ok(None);
         }
-        if message.suffix.head.index < common.log().head().index {
+        if message.suffix.head.index < local_head.index {
             // リーダのログが、ローカルのスナップショット地点以前のエントリを含んでいる
             // => その部分は、切り捨てる
-            track!(message.suffix.skip_to(common.log().head().index))?;
+            track!(message.suffix.skip_to(local_head.index))?;
         }
 
         if local_tail.index < message.suffix.head.index {
@@ -90,14 +103,26 @@ impl<IO: Io> FollowerIdle<IO> {
     ) -> Result<NextState<IO>> {
         // リーダとローカルのログの共通部分を探索
         let (matched, lcp) = track!(self.longest_common_prefix(common, &message.suffix))?;
-        if !matched {
+        if !matched && self.is_overwritable(common, &message, lcp) {
+            // 分岐した部分は全て未コミットで、かつ、リーダのエントリで置き換え可能
+            // => ロールバックしてリーダからの再送を待つ代わりに、直接上書きする
+            track!(common.handle_log_rollbacked(lcp))?;
+            track!(message.suffix.skip_to(lcp.index))?;
+            let next = FollowerAppend::new(common, message);
+            Ok(Some(RoleState::Follower(Follower::Append(next))))
+        } else if !matched {
             // 両者が分岐している
             // => ローカルログ(の未コミット領域)をロールバックして、同期位置まで戻る
+            //
+            // その際には、分岐したエントリの`Term`とその開始位置もリーダに伝えて、
+            // 同じ`Term`に属するエントリ群を一度に飛び越えられるようにする
+            let (conflict_term, conflict_index) = self.conflicting_term(common, lcp.index);
             let new_log_tail = lcp;
             track!(common.handle_log_rollbacked(new_log_tail))?;
+            common.metrics().append_entries_rejected_total.increment();
             common
                 .rpc_callee(&message.header)
-                .reply_append_entries(new_log_tail);
+                .reply_append_entries_conflict(new_log_tail, conflict_term, conflict_index);
             Ok(None)
         } else {
             // 両者は包含関係にあるので、追記が可能
@@ -106,6 +131,35 @@ impl<IO: Io> FollowerIdle<IO> {
             Ok(Some(RoleState::Follower(Follower::Append(next))))
         }
     }
+    /// 分岐したローカルログを、`lcp`以降のリーダのエントリで直接上書きして良いかどうかを判定する.
+    fn is_overwritable(
+        &self,
+        common: &Common<IO>,
+        message: &AppendEntriesCall,
+        lcp: LogPosition,
+    ) -> bool {
+        common.options().overwrite_on_divergence
+            && common.log_committed_tail().index <= lcp.index
+            && message.suffix.head.index <= lcp.index
+            && lcp.index < message.suffix.tail().index
+    }
+    /// ローカルログの`index`の位置にあるエントリの`Term`と、その`Term`に属する最初のエントリのインデックスを返す.
+    ///
+    /// ただしインデックスは、歴史が保持されている最初の位置よりも前になることはない.
+    fn conflicting_term(&self, common: &Common<IO>, index: LogIndex) -> (Term, LogIndex) {
+        let log = common.log();
+        let mut record = log.get_record(index + 1).expect("Never fails");
+        let term = record.head.prev_term;
+        loop {
+            if record.head.index <= log.history_head().index {
+                return (term, log.history_head().index);
+            }
+            match log.get_record(record.head.index - 1) {
+                Some(r) if r.head.prev_term == term => record = r,
+                _ => return (term, record.head.index - 1),
+            }
+        }
+    }
     fn longest_common_prefix(
         &self,
         common: &Common<IO>,
@@ -1,15 +1,18 @@
 use self::append::FollowerAppend;
 use self::idle::FollowerIdle;
 use self::init::FollowerInit;
+use self::pre_vote::FollowerPreVote;
 use self::snapshot::FollowerSnapshot;
-use super::{Common, NextState};
+use super::{Common, NextState, RoleState};
 use crate::election::Role;
+use crate::log::LogPosition;
 use crate::message::{Message, MessageHeader};
-use crate::{Io, Result};
+use crate::{Io, Result, SnapshotProgress};
 
 mod append;
 mod idle;
 mod init;
+mod pre_vote;
 mod snapshot;
 
 /// 別の人(ノード)に投票しているフォロワー.
@@ -30,6 +33,9 @@ pub enum Follower<IO: Io> {
 
     /// ローカルログへのスナップショット保存中.
     Snapshot(FollowerSnapshot<IO>),
+
+    /// 立候補前のPreVote中.
+    PreVote(FollowerPreVote<IO>),
 }
 impl<IO: Io> Follower<IO> {
     pub fn new(common: &mut Common<IO>, pending_vote: Option<MessageHeader>) -> Self {
@@ -38,6 +44,19 @@ impl<IO: Io> Follower<IO> {
         Follower::Init(follower)
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
+        if !common.config().is_electable(&common.local_node().id) {
+            // ラーナーやウィットネスは選挙に立候補しない
+            common.set_timeout(Role::Follower);
+            return Ok(None);
+        }
+        if common.options().pre_vote {
+            if let Follower::Init(_) = *self {
+                // 投票状況の保存中の場合には、それを中断させないために、PreVoteは省略する
+            } else {
+                let next = FollowerPreVote::new(common);
+                return Ok(Some(RoleState::Follower(Follower::PreVote(next))));
+            }
+        }
         Ok(Some(common.transit_to_candidate()))
     }
     pub fn handle_message(
@@ -45,19 +64,47 @@ impl<IO: Io> Follower<IO> {
         common: &mut Common<IO>,
         message: Message,
     ) -> Result<NextState<IO>> {
-        if let Message::AppendEntriesCall { .. } = message {
+        if let Message::AppendEntriesCall(ref m) = message {
             common.set_timeout(Role::Follower);
+            if m.header.sender == common.local_node().ballot.voted_for {
+                common.record_leader_contact(m.committed_log_tail);
+            }
             if unsafe { common.io_mut().is_busy() } {
                 common.rpc_callee(message.header()).reply_busy();
                 return Ok(None);
             }
         }
+        if let Message::TimeoutNowCast(ref m) = message {
+            if m.header.sender == common.local_node().ballot.voted_for
+                && common.config().is_electable(&common.local_node().id)
+            {
+                // リーダからの指示を受けて、タイムアウトを待たずに即座に立候補する
+                common.stop_pre_vote();
+                return Ok(Some(common.transit_to_candidate_by_transfer()));
+            }
+            return Ok(None);
+        }
 
         match *self {
             Follower::Init(ref mut t) => track!(t.handle_message(common, message)),
             Follower::Idle(ref mut t) => track!(t.handle_message(common, message)),
             Follower::Append(ref mut t) => track!(t.handle_message(common, message)),
             Follower::Snapshot(ref mut t) => track!(t.handle_message(common, message)),
+            Follower::PreVote(ref mut t) => track!(t.handle_message(common, message)),
+        }
+    }
+    /// 投票状況ないしログの保存中かどうかを判定する.
+    ///
+    /// スナップショットの保存は`Common::is_snapshot_installing`で判定される.
+    pub fn has_pending_io(&self) -> bool {
+        matches!(*self, Follower::Init(_) | Follower::Append(_))
+    }
+    /// 分割されたスナップショットの断片を受信中の場合には、その進捗を返す.
+    pub fn snapshot_progress(&self) -> Option<SnapshotProgress> {
+        if let Follower::Snapshot(ref t) = *self {
+            t.progress()
+        } else {
+            None
         }
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
@@ -66,6 +113,580 @@ impl<IO: Io> Follower<IO> {
             Follower::Idle(_) => Ok(None),
             Follower::Append(ref mut t) => track!(t.run_once(common)),
             Follower::Snapshot(ref mut t) => track!(t.run_once(common)),
+            Follower::PreVote(_) => Ok(None),
+        }
+    }
+}
+
+/// `tail`を終端とするスナップショットが、ローカルのコミット済み地点から見て古いものかどうかを判定する.
+///
+/// コミット済み地点以前のスナップショットに加えて、
+/// 終端の`Term`がコミット済み地点の`Term`よりも古いもの(i.e., 既に退位したリーダから送られたもの)も、古いと判定する.
+/// 後者をインストールすると、コミット済みの`Term`が後退してしまう.
+fn is_stale_snapshot<IO: Io>(common: &Common<IO>, tail: LogPosition) -> bool {
+    let committed = common.log_committed_tail();
+    tail.index <= committed.index || tail.prev_term < committed.prev_term
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Async, Stream};
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use crate::election::{Role, Term};
+    use crate::log::{LogEntry, LogIndex, LogPosition, LogPrefix, LogSuffix};
+    use crate::message::{
+        AppendEntriesCall, GroupId, InstallSnapshotCast, Message, MessageHeader, SequenceNumber,
+    };
+    use crate::testing::{MockCluster, MockIo};
+    use crate::{Error, ErrorKind, Event, ReplicatedLog, ReplicatedLogOptions};
+
+    #[test]
+    fn follower_read_index_works() {
+        let options = ReplicatedLogOptions {
+            follower_read_staleness: Some(Duration::from_millis(50)),
+            lease_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("a").follower_read_index(), None);
+
+        // `LeaseNoop`がコミットされるまでは、読み込みは提供されない
+        assert_eq!(cluster.node("b").follower_read_index(), None);
+
+        // `LeaseNoop`を追加して、そのコミットをフォロワーに伝える
+        cluster.fire_timeout("a");
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        let committed = cluster.node("b").local_history().committed_tail().index;
+        assert_eq!(cluster.node("b").follower_read_index(), Some(committed));
+
+        // リーダからの通信が途絶えた
+        cluster.isolate("a");
+        thread::sleep(Duration::from_millis(100));
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.node("b").follower_read_index(), None);
+    }
+
+    #[test]
+    fn follower_read_index_is_not_extended_by_deposed_leader() {
+        let options = ReplicatedLogOptions {
+            follower_read_staleness: Some(Duration::from_millis(50)),
+            lease_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c", "d", "e"], options);
+        cluster.elect("a");
+        cluster.fire_timeout("a");
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("b").follower_read_index().is_some());
+
+        // `a`は少数派に取り残されたが、`b`との通信は続いている
+        cluster.partition(&["a", "b"]);
+        // 多数派側の全ノードをタイムアウトさせた上で、`c`を選出する (票が割れた場合に備えて二度立候補させる)
+        for id in &["d", "e", "c", "c"] {
+            cluster.fire_timeout(id);
+            cluster.run();
+        }
+        assert_eq!(cluster.node("c").local_node().role, Role::Leader);
+        cluster.node_mut("c").propose_command(vec![1]).unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("a").local_node().role, Role::Leader);
+
+        // `a`のハートビートは届くが、`LeaseNoop`はコミットされないので、`b`の読み込みは失効する
+        thread::sleep(Duration::from_millis(100));
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(
+            cluster.node("b").local_history().committed_tail().index
+                < cluster.node("c").local_history().committed_tail().index
+        );
+        assert_eq!(cluster.node("b").follower_read_index(), None);
+    }
+
+    #[test]
+    fn follower_reports_leader_commit_lag() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.node("a").leader_commit_lag(), None);
+        assert_eq!(cluster.node("c").leader_commit_lag(), Some(0));
+
+        // ローカルよりも先のコミット済み地点を通知する`AppendEntriesCall`を受信する
+        let term = cluster.node("a").local_node().ballot.term;
+        let tail = cluster.node("a").local_history().tail().index;
+        let message = AppendEntriesCall {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: "a".into(),
+                destination: "c".into(),
+                seq_no: SequenceNumber::new(1 << 32),
+                term,
+            },
+            committed_log_tail: tail + 5,
+            suffix: LogSuffix {
+                head: LogPosition {
+                    prev_term: term,
+                    index: tail + 5,
+                },
+                entries: Vec::new(),
+            },
+        };
+        cluster.network().lock().unwrap().replay(message.into());
+        cluster.step();
+        assert_eq!(cluster.node("c").leader_commit_lag(), Some(5));
+        assert_eq!(cluster.node("b").leader_commit_lag(), Some(0));
+    }
+
+    #[test]
+    fn stale_term_snapshot_is_rejected() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+
+        let history = cluster.node("c").local_history().clone();
+        let committed = history.committed_tail();
+        assert!(committed.prev_term > Term::new(0));
+
+        // 既に退位したリーダの`Term`を終端に持つスナップショットを受信する
+        let term = cluster.node("a").local_node().ballot.term;
+        for (i, &index) in [committed.index, committed.index + 2].iter().enumerate() {
+            let message = InstallSnapshotCast {
+                header: MessageHeader {
+                    group_id: GroupId::default(),
+                    sender: "a".into(),
+                    destination: "c".into(),
+                    seq_no: SequenceNumber::new((1 << 32) + i as u64),
+                    term,
+                },
+                prefix: LogPrefix::new(
+                    LogPosition {
+                        prev_term: Term::new(0),
+                        index,
+                    },
+                    history.config().clone(),
+                    vec![1, 2, 3],
+                ),
+            };
+            cluster.network().lock().unwrap().replay(message.into());
+            cluster.run();
+        }
+
+        // ローカルのログの状態は変化しない
+        let node = cluster.node("c");
+        assert_eq!(node.local_history().head(), history.head());
+        assert_eq!(node.local_history().tail(), history.tail());
+        assert_eq!(node.local_history().committed_tail(), committed);
+        assert_eq!(node.snapshot_install_progress(), None);
+        assert!(node.io().storage().lock().unwrap().prefix.is_none());
+    }
+
+    #[test]
+    fn committed_lease_noop_extends_read_lease() {
+        let options = ReplicatedLogOptions {
+            lease_duration: Some(Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        assert_eq!(cluster.node("b").read_lease(), None);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("a").read_lease() >= Some(now + 10_000));
+
+        // コミット済み地点をフォロワーに伝える
+        cluster.fire_timeout("a");
+        cluster.run();
+        let lease = cluster.node("b").read_lease();
+        assert!(lease >= Some(now + 10_000));
+        assert!(cluster.events("b").iter().any(|e| matches!(
+            e,
+            Event::Committed {
+                entry: LogEntry::LeaseNoop { .. },
+                ..
+            }
+        )));
+
+        thread::sleep(Duration::from_millis(10));
+        cluster.fire_timeout("a");
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("b").read_lease() > lease);
+    }
+
+    fn poll_until_error(node: &mut ReplicatedLog<MockIo>) -> Option<Error> {
+        loop {
+            match node.poll() {
+                Ok(Async::Ready(_)) => {}
+                Ok(Async::NotReady) => return None,
+                Err(e) => return Some(e),
+            }
         }
     }
+
+    #[test]
+    fn save_failure_is_surfaced() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let tail = cluster.node("b").local_history().tail();
+
+        // フォロワーでの追記の失敗
+        cluster
+            .node("b")
+            .io()
+            .inject_save_errors(ErrorKind::InconsistentState, 1);
+        cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        assert!(poll_until_error(cluster.node_mut("a")).is_none());
+        let e = poll_until_error(cluster.node_mut("b"));
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+        assert_eq!(cluster.node("b").local_history().tail(), tail);
+        assert_eq!(cluster.log_suffix("b").tail(), tail);
+
+        // スナップショットの保存の失敗
+        let head = cluster.node("a").local_history().head();
+        let committed = cluster.node("a").local_history().committed_tail();
+        cluster
+            .node("a")
+            .io()
+            .inject_save_errors(ErrorKind::InconsistentState, 1);
+        cluster
+            .node_mut("a")
+            .install_snapshot(committed.index, vec![1])
+            .unwrap();
+        let e = poll_until_error(cluster.node_mut("a"));
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+        assert_eq!(cluster.node("a").local_history().head(), head);
+        let storage = cluster.node("a").io().storage().lock().unwrap();
+        assert!(storage.prefix.is_none());
+    }
+
+    #[test]
+    fn heartbeat_advances_follower_commit() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        // 追記自体は完了しているが、コミット済み地点はまだ伝わっていない
+        let committed = cluster.node("a").local_history().committed_tail();
+        let tail = cluster.node("b").local_history().tail();
+        assert_eq!(tail, cluster.node("a").local_history().tail());
+        assert!(cluster.node("b").local_history().committed_tail().index < committed.index);
+
+        // 新規エントリを含まないハートビートによって、コミット済み地点が進む
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .all(|m| match m {
+                Message::AppendEntriesCall(m) => m.suffix.entries.is_empty(),
+                _ => true,
+            }));
+        assert_eq!(cluster.node("b").local_history().tail(), tail);
+        assert_eq!(
+            cluster.node("b").local_history().committed_tail(),
+            committed
+        );
+        assert_eq!(cluster.node("b").local_history().consumed_tail(), committed);
+        assert!(cluster.events("b").iter().any(|e| matches!(
+            e,
+            Event::Committed {
+                entry: LogEntry::Command { command, .. },
+                ..
+            } if command[..] == [1]
+        )));
+    }
+
+    #[test]
+    fn lagging_follower_is_not_up_to_date() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("c").is_up_to_date(0));
+
+        // `c`はコミット済みのエントリを読み込めない
+        cluster.node("c").io().set_loads_blocked(true);
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+        }
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("a").is_up_to_date(0));
+        assert!(cluster.node("b").is_up_to_date(0));
+        assert!(!cluster.node("c").is_up_to_date(1));
+        assert!(cluster.node("c").is_up_to_date(10));
+
+        cluster.node("c").io().set_loads_blocked(false);
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster.node("c").is_up_to_date(0));
+
+        // リーダとの疎通が途絶えた
+        cluster.isolate("a");
+        cluster.fire_timeout("c");
+        cluster.run();
+        assert!(!cluster.node("c").is_up_to_date(10));
+    }
+
+    fn diverge_and_heal(options: ReplicatedLogOptions) -> (MockCluster, Vec<(LogIndex, LogEntry)>) {
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![0]).unwrap();
+        cluster.run();
+
+        // `a`のログにだけ、未コミットのエントリ群が追記される
+        cluster.isolate("a");
+        for i in 1..4 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+        }
+        cluster.run();
+
+        // `b`と`c`の双方が`a`との疎通の途絶を検知した後に、`b`が当選する
+        cluster.fire_timeout("c");
+        cluster.run();
+        for _ in 0..2 {
+            cluster.fire_timeout("b");
+            cluster.run();
+        }
+        assert_eq!(cluster.node("b").local_node().role, Role::Leader);
+        cluster.node_mut("b").propose_command(vec![10]).unwrap();
+        cluster.run();
+
+        cluster.heal();
+        cluster.fire_timeout("b");
+        cluster.run();
+        cluster.fire_timeout("b");
+        cluster.run();
+        let committed = cluster
+            .events("a")
+            .iter()
+            .filter_map(|e| match e {
+                Event::Committed { index, entry } => Some((*index, entry.clone())),
+                _ => None,
+            })
+            .collect();
+        (cluster, committed)
+    }
+
+    #[test]
+    fn divergent_entries_are_overwritten() {
+        let (rollback, rollback_committed) = diverge_and_heal(ReplicatedLogOptions::default());
+        let options = ReplicatedLogOptions {
+            overwrite_on_divergence: true,
+            ..Default::default()
+        };
+        let (overwrite, overwrite_committed) = diverge_and_heal(options);
+
+        for cluster in &[&rollback, &overwrite] {
+            assert_eq!(
+                cluster.log_suffix("a").tail(),
+                cluster.log_suffix("b").tail()
+            );
+            assert_eq!(
+                cluster.node("a").local_history().committed_tail(),
+                cluster.node("b").local_history().committed_tail()
+            );
+        }
+        assert_eq!(rollback_committed, overwrite_committed);
+        assert!(overwrite_committed.iter().any(|(_, e)| matches!(
+            e,
+            LogEntry::Command { command, .. } if command[..] == [10]
+        )));
+
+        // 上書きの場合には、リーダのエントリを受け取った時点でロールバックが不要となる
+        let rejected = |c: &MockCluster| {
+            c.node("a")
+                .metrics()
+                .node_state
+                .append_entries_rejected_total
+                .value()
+        };
+        assert!(rejected(&overwrite) < rejected(&rollback));
+    }
+
+    #[test]
+    fn rolled_back_proposals_are_rejected() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // 孤立したリーダ`a`への提案は、コミットされない
+        cluster.isolate("a");
+        let lost = cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        cluster.fire_timeout("c");
+        cluster.run();
+        for _ in 0..2 {
+            cluster.fire_timeout("b");
+            cluster.run();
+        }
+        assert_eq!(cluster.node("b").local_node().role, Role::Leader);
+        cluster.node_mut("b").propose_command(vec![10]).unwrap();
+        cluster.run();
+        let rejected = |c: &MockCluster, id: &str| {
+            c.events(id)
+                .iter()
+                .filter_map(|e| match e {
+                    Event::ProposalRejected { id } => Some(*id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rejected(&cluster, "a"), []);
+
+        // 新リーダ`b`との同期時に、`a`の未コミットのエントリはロールバックされる
+        cluster.heal();
+        cluster.fire_timeout("b");
+        cluster.run();
+        cluster.fire_timeout("b");
+        cluster.run();
+        assert_eq!(rejected(&cluster, "a"), [lost]);
+        assert!(!cluster
+            .log_suffix("a")
+            .commands()
+            .any(|(_, c)| c[..] == [1]));
+
+        // コミットされた提案は通知されない
+        assert!(cluster
+            .log_suffix("a")
+            .commands()
+            .any(|(_, c)| c[..] == [10]));
+        assert_eq!(rejected(&cluster, "b"), []);
+    }
+
+    #[test]
+    fn follower_reports_current_leader() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        assert_eq!(cluster.node("a").current_leader(), Some("a".into()));
+        assert_eq!(cluster.node("c").current_leader(), Some("a".into()));
+
+        track_try_unwrap!(cluster.node_mut("a").transfer_leadership("b".into()));
+        cluster.run();
+        assert_eq!(cluster.leader(), Some("b".into()));
+        track_try_unwrap!(cluster.node_mut("b").heartbeat());
+        cluster.run();
+        assert_eq!(cluster.node("b").current_leader(), Some("b".into()));
+        assert_eq!(cluster.node("c").current_leader(), Some("b".into()));
+        assert_eq!(cluster.node("a").current_leader(), Some("b".into()));
+
+        // 選挙中の候補者は、リーダを把握していない
+        cluster.isolate("b");
+        cluster.trigger_election("c");
+        cluster.step();
+        assert_eq!(cluster.node("c").local_node().role, Role::Candidate);
+        assert_eq!(cluster.node("c").current_leader(), None);
+    }
+
+    #[test]
+    fn commit_advances_via_heartbeats_alone() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        // エントリは複製済みだが、コミットされたことはまだ知らない
+        let tail = cluster.node("c").local_history().tail();
+        assert!(cluster.node("c").local_history().committed_tail().index < tail.index);
+
+        // 新しいエントリを含まないハートビートのみで、コミット済みの地点が進む
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        assert!(cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .all(|m| match m {
+                Message::AppendEntriesCall(m) => m.suffix.entries.is_empty(),
+                _ => true,
+            }));
+        assert_eq!(cluster.node("c").local_history().tail(), tail);
+        assert_eq!(cluster.node("c").local_history().committed_tail(), tail);
+    }
+
+    #[test]
+    fn stale_term_append_does_not_advance_commit() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let stale = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .rev()
+            .find_map(|m| match m {
+                Message::AppendEntriesCall(m) if m.header.destination.as_str() == "b" => {
+                    Some(m.clone())
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        // `a`をフォロー中の`b`は、自身のタイムアウトまでは新しい`Term`に追従しない
+        cluster.elect("c");
+        cluster.fire_timeout("b");
+        cluster.run();
+        cluster.node_mut("c").heartbeat().unwrap();
+        cluster.run();
+        assert_eq!(cluster.leader(), Some("c".into()));
+        let term = cluster.node("c").local_node().ballot.term;
+        assert_eq!(cluster.node("b").local_node().ballot.term, term);
+        assert!(stale.header.term < term);
+        cluster.node_mut("c").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let committed = cluster.node("b").local_history().committed_tail();
+        let tail = cluster.node("b").local_history().tail();
+        assert!(committed.index < tail.index);
+
+        // 古い`Term`のリーダからのメッセージは、コミット済みの地点を進めない
+        let mut stale = stale;
+        stale.suffix = LogSuffix {
+            head: tail,
+            entries: Vec::new(),
+        };
+        stale.committed_log_tail = tail.index;
+        cluster.network().lock().unwrap().replay(stale.into());
+        cluster.run();
+        assert_eq!(
+            cluster.node("b").local_history().committed_tail(),
+            committed
+        );
+
+        cluster.node_mut("c").heartbeat().unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("b").local_history().committed_tail(), tail);
+    }
 }
@@ -0,0 +1,154 @@
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+
+use super::super::{Common, NextState, RoleState};
+use super::{Follower, FollowerIdle};
+use crate::election::Role;
+use crate::message::{Message, SequenceNumber};
+use crate::node::NodeId;
+use crate::{Io, Result};
+
+/// 立候補する前にPreVoteを行うフォロワーのサブ状態.
+///
+/// タイムアウトによってリーダとの疎通が途絶えたと判断した場合に遷移する.
+///
+/// ローカルノードの`Term`は進めずに`PreVoteCall`をブロードキャストし、
+/// 過半数から投票の見込みが得られた場合にのみ、実際に立候補する(i.e., `Candidate`に遷移する).
+/// それまでの間にリーダからメッセージを受信した場合には、通常のフォロワーに戻る.
+pub struct FollowerPreVote<IO: Io> {
+    seq_no: SequenceNumber,
+    granted: BTreeSet<NodeId>,
+    _phantom: PhantomData<IO>,
+}
+impl<IO: Io> FollowerPreVote<IO> {
+    pub fn new(common: &mut Common<IO>) -> Self {
+        common.set_timeout(Role::Follower);
+        let seq_no = common.start_pre_vote();
+        FollowerPreVote {
+            seq_no,
+            granted: BTreeSet::new(),
+            _phantom: PhantomData,
+        }
+    }
+    pub fn handle_message(
+        &mut self,
+        common: &mut Common<IO>,
+        message: Message,
+    ) -> Result<NextState<IO>> {
+        match message {
+            Message::PreVoteReply(m) => {
+                if m.voted && m.header.seq_no == self.seq_no {
+                    self.granted.insert(m.header.sender);
+                    let is_electable = track!(common
+                        .config()
+                        .consensus_value(|n| self.granted.contains(n)))?;
+                    if is_electable {
+                        return Ok(Some(common.transit_to_candidate()));
+                    }
+                }
+                Ok(None)
+            }
+            _ if message.header().sender == common.local_node().ballot.voted_for => {
+                // リーダとの疎通が回復した
+                common.stop_pre_vote();
+                let mut idle = FollowerIdle::new();
+                let next = track!(idle.handle_message(common, message))?;
+                Ok(Some(
+                    next.unwrap_or(RoleState::Follower(Follower::Idle(idle))),
+                ))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::election::Role;
+    use crate::node::NodeId;
+    use crate::testing::MockCluster;
+    use crate::ReplicatedLogOptions;
+
+    fn cluster() -> MockCluster {
+        let options = ReplicatedLogOptions {
+            pre_vote: true,
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        cluster
+    }
+
+    #[test]
+    fn partitioned_node_does_not_disrupt_leader() {
+        let mut cluster = cluster();
+        let term = cluster.node("a").local_node().ballot.term;
+
+        cluster.isolate("c");
+        for _ in 0..3 {
+            cluster.fire_timeout("c");
+            cluster.run();
+        }
+        assert_eq!(cluster.node("c").local_node().ballot.term, term);
+        assert_eq!(cluster.node("c").local_node().role, Role::Follower);
+
+        cluster.heal();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("a")));
+        assert_eq!(cluster.node("a").local_node().ballot.term, term);
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+    }
+
+    #[test]
+    fn new_leader_is_elected_after_pre_vote() {
+        let mut cluster = cluster();
+        let term = cluster.node("a").local_node().ballot.term;
+
+        cluster.isolate("a");
+        cluster.fire_timeout("c");
+        cluster.run();
+        assert_eq!(cluster.node("c").local_node().ballot.term, term);
+
+        cluster.fire_timeout("b");
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("b")));
+        assert_eq!(
+            cluster.node("b").local_node().ballot.term.as_u64(),
+            term.as_u64() + 1
+        );
+    }
+
+    #[test]
+    fn request_vote_after_reboot_is_not_dropped_as_replay() {
+        let mut cluster = cluster();
+        let term = cluster.node("a").local_node().ballot.term;
+
+        // `b`はリーダとの疎通が途絶えたと判断している
+        cluster.isolate("a");
+        cluster.fire_timeout("b");
+        cluster.run();
+
+        // `c`は`term + 1`でPreVoteを繰り返すが、応答は届かない
+        cluster.ban("b", "c");
+        for _ in 0..3 {
+            cluster.fire_timeout("c");
+            cluster.run();
+        }
+        assert_eq!(cluster.node("c").local_node().ballot.term, term);
+
+        // 再起動した`c`は、シーケンス番号を先頭から振り直して、`term + 1`で立候補する
+        cluster.heal();
+        cluster.isolate("a");
+        cluster.restart("c");
+        cluster.run();
+        assert_eq!(cluster.node("c").local_node().role, Role::Leader);
+        assert_eq!(
+            cluster.node("c").local_node().ballot.term.as_u64(),
+            term.as_u64() + 1
+        );
+    }
+}
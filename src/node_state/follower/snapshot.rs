@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
 use std::marker::PhantomData;
+use std::time::Instant;
 
 use super::super::{Common, NextState, RoleState};
-use super::{Follower, FollowerIdle};
-use crate::message::Message;
-use crate::{Io, Result};
+use super::{is_stale_snapshot, Follower, FollowerIdle};
+use crate::cluster::ClusterConfig;
+use crate::log::{Compression, LogPosition, LogPrefix};
+use crate::message::{InstallSnapshotChunkCast, Message};
+use crate::{ErrorKind, Io, Result, SnapshotProgress};
 
 /// ローカルログへのスナップショット保存を処理するためのフォロワーのサブ状態.
 ///
@@ -17,31 +21,492 @@ use crate::{Io, Result};
 /// 新規ログ追加が行われても問題は発生しないので、
 /// このサブ状態が使われるのは「未コミット地点に対するスナップショット」の
 /// インストールをリーダから指示された場合、のみである.
+///
+/// スナップショットが`InstallSnapshotChunkCast`に分割されて送られてきた場合には、
+/// 全ての断片が揃うまでの間、その受信(再構築)もこのサブ状態で行われる.
+///
+/// `ReplicatedLogOptions::snapshot_install_stall_timeout`が指定されている場合には、
+/// その時間内に断片の受信や保存の完了といった進捗が無ければ`Event::SnapshotInstallStalled`を発行する.
 pub struct FollowerSnapshot<IO: Io> {
+    chunks: Option<SnapshotChunks>,
+    last_progress: Instant,
+    stall_reported: bool,
     _phantom: PhantomData<IO>,
 }
 impl<IO: Io> FollowerSnapshot<IO> {
     pub fn new() -> Self {
         FollowerSnapshot {
+            chunks: None,
+            last_progress: Instant::now(),
+            stall_reported: false,
             _phantom: PhantomData,
         }
     }
+
+    /// 最初に受信した断片を処理して、分割されたスナップショットの受信を開始する.
+    pub fn receive(common: &mut Common<IO>, chunk: InstallSnapshotChunkCast) -> Result<Self> {
+        let mut this = FollowerSnapshot {
+            chunks: Some(SnapshotChunks::new(
+                chunk.tail,
+                chunk.config.clone(),
+                chunk.compression,
+            )),
+            last_progress: Instant::now(),
+            stall_reported: false,
+            _phantom: PhantomData,
+        };
+        track!(this.handle_chunk(common, chunk))?;
+        Ok(this)
+    }
+
     pub fn handle_message(
         &mut self,
         common: &mut Common<IO>,
         message: Message,
     ) -> Result<NextState<IO>> {
-        if let Message::AppendEntriesCall(m) = message {
-            common.rpc_callee(&m.header).reply_busy();
+        match message {
+            Message::AppendEntriesCall(m) => {
+                if self.chunks.is_some() {
+                    // 断片の受信中は、ローカルログの終端を通知して、
+                    // 欠落した断片があればリーダに再送して貰う
+                    let tail = common.log().tail();
+                    common.rpc_callee(&m.header).reply_append_entries(tail);
+                } else {
+                    common.rpc_callee(&m.header).reply_busy();
+                }
+            }
+            Message::InstallSnapshotChunkCast(m) => track!(self.handle_chunk(common, m))?,
+            _ => {}
         }
         Ok(None)
     }
+    /// 断片の受信中の場合には、その進捗を返す.
+    pub fn progress(&self) -> Option<SnapshotProgress> {
+        self.chunks.as_ref().map(|chunks| SnapshotProgress {
+            tail: chunks.tail(),
+            received_bytes: chunks.received_bytes(),
+            total_bytes: chunks.len,
+            saving: false,
+        })
+    }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        if common.is_snapshot_installing() {
+        if self.chunks.is_some() || common.is_snapshot_installing() {
+            self.check_stall(common);
             Ok(None)
         } else {
             let next = Follower::Idle(FollowerIdle::new());
             Ok(Some(RoleState::Follower(next)))
         }
     }
+
+    fn check_stall(&mut self, common: &mut Common<IO>) {
+        let timeout = match common.options().snapshot_install_stall_timeout {
+            None => return,
+            Some(timeout) => timeout,
+        };
+        if self.stall_reported || self.last_progress.elapsed() < timeout {
+            return;
+        }
+        let tail = match self
+            .progress()
+            .or_else(|| common.snapshot_install_progress())
+        {
+            None => return,
+            Some(progress) => progress.tail,
+        };
+        self.stall_reported = true;
+        common.notify_snapshot_install_stalled(tail);
+    }
+    fn handle_chunk(
+        &mut self,
+        common: &mut Common<IO>,
+        chunk: InstallSnapshotChunkCast,
+    ) -> Result<()> {
+        let chunks = match self.chunks {
+            None => return Ok(()), // インストール中
+            Some(ref mut chunks) => chunks,
+        };
+        if chunk.tail != chunks.tail()
+            && (chunk.tail.index <= chunks.tail().index
+                || chunk.tail.prev_term < chunks.tail().prev_term)
+        {
+            // 古いスナップショットの断片 (ないし、既に退位したリーダから送られた断片)
+            return Ok(());
+        }
+        if chunk.tail != chunks.tail() || chunk.compression != chunks.compression {
+            // より新しいスナップショットが送られてきたので、受信をやり直す
+            //
+            // 同じ地点のものでも、圧縮方式が異なる(i.e., 設定の異なる新しいリーダから送られた)場合には、
+            // 断片の境界が一致しないので、同様にやり直す
+            *chunks = SnapshotChunks::new(chunk.tail, chunk.config.clone(), chunk.compression);
+        }
+        let received_bytes = chunks.received_bytes();
+        if chunks
+            .insert(chunk.offset, chunk.bytes, chunk.done)
+            .is_err()
+        {
+            // 既に受信済みのものと整合しない断片は破棄する
+            return Ok(());
+        }
+        if chunks.received_bytes() != received_bytes {
+            self.last_progress = Instant::now();
+            self.stall_reported = false;
+        }
+        if !chunks.is_complete() {
+            return Ok(());
+        }
+
+        let prefix = self.chunks.take().expect("Never fails").into_prefix();
+        self.last_progress = Instant::now();
+        self.stall_reported = false;
+        if is_stale_snapshot(common, prefix.tail) || common.is_snapshot_installing() {
+            // 受信中に、コミット済み地点が追い越された or 別のスナップショットのインストールが始まった
+            return Ok(());
+        }
+        track!(common.install_snapshot(prefix))?;
+        Ok(())
+    }
+}
+
+/// 分割送信されたスナップショットの断片群.
+///
+/// 断片は任意の順序で追加可能で、全ての断片が揃った時点で`LogPrefix`に再構築される.
+#[derive(Debug)]
+pub struct SnapshotChunks {
+    tail: LogPosition,
+    config: ClusterConfig,
+    compression: Compression,
+    chunks: BTreeMap<u64, Vec<u8>>,
+
+    // 最後の断片を受信済みの場合には、スナップショット全体のバイト数が設定される
+    len: Option<u64>,
+}
+impl SnapshotChunks {
+    /// 新しい`SnapshotChunks`インスタンスを生成する.
+    ///
+    /// `compression`は、断片群を連結したバイト列の圧縮方式.
+    pub fn new(tail: LogPosition, config: ClusterConfig, compression: Compression) -> Self {
+        SnapshotChunks {
+            tail,
+            config,
+            compression,
+            chunks: BTreeMap::new(),
+            len: None,
+        }
+    }
+
+    /// 再構築対象のスナップショットの終端位置を返す.
+    pub fn tail(&self) -> LogPosition {
+        self.tail
+    }
+
+    /// 受信済みの断片群の合計バイト数を返す.
+    pub fn received_bytes(&self) -> u64 {
+        self.chunks.values().map(|b| b.len() as u64).sum()
+    }
+
+    /// `offset`から始まる断片を追加する.
+    ///
+    /// 受信済みの断片と完全に一致するもの(i.e., 重複)は無視される.
+    ///
+    /// # Errors
+    ///
+    /// 受信済みの断片と部分的に重なる場合や、
+    /// 最後の断片の終端と矛盾する場合には、`ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn insert(&mut self, offset: u64, bytes: Vec<u8>, done: bool) -> Result<()> {
+        let end = offset + bytes.len() as u64;
+        if let Some(len) = self.len {
+            track_assert!(end <= len, ErrorKind::InvalidInput; offset, end, len);
+            track_assert!(!done || end == len, ErrorKind::InvalidInput; offset, end, len);
+        }
+        if done {
+            if let Some((&last, b)) = self.chunks.iter().next_back() {
+                let last_end = last + b.len() as u64;
+                track_assert!(last_end <= end, ErrorKind::InvalidInput; end, last_end);
+            }
+        }
+        if let Some((&prev, b)) = self.chunks.range(..=offset).next_back() {
+            if prev == offset {
+                track_assert_eq!(b.len(), bytes.len(), ErrorKind::InvalidInput; offset);
+                return Ok(());
+            }
+            let prev_end = prev + b.len() as u64;
+            track_assert!(prev_end <= offset, ErrorKind::InvalidInput; offset, prev_end);
+        }
+        if let Some((&next, _)) = self.chunks.range(offset + 1..).next() {
+            track_assert!(end <= next, ErrorKind::InvalidInput; end, next);
+        }
+
+        self.chunks.insert(offset, bytes);
+        if done {
+            self.len = Some(end);
+        }
+        Ok(())
+    }
+
+    /// 全ての断片が揃っているかどうかを判定する.
+    pub fn is_complete(&self) -> bool {
+        let len = match self.len {
+            None => return false,
+            Some(len) => len,
+        };
+        let mut expected = 0;
+        for (&offset, bytes) in &self.chunks {
+            if offset != expected {
+                return false;
+            }
+            expected += bytes.len() as u64;
+        }
+        expected == len
+    }
+
+    /// 断片群を連結して`LogPrefix`を再構築する.
+    ///
+    /// `is_complete`が`true`を返す状態で呼び出される必要がある.
+    /// 返り値の`snapshot`は圧縮されたままなので、その展開は`Common::install_snapshot`で行われる.
+    pub fn into_prefix(self) -> LogPrefix {
+        let snapshot = self.chunks.into_values().flatten().collect();
+        LogPrefix {
+            compression: self.compression,
+            ..LogPrefix::new(self.tail, self.config, snapshot)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterMembers;
+    use crate::log::LogIndex;
+    use crate::testing::MockCluster;
+    use crate::{Event, ReplicatedLogOptions};
+    use std::thread;
+    use std::time::Duration;
+
+    fn chunks() -> SnapshotChunks {
+        let tail = LogPosition {
+            prev_term: 1.into(),
+            index: LogIndex::new(10),
+        };
+        SnapshotChunks::new(
+            tail,
+            ClusterConfig::new(ClusterMembers::new()),
+            Compression::None,
+        )
+    }
+
+    #[test]
+    fn three_chunks_are_reassembled_in_any_order() {
+        let mut c = chunks();
+        c.insert(8, vec![8, 9], true).unwrap();
+        assert!(!c.is_complete());
+        c.insert(0, vec![0, 1, 2, 3], false).unwrap();
+        assert!(!c.is_complete());
+
+        // duplicate
+        c.insert(8, vec![8, 9], true).unwrap();
+        assert!(!c.is_complete());
+
+        c.insert(4, vec![4, 5, 6, 7], false).unwrap();
+        assert!(c.is_complete());
+        assert_eq!(c.into_prefix().snapshot, (0..10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn misaligned_chunks_are_rejected() {
+        let mut c = chunks();
+        c.insert(0, vec![0, 1, 2, 3], false).unwrap();
+        c.insert(8, vec![8, 9], true).unwrap();
+
+        assert!(c.insert(2, vec![2, 3, 4, 5], false).is_err());
+        assert!(c.insert(6, vec![6, 7, 8], false).is_err());
+        assert!(c.insert(10, vec![10], false).is_err());
+        assert!(c.insert(0, vec![0, 1], false).is_err());
+        assert!(!c.is_complete());
+
+        c.insert(4, vec![4, 5, 6, 7], false).unwrap();
+        assert!(c.is_complete());
+    }
+
+    #[test]
+    fn dropped_chunk_is_resent() {
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: Some(4),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let head = cluster.node("a").local_history().committed_tail().index;
+        let snapshot = (0..10).collect::<Vec<u8>>();
+        cluster
+            .node_mut("a")
+            .install_snapshot(head, snapshot.clone())
+            .unwrap();
+        cluster.run();
+        cluster.heal();
+
+        // 三つに分割された断片の内、真ん中のものを欠落させる
+        cluster.network().lock().unwrap().drop_once(|m| match m {
+            Message::InstallSnapshotChunkCast(m) => m.offset == 4,
+            _ => false,
+        });
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert!(cluster
+            .node("c")
+            .io()
+            .storage()
+            .lock()
+            .unwrap()
+            .prefix
+            .is_none());
+        let chunks = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .filter(|m| matches!(m, Message::InstallSnapshotChunkCast(_)))
+            .count();
+        assert_eq!(chunks, 3);
+
+        cluster.fire_timeout("a");
+        cluster.run();
+        let prefix = cluster
+            .node("c")
+            .io()
+            .storage()
+            .lock()
+            .unwrap()
+            .prefix
+            .clone();
+        assert_eq!(prefix.map(|p| p.snapshot), Some(snapshot));
+        assert_eq!(cluster.node("c").local_history().head().index, head);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compressed_snapshot_is_sent_without_decompression() {
+        for &chunk_size in &[None, Some(64)] {
+            let options = ReplicatedLogOptions {
+                snapshot_chunk_size: chunk_size,
+                snapshot_compression: Compression::Lz4,
+                ..Default::default()
+            };
+            let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+            cluster.elect("a");
+
+            cluster.isolate("c");
+            for i in 0..3 {
+                cluster.node_mut("a").propose_command(vec![i]).unwrap();
+                cluster.run();
+            }
+            let head = cluster.node("a").local_history().committed_tail().index;
+            let snapshot = b"0123456789".repeat(100);
+            cluster
+                .node_mut("a")
+                .install_snapshot(head, snapshot.clone())
+                .unwrap();
+            cluster.run();
+            cluster.heal();
+            cluster.fire_timeout("a");
+            cluster.run();
+
+            // 送信されたのは、圧縮後のバイト列
+            let sent = cluster
+                .network()
+                .lock()
+                .unwrap()
+                .sent_messages()
+                .iter()
+                .filter_map(|m| match m {
+                    Message::InstallSnapshotCast(m) if m.header.destination == "c".into() => {
+                        Some((m.prefix.compression, m.prefix.snapshot.len()))
+                    }
+                    Message::InstallSnapshotChunkCast(m) if m.header.destination == "c".into() => {
+                        Some((m.compression, m.bytes.len()))
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert!(!sent.is_empty(), "chunk_size={:?}", chunk_size);
+            assert!(sent.iter().all(|&(c, _)| c == Compression::Lz4));
+            let sent_bytes = sent.iter().map(|&(_, len)| len).sum::<usize>();
+            assert!(sent_bytes < snapshot.len() / 2, "sent_bytes={}", sent_bytes);
+
+            // フォロワー側で展開され、元のスナップショットと同じ内容がインストールされる
+            let prefix = cluster
+                .node("c")
+                .io()
+                .storage()
+                .lock()
+                .unwrap()
+                .prefix
+                .clone()
+                .unwrap();
+            assert_eq!(prefix.compression, Compression::Lz4);
+            assert_eq!(prefix.decompress().unwrap().snapshot, snapshot);
+            assert_eq!(cluster.node("c").local_history().head().index, head);
+        }
+    }
+
+    #[test]
+    fn stalled_snapshot_install_is_reported() {
+        let options = ReplicatedLogOptions {
+            snapshot_chunk_size: Some(4),
+            snapshot_install_stall_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let head = cluster.node("a").local_history().committed_tail().index;
+        cluster
+            .node_mut("a")
+            .install_snapshot(head, (0..10).collect())
+            .unwrap();
+        cluster.run();
+        cluster.heal();
+        assert_eq!(cluster.node("c").snapshot_install_progress(), None);
+
+        // 後続の断片を欠落させる
+        cluster.network().lock().unwrap().drop_once(|m| match m {
+            Message::InstallSnapshotChunkCast(m) => m.offset == 4,
+            _ => false,
+        });
+        cluster.fire_timeout("a");
+        cluster.run();
+        let progress = cluster.node("c").snapshot_install_progress().unwrap();
+        assert_eq!(progress.tail.index, head);
+        assert_eq!(progress.received_bytes, 6);
+        assert_eq!(progress.total_bytes, Some(10));
+        assert!(!progress.saving);
+
+        let is_stalled = |e: &Event| matches!(e, Event::SnapshotInstallStalled { .. });
+        assert!(!cluster.events("c").iter().any(is_stalled));
+        thread::sleep(Duration::from_millis(100));
+        cluster.step();
+        cluster.step();
+        assert_eq!(
+            cluster.events("c").iter().filter(|e| is_stalled(e)).count(),
+            1
+        );
+
+        // 欠落した断片が再送されれば、インストールが完了する
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.node("c").snapshot_install_progress(), None);
+        assert_eq!(cluster.node("c").local_history().head().index, head);
+    }
 }
@@ -1,7 +1,7 @@
 use futures::{Async, Future};
 use std::mem;
 
-use super::super::Common;
+use super::super::common::{self, Common};
 use crate::log::{LogEntry, LogIndex, LogSuffix};
 use crate::{Io, Result};
 
@@ -14,6 +14,7 @@ pub struct LogAppender<IO: Io> {
     task: Option<IO::SaveLog>,
     in_progress: Option<LogSuffix>,
     pendings: Vec<LogEntry>,
+    retries: usize,
 }
 impl<IO: Io> LogAppender<IO> {
     pub fn new() -> Self {
@@ -21,6 +22,7 @@ impl<IO: Io> LogAppender<IO> {
             task: None,
             in_progress: None,
             pendings: Vec::new(),
+            retries: 0,
         }
     }
     pub fn is_busy(&self) -> bool {
@@ -37,12 +39,27 @@ impl<IO: Io> LogAppender<IO> {
         }
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<Option<LogSuffix>> {
-        if let Async::Ready(Some(())) = track!(self.task.poll())? {
+        let polled = loop {
+            match track!(self.task.poll()) {
+                Err(e)
+                    if common::is_transient_error(&e)
+                        && self.retries < common::MAX_SAVE_RETRIES =>
+                {
+                    // 一時的なエラーなので、同じ内容の保存をやり直す
+                    self.retries += 1;
+                    let suffix = self.in_progress.as_ref().expect("Never fails");
+                    self.task = Some(common.save_log_suffix(suffix));
+                }
+                polled => break track!(polled)?,
+            }
+        };
+        if let Async::Ready(Some(())) = polled {
             self.task = None;
+            self.retries = 0;
             let suffix = self.in_progress.take().expect("Never fails");
             track!(common.handle_log_appended(&suffix))?;
 
-            let pendings = mem::replace(&mut self.pendings, Vec::new());
+            let pendings = mem::take(&mut self.pendings);
             if !pendings.is_empty() {
                 self.append(common, pendings);
             }
@@ -52,6 +69,15 @@ impl<IO: Io> LogAppender<IO> {
         }
     }
 
+    /// 追記処理中ないし追記待ちのエントリ群に、クラスタ構成が含まれているかどうかを判定する.
+    pub fn has_unappended_config(&self) -> bool {
+        self.in_progress
+            .iter()
+            .flat_map(|s| s.entries.iter())
+            .chain(self.pendings.iter())
+            .any(|e| matches!(e, LogEntry::Config { .. }))
+    }
+
     /// 追記処理中のものも含めて、ローカルログの終端インデックス(i.e., 長さ)を返す
     pub fn unappended_log_tail(&self, common: &Common<IO>) -> LogIndex {
         let mut tail = common.log().tail().index;
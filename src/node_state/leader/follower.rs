@@ -1,14 +1,27 @@
 use futures::{Async, Future};
-use std::collections::BTreeMap;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
 use std::mem;
+use std::time::Instant;
 use trackable::error::ErrorKindExt;
 
-use super::super::Common;
+use super::super::{CheckedLoadLog, Common};
 use crate::cluster::ClusterConfig;
-use crate::log::{Log, LogIndex};
+use crate::election::Term;
+use crate::log::{Log, LogEntry, LogHistory, LogIndex, LogSuffix};
 use crate::message::{AppendEntriesReply, SequenceNumber};
 use crate::node::NodeId;
-use crate::{ErrorKind, Io, Result};
+use crate::{ErrorKind, FollowerProgress, Io, Result};
+
+/// ビジーなフォロワーに対するログ同期のバックオフ間隔(ブロードキャスト回数)の上限の指数.
+const MAX_BUSY_BACKOFF_SHIFT: u32 = 6;
+
+/// メトリクス計測用に保持しておく、送信済みの`AppendEntriesCall`の最大数.
+const MAX_SENT_APPENDS: usize = 128;
+
+/// フォロワーへの追記速度の指数移動平均における、最新の観測値の重み.
+const APPEND_RATE_WEIGHT: f64 = 0.5;
 
 /// フォロワーの管理者.
 ///
@@ -19,26 +32,75 @@ pub struct FollowersManager<IO: Io> {
     followers: BTreeMap<NodeId, Follower>,
     config: ClusterConfig,
     latest_hearbeat_ack: SequenceNumber,
+    latest_hearbeat_ack_at: Option<Instant>,
     last_broadcast_seq_no: SequenceNumber,
 
+    // 管理開始時点で発行済みの最新のシーケンス番号.
+    //
+    // シーケンス番号の巡回を考慮した合意値の算出に、基準点として使用される.
+    seq_no_base: SequenceNumber,
+
+    // これまでに行われたブロードキャストの回数 (ビジーなフォロワーのバックオフ管理に使用される).
+    broadcast_rounds: u64,
+
     // `raft_test_simu`のために非決定的な要素は排除したいので、
     // `HashMap`ではなく`BTreeMap`を使用している.
-    tasks: BTreeMap<NodeId, IO::LoadLog>,
+    tasks: BTreeMap<NodeId, CheckedLoadLog<IO>>,
+
+    // ログ同期が必要だが、同時実行数の上限により待機中のフォロワー群.
+    //
+    // 先頭から順番にスケジュールされるので、
+    // 特定のフォロワーが同期処理の枠を独占してしまうことはない.
+    waiting: VecDeque<NodeId>,
+
+    // コミットの際に、過半数に加えて、常にエントリの保持が要求されるレプリカ群.
+    synchronous_replicas: BTreeSet<NodeId>,
+
+    // 最近送信した`AppendEntriesCall`群 (フォロワー毎のスループットと応答時間の計測に使用される).
+    sent_appends: VecDeque<SentAppend>,
 }
 impl<IO: Io> FollowersManager<IO> {
     pub fn new(config: ClusterConfig) -> Self {
         let followers = config
             .members()
-            .map(|n| (n.clone(), Follower::new()))
+            .map(|n| (n.clone(), Follower::new(SequenceNumber::new(0))))
             .collect();
         FollowersManager {
             followers,
             config,
             tasks: BTreeMap::new(),
+            waiting: VecDeque::new(),
             latest_hearbeat_ack: SequenceNumber::new(0),
+            latest_hearbeat_ack_at: None,
             last_broadcast_seq_no: SequenceNumber::new(0),
+            seq_no_base: SequenceNumber::new(0),
+            broadcast_rounds: 0,
+            synchronous_replicas: BTreeSet::new(),
+            sent_appends: VecDeque::new(),
         }
     }
+
+    /// 以降のメッセージ送信に使用されるシーケンス番号の開始値を設定する.
+    ///
+    /// `next_seq_no`よりも前の番号は全て発行済みのものとして扱われ、
+    /// 以後のシーケンス番号の比較は、巡回を考慮してこの地点を基準に行われる.
+    pub fn set_seq_no_base(&mut self, next_seq_no: SequenceNumber) {
+        let base = SequenceNumber::new(next_seq_no.as_u64().wrapping_sub(1));
+        self.seq_no_base = base;
+        self.latest_hearbeat_ack = base;
+        self.last_broadcast_seq_no = base;
+        for f in self.followers.values_mut() {
+            f.last_seq_no = base;
+            f.obsolete_seq_no = base;
+        }
+    }
+
+    /// コミットの際に、過半数に加えて、常にエントリの保持が要求されるレプリカ群を設定する.
+    ///
+    /// 現在のクラスタ構成に含まれないレプリカは無視される.
+    pub fn set_synchronous_replicas(&mut self, replicas: BTreeSet<NodeId>) {
+        self.synchronous_replicas = replicas;
+    }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<()> {
         // バックグランドタスク(ログ同期用の読み込み処理)を実行する.
         let mut dones = Vec::new();
@@ -47,33 +109,118 @@ impl<IO: Io> FollowersManager<IO> {
                 dones.push((follower.clone(), log));
             }
         }
+        if let Some(ref order) = common.options().follower_order {
+            dones.sort_by(|a, b| order.sort_key(&a.0).cmp(&order.sort_key(&b.0)));
+        }
+        let max_inflight = max_inflight_appends(common);
         for (follower, log) in dones {
+            let max_bytes = common.options().max_append_bytes;
+            let chunk_size = common.options().snapshot_chunk_size;
+            let leader_tail = common.log().tail().index;
+            let seq_no = common.next_seq_no();
             let rpc = common.rpc_caller();
             match log {
-                Log::Prefix(snapshot) => rpc.send_install_snapshot(&follower, snapshot),
-                Log::Suffix(slice) => rpc.send_append_entries(&follower, slice),
+                Log::Prefix(snapshot) => {
+                    // スナップショットは圧縮されたまま送信し、展開は受信側に任せる
+                    if let Some(chunk_size) = chunk_size {
+                        rpc.send_install_snapshot_chunks(&follower, snapshot, chunk_size)
+                    } else {
+                        rpc.send_install_snapshot(&follower, snapshot)
+                    }
+                }
+                Log::Suffix(mut slice) => {
+                    if let Some(max_bytes) = max_bytes {
+                        truncate_by_bytes(&mut slice, max_bytes);
+                    }
+                    if let Some(f) = self.followers.get_mut(&follower) {
+                        if f.synced && max_inflight > 1 {
+                            // 応答を待たずに、続きの差分の送信を開始できるようにする
+                            let end = slice.tail().index;
+                            f.inflight.push_back((seq_no, end));
+                            if f.inflight.len() < max_inflight
+                                && end < leader_tail
+                                && !self.waiting.contains(&follower)
+                            {
+                                self.waiting.push_back(follower.clone());
+                            }
+                        }
+                    }
+                    self.sent_appends.push_back(SentAppend::new(seq_no, &slice));
+                    rpc.send_append_entries(&follower, slice)
+                }
             }
             self.tasks.remove(&follower);
         }
+        while self.sent_appends.len() > MAX_SENT_APPENDS {
+            self.sent_appends.pop_front();
+        }
+        self.schedule_syncs(common);
         Ok(())
     }
-    pub fn latest_hearbeat_ack(&self) -> SequenceNumber {
+    /// ログ同期用の読み込み処理を実行中かどうかを判定する.
+    pub fn has_pending_loads(&self) -> bool {
+        !self.tasks.is_empty()
+    }
+
+    /// 投票権を有するメンバの過半数から応答を得られた、最新のシーケンス番号を返す.
+    pub fn majority_acked_seq_no(&self) -> SequenceNumber {
         self.latest_hearbeat_ack
     }
 
+    /// `majority_acked_seq_no`の値が最後に進んだ時刻を返す.
+    ///
+    /// 一度も進んでいない場合には`None`が返される.
+    pub fn majority_acked_at(&self) -> Option<Instant> {
+        self.latest_hearbeat_ack_at
+    }
+
+    /// 投票権を有するメンバの過半数から、最後に応答を受信した時刻を返す.
+    ///
+    /// ローカルノード(`local`)は、常に現在時刻に応答したものとして扱われる.
+    /// 一度も応答していないフォロワーについては、そのフォロワーの管理を開始した時刻が使用される.
+    pub fn quorum_contact(&self, local: &NodeId) -> Result<Instant> {
+        let now = Instant::now();
+        let contact = track!(self.config.consensus_value(|node_id| {
+            if node_id == local {
+                Some(now)
+            } else {
+                self.followers.get(node_id).map(|f| f.last_ack_at)
+            }
+        }))?;
+        Ok(contact.unwrap_or(now))
+    }
+
     /// コミット済みログ領域の終端を返す.
     ///
     /// "コミット済み"とは「投票権を有するメンバの過半数以上のローカルログに存在する」ということを意味する.
     /// (構成変更中で、新旧構成の両方に投票権が存在する場合には、そのそれぞれの過半数以上)
-    pub fn committed_log_tail(&self) -> LogIndex {
-        self.config.consensus_value(|node_id| {
-            let f = &self.followers[node_id];
-            if f.synced {
-                f.log_tail
-            } else {
-                LogIndex::new(0)
+    ///
+    /// 過半数の判定には、最新の構成ではなく、各地点の時点で有効だった構成が使用される
+    /// (`ClusterConfig::effective_for_commit`).
+    ///
+    /// 同期レプリカ(`set_synchronous_replicas`)が設定されている場合には、
+    /// その全てのローカルログに存在することも要求される.
+    pub fn committed_log_tail(&self, history: &LogHistory) -> Result<LogIndex> {
+        let log_tail = |node_id: &NodeId| match self.followers.get(node_id) {
+            Some(f) if f.synced => f.log_tail,
+            _ => LogIndex::new(0),
+        };
+
+        // 末尾から、同じ構成が有効な区間毎に遡って、合意が得られている地点を探す
+        let mut tail = self
+            .synchronous_replicas
+            .iter()
+            .filter(|id| self.followers.contains_key(*id))
+            .map(log_tail)
+            .fold(history.tail().index, cmp::min);
+        loop {
+            let config = ClusterConfig::effective_for_commit(history, tail);
+            let committed = cmp::min(tail, track!(config.consensus_value(log_tail))?);
+            match history.get_record(tail) {
+                Some(r) if committed < r.head.index => tail = r.head.index - 1,
+                _ => return Ok(committed),
             }
-        })
+        }
     }
 
     /// ジョイントコミット済みのログ領域の終端を返す.
@@ -81,47 +228,180 @@ impl<IO: Io> FollowersManager<IO> {
     /// 基本的には`committed_log_tail`と同じ動作となるが、
     /// 「構成変更中」かつ「`ClusterState`の値が`CatchUp`」の場合でも、
     /// こちらの関数は常に新旧両方から「過半数以上」を要求する点が異なる.
-    pub fn joint_committed_log_tail(&self) -> LogIndex {
-        self.config.full_consensus_value(|node_id| {
+    pub fn joint_committed_log_tail(&self) -> Result<LogIndex> {
+        track!(self.config.full_consensus_value(|node_id| {
             let f = &self.followers[node_id];
             if f.synced {
                 f.log_tail
             } else {
                 LogIndex::new(0)
             }
-        })
+        }))
     }
 
     pub fn handle_append_entries_reply(
         &mut self,
         common: &Common<IO>,
         reply: &AppendEntriesReply,
-    ) -> bool {
+    ) -> Result<bool> {
         let updated = self.update_follower_state(common, reply);
-        if self.latest_hearbeat_ack < reply.header.seq_no {
-            self.latest_hearbeat_ack = self
-                .config
-                .consensus_value(|node_id| self.followers[node_id].last_seq_no);
+        if reply.header.seq_no.is_newer_than(self.latest_hearbeat_ack) {
+            // 番号の巡回に備えて、基準点からの距離で合意値を求める
+            let base = self.seq_no_base.as_u64();
+            let offset = track!(self.config.consensus_value(|node_id| {
+                self.followers[node_id]
+                    .last_seq_no
+                    .as_u64()
+                    .wrapping_sub(base)
+            }))?;
+            let acked = SequenceNumber::new(base.wrapping_add(offset));
+            if acked.is_newer_than(self.latest_hearbeat_ack) {
+                self.latest_hearbeat_ack = acked;
+                self.latest_hearbeat_ack_at = Some(Instant::now());
+            }
+        }
+        Ok(updated)
+    }
+
+    /// フォロワーのローカルログが`tail`まで追い付いているかどうかを判定する.
+    pub fn is_caught_up(&self, follower: &NodeId, tail: LogIndex) -> bool {
+        self.followers
+            .get(follower)
+            .is_some_and(|f| f.synced && f.log_tail >= tail)
+    }
+
+    /// ローカルノード以外の各フォロワーのログ同期の進捗を返す.
+    pub fn progress(&self, common: &Common<IO>) -> Vec<FollowerProgress> {
+        let local = &common.local_node().id;
+        self.followers
+            .iter()
+            .filter(|(id, _)| *id != local)
+            .map(|(id, f)| FollowerProgress {
+                node: id.clone(),
+                log_tail: f.log_tail,
+                leader_tail: common.log().tail().index,
+                synced: f.synced,
+                append_rate: f.append_rate,
+            })
+            .collect()
+    }
+
+    /// ログ同期用の読み込み処理を実行中のフォロワーの数を返す.
+    ///
+    /// `ReplicatedLogOptions::max_concurrent_syncs`が指定されている場合には、その値を超えることはない.
+    pub fn inflight_syncs(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// ログ同期用の読み込み処理を実行中のフォロワー群を返す.
+    pub fn pending_sync_targets(&self) -> Vec<NodeId> {
+        self.tasks.keys().cloned().collect()
+    }
+
+    /// ローカルログ(の終端`leader_tail`)よりも遅れているが、
+    /// ログ同期用の読み込み処理を実行していないフォロワー群を返す.
+    ///
+    /// 同時実行数の上限により、同期処理の開始を待機しているフォロワーも、これに含まれる.
+    pub fn waiting_sync_targets(&self, leader_tail: LogIndex) -> Vec<NodeId> {
+        self.followers
+            .iter()
+            .filter(|(id, f)| f.log_tail < leader_tail && !self.tasks.contains_key(*id))
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// フォロワーから停止通知を受け取ったことを記録する.
+    ///
+    /// そのフォロワーから再び応答を受け取るまでの間は、ログ同期は行われない.
+    pub fn handle_departure(&mut self, follower: &NodeId) {
+        if let Some(f) = self.followers.get_mut(follower) {
+            f.departed = true;
+            f.inflight.clear();
+            self.tasks.remove(follower);
+            self.waiting.retain(|id| id != follower);
+        }
+    }
+
+    /// リーダシップの移譲先として最適なフォロワーを返す.
+    ///
+    /// 投票権を有するメンバ(`local`とウィットネスは除く)の内で、停止通知を送ってきておらず、
+    /// かつ、ローカルログが最も進んでいるものが選択される.
+    pub fn transfer_candidate(&self, local: &NodeId) -> Option<NodeId> {
+        self.config
+            .primary_members()
+            .iter()
+            .filter(|id| *id != local && !self.config.is_witness(id))
+            .filter_map(|id| self.followers.get(id).map(|f| (id, f)))
+            .filter(|(_, f)| !f.departed)
+            .max_by_key(|(_, f)| (f.synced, f.log_tail))
+            .map(|(id, _)| id.clone())
+    }
+
+    /// `local`以外の各フォロワーの同期状況を、一行ずつ`buf`に追記する.
+    pub fn describe(&self, local: &NodeId, buf: &mut String) {
+        for (id, f) in self.followers.iter().filter(|(id, _)| *id != local) {
+            let _ = writeln!(
+                buf,
+                "  {}: log_tail={}, synced={}, syncing={}, departed={}, backoff={}",
+                id.as_str(),
+                f.log_tail.as_u64(),
+                f.synced,
+                self.tasks.contains_key(id),
+                f.departed,
+                f.next_eligible_round.saturating_sub(self.broadcast_rounds)
+            );
         }
-        updated
     }
 
     pub fn set_last_broadcast_seq_no(&mut self, seq_no: SequenceNumber) {
         self.last_broadcast_seq_no = seq_no;
+        self.broadcast_rounds += 1;
     }
 
-    /// フォロワーのローカルログとの同期処理を実行する.
-    pub fn log_sync(&mut self, common: &mut Common<IO>, reply: &AppendEntriesReply) -> Result<()> {
-        if reply.busy || self.tasks.contains_key(&reply.header.sender) {
-            // フォロワーが忙しい or 既に同期処理が進行中
-            return Ok(());
+    /// シーケンス番号`seq_no`で`slice`をブロードキャストすることを記録する.
+    pub fn record_broadcast(&mut self, seq_no: SequenceNumber, slice: &LogSuffix) {
+        self.set_last_broadcast_seq_no(seq_no);
+        self.sent_appends.push_back(SentAppend::new(seq_no, slice));
+        if self.sent_appends.len() > MAX_SENT_APPENDS {
+            self.sent_appends.pop_front();
         }
+    }
 
+    /// フォロワーのログ同期のバックオフの残り(ブロードキャスト回数)を返す.
+    ///
+    /// バックオフ中ではない場合には`0`が、未知のフォロワーの場合には`None`が返される.
+    pub fn sync_backoff(&self, follower: &NodeId) -> Option<u64> {
+        self.followers
+            .get(follower)
+            .map(|f| f.next_eligible_round.saturating_sub(self.broadcast_rounds))
+    }
+
+    /// フォロワーのローカルログとの同期処理を実行する.
+    ///
+    /// フォロワーからビジー応答が返される度に、次に同期を試みるまでの間隔(ブロードキャスト回数)は
+    /// 指数的に延ばされ、フォロワーのログが進んだ時点で元に戻される.
+    pub fn log_sync(&mut self, common: &mut Common<IO>, reply: &AppendEntriesReply) -> Result<()> {
         let follower = track!(self
             .followers
             .get_mut(&reply.header.sender)
             .ok_or_else(|| ErrorKind::InconsistentState.error()))?;
-        if reply.header.seq_no <= follower.obsolete_seq_no {
+        if reply.busy {
+            // フォロワーが忙しいので、一定期間は同期を控える
+            // (単発のビジー応答では待機は発生せず、連続する度に`0, 1, 3, 7, ...`回と間隔が延びる)
+            follower.busy_streak += 1;
+            let shift = cmp::min(follower.busy_streak - 1, MAX_BUSY_BACKOFF_SHIFT);
+            follower.next_eligible_round = self.broadcast_rounds + (1 << shift) - 1;
+            return Ok(());
+        }
+        if self.broadcast_rounds < follower.next_eligible_round {
+            // バックオフ中
+            return Ok(());
+        }
+        if self.tasks.contains_key(&reply.header.sender) {
+            // 既に同期処理が進行中
+            return Ok(());
+        }
+        if !reply.header.seq_no.is_newer_than(follower.obsolete_seq_no) {
             // 平行度が高くなりすぎるのを防止するために、
             // propose(broadcast)が重なった場合には、
             // `obsolete_seq_no`以前のbroadcastに対する応答は古いものとして処理を省く.
@@ -129,72 +409,180 @@ impl<IO: Io> FollowersManager<IO> {
         }
         follower.obsolete_seq_no = self.last_broadcast_seq_no;
 
-        if common.log().tail().index <= follower.log_tail {
-            // The follower is up-to-date
+        if common.log().tail().index <= follower.next_index() {
+            // The follower is up-to-date (or all the remaining entries are in flight)
             return Ok(());
         }
 
-        let end = if follower.synced {
-            // フォロワーのログとリーダのログの差分を送信
-            common.log().tail().index
-        } else {
-            // フォロワーのログとリーダのログの同期(合流)点を探索中
-            follower.log_tail
-        };
-        let future = common.load_log(follower.log_tail, Some(end));
-        self.tasks.insert(reply.header.sender.clone(), future);
+        if !self.waiting.contains(&reply.header.sender) {
+            self.waiting.push_back(reply.header.sender.clone());
+        }
+        self.schedule_syncs(common);
         Ok(())
     }
 
+    /// 同時実行数の上限に達しない範囲で、待機中のフォロワーのログ同期処理を開始する.
+    fn schedule_syncs(&mut self, common: &mut Common<IO>) {
+        let max_syncs = common
+            .options()
+            .max_concurrent_syncs
+            .map_or(usize::MAX, |n| cmp::max(n, 1));
+        let max_inflight = max_inflight_appends(common);
+        while self.tasks.len() < max_syncs {
+            let next = match common.options().follower_order {
+                None => self.waiting.pop_front(),
+                Some(ref order) => {
+                    // 到着順ではなく、順番が最も先のものを選ぶ
+                    let i =
+                        (0..self.waiting.len()).min_by_key(|&i| order.sort_key(&self.waiting[i]));
+                    i.and_then(|i| self.waiting.remove(i))
+                }
+            };
+            let id = match next {
+                None => break,
+                Some(id) => id,
+            };
+            let follower = match self.followers.get(&id) {
+                None => continue, // 構成変更によって削除された
+                Some(f) => f,
+            };
+            let start = follower.next_index();
+            if self.tasks.contains_key(&id)
+                || follower.departed
+                || common.log().tail().index <= start
+                || follower.inflight.len() >= max_inflight
+            {
+                continue;
+            }
+
+            let end = if follower.synced {
+                // フォロワーのログとリーダのログの差分を送信
+                common.log().tail().index
+            } else if follower.log_tail < common.log().history_head().index {
+                // 歴史が破棄された領域ではリーダ側で同期点を判定できないので、
+                // 差分を送信して、フォロワー側に判定して貰う
+                common.log().tail().index
+            } else {
+                // フォロワーのログとリーダのログの同期(合流)点を探索中
+                follower.log_tail
+            };
+            // 一つのメッセージが巨大になり過ぎないように、送信するエントリ数やバイト数を制限する.
+            // 残りの差分は、このメッセージに対する応答を受け取った後
+            // (パイプライン化が有効な場合は、このメッセージの送信後)に送信される.
+            let max_entries = common
+                .options()
+                .max_append_entries
+                .map(|n| cmp::min(end - start, cmp::max(n, 1)));
+            let future = match (max_entries, common.options().max_append_bytes) {
+                (None, None) => common.load_log(start, Some(end)),
+                (Some(max_entries), None) => common.load_log_limited(start, max_entries),
+                (max_entries, Some(max_bytes)) => {
+                    // 送信時に切り詰められるエントリ群まで、ストレージから読み込まないようにする
+                    let max_entries = max_entries.unwrap_or(end - start);
+                    common.load_log_limited_bytes(start, max_entries, max_bytes)
+                }
+            };
+            self.tasks.insert(id, future);
+        }
+    }
+
     /// クラスタ構成の変更に追従する.
     pub fn handle_config_updated(&mut self, config: &ClusterConfig) {
         // Add
         for id in config.members() {
             if !self.followers.contains_key(id) {
-                self.followers.insert(id.clone(), Follower::new());
+                let follower = Follower::new(self.seq_no_base);
+                self.followers.insert(id.clone(), follower);
             }
         }
 
         // Delete
-        self.followers = mem::replace(&mut self.followers, BTreeMap::new())
+        self.followers = mem::take(&mut self.followers)
             .into_iter()
-            .filter(|&(ref id, _)| config.is_known_node(id))
+            .filter(|(id, _)| config.is_known_node(id))
             .collect();
+        self.waiting.retain(|id| config.is_known_node(id));
 
         self.config = config.clone();
     }
 
     fn update_follower_state(&mut self, common: &Common<IO>, reply: &AppendEntriesReply) -> bool {
-        let follower = &mut self
-            .followers
-            .get_mut(&reply.header.sender)
-            .expect("Never fails");
-        if follower.last_seq_no < reply.header.seq_no {
+        let sender = &reply.header.sender;
+        let metrics = if *sender == common.local_node().id {
+            None
+        } else {
+            common.metrics().follower(sender).ok()
+        };
+        let sent = self
+            .sent_appends
+            .iter()
+            .rev()
+            .find(|s| s.seq_no == reply.header.seq_no);
+        if let (Some(m), Some(s)) = (&metrics, sent) {
+            let elapsed = prometrics::timestamp::duration_to_seconds(s.sent_at.elapsed());
+            m.append_latency_seconds.observe(elapsed);
+        }
+
+        let follower = &mut self.followers.get_mut(sender).expect("Never fails");
+        if reply.header.seq_no.is_newer_than(follower.last_seq_no) {
             follower.last_seq_no = reply.header.seq_no;
         }
+        follower.last_ack_at = Instant::now();
+        follower.departed = false;
+
+        // 応答済みのメッセージ以前に送信したものは、処理済みか、あるいは失われている
+        // (後者の場合は、次回の同期時に`log_tail`から再送される)
+        let seq_no = reply.header.seq_no;
+        follower.inflight.retain(|&(s, _)| s.is_newer_than(seq_no));
+        if follower.epoch != Some(reply.epoch) {
+            // 初回の応答、あるいは、フォロワーが再起動した
+            // => フォロワーのログの内容は不明なので、同期位置を一から探索し直す
+            follower.epoch = Some(reply.epoch);
+            follower.synced = false;
+        }
         match *reply {
             AppendEntriesReply { busy: true, .. } => false,
             AppendEntriesReply { log_tail, .. } if follower.synced => {
                 let updated = follower.log_tail < log_tail.index;
                 if updated {
+                    if let Some(m) = metrics {
+                        let entries = log_tail.index - follower.log_tail;
+                        let bytes =
+                            sent.map_or(0, |s| s.bytes_between(follower.log_tail, log_tail.index));
+                        m.appended_entries_total.add_u64(entries as u64);
+                        m.appended_bytes_total.add_u64(bytes as u64);
+                    }
+                    follower.record_advance(log_tail.index);
                     follower.log_tail = log_tail.index;
-                } else if log_tail.index.as_u64() == 0 && follower.log_tail.as_u64() != 0 {
-                    // NOTE: followerのデータがクリアされたものと判断する
-                    // FIXME: ちゃんとした実装にする(e.g., ノードに再起動毎に替わるようなIDを付与して、その一致を確認する)
-                    follower.synced = false;
+                    follower.busy_streak = 0;
+                    follower.next_eligible_round = 0;
                 }
                 updated
             }
             AppendEntriesReply { log_tail, .. } => {
-                let leader_term = common
-                    .log()
-                    .get_record(log_tail.index)
-                    .map(|r| r.head.prev_term);
+                // 同期点の探索中はパイプライン化を行わない
+                follower.inflight.clear();
+                let leader_term = common.log().term_at(log_tail.index);
                 follower.synced = leader_term == Some(log_tail.prev_term);
                 if follower.synced {
                     follower.log_tail = log_tail.index;
                 } else {
-                    follower.log_tail = log_tail.index.as_u64().saturating_sub(1).into();
+                    let mut next =
+                        if leader_term.is_none() && common.log().head().index <= log_tail.index {
+                            // 歴史は破棄されているが、ローカルログにはエントリが残っている
+                            log_tail.index
+                        } else {
+                            log_tail.index.as_u64().saturating_sub(1).into()
+                        };
+                    if let Some(term) = reply.conflict_term {
+                        // 分岐した`Term`に属するエントリ群を一度に飛び越える:
+                        // - リーダのログにその`Term`のエントリが存在する場合は、その終端まで戻る
+                        // - 存在しない場合は、フォロワーのログでのその`Term`の開始位置まで戻る
+                        let jump = last_position_of_term(common.log(), term)
+                            .unwrap_or(reply.conflict_index);
+                        next = cmp::min(next, jump);
+                    }
+                    follower.log_tail = next;
                 }
                 follower.synced
             }
@@ -202,6 +590,81 @@ impl<IO: Io> FollowersManager<IO> {
     }
 }
 
+/// リーダのログにおける、`term`に属する最後のエントリの直後の位置を返す.
+///
+/// ローカルログ(の削除されていない領域)に`term`のエントリが存在しない場合には`None`が返される.
+fn last_position_of_term(log: &LogHistory, term: Term) -> Option<LogIndex> {
+    let mut index = log.tail().index;
+    loop {
+        let record = log.get_record(index)?;
+        if record.head.prev_term == term {
+            return Some(index);
+        }
+        if record.head.prev_term < term || record.head.index <= log.history_head().index {
+            return None;
+        }
+
+        // `record.head`は、`record`の`Term`の最初のエントリの直後の位置なので、
+        // その一つ前の位置(`record.head.index - 1`)が、直前の`Term`の最後のエントリの直後の位置となる
+        index = record.head.index - 1;
+    }
+}
+
+/// `suffix`に含まれる`Command`ペイロードの合計サイズが`max_bytes`以下になるように末尾を切り詰める.
+///
+/// ただし、同期が停滞しないように、先頭のエントリは常に残される.
+fn truncate_by_bytes(suffix: &mut LogSuffix, max_bytes: usize) {
+    let mut bytes = 0;
+    let mut len = 0;
+    for e in &suffix.entries {
+        if let LogEntry::Command { ref command, .. } = *e {
+            bytes += command.len();
+        }
+        if len > 0 && bytes > max_bytes {
+            break;
+        }
+        len += 1;
+    }
+    suffix.entries.truncate(len);
+}
+
+/// 送信済みの`AppendEntriesCall`の情報.
+#[derive(Debug)]
+struct SentAppend {
+    seq_no: SequenceNumber,
+    sent_at: Instant,
+    head: LogIndex,
+
+    // 各エントリの`Command`ペイロードのサイズ.
+    entry_bytes: Vec<usize>,
+}
+impl SentAppend {
+    fn new(seq_no: SequenceNumber, slice: &LogSuffix) -> Self {
+        let entry_bytes = slice
+            .entries
+            .iter()
+            .map(|e| match e {
+                LogEntry::Command { command, .. } => command.len(),
+                _ => 0,
+            })
+            .collect();
+        SentAppend {
+            seq_no,
+            sent_at: Instant::now(),
+            head: slice.head.index,
+            entry_bytes,
+        }
+    }
+
+    // `[start, end)`の範囲に含まれる(このメッセージで送信された)エントリ群の合計サイズを返す.
+    fn bytes_between(&self, start: LogIndex, end: LogIndex) -> usize {
+        (start.as_u64()..end.as_u64())
+            .filter(|&i| self.head.as_u64() <= i)
+            .filter_map(|i| self.entry_bytes.get((i - self.head.as_u64()) as usize))
+            .sum()
+    }
+}
+
 #[derive(Debug)]
 struct Follower {
     pub obsolete_seq_no: SequenceNumber,
@@ -209,15 +672,998 @@ struct Follower {
     pub log_tail: LogIndex,
     pub last_seq_no: SequenceNumber,
     pub synced: bool,
+
+    // フォロワーのノードの起動毎に替わる識別子 (最後に受信した応答に含まれていたもの).
+    pub epoch: Option<u64>,
+
+    // 停止通知(`DepartureCast`)を受け取ってから、まだ応答を受信していない場合には`true`.
+    pub departed: bool,
+
+    // 連続してビジー応答を受け取った回数と、次にログ同期を試みることが可能なブロードキャストの回数.
+    pub busy_streak: u32,
+    pub next_eligible_round: u64,
+
+    // 最後に応答を受信した時刻 (未受信の場合は、管理を開始した時刻).
+    pub last_ack_at: Instant,
+
+    // 応答待ちの(パイプライン化された)ログ同期用の`AppendEntriesCall`群.
+    //
+    // 各要素は、送信時のシーケンス番号と、送信したエントリ群の終端の組で、送信順に並んでいる.
+    pub inflight: VecDeque<(SequenceNumber, LogIndex)>,
+
+    // 最後に`log_tail`が進んだ時刻と、追記速度(エントリ数/秒)の指数移動平均.
+    pub last_advance_at: Option<Instant>,
+    pub append_rate: Option<f64>,
 }
 impl Follower {
-    pub fn new() -> Self {
+    pub fn new(seq_no_base: SequenceNumber) -> Self {
         Follower {
-            obsolete_seq_no: SequenceNumber::new(0),
+            obsolete_seq_no: seq_no_base,
 
             log_tail: LogIndex::new(0),
-            last_seq_no: SequenceNumber::new(0),
+            last_seq_no: seq_no_base,
             synced: false,
+            epoch: None,
+            departed: false,
+            busy_streak: 0,
+            next_eligible_round: 0,
+            last_ack_at: Instant::now(),
+            inflight: VecDeque::new(),
+            last_advance_at: None,
+            append_rate: None,
+        }
+    }
+
+    // `log_tail`が`new_tail`まで進んだことを、追記速度に反映する.
+    fn record_advance(&mut self, new_tail: LogIndex) {
+        let now = Instant::now();
+        if let Some(last) = self.last_advance_at {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = (new_tail - self.log_tail) as f64 / elapsed;
+                self.append_rate = Some(match self.append_rate {
+                    None => rate,
+                    Some(r) => r * (1.0 - APPEND_RATE_WEIGHT) + rate * APPEND_RATE_WEIGHT,
+                });
+            }
+        }
+        self.last_advance_at = Some(now);
+    }
+
+    // 次のログ同期で送信を開始する位置を返す.
+    //
+    // 応答待ちの`AppendEntriesCall`がある場合には、それらの続きから送信する.
+    pub fn next_index(&self) -> LogIndex {
+        self.inflight
+            .back()
+            .map_or(self.log_tail, |&(_, end)| cmp::max(end, self.log_tail))
+    }
+}
+
+/// フォロワー毎に許容される、応答待ちのログ同期用`AppendEntriesCall`の数を返す.
+fn max_inflight_appends<IO: Io>(common: &Common<IO>) -> usize {
+    common
+        .options()
+        .max_inflight_appends
+        .map_or(1, |n| cmp::max(n, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use prometrics::metrics::MetricBuilder;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use trackable::result::TestResult;
+
+    use super::*;
+    use crate::cluster::ClusterState;
+    use crate::log::LogPosition;
+    use crate::message::{GroupId, Message, MessageHeader};
+    use crate::metrics::NodeStateMetrics;
+    use crate::node::FollowerOrder;
+    use crate::testing::{MockCluster, MockIo};
+    use crate::ReplicatedLogOptions;
+
+    fn sync_message_sizes(cluster: &MockCluster, to: &str) -> Vec<(usize, usize)> {
+        let network = cluster.network().lock().expect("Never fails");
+        network
+            .sent_messages()
+            .iter()
+            .filter_map(|m| match m {
+                Message::AppendEntriesCall(m)
+                    if m.header.destination.as_str() == to && !m.suffix.entries.is_empty() =>
+                {
+                    let bytes = m
+                        .suffix
+                        .entries
+                        .iter()
+                        .map(|e| match e {
+                            LogEntry::Command { command, .. } => command.len(),
+                            _ => 0,
+                        })
+                        .sum();
+                    Some((m.suffix.entries.len(), bytes))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// 遅れたフォロワー`c`を追い付かせた後のクラスタと、その間にリーダがストレージから読み込んだエントリ数を返す.
+    fn catch_up_lagging_follower(options: ReplicatedLogOptions) -> (MockCluster, usize) {
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..10 {
+            cluster.node_mut("a").propose_command(vec![i; 10]).unwrap();
+            cluster.run();
+        }
+        assert!(cluster.log_suffix("c").tail().index < cluster.log_suffix("a").tail().index);
+
+        cluster.heal();
+        cluster.network().lock().unwrap().clear_sent_messages();
+        let loaded = cluster.node("a").io().loaded_entries();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        let loaded = cluster.node("a").io().loaded_entries() - loaded;
+        (cluster, loaded)
+    }
+
+    #[test]
+    fn log_sync_is_bounded_by_entry_count() {
+        let options = ReplicatedLogOptions {
+            max_append_entries: Some(3),
+            ..Default::default()
+        };
+        let (cluster, _) = catch_up_lagging_follower(options);
+        let sizes = sync_message_sizes(&cluster, "c");
+        assert!(sizes.len() > 1, "{:?}", sizes);
+        assert!(sizes.iter().all(|&(n, _)| n <= 3), "{:?}", sizes);
+    }
+
+    #[test]
+    fn log_sync_is_bounded_by_bytes() {
+        let options = ReplicatedLogOptions {
+            max_append_bytes: Some(25),
+            ..Default::default()
+        };
+        let (cluster, loaded) = catch_up_lagging_follower(options);
+        let sizes = sync_message_sizes(&cluster, "c");
+        assert!(sizes.len() > 1, "{:?}", sizes);
+        assert!(sizes.iter().all(|&(_, bytes)| bytes <= 25), "{:?}", sizes);
+
+        // 送信されないエントリ群は、ストレージからも読み込まれない
+        let sent = sizes.iter().map(|&(n, _)| n).sum::<usize>();
+        assert_eq!(loaded, sent);
+    }
+
+    #[test]
+    fn log_sync_is_unbounded_by_default() {
+        let (cluster, _) = catch_up_lagging_follower(ReplicatedLogOptions::default());
+        let sizes = sync_message_sizes(&cluster, "c");
+        assert_eq!(sizes.len(), 1, "{:?}", sizes);
+    }
+
+    #[test]
+    fn log_sync_is_pipelined() {
+        let options = ReplicatedLogOptions {
+            max_append_entries: Some(1),
+            max_inflight_appends: Some(4),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..10 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        cluster.heal();
+        cluster.network().lock().unwrap().set_delay("c", 3);
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.fire_timeout("a");
+
+        // 遅延の大きいフォロワーに対して、応答を待たずに複数の差分が送信される
+        let mut max_inflight = 0;
+        while cluster.step() {
+            let network = cluster.network().lock().unwrap();
+            let mut acked = SequenceNumber::new(0);
+            let mut sent = Vec::new();
+            for m in network.sent_messages() {
+                match m {
+                    Message::AppendEntriesCall(m)
+                        if m.header.destination.as_str() == "c" && !m.suffix.entries.is_empty() =>
+                    {
+                        sent.push(m.header.seq_no);
+                    }
+                    Message::AppendEntriesReply(m) if m.header.sender.as_str() == "c" => {
+                        acked = cmp::max(acked, m.header.seq_no);
+                    }
+                    _ => {}
+                }
+            }
+            let inflight = sent.iter().filter(|&&s| acked < s).count();
+            max_inflight = cmp::max(max_inflight, inflight);
+        }
+        assert!(max_inflight > 1, "max_inflight={}", max_inflight);
+        assert!(max_inflight <= 4, "max_inflight={}", max_inflight);
+
+        // 最終的には、ログは収束する
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        let entries = |id| cluster.log_suffix(id).entries;
+        assert_eq!(entries("c"), entries("a"));
+    }
+
+    #[test]
+    fn log_sync_is_pipelined_even_if_messages_are_reordered() {
+        let options = ReplicatedLogOptions {
+            max_append_entries: Some(1),
+            max_inflight_appends: Some(4),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..10 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        cluster.heal();
+        cluster.network().lock().unwrap().set_delay("c", 3);
+        cluster.network().lock().unwrap().set_delay("a", 2);
+        cluster.network().lock().unwrap().set_shuffle_seed(Some(7));
+        cluster.fire_timeout("a");
+        cluster.run();
+        for _ in 0..5 {
+            cluster.fire_timeout("a");
+            cluster.run();
+        }
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+    }
+
+    #[test]
+    fn log_sync_slots_are_shared_fairly() {
+        let options = ReplicatedLogOptions {
+            max_append_entries: Some(1),
+            max_concurrent_syncs: Some(1),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c", "d"], options);
+        cluster.elect("a");
+
+        for id in &["b", "c", "d"] {
+            cluster.isolate(id);
+        }
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        cluster.heal();
+
+        // 全てのフォロワーからのハートビート応答が、同期処理の枠が埋まっている状態で届くようにする
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.node("a").io().set_loads_blocked(true);
+        cluster.fire_timeout("a");
+        cluster.run();
+        cluster.node("a").io().set_loads_blocked(false);
+        cluster.run();
+
+        let synced = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .filter_map(|m| match m {
+                Message::AppendEntriesCall(m) if !m.suffix.entries.is_empty() => {
+                    Some(m.header.destination.as_str().to_owned())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for round in synced.chunks(3).filter(|r| r.len() == 3) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(round, ["b", "c", "d"], "{:?}", synced);
+        }
+        for id in &["b", "c", "d"] {
+            assert_eq!(
+                cluster.log_suffix(id).tail(),
+                cluster.log_suffix("a").tail()
+            );
+        }
+    }
+
+    #[test]
+    fn follower_progress_reports_shrinking_lag() {
+        let options = ReplicatedLogOptions {
+            max_append_entries: Some(1),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        cluster.isolate("c");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        cluster.heal();
+        let progress = |cluster: &MockCluster| {
+            let all = cluster.node("a").follower_progress();
+            assert_eq!(all.len(), 2);
+            all.into_iter()
+                .find(|p| p.node.as_str() == "c")
+                .expect("Never fails")
+        };
+        assert!(progress(&cluster).lag() >= 5);
+
+        // 一エントリずつ同期が進み、その度に遅れが縮まる
+        cluster.fire_timeout("a");
+        let mut lags = vec![progress(&cluster).lag()];
+        while cluster.step() {
+            lags.push(progress(&cluster).lag());
         }
+        lags.dedup();
+        assert!(lags.len() > 2, "{:?}", lags);
+        assert!(lags.windows(2).all(|w| w[1] < w[0]), "{:?}", lags);
+
+        let last = progress(&cluster);
+        assert_eq!(last.lag(), 0);
+        assert!(last.synced);
+        assert!(last.append_rate.is_some());
+        assert_eq!(last.eta(), Some(Duration::from_secs(0)));
+        assert!(cluster.node("b").follower_progress().is_empty());
+    }
+
+    #[test]
+    fn log_sync_tasks_are_capped() {
+        let options = ReplicatedLogOptions {
+            max_concurrent_syncs: Some(2),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c", "d", "e"], options);
+        cluster.elect("a");
+
+        for id in &["b", "c", "d", "e"] {
+            cluster.isolate(id);
+        }
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        cluster.heal();
+
+        // 読み込みが完了しない間は、上限を超えて同期処理が開始されることはない
+        cluster.node("a").io().set_loads_blocked(true);
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.node("a").inflight_log_syncs(), Some(2));
+        let pending = cluster.node("a").pending_log_sync_targets().unwrap();
+        let waiting = cluster.node("a").waiting_log_sync_targets().unwrap();
+        assert_eq!((pending.len(), waiting.len()), (2, 2));
+        assert!(pending.iter().all(|n| !waiting.contains(n)));
+        assert_eq!(cluster.node("b").pending_log_sync_targets(), None);
+        assert_eq!(cluster.node("b").waiting_log_sync_targets(), None);
+
+        cluster.node("a").io().set_loads_blocked(false);
+        while cluster.step() {
+            assert!(cluster.node("a").inflight_log_syncs().unwrap() <= 2);
+        }
+        assert_eq!(cluster.node("a").inflight_log_syncs(), Some(0));
+        assert_eq!(
+            cluster.node("a").pending_log_sync_targets(),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            cluster.node("a").waiting_log_sync_targets(),
+            Some(Vec::new())
+        );
+        for id in &["b", "c", "d", "e"] {
+            assert_eq!(
+                cluster.log_suffix(id).tail(),
+                cluster.log_suffix("a").tail()
+            );
+        }
+    }
+
+    #[test]
+    fn learner_does_not_affect_commit() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        cluster.add_node("d");
+        cluster.node_mut("a").propose_learner("d".into()).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("d").tail(),
+            cluster.log_suffix("a").tail()
+        );
+
+        // ラーナーにはログが複製されるが、コミットには寄与しない
+        cluster.isolate("b");
+        cluster.isolate("c");
+        let committed = cluster.node("a").local_history().committed_tail();
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("d").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        assert_eq!(
+            cluster.node("a").local_history().committed_tail(),
+            committed
+        );
+
+        cluster.heal();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(
+            cluster.node("a").local_history().committed_tail(),
+            cluster.log_suffix("a").tail()
+        );
+
+        // 昇格
+        cluster.node_mut("a").promote_learner(&"d".into()).unwrap();
+        cluster.run();
+        let config = cluster.node("a").local_history().config().clone();
+        assert!(config.state().is_stable());
+        assert!(config.primary_members().contains(&"d".into()));
+        assert!(config.learners().is_empty());
+    }
+
+    #[test]
+    fn last_position_of_term_points_past_the_term() -> TestResult {
+        // 各エントリの`Term`: [1, 1, 2, 2, 2, 4]
+        let mut history = LogHistory::new(ClusterConfig::new(
+            ["a"].iter().map(|&n| n.into()).collect(),
+        ));
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: [1, 1, 2, 2, 2, 4]
+                .iter()
+                .map(|&t| LogEntry::Noop { term: t.into() })
+                .collect(),
+        };
+        track!(history.record_appended(&suffix))?;
+
+        // 最後の`Term`以外でも、その`Term`の最後のエントリの直後の位置が返される
+        assert_eq!(last_position_of_term(&history, 1.into()), Some(2.into()));
+        assert_eq!(last_position_of_term(&history, 2.into()), Some(5.into()));
+        assert_eq!(last_position_of_term(&history, 4.into()), Some(6.into()));
+
+        // ログに存在しない`Term`
+        assert_eq!(last_position_of_term(&history, 3.into()), None);
+        assert_eq!(last_position_of_term(&history, 5.into()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn diverged_follower_is_reconciled_quickly() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // 孤立した旧リーダのログに、コミットされないエントリ群を大量に追記する
+        cluster.isolate("a");
+        for i in 0..30 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+        }
+        cluster.run();
+        cluster.fire_timeout("b");
+        cluster.run();
+        cluster.fire_timeout("c");
+        cluster.run();
+        cluster.elect("c");
+        // 新リーダのログも、旧リーダの分岐部分より長くなるまで進めておく
+        for i in 0..30 {
+            cluster.node_mut("c").propose_command(vec![i]).unwrap();
+        }
+        cluster.run();
+
+        cluster.heal();
+        cluster.network().lock().unwrap().clear_sent_messages();
+        cluster.fire_timeout("c");
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("a").tail(),
+            cluster.log_suffix("c").tail()
+        );
+
+        let exchanges = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .filter(|m| match m {
+                Message::AppendEntriesCall(m) => m.header.destination.as_str() == "a",
+                _ => false,
+            })
+            .count();
+        // 分岐したエントリ数に依らず、一定回数の往復で同期が完了する
+        assert!(exchanges <= 5, "exchanges={}", exchanges);
+    }
+
+    #[test]
+    fn sync_targets_are_reported() -> TestResult {
+        let options = ReplicatedLogOptions {
+            max_concurrent_syncs: Some(1),
+            ..Default::default()
+        };
+        let io = MockIo::new("a".into(), Arc::default());
+        io.set_loads_blocked(true);
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new("a".into(), io, config.clone(), metrics, options);
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: 0.into() }; 3],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        let tail = common.log().tail();
+
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        for &(id, log_tail) in &[
+            ("a", tail),
+            ("b", LogPosition::default()),
+            ("c", LogPosition::default()),
+        ] {
+            let reply = AppendEntriesReply {
+                header: MessageHeader {
+                    group_id: GroupId::default(),
+                    sender: id.into(),
+                    destination: "a".into(),
+                    seq_no: SequenceNumber::new(1),
+                    term: 0.into(),
+                },
+                log_tail,
+                conflict_term: None,
+                conflict_index: LogIndex::new(0),
+                busy: false,
+                epoch: 0,
+            };
+            track!(followers.handle_append_entries_reply(&common, &reply))?;
+            track!(followers.log_sync(&mut common, &reply))?;
+        }
+
+        // "b"の同期処理によって枠が埋まっているので、"c"は待機中
+        assert_eq!(followers.pending_sync_targets(), [NodeId::from("b")]);
+        assert_eq!(
+            followers.waiting_sync_targets(tail.index),
+            [NodeId::from("c")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn epoch_change_resets_sync_state() -> TestResult {
+        let io = MockIo::new("a".into(), Arc::default());
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new("a".into(), io, config.clone(), metrics, Default::default());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: 0.into() }; 3],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        let tail = common.log().tail();
+
+        let reply = |seq_no: u64, log_tail: LogPosition, epoch: u64| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: "b".into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(seq_no),
+                term: 0.into(),
+            },
+            log_tail,
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        track!(followers.handle_append_entries_reply(&common, &reply(1, tail, 10)))?;
+        assert!(followers.is_caught_up(&"b".into(), tail.index));
+
+        // 同じ起動中に、より短いログ終端が報告されても(e.g., 遅延した応答)、同期位置は巻き戻らない
+        let shorter = LogPosition {
+            prev_term: 0.into(),
+            index: 1.into(),
+        };
+        track!(followers.handle_append_entries_reply(&common, &reply(2, shorter, 10)))?;
+        assert!(followers.is_caught_up(&"b".into(), tail.index));
+
+        // 再起動後(i.e., `epoch`が変化)に報告されたログ終端は、`0`でなくとも採用される
+        track!(followers.handle_append_entries_reply(&common, &reply(3, shorter, 11)))?;
+        assert!(!followers.is_caught_up(&"b".into(), tail.index));
+        assert!(followers.is_caught_up(&"b".into(), shorter.index));
+        Ok(())
+    }
+
+    #[test]
+    fn commit_uses_config_effective_at_each_index() -> TestResult {
+        let ids = |ids: &[&str]| ids.iter().map(|&id| NodeId::from(id)).collect();
+        let old_config = ClusterConfig::new(ids(&["a", "b", "c"]));
+        let joint_config = track!(ClusterConfig::with_state(
+            ids(&["c", "d", "e"]),
+            ids(&["a", "b", "c"]),
+            ClusterState::Joint,
+        ))?;
+        let io = MockIo::new("a".into(), Arc::default());
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new("a".into(), io, old_config, metrics, Default::default());
+
+        // [0: Noop, 1: Noop, 2: Config(joint), 3: Noop]
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![
+                LogEntry::Noop { term: 0.into() },
+                LogEntry::Noop { term: 0.into() },
+                LogEntry::Config {
+                    term: 0.into(),
+                    config: joint_config.clone(),
+                },
+                LogEntry::Noop { term: 0.into() },
+            ],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+        let tail = common.log().tail();
+
+        let reply = |sender: &str| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: sender.into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(1),
+                term: 0.into(),
+            },
+            log_tail: tail,
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch: 0,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(joint_config);
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(0)
+        );
+
+        // 構成変更より前のエントリは、旧構成の過半数のみでコミットされる
+        track!(followers.handle_append_entries_reply(&common, &reply("a")))?;
+        track!(followers.handle_append_entries_reply(&common, &reply("b")))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(2)
+        );
+
+        // 構成変更以降のエントリには、新旧両方の過半数が必要
+        track!(followers.handle_append_entries_reply(&common, &reply("d")))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(2)
+        );
+        track!(followers.handle_append_entries_reply(&common, &reply("e")))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            tail.index
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn follower_order_exposes_lost_append_to_synchronous_replica() {
+        // 最初に送信された`AppendEntriesCall`が失われた場合の、提案直後のコミット済み地点とログの終端を返す
+        fn propose_with_first_append_lost(order: Option<FollowerOrder>) -> (LogIndex, LogIndex) {
+            let options = ReplicatedLogOptions {
+                synchronous_replicas: vec!["d".into()].into_iter().collect(),
+                follower_order: order,
+                ..Default::default()
+            };
+            let mut cluster = MockCluster::with_options(&["a", "b", "c", "d"], options);
+            cluster.elect("a");
+
+            cluster.network().lock().unwrap().drop_once(|m| match m {
+                Message::AppendEntriesCall(m) => !m.suffix.entries.is_empty(),
+                _ => false,
+            });
+            cluster.node_mut("a").propose_command(vec![1]).unwrap();
+            cluster.run();
+            let committed = cluster.node("a").local_history().committed_tail().index;
+            let tail = cluster.log_suffix("a").tail().index;
+
+            // 次のハートビートで再送されれば、コミットされる
+            cluster.node_mut("a").heartbeat().unwrap();
+            cluster.run();
+            assert_eq!(
+                cluster.node("a").local_history().committed_tail().index,
+                tail
+            );
+            (committed, tail)
+        }
+
+        // 既定の順番では、失われるのは`b`宛てのメッセージなので、コミットは即座に進む
+        let (committed, tail) = propose_with_first_append_lost(None);
+        assert_eq!(committed, tail);
+
+        // 同期レプリカの`d`を最初に処理すると、`d`宛てのメッセージが失われ、
+        // 過半数(`a`, `b`, `c`)がエントリを保持していても、再送されるまでコミットが進まない
+        let order = FollowerOrder::Fixed(vec!["d".into(), "b".into(), "c".into()]);
+        let (committed, tail) = propose_with_first_append_lost(Some(order));
+        assert!(
+            committed < tail,
+            "committed={:?}, tail={:?}",
+            committed,
+            tail
+        );
+
+        // 乱数による順番も、同じ`seed`に対しては常に同じとなる
+        let order = FollowerOrder::Shuffled(7);
+        assert_eq!(
+            propose_with_first_append_lost(Some(order.clone())),
+            propose_with_first_append_lost(Some(order))
+        );
+    }
+
+    #[test]
+    fn commit_waits_for_synchronous_replica() -> TestResult {
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let io = MockIo::new("a".into(), Arc::default());
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new("a".into(), io, config.clone(), metrics, Default::default());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: 0.into() }; 2],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+
+        let reply = |sender: &str, index: u64| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: sender.into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(1),
+                term: 0.into(),
+            },
+            log_tail: LogPosition {
+                prev_term: 0.into(),
+                index: index.into(),
+            },
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch: 0,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        followers.set_synchronous_replicas(vec!["c".into(), "z".into()].into_iter().collect());
+
+        // `a`と`b`で過半数となるが、同期レプリカの`c`が追い付くまではコミットされない
+        track!(followers.handle_append_entries_reply(&common, &reply("a", 2)))?;
+        track!(followers.handle_append_entries_reply(&common, &reply("b", 2)))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(0)
+        );
+
+        track!(followers.handle_append_entries_reply(&common, &reply("c", 1)))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(1)
+        );
+
+        track!(followers.handle_append_entries_reply(&common, &reply("c", 2)))?;
+        assert_eq!(
+            track!(followers.committed_log_tail(common.log()))?,
+            LogIndex::new(2)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn majority_acked_seq_no_follows_heartbeat_round() -> TestResult {
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let io = MockIo::new("a".into(), Arc::default());
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let common = Common::new("a".into(), io, config.clone(), metrics, Default::default());
+
+        let reply = |sender: &str, seq_no: u64| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: sender.into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(seq_no),
+                term: 0.into(),
+            },
+            log_tail: LogPosition::default(),
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch: 0,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        assert_eq!(followers.majority_acked_seq_no(), SequenceNumber::new(0));
+        assert_eq!(followers.majority_acked_at(), None);
+
+        // ハートビートをブロードキャストし、過半数から応答を得る
+        let seq_no = SequenceNumber::new(7);
+        followers.set_last_broadcast_seq_no(seq_no);
+        track!(followers.handle_append_entries_reply(&common, &reply("a", 7)))?;
+        assert_eq!(followers.majority_acked_seq_no(), SequenceNumber::new(0));
+        assert_eq!(followers.majority_acked_at(), None);
+
+        let before = Instant::now();
+        track!(followers.handle_append_entries_reply(&common, &reply("b", 7)))?;
+        assert_eq!(followers.majority_acked_seq_no(), seq_no);
+        let acked_at = followers.majority_acked_at().unwrap();
+        assert!(before <= acked_at);
+
+        // 既に過半数から応答を得ている場合には、時刻は更新されない
+        track!(followers.handle_append_entries_reply(&common, &reply("c", 7)))?;
+        assert_eq!(followers.majority_acked_seq_no(), seq_no);
+        assert_eq!(followers.majority_acked_at(), Some(acked_at));
+        Ok(())
+    }
+
+    #[test]
+    fn seq_no_comparison_survives_wraparound() -> TestResult {
+        let seq = SequenceNumber::new;
+        assert!(seq(0).is_newer_than(seq(u64::MAX)));
+        assert!(!seq(u64::MAX).is_newer_than(seq(0)));
+        assert!(!seq(3).is_newer_than(seq(3)));
+        assert_eq!(seq(u64::MAX).next(), seq(0));
+
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let io = MockIo::new("a".into(), Arc::default());
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let common = Common::new("a".into(), io, config.clone(), metrics, Default::default());
+
+        let reply = |sender: &str, seq_no: SequenceNumber| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: sender.into(),
+                destination: "a".into(),
+                seq_no,
+                term: 0.into(),
+            },
+            log_tail: LogPosition::default(),
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy: false,
+            epoch: 0,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        followers.set_seq_no_base(seq(u64::MAX - 1));
+
+        // `u64::MAX`を跨いでハートビートを繰り返しても、応答の順序が正しく判定される
+        let mut seq_no = seq(u64::MAX - 1);
+        for _ in 0..4 {
+            followers.set_last_broadcast_seq_no(seq_no);
+            track!(followers.handle_append_entries_reply(&common, &reply("b", seq_no)))?;
+            track!(followers.handle_append_entries_reply(&common, &reply("c", seq_no)))?;
+            assert_eq!(followers.majority_acked_seq_no(), seq_no);
+            seq_no = seq_no.next();
+        }
+        assert_eq!(seq_no, seq(2));
+
+        // 巡回前の古い応答によって、合意値が巻き戻ることはない
+        track!(followers.handle_append_entries_reply(&common, &reply("b", seq(u64::MAX))))?;
+        assert_eq!(followers.majority_acked_seq_no(), seq(1));
+        assert_eq!(followers.followers[&NodeId::from("b")].last_seq_no, seq(1));
+        Ok(())
+    }
+
+    #[test]
+    fn busy_follower_is_backed_off() -> TestResult {
+        let io = MockIo::new("a".into(), Arc::default());
+        let members = ["a", "b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        let config = ClusterConfig::new(members);
+        let metrics = track!(NodeStateMetrics::new(&MetricBuilder::new()))?;
+        let mut common = Common::new("a".into(), io, config.clone(), metrics, Default::default());
+        let suffix = LogSuffix {
+            head: LogPosition::default(),
+            entries: vec![LogEntry::Noop { term: 0.into() }; 3],
+        };
+        track!(common.handle_log_appended(&suffix))?;
+
+        // フォロワーは常にログ終端`1`を報告し、同期用のメッセージに対してはビジー応答を返す
+        let reply = |seq_no: u64, busy: bool| AppendEntriesReply {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: "b".into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(seq_no),
+                term: 0.into(),
+            },
+            log_tail: LogPosition {
+                prev_term: 0.into(),
+                index: 1.into(),
+            },
+            conflict_term: None,
+            conflict_index: LogIndex::new(0),
+            busy,
+            epoch: 0,
+        };
+        let mut followers = FollowersManager::<MockIo>::new(config);
+        let mut attempts = Vec::new();
+        for round in 1..=16 {
+            followers.set_last_broadcast_seq_no(SequenceNumber::new(round * 10));
+            let r = reply(round * 10, false);
+            track!(followers.handle_append_entries_reply(&common, &r))?;
+            track!(followers.log_sync(&mut common, &r))?;
+            if followers.tasks.remove(&NodeId::from("b")).is_some() {
+                attempts.push(round);
+                let r = reply(round * 10 + 1, true);
+                track!(followers.handle_append_entries_reply(&common, &r))?;
+                track!(followers.log_sync(&mut common, &r))?;
+            }
+        }
+        assert_eq!(attempts, [1, 2, 3, 6, 13]);
+        assert_eq!(followers.sync_backoff(&"b".into()), Some(12));
+        assert_eq!(followers.sync_backoff(&"c".into()), Some(0));
+        assert_eq!(followers.sync_backoff(&"d".into()), None);
+
+        // フォロワーのログが進むと、バックオフは解除される
+        let mut r = reply(200, false);
+        r.log_tail = common.log().tail();
+        track!(followers.handle_append_entries_reply(&common, &r))?;
+        assert_eq!(followers.sync_backoff(&"b".into()), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn rebooted_follower_is_resynced() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..5 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+        }
+        cluster.run();
+
+        // `c`は、ログの後半部分を失った状態で再起動する
+        let tail = cluster.log_suffix("c").tail();
+        {
+            let storage = cluster.node("c").io().storage().clone();
+            let mut storage = storage.lock().unwrap();
+            let new_tail = storage.suffix.head.index + 2;
+            storage.suffix.truncate(new_tail).unwrap();
+            assert_ne!(storage.suffix.tail().index.as_u64(), 0);
+            assert!(storage.suffix.tail().index < tail.index);
+        }
+        cluster.restart("c");
+        cluster.run();
+
+        cluster.node_mut("a").propose_command(vec![5]).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        assert_eq!(
+            cluster.node("c").local_history().committed_tail(),
+            cluster.node("a").local_history().committed_tail()
+        );
     }
 }
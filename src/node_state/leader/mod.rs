@@ -1,14 +1,23 @@
+use futures::sync::oneshot;
+use std::cmp;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
 use self::appender::LogAppender;
 use self::follower::FollowersManager;
 use super::{Common, NextState};
+use crate::cluster::{ClusterMembers, ClusterState};
 use crate::election::Role;
 use crate::log::{LogEntry, LogIndex, LogSuffix, ProposalId};
 use crate::message::{Message, SequenceNumber};
-use crate::{ErrorKind, Io, Result};
+use crate::node::NodeId;
+use crate::{ErrorKind, FollowerProgress, Io, Result};
 
 mod appender;
 mod follower;
 
+/// リーダシップの移譲を諦めるまでのハートビート回数.
+const MAX_TRANSFER_HEARTBEATS: usize = 10;
+
 /// 選挙で選ばれたリーダ.
 ///
 /// 主に、以下のようなことを行う:
@@ -23,16 +32,27 @@ mod follower;
 ///   - 整合性を維持しながらの動的クラスタ構成変更用の処理諸々
 ///   - e.g., join-consensusを間に挟んだ段階的な構成移行
 /// - 定期的なハートビートメッセージのブロードキャストによるリーダ維持
+/// - リーダシップの移譲:
+///   - 移譲先のログが追い付いた時点で`TimeoutNowCast`を送信して、即座に立候補させる
 pub struct Leader<IO: Io> {
     followers: FollowersManager<IO>,
     appender: LogAppender<IO>,
     commit_lower_bound: LogIndex,
+    term_start_index: LogIndex,
+    transfer: Option<LeadershipTransfer>,
+    pending_reads: Vec<PendingRead>,
+    pending_heartbeats: Vec<(SequenceNumber, oneshot::Sender<Result<SequenceNumber>>)>,
+
+    // 構成変更の`CatchUp`状態を最初に観測した時刻.
+    catchup_started: Option<Instant>,
 }
 impl<IO: Io> Leader<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
         common.set_timeout(Role::Leader);
         let term_start_index = common.log().tail().index;
-        let followers = FollowersManager::new(common.config().clone());
+        let mut followers = FollowersManager::new(common.config().clone());
+        followers.set_synchronous_replicas(common.options().synchronous_replicas.clone());
+        followers.set_seq_no_base(common.next_seq_no());
         let mut appender = LogAppender::new();
 
         // 新しいリーダ選出直後に追加されるログエントリ.
@@ -46,28 +66,97 @@ impl<IO: Io> Leader<IO> {
             followers,
             appender,
             commit_lower_bound: term_start_index,
+            term_start_index,
+            transfer: None,
+            pending_reads: Vec::new(),
+            pending_heartbeats: Vec::new(),
+            catchup_started: None,
         }
     }
     pub fn handle_timeout(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
+        if !track!(self.has_quorum_lease(common))? {
+            // 過半数との疎通が途絶えている: 古いリーダとして提案を受け付け続けないように退任する
+            let local = common.local_node().id.clone();
+            return Ok(Some(common.transit_to_follower(local, None)));
+        }
+        if let Some(ref mut transfer) = self.transfer {
+            if transfer.heartbeats_left == 0 {
+                // 移譲先が応答しない: 移譲を諦めて、リーダとしての処理を再開する
+                self.transfer = None;
+            } else {
+                transfer.heartbeats_left -= 1;
+            }
+        }
+        if let (Some(duration), None) = (common.options().lease_duration, &self.transfer) {
+            // リースを延長するためのエントリを追加する (追記完了時にブロードキャストされる)
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let lease_until = (now + duration).as_millis() as u64;
+            let entry = LogEntry::LeaseNoop {
+                term: common.term(),
+                lease_until,
+            };
+            self.appender.append(common, vec![entry]);
+        }
         self.broadcast_empty_entries(common);
         Ok(None)
     }
+
+    /// リーダとしての正当性(リース)が有効かどうかを判定する.
+    ///
+    /// `ReplicatedLogOptions::check_quorum`が有効な場合には、
+    /// 過半数からの最後の応答からの経過時間が、フォロワーのタイムアウト時間
+    /// (i.e., フォロワーが新しい選挙を始めるまでの時間)未満の場合にのみ`true`が返される.
+    /// 無効な場合には、常に`true`が返される.
+    pub fn has_quorum_lease(&self, common: &Common<IO>) -> Result<bool> {
+        if !common.options().check_quorum {
+            return Ok(true);
+        }
+        let election_timeout = common.options().timeout_policy.follower;
+        let contact = track!(self.followers.quorum_contact(&common.local_node().id))?;
+        Ok(contact.elapsed() < election_timeout)
+    }
     pub fn handle_message(
         &mut self,
         common: &mut Common<IO>,
         message: Message,
     ) -> Result<NextState<IO>> {
         if let Message::AppendEntriesReply(reply) = message {
-            let updated = self.followers.handle_append_entries_reply(&common, &reply);
+            // `Term`が異なる応答は`Common::handle_message`で処理済みのはずなので、
+            // ここに到達した場合には、古いリーダの応答が合意に数えられてしまう恐れがある
+            debug_assert_eq!(
+                reply.header.term,
+                common.term(),
+                "AppendEntriesReply from another term must not be counted: reply={:?}",
+                reply.header
+            );
+            let updated = track!(self.followers.handle_append_entries_reply(common, &reply))?;
 
             track!(self.followers.log_sync(common, &reply))?;
 
             if updated {
                 track!(self.handle_committed_log(common))?;
             }
+            self.try_send_timeout_now(common);
+        } else if let Message::DepartureCast(m) = message {
+            self.followers.handle_departure(&m.header.sender);
         }
         Ok(None)
     }
+
+    /// ノードの停止に備えて、最適なフォロワーへのリーダシップの移譲を開始する.
+    ///
+    /// 移譲先の候補が存在しない場合には`false`が返される.
+    pub fn start_shutdown(&mut self, common: &mut Common<IO>) -> bool {
+        match self.followers.transfer_candidate(&common.local_node().id) {
+            None => false,
+            Some(target) => {
+                self.transfer_leadership(common, target);
+                true
+            }
+        }
+    }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
         while let Some(appended) = track!(self.appender.run_once(common))? {
             for e in &appended.entries {
@@ -100,24 +189,146 @@ impl<IO: Io> Leader<IO> {
             self.broadcast_slice(common, appended);
         }
         track!(self.handle_change_config(common))?;
+        self.abort_stuck_catchup(common);
         track!(self.followers.run_once(common))?;
-        Ok(None)
+        self.handle_pending_reads(common);
+        Ok(self.hand_off_if_removed(common))
     }
     pub fn propose(&mut self, common: &mut Common<IO>, entry: LogEntry) -> ProposalId {
         let proposal_id = self.next_proposal_id(common);
         self.appender.append(common, vec![entry]);
         proposal_id
     }
+
+    /// `entries`をまとめて提案する.
+    ///
+    /// 返り値は、各エントリに対応する提案IDのリスト.
+    pub fn propose_batch(
+        &mut self,
+        common: &mut Common<IO>,
+        entries: Vec<LogEntry>,
+    ) -> Vec<ProposalId> {
+        let ProposalId { term, index } = self.next_proposal_id(common);
+        let proposal_ids: Vec<_> = (0..entries.len())
+            .map(|i| ProposalId {
+                term,
+                index: index + i,
+            })
+            .collect();
+        for (id, entry) in proposal_ids.iter().zip(&entries) {
+            if let LogEntry::Command { .. } = entry {
+                common.track_proposal(*id);
+            }
+        }
+        if !entries.is_empty() {
+            self.appender.append(common, entries);
+        }
+        proposal_ids
+    }
     pub fn heartbeat_syn(&mut self, common: &mut Common<IO>) -> SequenceNumber {
         let seq_no = common.next_seq_no();
         self.broadcast_empty_entries(common);
         seq_no
     }
+    /// 未コミット(ないし未追記)のクラスタ構成の変更が存在するかどうかを判定する.
+    pub fn is_changing_config(&self, common: &Common<IO>) -> bool {
+        self.appender.has_unappended_config() || common.log().has_uncommitted_config_change()
+    }
     pub fn proposal_queue_len(&self, common: &Common<IO>) -> usize {
         self.appender.unappended_log_tail(common) - common.log().tail().index
     }
+    /// 未コミット(未追記のものを含む)のエントリ数を返す.
+    pub fn uncommitted_entries(&self, common: &Common<IO>) -> usize {
+        self.appender.unappended_log_tail(common) - common.log().committed_tail().index
+    }
     pub fn last_heartbeat_ack(&self) -> SequenceNumber {
-        self.followers.latest_hearbeat_ack()
+        self.followers.majority_acked_seq_no()
+    }
+    pub fn last_heartbeat_ack_at(&self) -> Option<Instant> {
+        self.followers.majority_acked_at()
+    }
+    pub fn describe_followers(&self, common: &Common<IO>, buf: &mut String) {
+        self.followers.describe(&common.local_node().id, buf);
+    }
+    pub fn follower_progress(&self, common: &Common<IO>) -> Vec<FollowerProgress> {
+        self.followers.progress(common)
+    }
+    pub fn inflight_syncs(&self) -> usize {
+        self.followers.inflight_syncs()
+    }
+    pub fn pending_sync_targets(&self) -> Vec<NodeId> {
+        self.followers.pending_sync_targets()
+    }
+    pub fn waiting_sync_targets(&self, common: &Common<IO>) -> Vec<NodeId> {
+        self.followers
+            .waiting_sync_targets(common.log().tail().index)
+    }
+    pub fn sync_backoff(&self, follower: &NodeId) -> Option<u64> {
+        self.followers.sync_backoff(follower)
+    }
+
+    /// `target`へのリーダシップの移譲を開始する.
+    ///
+    /// `target`のローカルログがリーダに追い付き次第、即座に立候補するよう指示が送られる.
+    pub fn transfer_leadership(&mut self, common: &mut Common<IO>, target: NodeId) {
+        self.transfer = Some(LeadershipTransfer {
+            target,
+            timeout_now_sent: false,
+            heartbeats_left: MAX_TRANSFER_HEARTBEATS,
+        });
+        self.try_send_timeout_now(common);
+        if self.transfer.as_ref().is_some_and(|t| !t.timeout_now_sent) {
+            // 移譲先のログ同期を促す
+            self.broadcast_empty_entries(common);
+        }
+    }
+
+    /// ReadIndexプロトコルに基づく読み込み要求を開始する.
+    ///
+    /// 現在のコミット済み地点を記録した上でハートビートをブロードキャストし、
+    /// 過半数からの応答によってリーダであることが確認され、
+    /// かつ、記録した地点までのログが消費された時点で、`reply`に結果が送信される.
+    pub fn read_index(
+        &mut self,
+        common: &mut Common<IO>,
+        reply: oneshot::Sender<Result<LogIndex>>,
+    ) {
+        // 現在の`Term`で追加されたエントリがまだコミットされていない場合には、
+        // 真のコミット済み地点が分からないので、それ(i.e., 最初の`Noop`)のコミットを待つ.
+        // 詳細は論文の「8 Client interaction」を参照のこと.
+        let index = cmp::max(
+            common.log().committed_tail().index,
+            self.term_start_index + 1,
+        );
+        let seq_no = self.heartbeat_syn(common);
+        self.pending_reads.push(PendingRead {
+            index,
+            seq_no,
+            reply,
+        });
+    }
+
+    /// ハートビートをブロードキャストし、過半数からの応答を待つ.
+    ///
+    /// 過半数からの応答によってリーダであることが確認された時点で、
+    /// `reply`にハートビートのシーケンス番号が送信される.
+    pub fn confirm_heartbeat(
+        &mut self,
+        common: &mut Common<IO>,
+        reply: oneshot::Sender<Result<SequenceNumber>>,
+    ) {
+        let seq_no = self.heartbeat_syn(common);
+        self.pending_heartbeats.push((seq_no, reply));
+    }
+
+    /// ローカルログへの追記中、あるいは、ログ同期用の読み込み中かどうかを判定する.
+    pub fn has_pending_io(&self) -> bool {
+        self.appender.is_busy() || self.followers.has_pending_loads()
+    }
+
+    /// リーダシップの移譲中かどうかを判定する.
+    pub fn is_transferring(&self) -> bool {
+        self.transfer.is_some()
     }
 
     #[allow(clippy::unnecessary_wraps)]
@@ -131,13 +342,13 @@ impl<IO: Io> Leader<IO> {
             return Ok(());
         }
 
-        let committed = self.followers.committed_log_tail();
+        let committed = track!(self.followers.committed_log_tail(common.log()))?;
         if committed < common.log().last_record().head.index {
             // まだ新構成がコミットされていない可能性がある
             return Ok(());
         }
 
-        let joint_committed = self.followers.joint_committed_log_tail();
+        let joint_committed = track!(self.followers.joint_committed_log_tail())?;
         if joint_committed == committed {
             // 新構成のメンバのローカルログが、旧構成のものに追い付いた
             // => 構成変更の次のフェーズに遷移
@@ -148,6 +359,105 @@ impl<IO: Io> Leader<IO> {
         }
         Ok(())
     }
+    /// `ReplicatedLogOptions::catchup_timeout`以内に新メンバ群のログが追い付かない場合には、
+    /// 旧メンバ群のみからなる構成を提案して、構成変更を中止する.
+    fn abort_stuck_catchup(&mut self, common: &mut Common<IO>) {
+        if common.config().state() != ClusterState::CatchUp {
+            self.catchup_started = None;
+            return;
+        }
+        let timeout = match common.options().catchup_timeout {
+            None => return,
+            Some(timeout) => timeout,
+        };
+        let started = *self.catchup_started.get_or_insert_with(Instant::now);
+        if started.elapsed() < timeout || self.appender.is_busy() {
+            return;
+        }
+
+        let config = common.config();
+        let committed = common.log().committed_tail().index;
+        let lagging = config
+            .new_members()
+            .difference(config.old_members())
+            .filter(|n| !self.followers.is_caught_up(n, committed))
+            .cloned()
+            .collect::<ClusterMembers>();
+        if lagging.is_empty() {
+            // 追い付いているので、`handle_change_config`によって`Joint`状態に遷移する
+            return;
+        }
+        let new_members = config.new_members().clone();
+        let entry = LogEntry::Config {
+            term: common.term(),
+            config: config.abort_catchup(),
+        };
+        self.propose(common, entry);
+        self.catchup_started = None;
+        common.notify_config_change_aborted(new_members, lagging);
+    }
+    /// ローカルノードを含まない新構成がコミットされた場合には、残ったメンバにリーダシップを移譲して退く.
+    ///
+    /// 新構成のコミットまではリーダとして振る舞う(ただし、自身のログは合意には数えない)が、
+    /// その後は、移譲先に`TimeoutNowCast`を送信した時点でフォロワーに遷移する.
+    /// 詳細は論文の「6 Cluster membership changes」の"The second issue is ..."部分を参照のこと.
+    fn hand_off_if_removed(&mut self, common: &mut Common<IO>) -> NextState<IO> {
+        let local = common.local_node().id.clone();
+        if !common.config().state().is_stable()
+            || common.config().is_known_node(&local)
+            || common.log().has_uncommitted_config_change()
+        {
+            return None;
+        }
+        if self.transfer.is_none() {
+            let target = self.followers.transfer_candidate(&local)?;
+            self.transfer_leadership(common, target);
+        }
+        if self.transfer.as_ref().is_some_and(|t| t.timeout_now_sent) {
+            Some(common.transit_to_follower(local, None))
+        } else {
+            None
+        }
+    }
+    fn handle_pending_reads(&mut self, common: &Common<IO>) {
+        let acked = self.followers.majority_acked_seq_no();
+        let consumed = common.log().consumed_tail().index;
+        let mut i = 0;
+        while i < self.pending_reads.len() {
+            let r = &self.pending_reads[i];
+            if !r.seq_no.is_newer_than(acked) && r.index <= consumed {
+                let r = self.pending_reads.swap_remove(i);
+                let _ = r.reply.send(Ok(r.index));
+            } else {
+                i += 1;
+            }
+        }
+
+        let mut i = 0;
+        while i < self.pending_heartbeats.len() {
+            if !self.pending_heartbeats[i].0.is_newer_than(acked) {
+                let (seq_no, reply) = self.pending_heartbeats.swap_remove(i);
+                let _ = reply.send(Ok(seq_no));
+            } else {
+                i += 1;
+            }
+        }
+    }
+    fn try_send_timeout_now(&mut self, common: &mut Common<IO>) {
+        let transfer = match self.transfer {
+            Some(ref mut t) if !t.timeout_now_sent => t,
+            _ => return,
+        };
+        if self.appender.is_busy() {
+            // ローカルログへの追記中
+            return;
+        }
+        let tail = common.log().tail().index;
+        if self.followers.is_caught_up(&transfer.target, tail) {
+            transfer.timeout_now_sent = true;
+            common.rpc_caller().send_timeout_now(&transfer.target);
+        }
+    }
     fn next_proposal_id(&self, common: &Common<IO>) -> ProposalId {
         let term = common.term();
         let index = self.appender.unappended_log_tail(common);
@@ -155,7 +465,7 @@ impl<IO: Io> Leader<IO> {
     }
     fn broadcast_slice(&mut self, common: &mut Common<IO>, slice: LogSuffix) {
         self.followers
-            .set_last_broadcast_seq_no(common.next_seq_no());
+            .record_broadcast(common.next_seq_no(), &slice);
         common.set_timeout(Role::Leader);
         common.rpc_caller().broadcast_append_entries(slice);
     }
@@ -166,7 +476,7 @@ impl<IO: Io> Leader<IO> {
         self.broadcast_slice(common, slice);
     }
     fn handle_committed_log(&mut self, common: &mut Common<IO>) -> Result<()> {
-        let committed = self.followers.committed_log_tail();
+        let committed = track!(self.followers.committed_log_tail(common.log()))?;
         if committed < self.commit_lower_bound {
             // コミット済みのログ領域でも、現在のtermよりも前に追加されたものはまだコミットできない.
             // 詳細は論文の「5.4.2 Committing entries from previous terms」を参照のこと.
@@ -192,3 +502,658 @@ impl<IO: Io> Leader<IO> {
         Ok(())
     }
 }
+
+#[derive(Debug)]
+struct LeadershipTransfer {
+    target: NodeId,
+    timeout_now_sent: bool,
+    heartbeats_left: usize,
+}
+
+/// リーダであることの確認待ち、ないし、ログの消費待ちの読み込み要求.
+#[derive(Debug)]
+struct PendingRead {
+    index: LogIndex,
+    seq_no: SequenceNumber,
+    reply: oneshot::Sender<Result<LogIndex>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Async, Future, Stream};
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::cluster::{ClusterMembers, ClusterState};
+    use crate::election::{Role, TimeoutPolicy};
+    use crate::log::{LogEntry, LogSuffix};
+    use crate::message::Message;
+    use crate::node::NodeId;
+    use crate::testing::MockCluster;
+    use crate::{ErrorKind, Event, ReplicatedLogOptions};
+
+    #[test]
+    fn leadership_is_transferred() {
+        let mut cluster = MockCluster::new(&["a", "b", "c", "d", "e"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let term = cluster.node("a").local_node().ballot.term;
+
+        let e = cluster
+            .node_mut("a")
+            .transfer_leadership(NodeId::from("f"))
+            .err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+
+        cluster
+            .node_mut("a")
+            .transfer_leadership(NodeId::from("c"))
+            .unwrap();
+        let e = cluster.node_mut("a").propose_command(vec![2]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Busy));
+
+        // タイムアウトを待たずに、移譲先が新しいリーダに選出される
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("c")));
+        assert_eq!(
+            cluster.node("c").local_node().ballot.term.as_u64(),
+            term.as_u64() + 1
+        );
+        cluster.node_mut("c").propose_command(vec![2]).unwrap();
+    }
+
+    #[test]
+    fn proposals_are_rejected_while_uncommitted_window_is_full() {
+        let options = ReplicatedLogOptions {
+            max_uncommitted_entries: Some(3),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b"], options);
+        cluster.elect("a");
+
+        cluster.isolate("b");
+        for i in 0..3 {
+            cluster.node_mut("a").propose_command(vec![i]).unwrap();
+            cluster.run();
+        }
+        let e = cluster.node_mut("a").propose_command(vec![3]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Busy));
+        let e = cluster
+            .node_mut("a")
+            .propose_commands(vec![vec![3], vec![4]])
+            .err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Busy));
+
+        // フォロワーが追い付けば、再び受け付けられる
+        cluster.heal();
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("b").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        cluster.node_mut("a").propose_command(vec![3]).unwrap();
+    }
+
+    #[test]
+    fn committed_proposals_are_notified_with_their_ids() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        let ids = cluster
+            .node_mut("a")
+            .propose_commands(vec![vec![1], vec![2]])
+            .unwrap();
+        let id = cluster.node_mut("a").propose_command(vec![3]).unwrap();
+        assert_eq!(ids[1].index + 1, id.index);
+        cluster.run();
+
+        let committed = cluster
+            .events("a")
+            .iter()
+            .filter_map(|e| match e {
+                Event::ProposalCommitted { id } => Some(*id),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(committed, [ids[0], ids[1], id]);
+
+        // 提案IDのインデックスには、提案したコマンドがコミットされている
+        let entry = cluster.events("a").iter().find_map(|e| match e {
+            Event::Committed { index, entry } if *index == id.index => Some(entry.clone()),
+            _ => None,
+        });
+        assert!(matches!(
+            entry,
+            Some(LogEntry::Command { term, ref command }) if term == id.term && command[..] == [3]
+        ));
+
+        // 提案していないノードでは発行されない
+        assert!(!cluster
+            .events("b")
+            .iter()
+            .any(|e| matches!(e, Event::ProposalCommitted { .. })));
+
+        // 非リーダへの提案は失敗する
+        let e = cluster.node_mut("b").propose_command(vec![4]).unwrap_err();
+        assert_eq!(*e.kind(), ErrorKind::NotLeader);
+    }
+
+    #[test]
+    fn proposals_larger_than_max_command_bytes_are_rejected() {
+        let options = ReplicatedLogOptions {
+            max_command_bytes: Some(8),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        // 上限ちょうどのコマンドは受け付けられる
+        cluster.node_mut("a").propose_command(vec![0; 8]).unwrap();
+        cluster.run();
+        let tail = cluster.log_suffix("a").tail();
+
+        // 上限を超えるコマンドは、ログを変更せずに拒否される
+        let e = cluster.node_mut("a").propose_command(vec![0; 9]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        let e = cluster
+            .node_mut("a")
+            .propose_commands(vec![vec![1], vec![0; 9]])
+            .err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InvalidInput));
+        cluster.run();
+        assert_eq!(cluster.log_suffix("a").tail(), tail);
+        assert_eq!(cluster.node("a").local_history().tail(), tail);
+    }
+
+    #[test]
+    fn leader_partitioned_from_majority_rejects_proposals() {
+        let options = ReplicatedLogOptions {
+            check_quorum: true,
+            timeout_policy: TimeoutPolicy {
+                follower: Duration::from_millis(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        // 過半数から応答があれば、リースは更新され続ける
+        thread::sleep(Duration::from_millis(100));
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        cluster.isolate("a");
+        thread::sleep(Duration::from_millis(100));
+
+        // 新しいリーダが選出される前に、提案を拒否するようになる
+        let e = cluster.node_mut("a").propose_command(vec![2]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::NotLeader));
+        assert_eq!(cluster.node("b").current_leader(), Some("a".into()));
+        assert_eq!(cluster.node("c").current_leader(), Some("a".into()));
+
+        // 次のハートビートのタイミングで退任する
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.node("a").local_node().role, Role::Follower);
+    }
+
+    #[test]
+    fn removed_leader_hands_off_leadership() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let members = ["b", "c"].iter().map(|&id| NodeId::from(id)).collect();
+        cluster.node_mut("a").propose_config(members).unwrap();
+        cluster.run();
+
+        // 新構成のコミット後に、残ったメンバにリーダシップが移譲される
+        let leader = cluster.leader().unwrap();
+        assert_ne!(leader, NodeId::from("a"));
+        assert_ne!(cluster.node("a").local_node().role, Role::Leader);
+        assert!(!cluster
+            .node(leader.as_str())
+            .cluster_config()
+            .is_known_node(&"a".into()));
+
+        // 以後も、新構成でのコミットが継続する
+        let committed = cluster.node("b").local_history().committed_tail().index;
+        cluster
+            .node_mut(leader.as_str())
+            .propose_command(vec![1])
+            .unwrap();
+        cluster.run();
+        cluster.node_mut(leader.as_str()).heartbeat().unwrap();
+        cluster.run();
+        for id in &["b", "c"] {
+            assert!(cluster.node(id).local_history().committed_tail().index > committed);
+        }
+    }
+
+    #[test]
+    fn stuck_catchup_is_aborted() {
+        let options = ReplicatedLogOptions {
+            catchup_timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+
+        // `b`と`c`を`d`と`e`で置き換えるが、`d`と`e`は一切応答しない
+        // (新メンバ群の過半数が追い付かないので、`CatchUp`状態から進まない)
+        for id in &["d", "e"] {
+            cluster.add_node(id);
+            cluster.isolate(id);
+        }
+        let members = |ids: &[&str]| {
+            ids.iter()
+                .map(|&id| NodeId::from(id))
+                .collect::<ClusterMembers>()
+        };
+        let old = members(&["a", "b", "c"]);
+        let new = members(&["a", "d", "e"]);
+        cluster.node_mut("a").propose_config(new.clone()).unwrap();
+        cluster.run();
+        assert_eq!(
+            cluster.node("a").cluster_config().state(),
+            ClusterState::CatchUp
+        );
+
+        // 期限を過ぎると、旧メンバ群のみの構成に戻される
+        thread::sleep(Duration::from_millis(100));
+        cluster.fire_timeout("a");
+        cluster.run();
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        assert!(cluster.events("a").iter().any(|e| matches!(
+            e,
+            Event::ConfigChangeAborted { new_members, lagging }
+                if *new_members == new && *lagging == members(&["d", "e"])
+        )));
+        for id in &["a", "b", "c"] {
+            let config = cluster.node(id).cluster_config();
+            assert_eq!(config.state(), ClusterState::Stable);
+            assert_eq!(*config.new_members(), old);
+            assert!(!cluster
+                .node(id)
+                .local_history()
+                .has_uncommitted_config_change());
+        }
+        assert_eq!(cluster.leader(), Some(NodeId::from("a")));
+    }
+
+    #[test]
+    fn drain_persists_in_flight_appends() {
+        let has_command = |suffix: &LogSuffix, command: &[u8]| {
+            suffix.entries.iter().any(|e| match e {
+                LogEntry::Command { command: c, .. } => c[..] == *command,
+                _ => false,
+            })
+        };
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node("a").io().set_saves_deferred(true);
+
+        // 追記の完了を待たずに破棄すると、提案したエントリは失われる
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        let node = cluster.take_node("a");
+        let storage = Arc::clone(node.io().storage());
+        let _ = node.finish();
+        assert!(!has_command(&storage.lock().unwrap().suffix, &[1]));
+
+        // `drain`を使えば、実行中の追記が完了してから破棄される
+        cluster.restart_from_storage("a", storage);
+        cluster.run();
+        cluster.elect("a");
+        cluster.node("a").io().set_saves_deferred(true);
+        cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        let io = cluster.take_node("a").drain().wait().unwrap();
+        assert!(has_command(&io.storage().lock().unwrap().suffix, &[2]));
+
+        // 再起動後も、エントリは残っている
+        cluster.restart_from_storage("a", Arc::clone(io.storage()));
+        cluster.run();
+        assert!(has_command(&cluster.log_suffix("a"), &[2]));
+    }
+
+    #[test]
+    fn leader_shutdown_hands_off_leadership() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        let term = cluster.node("a").local_node().ballot.term;
+
+        let shutdown = cluster.node_mut("a").shutdown().unwrap();
+        let e = cluster.node_mut("a").shutdown().err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Busy));
+
+        // タイムアウトを待たずに、後継のリーダが選出される
+        cluster.run();
+        shutdown.wait().unwrap();
+        assert_ne!(cluster.node("a").local_node().role, Role::Leader);
+        let leader = cluster.leader().unwrap();
+        assert_ne!(leader, NodeId::from("a"));
+        assert_eq!(
+            cluster
+                .node(leader.as_str())
+                .local_node()
+                .ballot
+                .term
+                .as_u64(),
+            term.as_u64() + 1
+        );
+    }
+
+    #[test]
+    fn stepped_down_leader_rejects_proposals() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let term = cluster.node("a").local_node().ballot.term;
+
+        cluster.node_mut("a").step_down();
+        cluster.run();
+        assert_eq!(cluster.node("a").local_node().role, Role::Follower);
+        assert_eq!(cluster.node("a").local_node().ballot.term, term);
+        assert!(cluster.events("a").iter().any(|e| match e {
+            Event::RoleChanged { new_role } => *new_role == Role::Follower,
+            _ => false,
+        }));
+        assert_eq!(cluster.leader(), None);
+
+        let e = cluster.node_mut("a").propose_command(vec![1]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::NotLeader));
+
+        cluster.elect("b");
+        assert_eq!(
+            cluster.node("b").local_node().ballot.term.as_u64(),
+            term.as_u64() + 1
+        );
+        assert_eq!(cluster.node("a").local_node().ballot.voted_for, "b".into());
+        cluster.node_mut("b").propose_command(vec![2]).unwrap();
+    }
+
+    #[test]
+    fn leader_describes_cluster() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        let node = cluster.node("a");
+        let term = node.local_node().ballot.term.as_u64();
+        let committed = node.local_history().committed_tail().index.as_u64();
+        let report = node.describe_cluster();
+        assert!(report.contains("role: leader\n"));
+        assert!(report.contains(&format!("term: {}\n", term)));
+        assert!(report.contains("leader: a\n"));
+        assert!(report.contains(&format!("committed={},", committed)));
+        assert!(report.contains("followers: 2\n"));
+        assert!(report.contains("  b: "));
+        assert!(report.contains("  c: "));
+
+        let report = cluster.node("b").describe_cluster();
+        assert!(report.contains("role: follower\n"));
+        assert!(report.contains("leader: a\n"));
+        assert!(!report.contains("followers:"));
+    }
+
+    #[test]
+    fn follower_shutdown_notifies_leader() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.network().lock().unwrap().clear_sent_messages();
+
+        cluster.node_mut("c").shutdown().unwrap().wait().unwrap();
+        let departures = cluster
+            .network()
+            .lock()
+            .unwrap()
+            .sent_messages()
+            .iter()
+            .filter(|m| match m {
+                Message::DepartureCast(m) => m.header.destination.as_str() == "a",
+                _ => false,
+            })
+            .count();
+        assert_eq!(departures, 1);
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("a")));
+
+        // 停止したフォロワーは、移譲先には選ばれない
+        cluster.node_mut("a").shutdown().unwrap();
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("b")));
+    }
+
+    #[test]
+    fn read_index_observes_committed_entries() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let committed = cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+
+        let read = cluster.node_mut("a").read_index();
+        let uncommitted = cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        cluster.run();
+        let index = read.wait().unwrap();
+        assert_eq!(index, committed.index + 1);
+        assert!(index <= uncommitted.index);
+
+        let e = cluster.node_mut("b").read_index().wait().err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::NotLeader));
+    }
+
+    #[test]
+    fn heartbeat_quorum_waits_for_majority_acks() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // 過半数と疎通できない間は完了しない
+        cluster.isolate("b");
+        cluster.isolate("c");
+        let mut quorum = cluster.node_mut("a").heartbeat_quorum();
+        cluster.run();
+        let polled = futures::future::lazy(|| Ok::<_, ()>(quorum.poll().ok())).wait();
+        assert_eq!(polled, Ok(Some(Async::NotReady)));
+
+        // 過半数からの応答を得られた時点で完了する
+        cluster.heal();
+        cluster.node_mut("a").heartbeat().unwrap();
+        cluster.run();
+        let seq_no = quorum.wait().unwrap();
+        assert!(seq_no <= cluster.node("a").last_heartbeat_ack());
+
+        let e = cluster.node_mut("b").heartbeat_quorum().wait().err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::NotLeader));
+    }
+
+    #[test]
+    fn heartbeat_quorum_fails_when_leader_steps_down() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        cluster.isolate("a");
+        let quorum = cluster.node_mut("a").heartbeat_quorum();
+        cluster.run();
+
+        // 応答を得る前に、新しいリーダが選出されて退任する
+        cluster.fire_timeout("b");
+        cluster.fire_timeout("c");
+        cluster.run();
+        cluster.fire_timeout("b"); // 票が割れた場合に備えて再度立候補させる
+        cluster.run();
+        cluster.heal();
+        cluster.node_mut("b").heartbeat().unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("a").local_node().role, Role::Follower);
+
+        let e = quorum.wait().err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::NotLeader));
+    }
+
+    #[test]
+    fn stale_append_entries_reply_is_ignored() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let network = cluster.network().clone();
+        let step = |cluster: &mut MockCluster, n: usize| {
+            for _ in 0..n {
+                cluster.step();
+            }
+        };
+
+        // 追記に対する応答よりも、その後のハートビートに対する応答の方が遅れて届く
+        network.lock().unwrap().set_delay("a", 10);
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        step(&mut cluster, 5);
+        network.lock().unwrap().set_delay("a", 40);
+        cluster.fire_timeout("a");
+        step(&mut cluster, 15);
+
+        // ハートビートに対する応答が届く前に、次の追記を行う (この応答は当面は届かない)
+        network.lock().unwrap().set_delay("a", 1000);
+        cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        step(&mut cluster, 5);
+
+        // ハートビートに対する応答は、その時点では既に古いので、同期処理は開始されない
+        network.lock().unwrap().clear_sent_messages();
+        step(&mut cluster, 40);
+        let sent = network.lock().unwrap().sent_messages().to_owned();
+        assert!(
+            !sent
+                .iter()
+                .any(|m| matches!(m, Message::AppendEntriesCall(_))),
+            "{:?}",
+            sent
+        );
+
+        network.lock().unwrap().set_delay("a", 0);
+        cluster.run();
+        assert_eq!(
+            cluster.log_suffix("b").tail(),
+            cluster.log_suffix("a").tail()
+        );
+        assert_eq!(
+            cluster.log_suffix("c").tail(),
+            cluster.log_suffix("a").tail()
+        );
+    }
+
+    #[test]
+    fn transient_save_errors_are_retried() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // リーダおよびフォロワーでの一時的な保存エラーは、リトライによって回復する
+        cluster
+            .node("a")
+            .io()
+            .inject_save_errors(ErrorKind::Other, 1);
+        cluster
+            .node("b")
+            .io()
+            .inject_save_errors(ErrorKind::Other, 2);
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        assert_eq!(cluster.leader(), Some(NodeId::from("a")));
+        let tail = cluster.log_suffix("a").tail();
+        assert_eq!(cluster.node("a").local_history().committed_tail(), tail);
+        assert_eq!(cluster.log_suffix("b").tail(), tail);
+        assert_eq!(cluster.node("b").local_history().committed_tail(), tail);
+
+        // 不整合エラーは、そのまま呼び出し元に返される
+        cluster
+            .node("a")
+            .io()
+            .inject_save_errors(ErrorKind::InconsistentState, 1);
+        cluster.node_mut("a").propose_command(vec![2]).unwrap();
+        let result = loop {
+            match cluster.node_mut("a").poll() {
+                Ok(Async::Ready(_)) => {}
+                result => break result,
+            }
+        };
+        let e = result.err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::InconsistentState));
+    }
+
+    #[test]
+    fn commands_are_rejected_during_config_change() {
+        let options = ReplicatedLogOptions {
+            reject_commands_during_config_change: true,
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.add_node("d");
+        cluster.elect("a");
+        assert!(!cluster
+            .node("a")
+            .local_history()
+            .has_uncommitted_config_change());
+
+        let members = vec!["a", "b", "c", "d"];
+        let members = members.into_iter().map(Into::into).collect();
+        cluster.node_mut("a").propose_config(members).unwrap();
+        let e = cluster.node_mut("a").propose_command(vec![1]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Other));
+
+        cluster.step();
+        assert!(cluster
+            .node("a")
+            .local_history()
+            .has_uncommitted_config_change());
+        let e = cluster.node_mut("a").propose_command(vec![1]).err();
+        assert_eq!(e.map(|e| *e.kind()), Some(ErrorKind::Other));
+
+        cluster.run();
+        assert!(!cluster
+            .node("a")
+            .local_history()
+            .has_uncommitted_config_change());
+        assert!(cluster.node_mut("a").propose_command(vec![1]).is_ok());
+    }
+
+    #[test]
+    fn commands_are_proposed_in_batch() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let commands = (0..10).map(|i| vec![i]).collect();
+        let proposal_ids = cluster.node_mut("a").propose_commands(commands).unwrap();
+        assert_eq!(proposal_ids.len(), 10);
+        assert!(proposal_ids
+            .windows(2)
+            .all(|w| w[0].index + 1 == w[1].index));
+        assert!(cluster
+            .node_mut("a")
+            .propose_commands(Vec::new())
+            .unwrap()
+            .is_empty());
+
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+        let last = proposal_ids.last().unwrap();
+        for id in &["a", "b", "c"] {
+            assert!(cluster.node(id).local_history().committed_tail().index > last.index);
+        }
+        let suffix = cluster.log_suffix("c");
+        let offset = proposal_ids[0].index - suffix.head.index;
+        for (i, e) in suffix.entries[offset..offset + 10].iter().enumerate() {
+            assert_eq!(
+                *e,
+                LogEntry::Command {
+                    term: last.term,
+                    command: vec![i as u8].into()
+                }
+            );
+        }
+    }
+}
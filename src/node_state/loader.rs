@@ -1,13 +1,13 @@
 use futures::{Async, Future, Poll};
 
-use super::{Common, NextState};
+use super::{CheckedLoadLog, Common, NextState};
 use crate::election::Role;
 use crate::log::{Log, LogIndex};
 use crate::{Error, Io, Result};
 
 /// ノード起動時に、前回の状況を復元(ロード)を行う.
 pub struct Loader<IO: Io> {
-    phase: Phase<IO::LoadBallot, IO::LoadLog>,
+    phase: Phase<IO::LoadBallot, CheckedLoadLog<IO>>,
 }
 impl<IO: Io> Loader<IO> {
     pub fn new(common: &mut Common<IO>) -> Self {
@@ -20,12 +20,12 @@ impl<IO: Io> Loader<IO> {
         Ok(None)
     }
     pub fn run_once(&mut self, common: &mut Common<IO>) -> Result<NextState<IO>> {
-        while let Async::Ready(phase) = track!(self.phase.poll().map_err(Error::from))? {
+        while let Async::Ready(phase) = track!(self.phase.poll())? {
             let next = match phase {
                 Phase::A(ballot) => {
                     // 1) 前回の投票状況を復元
                     if let Some(ballot) = ballot {
-                        common.set_ballot(ballot);
+                        common.restore_ballot(ballot);
                     }
                     let future = common.load_log(LogIndex::new(0), None);
                     Phase::B(future) // => ログ復元へ
@@ -55,7 +55,11 @@ impl<IO: Io> Loader<IO> {
                             // もしこれに起因した現実的な性能問題が発生するようであれば、
                             // 「ローカルログの長さ取得」を行うための専用メソッドを、
                             // `Io`トレイトに追加しても良いかもしれない.
-                            track!(common.handle_log_appended(&suffix))?;
+                            track!(suffix.validate())?;
+                            if !track!(common.handle_log_restored(&suffix))? {
+                                // 保存された履歴の要約が無い(ないし古い)ので、エントリ群から再構築する
+                                track!(common.handle_log_appended(&suffix))?;
+                            }
 
                             // FIXME:
                             // 起動直後にcandidate状態に遷移してしまうと、
@@ -109,6 +113,8 @@ mod tests {
     use crate::metrics::NodeStateMetrics;
     use crate::node::NodeId;
     use crate::test_util::tests::TestIoBuilder;
+    use crate::testing::MockCluster;
+    use crate::ReplicatedLogOptions;
     use trackable::result::TestResult;
 
     #[test]
@@ -118,7 +124,7 @@ mod tests {
         let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
         let mut handle = io.handle();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), metrics, Default::default());
         let mut loader = Loader::new(&mut common);
 
         // prefix には空の snapshot があり、tail は 1 を指している。
@@ -130,11 +136,7 @@ mod tests {
             prev_term: term,
             index: suffix_head,
         };
-        handle.set_initial_log_prefix(LogPrefix {
-            tail: prefix_tail,
-            config: cluster,
-            snapshot: vec![],
-        });
+        handle.set_initial_log_prefix(LogPrefix::new(prefix_tail, cluster, vec![]));
         handle.set_initial_log_suffix(
             suffix_head,
             LogSuffix {
@@ -169,7 +171,7 @@ mod tests {
         let io = TestIoBuilder::new().add_member(node_id.clone()).finish();
         let mut handle = io.handle();
         let cluster = io.cluster.clone();
-        let mut common = Common::new(node_id, io, cluster.clone(), metrics);
+        let mut common = Common::new(node_id, io, cluster.clone(), metrics, Default::default());
         let mut loader = Loader::new(&mut common);
 
         // 古い term のログが紛れ込んでいるとエラーになる
@@ -179,11 +181,7 @@ mod tests {
             prev_term: term,
             index: suffix_head,
         };
-        handle.set_initial_log_prefix(LogPrefix {
-            tail: prefix_tail,
-            config: cluster,
-            snapshot: vec![],
-        });
+        handle.set_initial_log_prefix(LogPrefix::new(prefix_tail, cluster, vec![]));
         handle.set_initial_log_suffix(
             suffix_head,
             LogSuffix {
@@ -210,4 +208,58 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn restored_history_equals_replayed_one() -> TestResult {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        for i in 0..3 {
+            track!(cluster.propose("a", vec![i]))?;
+        }
+        cluster.run();
+        let stale = cluster.node("c").local_history().summarize();
+
+        cluster.elect("b");
+        for i in 0..3 {
+            track!(cluster.propose("b", vec![i]))?;
+        }
+        cluster.run();
+        let snapshot_tail = cluster.node("c").local_history().consumed_tail().index;
+        track!(cluster
+            .node_mut("c")
+            .install_snapshot(snapshot_tail, vec![]))?;
+        cluster.run();
+        for i in 0..3 {
+            track!(cluster.propose("b", vec![i]))?;
+        }
+        cluster.run();
+        let summary = cluster.node("c").local_history().summarize();
+        assert_eq!(summary.head.index, snapshot_tail);
+
+        // ログの再走査による再構築
+        cluster.restart("c");
+        cluster.step();
+        assert!(cluster.node("c").local_node().role == Role::Candidate);
+        let replayed = cluster.node("c").local_history().summarize();
+        assert_eq!(replayed.appended_tail, summary.appended_tail);
+
+        // 保存された要約からの復元
+        let options = ReplicatedLogOptions {
+            restored_history: Some(summary),
+            ..Default::default()
+        };
+        cluster.restart_with_options("c", options);
+        cluster.step();
+        assert_eq!(cluster.node("c").local_history().summarize(), replayed);
+
+        // 先端位置が一致しない古い要約は無視される
+        let options = ReplicatedLogOptions {
+            restored_history: Some(stale),
+            ..Default::default()
+        };
+        cluster.restart_with_options("c", options);
+        cluster.step();
+        assert_eq!(cluster.node("c").local_history().summarize(), replayed);
+        Ok(())
+    }
 }
@@ -1,7 +1,8 @@
+use futures::sync::oneshot;
 use futures::{Async, Poll, Stream};
 use std::time::Instant;
 
-pub use self::common::Common;
+pub use self::common::{CheckedLoadLog, Common};
 
 use self::candidate::Candidate;
 use self::common::HandleMessageResult;
@@ -9,10 +10,11 @@ use self::follower::Follower;
 use self::leader::Leader;
 use self::loader::Loader;
 use crate::cluster::ClusterConfig;
+use crate::election::Term;
 use crate::message::Message;
 use crate::metrics::NodeStateMetrics;
 use crate::node::NodeId;
-use crate::{Error, Event, Io, Result};
+use crate::{ElectionStats, Error, Event, Io, ReplicatedLogOptions, Result};
 
 mod candidate;
 mod common;
@@ -31,10 +33,30 @@ pub struct NodeState<IO: Io> {
     pub role: RoleState<IO>,
     started_at: Instant,
     pub metrics: NodeStateMetrics,
+    pub on_become_leader: Option<Box<dyn FnMut(Term) + Send>>,
+    pub on_lose_leadership: Option<Box<dyn FnMut() + Send>>,
+
+    /// 停止に伴うリーダシップの移譲の完了通知先.
+    pub shutdown: Option<oneshot::Sender<()>>,
+
+    /// `true`の場合には、新しい処理を受け付けずに、実行中の保存・読み込み処理の完了のみを待つ.
+    pub draining: bool,
+
+    /// 選挙に関する統計情報.
+    pub election_stats: ElectionStats,
+
+    // 安定したリーダが存在する現在の`Term`と、そのリーダを認識した時刻.
+    stable_term: Option<(Term, Instant)>,
 }
 impl<IO: Io> NodeState<IO> {
-    pub fn load(node_id: NodeId, config: ClusterConfig, io: IO, metrics: NodeStateMetrics) -> Self {
-        let mut common = Common::new(node_id, io, config, metrics.clone());
+    pub fn load(
+        node_id: NodeId,
+        config: ClusterConfig,
+        io: IO,
+        metrics: NodeStateMetrics,
+        options: ReplicatedLogOptions,
+    ) -> Self {
+        let mut common = Common::new(node_id, io, config, metrics.clone(), options);
         let role = RoleState::Loader(Loader::new(&mut common));
         let started_at = Instant::now();
         NodeState {
@@ -42,17 +64,57 @@ impl<IO: Io> NodeState<IO> {
             role,
             started_at,
             metrics,
+            on_become_leader: None,
+            on_lose_leadership: None,
+            shutdown: None,
+            draining: false,
+            election_stats: ElectionStats::default(),
+            stable_term: None,
         }
     }
     pub fn is_loading(&self) -> bool {
         self.role.is_loader()
     }
+
+    /// 実行中のストレージへの保存ないし読み込み処理が存在するかどうかを判定する.
+    pub fn has_pending_io(&self) -> bool {
+        if self.common.is_snapshot_installing() {
+            return true;
+        }
+        match self.role {
+            RoleState::Loader(_) => true,
+            RoleState::Follower(ref t) => t.has_pending_io(),
+            RoleState::Candidate(ref t) => t.has_pending_io(),
+            RoleState::Leader(ref t) => t.has_pending_io(),
+        }
+    }
+
+    /// 所有している`Io`を返す.
+    pub fn into_io(self) -> IO {
+        self.common.into_io()
+    }
     pub fn start_election(&mut self) {
+        if self
+            .common
+            .config()
+            .is_witness(&self.common.local_node().id)
+        {
+            // ウィットネスはログの内容を保持していないので、リーダになってはならない
+            return;
+        }
         if let RoleState::Follower(_) = self.role {
             let next = self.common.transit_to_candidate();
             self.handle_role_change(next);
         }
     }
+    pub fn step_down(&mut self) {
+        if let RoleState::Leader(_) = self.role {
+            // 同一`Term`内での投票先は変更できないので、自分自身に投票したままフォロワーとなる
+            let local = self.common.local_node().id.clone();
+            let next = self.common.transit_to_follower(local, None);
+            self.handle_role_change(next);
+        }
+    }
     fn handle_timeout(&mut self) -> Result<Option<RoleState<IO>>> {
         match self.role {
             RoleState::Loader(ref mut t) => track!(t.handle_timeout(&mut self.common)),
@@ -81,6 +143,24 @@ impl<IO: Io> NodeState<IO> {
         }
     }
     fn handle_role_change(&mut self, next: RoleState<IO>) {
+        match (&self.role, &next) {
+            (RoleState::Leader(_), RoleState::Leader(_)) => {}
+            (_, RoleState::Leader(_)) => {
+                if let Some(f) = self.on_become_leader.as_mut() {
+                    f(self.common.local_node().ballot.term);
+                }
+            }
+            (RoleState::Leader(_), _) => {
+                if let Some(f) = self.on_lose_leadership.as_mut() {
+                    f();
+                }
+                if let Some(tx) = self.shutdown.take() {
+                    let _ = tx.send(());
+                }
+            }
+            _ => {}
+        }
+
         // For now, we don't require the metrics of other state transitions.
         match (&self.role, &next) {
             (RoleState::Candidate(_), RoleState::Leader(_)) => {
@@ -110,7 +190,43 @@ impl<IO: Io> NodeState<IO> {
             | (RoleState::Loader(_), RoleState::Loader(_)) => {}
             _ => self.started_at = Instant::now(),
         }
+
+        match (&self.role, &next) {
+            (RoleState::Candidate(_), RoleState::Leader(_)) => {
+                self.election_stats.elections_won += 1;
+            }
+            (RoleState::Candidate(_), RoleState::Follower(_))
+            | (RoleState::Candidate(_), RoleState::Candidate(_)) => {
+                self.election_stats.elections_lost += 1;
+                self.metrics.elections_lost_total.increment();
+            }
+            _ => {}
+        }
+        if let RoleState::Candidate(_) = next {
+            self.election_stats.elections_started += 1;
+        }
         self.role = next;
+        self.update_stable_term();
+    }
+
+    /// `Term`毎の、安定したリーダが存在した期間を記録する.
+    fn update_stable_term(&mut self) {
+        let term = self.common.term();
+        if let Some((stable_term, since)) = self.stable_term {
+            if stable_term == term {
+                return;
+            }
+            let lifetime = since.elapsed();
+            self.election_stats.completed_terms += 1;
+            self.election_stats.total_term_lifetime += lifetime;
+            self.metrics
+                .term_lifetime_seconds
+                .observe(prometrics::timestamp::duration_to_seconds(lifetime));
+            self.stable_term = None;
+        }
+        if let RoleState::Leader(_) | RoleState::Follower(_) = self.role {
+            self.stable_term = Some((term, Instant::now()));
+        }
     }
 }
 impl<IO: Io> Stream for NodeState<IO> {
@@ -126,7 +242,13 @@ impl<IO: Io> Stream for NodeState<IO> {
             }
 
             // タイムアウト処理
-            if let Async::Ready(()) = track!(self.common.poll_timeout())? {
+            // (停止処理中は、新たな処理の契機となるタイムアウトや受信メッセージは扱わない)
+            let timeout = if self.draining {
+                Async::NotReady
+            } else {
+                track!(self.common.poll_timeout())?
+            };
+            if let Async::Ready(()) = timeout {
                 did_something = true;
                 self.metrics.poll_timeout_total.increment();
                 if let Some(next) = track!(self.handle_timeout())? {
@@ -162,7 +284,12 @@ impl<IO: Io> Stream for NodeState<IO> {
             }
 
             // 受信メッセージ処理
-            if let Some(message) = track!(self.common.try_recv_message())? {
+            let message = if self.draining {
+                None
+            } else {
+                track!(self.common.try_recv_message())?
+            };
+            if let Some(message) = message {
                 did_something = true;
                 if let Some(next) = track!(self.handle_message(message))? {
                     self.handle_role_change(next);
@@ -172,11 +299,20 @@ impl<IO: Io> Stream for NodeState<IO> {
                 }
             }
         }
+        if let RoleState::Leader(ref leader) = self.role {
+            if !leader.is_transferring() {
+                // リーダシップの移譲が失敗した (i.e., 移譲先が選出されないまま打ち切られた)
+                if let Some(tx) = self.shutdown.take() {
+                    let _ = tx.send(());
+                }
+            }
+        }
         Ok(Async::NotReady)
     }
 }
 
 /// 各役割固有の状態.
+#[allow(clippy::large_enum_variant)] // ノード毎に一つしか存在しないので、サイズは問題とならない
 pub enum RoleState<IO: Io> {
     /// ノード起動時にストレージから前回の状況を復元するための状態
     Loader(Loader<IO>),
@@ -208,15 +344,23 @@ impl<IO: Io> RoleState<IO> {
 mod tests {
     use super::*;
     use prometrics::metrics::MetricBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
 
+    use crate::election::{Role, TimeoutPolicy};
+    use crate::message::VoteRejectionReason;
+    use crate::node::NodeId;
     use crate::test_util::tests::TestIoBuilder;
+    use crate::testing::MockCluster;
 
     #[test]
     fn node_state_is_loading_works() {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let node = NodeState::load("test".into(), cluster, io, metrics);
+        let node = NodeState::load("test".into(), cluster, io, metrics, Default::default());
         assert!(node.is_loading());
     }
 
@@ -225,7 +369,7 @@ mod tests {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new("test".into(), io, cluster, metrics);
+        let mut common = Common::new("test".into(), io, cluster, metrics, Default::default());
         let state = RoleState::Loader(Loader::new(&mut common));
         assert!(state.is_loader());
         assert!(!state.is_candidate());
@@ -236,9 +380,339 @@ mod tests {
         let metrics = NodeStateMetrics::new(&MetricBuilder::new()).expect("Never fails");
         let io = TestIoBuilder::new().finish();
         let cluster = io.cluster.clone();
-        let mut common = Common::new("test".into(), io, cluster, metrics);
+        let mut common = Common::new("test".into(), io, cluster, metrics, Default::default());
         let state = RoleState::Candidate(Candidate::new(&mut common));
         assert!(!state.is_loader());
         assert!(state.is_candidate());
     }
+
+    #[test]
+    fn randomized_candidate_timeouts_resolve_split_vote() {
+        let options = ReplicatedLogOptions {
+            timeout_policy: TimeoutPolicy {
+                candidate_jitter: Duration::from_millis(300),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c"], options);
+        cluster.elect("a");
+        let term = cluster.node("a").local_node().ballot.term.as_u64();
+
+        // `b`と`c`が同時に立候補して、票が割れる
+        cluster.isolate("a");
+        cluster.fire_timeout("b");
+        cluster.fire_timeout("c");
+        cluster.run();
+        for id in &["b", "c"] {
+            let node = cluster.node(id).local_node();
+            assert_eq!(node.role, Role::Candidate);
+            assert_eq!(node.ballot.voted_for, NodeId::from(*id));
+        }
+        assert_ne!(cluster.timeout_duration("b"), cluster.timeout_duration("c"));
+
+        // タイムアウト時間が短い方が先に再立候補して、当選する
+        let mut rounds = 0;
+        while !["b", "c"]
+            .iter()
+            .any(|id| cluster.node(id).local_node().role == Role::Leader)
+        {
+            rounds += 1;
+            assert!(rounds <= 3, "The split vote was not resolved");
+            cluster.fire_earliest_timeout(&["b", "c"]).unwrap();
+            cluster.run();
+        }
+        let leader = cluster.leader().unwrap();
+        assert_ne!(leader, NodeId::from("a"));
+        assert!(
+            cluster
+                .node(leader.as_str())
+                .local_node()
+                .ballot
+                .term
+                .as_u64()
+                > term + 1
+        );
+    }
+
+    #[test]
+    fn same_random_seed_reproduces_elections() {
+        type Election = (NodeId, u64, Vec<Option<Duration>>);
+        fn elections(seed: u64) -> Vec<Election> {
+            let ids = ["a", "b", "c", "d", "e"];
+            let options = ReplicatedLogOptions {
+                timeout_policy: TimeoutPolicy {
+                    candidate_jitter: Duration::from_millis(300),
+                    ..Default::default()
+                },
+                random_seed: Some(seed),
+                ..Default::default()
+            };
+            let mut cluster = MockCluster::with_options(&ids, options);
+            cluster.elect("a");
+
+            let mut history = Vec::new();
+            for _ in 0..5 {
+                // リーダ以外の全ノードが同時に立候補して、票が割れる
+                let old = cluster.leader().unwrap();
+                let candidates = ids
+                    .iter()
+                    .cloned()
+                    .filter(|&id| id != old.as_str())
+                    .collect::<Vec<_>>();
+                cluster.isolate(old.as_str());
+                for id in &candidates {
+                    cluster.fire_timeout(id);
+                }
+                cluster.run();
+                let durations = candidates
+                    .iter()
+                    .map(|id| cluster.timeout_duration(id))
+                    .collect();
+
+                let is_elected = |cluster: &MockCluster| {
+                    candidates
+                        .iter()
+                        .find(|id| cluster.node(id).local_node().role == Role::Leader)
+                        .map(|&id| NodeId::from(id))
+                };
+                let mut rounds = 0;
+                let leader = loop {
+                    if let Some(leader) = is_elected(&cluster) {
+                        break leader;
+                    }
+                    rounds += 1;
+                    assert!(rounds <= 10, "The split vote was not resolved");
+                    cluster.fire_earliest_timeout(&candidates).unwrap();
+                    cluster.run();
+                };
+                let term = cluster.node(leader.as_str()).local_node().ballot.term;
+                history.push((leader.clone(), term.as_u64(), durations));
+
+                cluster.heal();
+                cluster.node_mut(leader.as_str()).heartbeat().unwrap();
+                cluster.run();
+                assert_eq!(cluster.leader(), Some(leader));
+            }
+            history
+        }
+
+        assert_eq!(elections(7), elections(7));
+        assert_ne!(elections(7), elections(8));
+    }
+
+    #[test]
+    fn high_priority_follower_wins_election_after_leader_failure() {
+        let options = ReplicatedLogOptions {
+            election_priorities: vec![(NodeId::from("d"), 10)].into_iter().collect(),
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c", "d", "e"], options);
+        cluster.elect("a");
+        for id in &["b", "c", "e"] {
+            assert!(cluster.timeout_duration("d") < cluster.timeout_duration(id));
+        }
+
+        // 全てのフォロワーがリーダとの疎通断を検知して、一斉に立候補する
+        cluster.isolate("a");
+        for id in &["b", "c", "d", "e"] {
+            cluster.fire_timeout(id);
+        }
+        cluster.run();
+        for id in &["b", "c", "d", "e"] {
+            assert_eq!(cluster.node(id).local_node().role, Role::Candidate);
+        }
+
+        // 票が割れた後は、優先度の高い`d`が最初に再立候補して、当選する
+        assert_eq!(
+            cluster.fire_earliest_timeout(&["b", "c", "d", "e"]),
+            Some(NodeId::from("d"))
+        );
+        cluster.run();
+        assert_eq!(cluster.node("d").local_node().role, Role::Leader);
+
+        // 優先度の高いノードが停止していても、残りの過半数の中から当選する
+        cluster.isolate("d");
+        for id in &["b", "c", "e"] {
+            cluster.fire_timeout(id);
+        }
+        cluster.run();
+        let mut rounds = 0;
+        while !["b", "c", "e"]
+            .iter()
+            .any(|id| cluster.node(id).local_node().role == Role::Leader)
+        {
+            rounds += 1;
+            assert!(rounds <= 3, "No leader was elected");
+            cluster.fire_earliest_timeout(&["b", "c", "e"]).unwrap();
+            cluster.run();
+        }
+    }
+
+    fn vote_rejections(cluster: &MockCluster, id: &str) -> Vec<(NodeId, VoteRejectionReason)> {
+        cluster
+            .events(id)
+            .iter()
+            .filter_map(|e| match e {
+                Event::VoteRejected { voter, reason } => Some((voter.clone(), reason.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vote_rejected_by_log_behind() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // `c`のログだけが遅れている状態で、`c`が立候補する
+        cluster.isolate("c");
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        cluster.heal();
+        let tail = cluster.node("a").local_history().tail();
+        cluster.fire_timeout("c");
+        cluster.run();
+
+        assert!(vote_rejections(&cluster, "c").contains(&(
+            NodeId::from("a"),
+            VoteRejectionReason::LogBehind { their_tail: tail }
+        )));
+        assert_ne!(cluster.node("c").local_node().role, Role::Leader);
+    }
+
+    #[test]
+    fn vote_rejected_by_already_voted() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // `b`と`c`が同時に立候補して、互いに自分に投票済み
+        cluster.isolate("a");
+        cluster.fire_timeout("b");
+        cluster.fire_timeout("c");
+        cluster.run();
+        assert!(vote_rejections(&cluster, "b").contains(&(
+            NodeId::from("c"),
+            VoteRejectionReason::AlreadyVoted {
+                for_node: "c".into()
+            }
+        )));
+        assert_eq!(cluster.node("b").local_node().role, Role::Candidate);
+    }
+
+    #[test]
+    fn election_tie_break_resolves_symmetric_candidates() {
+        let options = ReplicatedLogOptions {
+            election_tie_break: true,
+            ..Default::default()
+        };
+        let mut cluster = MockCluster::with_options(&["a", "b", "c", "d"], options);
+        cluster.run();
+
+        // `c`には`a`の、`d`には`b`の投票依頼のみが届くようにして、票を割れさせる
+        cluster.ban("a", "d");
+        cluster.ban("b", "c");
+        cluster.fire_timeout("a");
+        cluster.fire_timeout("b");
+        cluster.run();
+
+        // 同じログを持つ候補者同士なので、`NodeId`の小さい`a`が立候補を取り下げる
+        assert_eq!(cluster.node("a").local_node().role, Role::Follower);
+        assert_eq!(cluster.node("b").local_node().role, Role::Candidate);
+
+        // 候補者のみがタイムアウトする状況を繰り返しても、有限回で一つのリーダに収束する
+        let mut rounds = 0;
+        while cluster.leader().is_none() {
+            rounds += 1;
+            assert!(rounds <= 3, "The election did not converge");
+            for id in &["a", "b"] {
+                if cluster.node(id).local_node().role == Role::Candidate {
+                    cluster.fire_timeout(id);
+                }
+            }
+            cluster.run();
+        }
+        assert_eq!(cluster.leader(), Some(NodeId::from("b")));
+    }
+
+    #[test]
+    fn leadership_hooks_are_invoked() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        let became = Arc::new(Mutex::new(Vec::new()));
+        let lost = Arc::new(AtomicUsize::new(0));
+        {
+            let became = Arc::clone(&became);
+            cluster
+                .node_mut("a")
+                .on_become_leader(Box::new(move |term| became.lock().unwrap().push(term)));
+            let lost = Arc::clone(&lost);
+            cluster.node_mut("a").on_lose_leadership(Box::new(move || {
+                lost.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        cluster.elect("a");
+        cluster.fire_timeout("a");
+        cluster.run();
+        let term = cluster.node("a").local_node().ballot.term;
+        assert_eq!(*became.lock().unwrap(), vec![term]);
+        assert_eq!(lost.load(Ordering::SeqCst), 0);
+
+        // `a`が孤立している間に`b`が当選し、疎通の回復後に`a`が退任する
+        cluster.isolate("a");
+        cluster.fire_timeout("c");
+        cluster.run();
+        for _ in 0..2 {
+            cluster.fire_timeout("b");
+            cluster.run();
+        }
+        assert_eq!(lost.load(Ordering::SeqCst), 0);
+        cluster.heal();
+        cluster.fire_timeout("b");
+        cluster.run();
+        assert_ne!(cluster.node("a").local_node().role, Role::Leader);
+        assert_eq!(*became.lock().unwrap(), vec![term]);
+        assert_eq!(lost.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn election_stats_are_tracked() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        let stats = |c: &MockCluster, id: &str| c.node(id).election_stats().clone();
+        let started = |c: &MockCluster| {
+            ["a", "b", "c"]
+                .iter()
+                .map(|id| stats(c, id).elections_started)
+                .sum::<u64>()
+        };
+        cluster.run();
+        let initial = started(&cluster);
+
+        // 一回目の選挙: `a`が当選する
+        cluster.elect("a");
+        assert_eq!(started(&cluster), initial + 1);
+        assert_eq!(stats(&cluster, "a").elections_won, 1);
+        assert_eq!(stats(&cluster, "a").completed_terms, 0);
+        assert_eq!(stats(&cluster, "a").average_term_lifetime(), None);
+
+        // 二回目の選挙: `b`が当選する
+        thread::sleep(Duration::from_millis(10));
+        cluster.fire_timeout("b");
+        cluster.run();
+        assert_eq!(cluster.leader(), Some("b".into()));
+        assert_eq!(started(&cluster), initial + 2);
+        assert_eq!(stats(&cluster, "a").elections_won, 1);
+        assert_eq!(stats(&cluster, "b").elections_won, 1);
+        assert_eq!(stats(&cluster, "c").elections_won, 0);
+
+        // `a`がリーダだった`Term`は終了した
+        let a = stats(&cluster, "a");
+        assert_eq!(a.completed_terms, 1);
+        assert!(a.average_term_lifetime().unwrap() >= Duration::from_millis(10));
+        let metrics = &cluster.node("a").metrics().node_state;
+        assert_eq!(metrics.term_lifetime_seconds.count(), 1);
+        assert_eq!(
+            metrics.elections_lost_total.value() as u64,
+            a.elections_lost
+        );
+    }
 }
@@ -1,16 +1,24 @@
-use futures::{Poll, Stream};
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll, Stream};
 use prometrics::metrics::MetricBuilder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use trackable::error::ErrorKindExt;
 
-use crate::cluster::{ClusterConfig, ClusterMembers};
-use crate::election::{Ballot, Role};
+use crate::cluster::{ClusterConfig, ClusterMembers, JointQuorum};
+use crate::compaction::{CompactionPolicy, SnapshotPolicy, StateMachine};
+use crate::election::{Ballot, Role, Term, TimeoutPolicy};
 use crate::io::Io;
-use crate::log::{LogEntry, LogHistory, LogIndex, LogPosition, LogPrefix, ProposalId};
-use crate::message::SequenceNumber;
+use crate::log::{
+    Compression, Log, LogEntry, LogHistory, LogHistorySnapshot, LogHistorySummary, LogIndex,
+    LogPosition, LogPrefix, LogSuffix, ProposalId,
+};
+use crate::message::{GroupId, SequenceNumber, VoteRejectionReason};
 use crate::metrics::RaftlogMetrics;
-use crate::node::{Node, NodeId};
-use crate::node_state::{NodeState, RoleState};
+use crate::node::{FollowerOrder, Node, NodeId};
+use crate::node_state::{CheckedLoadLog, NodeState, RoleState};
 use crate::{Error, ErrorKind, Result};
 
 /// Raftアルゴリズムに基づく分散複製ログ.
@@ -30,6 +38,7 @@ use crate::{Error, ErrorKind, Result};
 pub struct ReplicatedLog<IO: Io> {
     node: NodeState<IO>,
     metrics: Arc<RaftlogMetrics>,
+    compaction_policy: Option<Box<dyn CompactionPolicy + Send>>,
 }
 impl<IO: Io> ReplicatedLog<IO> {
     /// `members`で指定されたクラスタに属する`ReplicatedLog`のローカルインスタンス(ノード)を生成する.
@@ -54,14 +63,35 @@ impl<IO: Io> ReplicatedLog<IO> {
         io: IO,
         metric_builder: &MetricBuilder,
     ) -> Result<Self> {
-        let config = ClusterConfig::new(members);
+        Self::with_options(
+            node_id,
+            members,
+            io,
+            metric_builder,
+            ReplicatedLogOptions::default(),
+        )
+    }
+
+    /// `options`を指定して`ReplicatedLog`のインスタンスを生成する.
+    ///
+    /// `options`以外の引数の意味は`new`メソッドと同様.
+    pub fn with_options(
+        node_id: NodeId,
+        members: ClusterMembers,
+        io: IO,
+        metric_builder: &MetricBuilder,
+        options: ReplicatedLogOptions,
+    ) -> Result<Self> {
+        let config = track!(ClusterConfig::new(members).with_joint_quorum(options.joint_quorum))?
+            .with_witnesses(options.witnesses.clone());
         let mut metric_builder = metric_builder.clone();
         metric_builder.namespace("raftlog");
         let metrics = track!(RaftlogMetrics::new(&metric_builder))?;
-        let node = NodeState::load(node_id, config, io, metrics.node_state.clone());
+        let node = NodeState::load(node_id, config, io, metrics.node_state.clone(), options);
         Ok(ReplicatedLog {
             node,
             metrics: Arc::new(metrics),
+            compaction_policy: None,
         })
     }
 
@@ -82,12 +112,91 @@ impl<IO: Io> ReplicatedLog<IO> {
     ///
     /// 非リーダノードに対して、このメソッドが実行された場合には、
     /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// また`ReplicatedLogOptions::reject_commands_during_config_change`が`true`の場合には、
+    /// クラスタ構成の変更がコミットされるまでの間は、`ErrorKind::Other`を理由としたエラーが返される.
+    ///
+    /// 提案を受け付けると、未コミットのエントリ数が`ReplicatedLogOptions::max_uncommitted_entries`を
+    /// 超えてしまう場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `ReplicatedLogOptions::check_quorum`が`true`で、リーダが過半数との疎通を失っている場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `command`のサイズが`ReplicatedLogOptions::max_command_bytes`を超えている場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
     pub fn propose_command(&mut self, command: Vec<u8>) -> Result<ProposalId> {
+        let mut proposal_ids = track!(self.propose_commands(vec![command]))?;
+        Ok(proposal_ids.pop().expect("Never fails"))
+    }
+
+    /// 複数のコマンドをまとめて提案する.
+    ///
+    /// 基本的には`propose_command`メソッドを、`commands`の各要素に対して順番に呼び出すのと同様だが、
+    /// 全てのコマンドが一度の保存処理でローカルログに追記される点が異なる.
+    ///
+    /// 返り値は、各コマンドに対応する提案IDのリスト.
+    ///
+    /// # Errors
+    ///
+    /// `propose_command`メソッドと同様.
+    pub fn propose_commands(&mut self, commands: Vec<Vec<u8>>) -> Result<Vec<ProposalId>> {
+        if let Some(max) = self.node.common.options().max_command_bytes {
+            for command in &commands {
+                track_assert!(
+                    command.len() <= max,
+                    ErrorKind::InvalidInput,
+                    "Too large command: bytes={}, max={}",
+                    command.len(),
+                    max
+                );
+            }
+        }
         if let RoleState::Leader(ref mut leader) = self.node.role {
+            track_assert!(
+                !leader.is_transferring(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            if self
+                .node
+                .common
+                .options()
+                .reject_commands_during_config_change
+            {
+                track_assert!(
+                    !leader.is_changing_config(&self.node.common),
+                    ErrorKind::Other,
+                    "Cluster configuration change is in progress"
+                );
+            }
+            track_assert!(
+                track!(leader.has_quorum_lease(&self.node.common))?,
+                ErrorKind::NotLeader,
+                "No response from a majority within the election timeout"
+            );
+            if let Some(max) = self.node.common.options().max_uncommitted_entries {
+                let uncommitted = leader.uncommitted_entries(&self.node.common);
+                track_assert!(
+                    uncommitted + commands.len() <= max,
+                    ErrorKind::Busy,
+                    "Too many uncommitted entries: uncommitted={}, proposed={}, max={}",
+                    uncommitted,
+                    commands.len(),
+                    max
+                );
+            }
             let term = self.node.common.term();
-            let entry = LogEntry::Command { term, command };
-            let proposal_id = leader.propose(&mut self.node.common, entry);
-            Ok(proposal_id)
+            let entries = commands
+                .into_iter()
+                .map(|command| LogEntry::Command {
+                    term,
+                    command: command.into(),
+                })
+                .collect();
+            let proposal_ids = leader.propose_batch(&mut self.node.common, entries);
+            Ok(proposal_ids)
         } else {
             track_panic!(ErrorKind::NotLeader)
         }
@@ -111,9 +220,96 @@ impl<IO: Io> ReplicatedLog<IO> {
     ///
     /// 非リーダノードに対して、このメソッドが実行された場合には、
     /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `new_members`が現在のメンバ群と一つも共通するメンバを持たない場合や、
+    /// `JointQuorum::Union`の使用時に、その構成変更が安全ではない場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
     pub fn propose_config(&mut self, new_members: ClusterMembers) -> Result<ProposalId> {
         if let RoleState::Leader(ref mut leader) = self.node.role {
-            let config = self.node.common.config().start_config_change(new_members);
+            track_assert!(
+                !leader.is_transferring(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            let config = track!(self.node.common.config().start_config_change(new_members))?;
+            let term = self.node.common.term();
+            let entry = LogEntry::Config { term, config };
+            let proposal_id = leader.propose(&mut self.node.common, entry);
+            Ok(proposal_id)
+        } else {
+            track_panic!(ErrorKind::NotLeader)
+        }
+    }
+
+    /// `learner`をラーナー(投票権を持たないメンバ)としてクラスタに追加することを提案する.
+    ///
+    /// ラーナーはリーダからログの複製を受け取るが、
+    /// リーダ選出やログのコミットの判定には関与しない.
+    /// そのため、新規ノードのログが追い付くまでの間、コミットが停滞することがない.
+    ///
+    /// ログが追い付いた後は`promote_learner`メソッドを使って、投票権を有するメンバに昇格させることが可能.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `learner`が既にクラスタに属している場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn propose_learner(&mut self, learner: NodeId) -> Result<ProposalId> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            track_assert!(
+                !leader.is_transferring(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            track_assert!(
+                !self.node.common.config().is_known_node(&learner),
+                ErrorKind::InvalidInput,
+                "Already a member: {:?}",
+                learner
+            );
+            let config = self.node.common.config().add_learner(learner);
+            let term = self.node.common.term();
+            let entry = LogEntry::Config { term, config };
+            let proposal_id = leader.propose(&mut self.node.common, entry);
+            Ok(proposal_id)
+        } else {
+            track_panic!(ErrorKind::NotLeader)
+        }
+    }
+
+    /// ラーナーである`learner`を、投票権を有するメンバに昇格させることを提案する.
+    ///
+    /// 昇格は`propose_config`メソッドと同様の構成変更として実施される.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// リーダシップの移譲中の場合には、`ErrorKind::Busy`を理由としたエラーが返される.
+    ///
+    /// `learner`がラーナーではない場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn promote_learner(&mut self, learner: &NodeId) -> Result<ProposalId> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            track_assert!(
+                !leader.is_transferring(),
+                ErrorKind::Busy,
+                "Leadership transfer is in progress"
+            );
+            track_assert!(
+                self.node.common.config().is_learner(learner),
+                ErrorKind::InvalidInput,
+                "Not a learner: {:?}",
+                learner
+            );
+            let config = track!(self.node.common.config().promote_learner(learner))?;
             let term = self.node.common.term();
             let entry = LogEntry::Config { term, config };
             let proposal_id = leader.propose(&mut self.node.common, entry);
@@ -123,6 +319,142 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// リーダシップを`target`に移譲する.
+    ///
+    /// リーダは`target`のローカルログが自身に追い付くのを待ってから、
+    /// `target`に即座に選挙を開始するよう指示を送る.
+    /// 移譲が完了する(i.e., `target`が新しいリーダに選出される)までの間は、新規の提案は受け付けられない.
+    ///
+    /// 一定回数のハートビートの間に移譲が完了しなかった場合には、移譲は中止され、
+    /// このノードがリーダとしての処理を再開する.
+    ///
+    /// `target`が自ノードの場合には何も行われない.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合には、
+    /// `ErrorKind::NotLeader`を理由としたエラーが返される.
+    ///
+    /// `target`が投票権を有するクラスタのメンバではない場合や、ウィットネスの場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    pub fn transfer_leadership(&mut self, target: NodeId) -> Result<()> {
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            let config = self.node.common.config();
+            track_assert!(
+                config.is_electable(&target),
+                ErrorKind::InvalidInput,
+                "Not an electable voting member: {:?}",
+                target
+            );
+            if target != self.node.common.local_node().id {
+                leader.transfer_leadership(&mut self.node.common, target);
+            }
+            Ok(())
+        } else {
+            track_panic!(ErrorKind::NotLeader)
+        }
+    }
+
+    /// ローカルノードを停止するための準備を行う.
+    ///
+    /// 他のノードが、選挙タイムアウトを待たずに、ローカルノードの停止に対処できるようにする:
+    ///
+    /// - リーダの場合には、最もログが進んでいるフォロワーへのリーダシップの移譲を開始する
+    ///   - 返り値の`ShutdownFuture`は、移譲が完了(i.e., ローカルノードがリーダではなくなる)するか、
+    ///     移譲が打ち切られた時点で完了する
+    ///   - 完了は、`ReplicatedLog`自体がポーリングされることで進行する
+    /// - フォロワーの場合には、リーダに停止を通知する (`DepartureCast`)
+    ///   - この場合の`ShutdownFuture`は即座に完了する
+    ///
+    /// `ShutdownFuture`の完了後は、利用者はこのインスタンスを破棄して構わない.
+    ///
+    /// # Errors
+    ///
+    /// リーダシップの移譲が既に進行中の場合には、
+    /// `ErrorKind::Busy`を理由としたエラーが返される.
+    pub fn shutdown(&mut self) -> Result<ShutdownFuture> {
+        match self.node.role {
+            RoleState::Leader(ref mut leader) => {
+                track_assert!(!leader.is_transferring(), ErrorKind::Busy);
+                if !leader.start_shutdown(&mut self.node.common) {
+                    return Ok(ShutdownFuture(None));
+                }
+                let (tx, rx) = oneshot::channel();
+                self.node.shutdown = Some(tx);
+                Ok(ShutdownFuture(Some(rx)))
+            }
+            RoleState::Follower(_) => {
+                let leader = self.node.common.local_node().ballot.voted_for.clone();
+                if leader != self.node.common.local_node().id {
+                    self.node.common.rpc_caller().send_departure(&leader);
+                }
+                Ok(ShutdownFuture(None))
+            }
+            RoleState::Loader(_) | RoleState::Candidate(_) => Ok(ShutdownFuture(None)),
+        }
+    }
+
+    /// コミット済みだが、まだ消費されていない(i.e., `Event::Committed`が生成されていない)エントリ群を読み込む.
+    ///
+    /// `[consumed_tail.index, committed_tail.index)`の範囲が`Io::load_log`で読み込まれる.
+    /// 読み込みは`consumed_tail`を進めないので、何度呼び出しても同じ範囲のエントリ群が返される
+    /// (ただし、呼び出しの間にコミットが進んだ場合には、範囲は後方に伸びる).
+    ///
+    /// 状態機械の駆動側が、適用の前にエントリ群を先読みして、まとめて適用する単位を決定するために使用する.
+    /// エントリ群の消費自体は、従来通り`ReplicatedLog`をポーリングして`Event::Committed`を受け取ることで行われる.
+    ///
+    /// 未消費のエントリが存在しない場合には、`consumed_tail`を先頭とする空の`LogSuffix`で完了する.
+    ///
+    /// # Errors
+    ///
+    /// 範囲の先頭が既にスナップショットに含まれている場合には(`Event::SnapshotLoaded`の生成待ち)、
+    /// `PendingCommitted`は`ErrorKind::Busy`を理由としたエラーで完了する.
+    pub fn pending_committed(&mut self) -> PendingCommitted<IO> {
+        let consumed_tail = self.local_history().consumed_tail();
+        let committed_tail = self.local_history().committed_tail();
+        let future = if consumed_tail.index < committed_tail.index {
+            Some(
+                self.node
+                    .common
+                    .load_log(consumed_tail.index, Some(committed_tail.index)),
+            )
+        } else {
+            None
+        };
+        PendingCommitted {
+            future,
+            head: consumed_tail,
+        }
+    }
+
+    /// ReadIndexプロトコルに基づいて、線形化可能な読み込みを行うための地点を取得する.
+    ///
+    /// リーダは現在のコミット済み地点を記録した上で、ハートビートをブロードキャストする.
+    /// 過半数からの応答によって自身がまだリーダであることが確認でき、
+    /// かつ、記録した地点までのコミット済みログが消費された(i.e., `Event::Committed`が生成された)時点で、
+    /// 返り値の`ReadIndex`はその地点を結果として完了する.
+    ///
+    /// 利用者は、`ReadIndex`が完了した時点の状態機械に対して読み込みを行うことで、
+    /// ログにエントリを追加することなく、線形化可能な読み込みを実現することができる.
+    ///
+    /// なお`ReadIndex`の完了は、`ReplicatedLog`自体がポーリングされることで進行する.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合や、
+    /// `ReadIndex`の完了前にノードがリーダではなくなった場合には、
+    /// `ReadIndex`は`ErrorKind::NotLeader`を理由としたエラーで完了する.
+    pub fn read_index(&mut self) -> ReadIndex {
+        let (tx, rx) = oneshot::channel();
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            leader.read_index(&mut self.node.common, tx);
+        } else {
+            let e = track!(ErrorKind::NotLeader.error(), "node={:?}", self.local_node());
+            let _ = tx.send(Err(e.into()));
+        }
+        ReadIndex(rx)
+    }
+
     /// 強制的にハートビートメッセージ(i.e., AppendEntriesCall)をブロードキャストする.
     ///
     /// 返り値は、送信メッセージのシーケンス番号.
@@ -130,6 +462,7 @@ impl<IO: Io> ReplicatedLog<IO> {
     /// `last_heartbeat_ack`メソッドを用いることで、
     /// このハートビートに対して、過半数以上の応答を得られた
     /// タイミングを把握することが可能.
+    /// (応答を`Future`として待ちたい場合には`heartbeat_quorum`メソッドが利用可能)
     ///
     /// また、リーダのコミットを即座にフォロワーに伝えたい場合にも、
     /// このメソッドが活用可能。
@@ -151,10 +484,35 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// ハートビートをブロードキャストし、過半数のメンバからの応答を待つ.
+    ///
+    /// 返り値の`HeartbeatQuorum`は、`primary_members`の過半数が
+    /// このハートビートに応答した時点で、そのシーケンス番号を結果として完了する.
+    /// 現時点でリーダであることを確認するための生存確認として利用可能.
+    ///
+    /// なお`HeartbeatQuorum`の完了は、`ReplicatedLog`自体がポーリングされることで進行する.
+    ///
+    /// # Errors
+    ///
+    /// 非リーダノードに対して、このメソッドが実行された場合や、
+    /// `HeartbeatQuorum`の完了前にノードがリーダではなくなった場合には、
+    /// `HeartbeatQuorum`は`ErrorKind::NotLeader`を理由としたエラーで完了する.
+    pub fn heartbeat_quorum(&mut self) -> HeartbeatQuorum {
+        let (tx, rx) = oneshot::channel();
+        if let RoleState::Leader(ref mut leader) = self.node.role {
+            leader.confirm_heartbeat(&mut self.node.common, tx);
+        } else {
+            let e = track!(ErrorKind::NotLeader.error(), "node={:?}", self.local_node());
+            let _ = tx.send(Err(e.into()));
+        }
+        HeartbeatQuorum(rx)
+    }
+
     /// ローカルログにスナップショットをインストールする.
     ///
     /// `new_head`が新しいローカルログの先頭位置となり、
     /// `snapshot`はその地点までのコマンド群が適用済みの状態機械のスナップショット、となる.
+    /// スナップショットの構成情報には`LogHistory::config_at(new_head)`が使用される.
     ///
     /// # Errors
     ///
@@ -162,57 +520,420 @@ impl<IO: Io> ReplicatedLog<IO> {
     /// `ErrorKind::Busy`を理由としてエラーが返される.
     ///
     /// また現在のログの先頭よりも前の地点のスナップショットをインストールしようとした場合には、
-    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
     pub fn install_snapshot(&mut self, new_head: LogIndex, snapshot: Vec<u8>) -> Result<()> {
+        let config = track!(self.recorded_config_at(new_head))?.clone();
+        track!(self.install_snapshot_with_config(new_head, config, snapshot))
+    }
+
+    /// 構成情報を明示して、ローカルログにスナップショットをインストールする.
+    ///
+    /// `config`が`LogHistory::config_at(new_head)`と一致することが検証される点以外は、
+    /// `install_snapshot`メソッドと同様.
+    ///
+    /// # Errors
+    ///
+    /// `config`が、ローカルログに記録されている`new_head`の時点での構成と異なる場合には、
+    /// `ErrorKind::InconsistentState`を理由としたエラーが返される.
+    ///
+    /// その他のエラー条件は`install_snapshot`メソッドと同様.
+    pub fn install_snapshot_with_config(
+        &mut self,
+        new_head: LogIndex,
+        config: ClusterConfig,
+        snapshot: Vec<u8>,
+    ) -> Result<()> {
         track_assert!(
             !self.node.is_loading(),
             ErrorKind::Busy,
             "Loading node state"
         );
 
-        let (prev_term, config) = {
-            let record = track!(
-                self.node
-                    .common
-                    .log()
-                    .get_record(new_head)
-                    .ok_or_else(|| ErrorKind::InvalidInput.error()),
-                "Too old log position: new_head={:?}, current_head={:?}, node={:?}",
-                new_head,
-                self.local_history().head(),
-                self.local_node()
-            )?;
-            (record.head.prev_term, record.config.clone())
-        };
-        let prefix = LogPrefix {
-            tail: LogPosition {
+        let recorded = track!(self.recorded_config_at(new_head))?;
+        track_assert_eq!(
+            &config,
+            recorded,
+            ErrorKind::InconsistentState,
+            "Conflicting snapshot config: new_head={:?}, node={:?}",
+            new_head,
+            self.local_node()
+        );
+        let prev_term = self.local_history().term_at(new_head).expect("Never fails");
+        let prefix = LogPrefix::new(
+            LogPosition {
                 prev_term,
                 index: new_head,
             },
             config,
             snapshot,
-        };
+        );
         track!(self.node.common.install_snapshot(prefix))?;
         Ok(())
     }
 
+    /// 利用者が非同期に取得したスナップショットを受け取って、ローカルログにインストールする.
+    ///
+    /// `Event::SnapshotRequested`に応答するためのメソッドで、
+    /// `bytes`は`up_to`までのコマンド群が適用済みの状態機械のスナップショット.
+    /// 構成情報は`LogHistory::config_at(up_to)`から補われ、
+    /// インストールの完了後には、`up_to`以前のログ領域は破棄される(`Io::save_log_prefix`を参照).
+    ///
+    /// スナップショットの取得を途中で取り止める場合には、単にこのメソッドを呼び出さなければ良い.
+    /// また取得中に、より新しい地点のスナップショットが(e.g., リーダから送られて)インストールされた場合には、
+    /// `up_to`は古くなるので、このメソッドは何もせずに成功する.
+    ///
+    /// # Errors
+    ///
+    /// `up_to`がローカルログの消費済み終端(`LogHistory::consumed_tail`)よりも後ろの場合には、
+    /// `ErrorKind::InvalidInput`を理由としたエラーが返される.
+    ///
+    /// その他のエラー条件は`install_snapshot`メソッドと同様.
+    pub fn complete_snapshot(&mut self, up_to: LogIndex, bytes: Vec<u8>) -> Result<()> {
+        let (head, consumed_tail) = {
+            let history = self.local_history();
+            (history.head().index, history.consumed_tail().index)
+        };
+        if up_to <= head {
+            // 既にスナップショットが取得済みの地点
+            return Ok(());
+        }
+        track_assert!(
+            up_to <= consumed_tail,
+            ErrorKind::InvalidInput,
+            "Unconsumed log position: up_to={:?}, consumed_tail={:?}",
+            up_to,
+            consumed_tail
+        );
+        track!(self.install_snapshot(up_to, bytes))
+    }
+
+    /// ローカルログに記録されている`index`の時点での構成を返す.
+    fn recorded_config_at(&self, index: LogIndex) -> Result<&ClusterConfig> {
+        let history = self.local_history();
+        track_assert!(
+            history.head().index <= index,
+            ErrorKind::InconsistentState,
+            "Too old log position: new_head={:?}, current_head={:?}, node={:?}",
+            index,
+            history.head(),
+            self.local_node()
+        );
+        Ok(history.config_at(index).expect("Never fails"))
+    }
+
+    /// ローカルノードがリーダに就任した際に呼び出されるコールバックを設定する.
+    ///
+    /// コールバックには、就任時の`Term`が渡される.
+    /// リーダである間にのみ必要なリソース(e.g., 書き込み用のポート)の確保等に利用可能.
+    pub fn on_become_leader(&mut self, f: Box<dyn FnMut(Term) + Send>) {
+        self.node.on_become_leader = Some(f);
+    }
+
+    /// ローカルノードがリーダから退任した際に呼び出されるコールバックを設定する.
+    ///
+    /// `on_become_leader`で確保したリソースの解放等に利用可能.
+    pub fn on_lose_leadership(&mut self, f: Box<dyn FnMut() + Send>) {
+        self.node.on_lose_leadership = Some(f);
+    }
+
+    /// `maybe_compact`メソッドが参照するコンパクションポリシーを設定する.
+    pub fn set_compaction_policy<P>(&mut self, policy: P)
+    where
+        P: CompactionPolicy + Send + 'static,
+    {
+        self.compaction_policy = Some(Box::new(policy));
+    }
+
+    /// 設定済みのコンパクションポリシーが要求している場合には、
+    /// `machine`のスナップショットを取得して、ローカルログの消費済み終端の地点にインストールする.
+    ///
+    /// インストールを開始した場合には、新しいログの先頭位置が返される.
+    /// インストールの完了は`Event::SnapshotInstalled`によって通知される.
+    ///
+    /// ポリシーが未設定の場合や、コンパクションが不要な場合、
+    /// 既に別のスナップショットをインストール中の場合には`None`が返される.
+    pub fn maybe_compact<M: StateMachine>(&mut self, machine: &M) -> Result<Option<LogIndex>> {
+        if self.is_snapshot_installing() {
+            return Ok(None);
+        }
+        let history = self.local_history();
+        let new_head = history.consumed_tail().index;
+        if new_head <= history.head().index {
+            return Ok(None);
+        }
+        match self.compaction_policy {
+            Some(ref policy) if policy.should_compact(history) => {}
+            _ => return Ok(None),
+        }
+        let snapshot = machine.snapshot();
+        track!(self.install_snapshot(new_head, snapshot))?;
+        Ok(Some(new_head))
+    }
+
     /// 新しい選挙を開始する.
     ///
     /// 何らかの手段で現在のリーダのダウンを検知した場合に呼び出される.
+    ///
+    /// ローカルノードがウィットネスの場合には、何も行われない.
     pub fn start_election(&mut self) {
         self.node.start_election();
     }
 
+    /// リーダの座から自発的に降りて、現在の`Term`のフォロワーとなる.
+    ///
+    /// リーダシップの移譲とは異なり、後継のリーダの選出は通常の選挙に委ねられる.
+    /// 降格後はハートビートの送信やフォロワーとの同期処理は停止され、
+    /// 新規の提案は`ErrorKind::NotLeader`を理由として拒否される.
+    /// 降格は`Event::RoleChanged`によって通知される.
+    ///
+    /// ローカルノードがリーダではない場合には、何も行われない.
+    pub fn step_down(&mut self) {
+        self.node.step_down();
+    }
+
+    /// ローカルノードから見たクラスタの状況を、人間が読みやすい複数行のテキストとして返す.
+    ///
+    /// 役割や`Term`、リーダのID、ログの各地点、クラスタ構成の概要が含まれ、
+    /// ローカルノードがリーダの場合には、各フォロワーの同期状況も併せて出力される.
+    ///
+    /// 出力形式は運用時の確認用であり、機械的な解析は想定していない.
+    pub fn describe_cluster(&self) -> String {
+        fn join(members: &ClusterMembers) -> String {
+            members
+                .iter()
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        }
+
+        let node = self.local_node();
+        let leader = match self.node.role {
+            RoleState::Leader(_) | RoleState::Follower(_) => node.ballot.voted_for.as_str(),
+            RoleState::Loader(_) | RoleState::Candidate(_) => "-",
+        };
+        let history = self.local_history();
+        let config = history.config();
+
+        let mut buf = String::new();
+        let _ = writeln!(buf, "node: {}", node.id.as_str());
+        let _ = writeln!(buf, "role: {}", node.role);
+        let _ = writeln!(buf, "term: {}", node.ballot.term.as_u64());
+        let _ = writeln!(buf, "leader: {}", leader);
+        let _ = writeln!(
+            buf,
+            "log: head={}, applied={}, committed={}, tail={}",
+            history.head().index.as_u64(),
+            history.consumed_tail().index.as_u64(),
+            history.committed_tail().index.as_u64(),
+            history.tail().index.as_u64()
+        );
+        let _ = writeln!(
+            buf,
+            "config: state={:?}, new_members=[{}], old_members=[{}], learners=[{}]",
+            config.state(),
+            join(config.new_members()),
+            join(config.old_members()),
+            join(config.learners())
+        );
+        if let RoleState::Leader(ref leader) = self.node.role {
+            let mut followers = String::new();
+            leader.describe_followers(&self.node.common, &mut followers);
+            let _ = writeln!(buf, "followers: {}", followers.lines().count());
+            buf.push_str(&followers);
+        }
+        buf
+    }
+
+    /// ローカルノードの現在の状態をまとめて返す.
+    ///
+    /// `local_node`や`current_leader`、`local_history`を個別に呼び出す場合とは異なり、
+    /// 返り値の各値は、常に同じ時点の内部状態から取得されたものとなる.
+    pub fn status(&self) -> NodeStatus {
+        let node = self.local_node();
+        let history = self.local_history();
+        NodeStatus {
+            term: node.ballot.term,
+            role: node.role,
+            leader: self.current_leader(),
+            committed_tail: history.committed_tail(),
+            appended_tail: history.tail(),
+        }
+    }
+
     /// ローカルノードの情報を返す.
     pub fn local_node(&self) -> &Node {
         self.node.common.local_node()
     }
 
+    /// ローカルノードが認識している、現在の`Term`のリーダのIDを返す.
+    ///
+    /// ローカルノードがリーダの場合には自身のIDが返される.
+    /// フォロワーの場合には、現在の`Term`でリーダからのメッセージを受信済みであれば、
+    /// その送信者(i.e., `Ballot::voted_for`)が返される.
+    ///
+    /// 選挙中(i.e., 候補者の場合や、投票後にまだリーダからの連絡が無いフォロワーの場合)には`None`が返される.
+    /// リダイレクト先が不明なクライアントには、少し時間を置いて再試行させること.
+    pub fn current_leader(&self) -> Option<NodeId> {
+        let node = self.local_node();
+        match node.role {
+            Role::Leader => Some(node.id.clone()),
+            Role::Follower if self.node.common.is_following_leader() => {
+                Some(node.ballot.voted_for.clone())
+            }
+            Role::Follower | Role::Candidate => None,
+        }
+    }
+
     /// ローカルログの履歴を返す.
     pub fn local_history(&self) -> &LogHistory {
         self.node.common.log()
     }
 
+    /// ローカルノードが保持している最新のスナップショットを返す.
+    ///
+    /// インストール(`install_snapshot`ないしリーダからの受信)か、ストレージからのロードが完了したものの内で、
+    /// 最も新しいものが返される. スナップショットの内容は展開済みの状態となっている.
+    ///
+    /// 返り値の`tail`は、ローカルログの先頭(`local_history().head()`)と一致する.
+    /// 進行中のインストールの内容は、完了するまでは反映されない.
+    ///
+    /// 新規ノードに対して、スナップショットをRaftの外部で転送する場合等に利用可能.
+    /// 起動後にスナップショットを一度もインストールないしロードしていない場合には`None`が返される.
+    pub fn latest_snapshot(&self) -> Option<&LogPrefix> {
+        self.node.common.latest_snapshot()
+    }
+
+    /// ローカルログの履歴の現時点での要約を返す.
+    ///
+    /// `local_history`とは異なり、返り値はローカルログの以後の変更の影響を受けないので、
+    /// 監視用のスレッド等に受け渡して保持しておくことができる.
+    pub fn history_snapshot(&self) -> LogHistorySnapshot {
+        self.local_history().capture()
+    }
+
+    /// インスタンスを破棄し、次回起動時の`ReplicatedLogOptions::restored_history`に指定可能な履歴の要約を返す.
+    ///
+    /// 正常停止時に、`shutdown`が返した`ShutdownFuture`の完了後に呼び出されることを想定している.
+    /// 返り値の永続化は利用者の責務となる.
+    ///
+    /// このメソッドは、実行中のストレージへの保存処理の完了を待たずに、即座にインスタンスを破棄する.
+    /// 保存処理の完了を待ちたい場合には、`drain`メソッドを使用すること.
+    pub fn finish(self) -> LogHistorySummary {
+        self.local_history().summarize()
+    }
+
+    /// 実行中のストレージへの保存・読み込み処理の完了を待った上で、インスタンスを破棄する.
+    ///
+    /// 返り値の`Drain`は、全ての処理が完了した時点で、このインスタンスが使用していた`Io`を結果として完了する.
+    /// ローカルログへの追記中にインスタンスを破棄してしまい、
+    /// 再起動時に(呼び出し元には追記済みに見えていた)エントリが失われる、といったことを防ぐために使用する.
+    ///
+    /// 完了を待っている間は、新たなメッセージの受信やタイムアウトの処理は行われず、
+    /// 発生したイベントは全て破棄される.
+    /// そのため、`Drain`は、実行中の処理が完了し次第、必ず完了する.
+    pub fn drain(mut self) -> Drain<IO> {
+        self.node.draining = true;
+        Drain(Some(self.node))
+    }
+
+    /// フォロワーが読み込みを提供可能な地点(i.e., フォロワーが把握しているコミット済みの終端)を返す.
+    ///
+    /// ローカルでコミット済みの`LogEntry::LeaseNoop`の中で最新のものが、リーダによって発行されてからの経過時間が
+    /// `ReplicatedLogOptions::follower_read_staleness`以内の場合にのみ`Some`が返される.
+    /// 発行時刻は`lease_until - ReplicatedLogOptions::lease_duration`として算出される.
+    ///
+    /// `LeaseNoop`がコミットされたということは、その発行時点では、発行元のリーダが過半数の支持を得ており、
+    /// それ以前にコミットされたエントリは全て、このノードのコミット済み領域に含まれていることを意味する.
+    /// そのため、この地点までのコマンドが適用された状態機械に対する読み込みは、
+    /// 多少古い可能性はあるが、その古さは上記の経過時間の範囲内に収まっている
+    /// (ただし、ノード間の時刻のずれは考慮されない).
+    ///
+    /// なお、リーダからの受信時刻は判定に用いられない.
+    /// 過半数の支持を失ったリーダも、一部のフォロワーには通信を続けられるためである.
+    ///
+    /// ローカルノードがフォロワーではない場合や、
+    /// `follower_read_staleness`ないし`lease_duration`が指定されていない場合には、常に`None`が返される.
+    pub fn follower_read_index(&self) -> Option<LogIndex> {
+        let options = self.node.common.options();
+        let staleness = options.follower_read_staleness?;
+        let lease_duration = options.lease_duration?;
+        if self.local_node().role != Role::Follower {
+            return None;
+        }
+        let lease_until = Duration::from_millis(self.node.common.read_lease()?);
+        let issued_at = lease_until.checked_sub(lease_duration)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if now <= issued_at + staleness {
+            Some(self.local_history().committed_tail().index)
+        } else {
+            None
+        }
+    }
+
+    /// ローカルノードの状態が、安全性に関する不変項を満たしているかどうかを検査する.
+    ///
+    /// 以下の項目が確認される:
+    ///
+    /// - `consumed_tail <= committed_tail <= tail`
+    /// - ローカルログの履歴のレコード群が、インデックス順に並んでいる
+    /// - コミット済みの終端の地点のクラスタ構成および`Term`が、履歴と整合している
+    /// - 現在の`Term`が、起動時にストレージから復元した投票状況のものから後退していない
+    ///
+    /// デバッグやテスト用途のメソッドであり、通常の運用時に呼び出す必要はない.
+    ///
+    /// # Errors
+    ///
+    /// いずれかの不変項が破られている場合には、
+    /// 破られた不変項を示すメッセージと共に`ErrorKind::InconsistentState`が返される.
+    pub fn verify_safety_invariants(&self) -> Result<()> {
+        track!(self.node.common.check_invariants())
+    }
+
+    /// リーダのコミット済みの終端に対する、ローカルのコミット済みの終端の遅れ(エントリ数)を返す.
+    ///
+    /// リーダの値には、フォロー中のリーダから最後に受信した`AppendEntriesCall`で通知されたものが使われる.
+    /// ローカルノードがフォロワーではない場合や、現在のリーダからまだ何も受信していない場合には`None`が返される.
+    pub fn leader_commit_lag(&self) -> Option<u64> {
+        if self.local_node().role != Role::Follower {
+            return None;
+        }
+        let leader = self.node.common.leader_committed_tail()?;
+        let local = self.local_history().committed_tail().index;
+        Some(leader.as_u64().saturating_sub(local.as_u64()))
+    }
+
+    /// ローカルノードが、読み込みを提供するのに十分なほど最新の状態に追い付いているかどうかを判定する.
+    ///
+    /// コミット済みだが未消費のエントリの数(i.e., `committed_tail - consumed_tail`)が`max_lag`以下で、
+    /// かつ、リーダとの疎通が取れている場合に`true`が返される.
+    /// ローカルノードがリーダの場合には、前者の条件のみが判定される.
+    ///
+    /// ロードバランサ等で、読み込みの転送先となるノードを選択する際に使用される.
+    pub fn is_up_to_date(&self, max_lag: u64) -> bool {
+        let history = self.local_history();
+        let lag = history.committed_tail().index - history.consumed_tail().index;
+        if lag as u64 > max_lag {
+            return false;
+        }
+        match self.local_node().role {
+            Role::Leader => true,
+            Role::Candidate => false,
+            Role::Follower => self.node.common.is_following_leader(),
+        }
+    }
+
+    /// コミット済みの`LogEntry::LeaseNoop`によって確立された、読み込みリースの期限を返す.
+    ///
+    /// 返り値は論理タイムスタンプ(UNIXエポックからのミリ秒)で、
+    /// これまでにローカルノードでコミットされた`LogEntry::LeaseNoop`の`lease_until`の最大値となる.
+    ///
+    /// そのようなエントリがまだコミットされていない場合には`None`が返される.
+    pub fn read_lease(&self) -> Option<u64> {
+        self.node.common.read_lease()
+    }
+
     /// ローカルログへの書き込み待ちの状態の提案群の数を返す.
     ///
     /// この値は、ローカルストレージの詰まり具合を把握するために有用である.
@@ -239,9 +960,25 @@ impl<IO: Io> ReplicatedLog<IO> {
         self.node.is_loading() || self.node.common.is_snapshot_installing()
     }
 
+    /// スナップショットのインストール中の場合には、その進捗を返す.
+    ///
+    /// リーダから分割されて送られてくるスナップショットの断片を受信中の場合には、受信済みのバイト数が、
+    /// ローカルログへの保存中の場合には、保存対象のスナップショットのバイト数が含まれる.
+    ///
+    /// 停滞の検知には`ReplicatedLogOptions::snapshot_install_stall_timeout`も利用可能.
+    pub fn snapshot_install_progress(&self) -> Option<SnapshotProgress> {
+        if let RoleState::Follower(ref follower) = self.node.role {
+            if let Some(progress) = follower.snapshot_progress() {
+                return Some(progress);
+            }
+        }
+        self.node.common.snapshot_install_progress()
+    }
+
     /// 過半数以上の応答を得られた最新のハートビート(i.e., AppendEntriesCall) のシーケンス番号を返す.
     ///
-    /// この値は、同じ選挙期間に関しては減少することはないことが保証されている.
+    /// この値は、同じ選挙期間に関しては(`SequenceNumber::is_newer_than`の意味で)後退することはないことが保証されている.
+    /// リーダへの就任直後には、就任時点で発行済みの最新の番号が返される.
     ///
     /// # 注意
     ///
@@ -255,6 +992,92 @@ impl<IO: Io> ReplicatedLog<IO> {
         }
     }
 
+    /// ローカルノードの選挙に関する統計情報を返す.
+    ///
+    /// 同様の値は`metrics`経由でも取得可能.
+    pub fn election_stats(&self) -> &ElectionStats {
+        &self.node.election_stats
+    }
+
+    /// `last_heartbeat_ack`の値が最後に進んだ時刻を返す.
+    ///
+    /// リースに基づく読み込みや、過半数との疎通確認に利用可能.
+    ///
+    /// ローカルノードがリーダではない場合や、現在の選挙期間で一度も値が進んでいない場合には`None`が返される.
+    pub fn last_heartbeat_ack_at(&self) -> Option<Instant> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.last_heartbeat_ack_at()
+        } else {
+            None
+        }
+    }
+
+    /// リーダが`follower`へのログ同期を控えている残りの期間(ブロードキャスト回数)を返す.
+    ///
+    /// フォロワーからビジー応答が連続して返された場合には、
+    /// リーダはそのフォロワーへのログ同期の間隔を指数的に延ばす.
+    /// バックオフ中ではない場合には`0`が返される.
+    ///
+    /// ローカルノードがリーダではない場合や、`follower`が未知のノードの場合には`None`が返される.
+    pub fn follower_sync_backoff(&self, follower: &NodeId) -> Option<u64> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.sync_backoff(follower)
+        } else {
+            None
+        }
+    }
+
+    /// リーダから見た、各フォロワーのログ同期の進捗を返す.
+    ///
+    /// 結果は`NodeId`の昇順に並んでおり、ローカルノード自身は含まれない.
+    /// ローカルノードがリーダではない場合には、空の`Vec`が返される.
+    pub fn follower_progress(&self) -> Vec<FollowerProgress> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            leader.follower_progress(&self.node.common)
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// リーダが実行中のログ同期用の読み込み処理(`Io::LoadLog`)の数を返す.
+    ///
+    /// 同時実行数の上限は`ReplicatedLogOptions::max_concurrent_syncs`で指定可能.
+    ///
+    /// ローカルノードがリーダではない場合には`None`が返される.
+    pub fn inflight_log_syncs(&self) -> Option<usize> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            Some(leader.inflight_syncs())
+        } else {
+            None
+        }
+    }
+
+    /// リーダがログ同期用の読み込み処理を実行中のフォロワー群を返す.
+    ///
+    /// レプリケーションの停滞の調査等、デバッグ用途での使用を想定している.
+    ///
+    /// ローカルノードがリーダではない場合には`None`が返される.
+    pub fn pending_log_sync_targets(&self) -> Option<Vec<NodeId>> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            Some(leader.pending_sync_targets())
+        } else {
+            None
+        }
+    }
+
+    /// ローカルログよりも遅れているが、ログ同期用の読み込み処理が実行されていないフォロワー群を返す.
+    ///
+    /// `ReplicatedLogOptions::max_concurrent_syncs`による上限のために、同期処理の開始を待機しているフォロワーも含まれる.
+    ///
+    /// ローカルノードがリーダではない場合には`None`が返される.
+    pub fn waiting_log_sync_targets(&self) -> Option<Vec<NodeId>> {
+        if let RoleState::Leader(ref leader) = self.node.role {
+            Some(leader.waiting_sync_targets(&self.node.common))
+        } else {
+            None
+        }
+    }
+
     /// 現在のクラスタ構成を返す.
     pub fn cluster_config(&self) -> &ClusterConfig {
         self.node.common.config()
@@ -283,6 +1106,558 @@ impl<IO: Io> Stream for ReplicatedLog<IO> {
     }
 }
 
+/// `ReplicatedLog::read_index`メソッドの結果を表す`Future`.
+///
+/// 完了時には、線形化可能な読み込みを行うために待つ必要があるログの地点が返される.
+#[derive(Debug)]
+pub struct ReadIndex(oneshot::Receiver<Result<LogIndex>>);
+impl Future for ReadIndex {
+    type Item = LogIndex;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => track!(result).map(Async::Ready),
+            Err(_) => {
+                track_panic!(
+                    ErrorKind::NotLeader,
+                    "Leadership was lost before the read completed"
+                )
+            }
+        }
+    }
+}
+
+/// `ReplicatedLog::pending_committed`メソッドの結果を表す`Future`.
+///
+/// 完了時には、コミット済みだが未消費のエントリ群が返される.
+pub struct PendingCommitted<IO: Io> {
+    future: Option<CheckedLoadLog<IO>>,
+    head: LogPosition,
+}
+impl<IO: Io> Future for PendingCommitted<IO> {
+    type Item = LogSuffix;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let log = match self.future {
+            None => {
+                return Ok(Async::Ready(LogSuffix {
+                    head: self.head,
+                    entries: Vec::new(),
+                }));
+            }
+            Some(ref mut f) => match track!(f.poll())? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(log) => log,
+            },
+        };
+        match log {
+            Log::Prefix(prefix) => track_panic!(
+                ErrorKind::Busy,
+                "Pending entries have been compacted into a snapshot: snapshot_tail={:?}",
+                prefix.tail
+            ),
+            Log::Suffix(suffix) => Ok(Async::Ready(suffix)),
+        }
+    }
+}
+
+/// `ReplicatedLog::heartbeat_quorum`メソッドの結果を表す`Future`.
+///
+/// 完了時には、過半数から応答を得られたハートビートのシーケンス番号が返される.
+#[derive(Debug)]
+pub struct HeartbeatQuorum(oneshot::Receiver<Result<SequenceNumber>>);
+impl Future for HeartbeatQuorum {
+    type Item = SequenceNumber;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0.poll() {
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Ok(Async::Ready(result)) => track!(result).map(Async::Ready),
+            Err(_) => {
+                track_panic!(
+                    ErrorKind::NotLeader,
+                    "Leadership was lost before the heartbeat was acknowledged"
+                )
+            }
+        }
+    }
+}
+
+/// `ReplicatedLog::shutdown`メソッドの結果を表す`Future`.
+///
+/// ローカルノードの停止の準備が整った時点で完了する.
+#[derive(Debug)]
+pub struct ShutdownFuture(Option<oneshot::Receiver<()>>);
+impl Future for ShutdownFuture {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.0 {
+            None => Ok(Async::Ready(())),
+            Some(ref mut rx) => match rx.poll() {
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Ok(Async::Ready(())) | Err(_) => {
+                    // `Err`の場合は`ReplicatedLog`自体が破棄されている
+                    Ok(Async::Ready(()))
+                }
+            },
+        }
+    }
+}
+
+/// `ReplicatedLog::drain`メソッドの結果を表す`Future`.
+///
+/// 実行中のストレージへの保存・読み込み処理が全て完了した時点で、`Io`を返して完了する.
+pub struct Drain<IO: Io>(Option<NodeState<IO>>);
+impl<IO: Io> Future for Drain<IO> {
+    type Item = IO;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let node = track_assert_some!(
+                self.0.as_mut(),
+                ErrorKind::InconsistentState,
+                "Drain has already completed"
+            );
+            while let Async::Ready(_event) = track!(node.poll())? {}
+            if node.has_pending_io() {
+                return Ok(Async::NotReady);
+            }
+        }
+        let node = self.0.take().expect("Never fails");
+        Ok(Async::Ready(node.into_io()))
+    }
+}
+
+/// `ReplicatedLog`の挙動を調整するためのオプション群.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReplicatedLogOptions {
+    /// ログ同期用の`AppendEntriesCall`一つに含めるエントリ数の上限.
+    ///
+    /// リーダが、ログが遅れているフォロワーに差分を送信する際に適用される.
+    /// 差分がこの値を超える場合には、複数のメッセージに分割して送信される.
+    ///
+    /// `None`の場合は無制限. なお`0`が指定された場合は`1`として扱われる.
+    ///
+    /// デフォルト値は`None`.
+    pub max_append_entries: Option<usize>,
+
+    /// ログ同期用の`AppendEntriesCall`一つに含める`Command`ペイロードの合計バイト数の上限.
+    ///
+    /// `max_append_entries`と同様に、ログが遅れているフォロワーへの差分送信時に適用される.
+    /// ただし、単体でこの値を超えるエントリが存在する場合でも、
+    /// 同期が停滞しないように、最低一つのエントリは送信される.
+    ///
+    /// 送信対象のエントリ群は`Io::load_log_limited_bytes`で読み込まれるので、
+    /// それを上書きした`Io`実装では、ストレージからの読み込み量もこの値で制限される.
+    ///
+    /// `None`の場合は無制限.
+    ///
+    /// デフォルト値は`None`.
+    pub max_append_bytes: Option<usize>,
+
+    /// フォロワー毎に許容される、応答待ちのログ同期用`AppendEntriesCall`の数の上限.
+    ///
+    /// ログが遅れているフォロワーへの差分送信時に、`2`以上が指定されている場合には、
+    /// リーダは前のメッセージへの応答を待たずに、続きの差分を送信する.
+    /// `max_append_entries`等でメッセージが分割される場合に、遅延の大きいネットワークでの同期が速くなる.
+    ///
+    /// 送信したメッセージが失われたり、順序が入れ替わった場合でも、
+    /// フォロワーのログの終端はフォロワーからの応答に基づいてのみ進められ、欠落部分は再送される.
+    ///
+    /// `None`の場合は`1`(i.e., パイプライン化しない)として扱われる. なお`0`が指定された場合も`1`として扱われる.
+    ///
+    /// デフォルト値は`None`.
+    pub max_inflight_appends: Option<usize>,
+
+    /// リーダが同時に実行するログ同期処理(フォロワー毎のログ読み込み)の数の上限.
+    ///
+    /// 上限に達している間に同期が必要となったフォロワーは待ち行列に入れられ、
+    /// 実行中の同期処理が完了する度に、先頭から順番(ラウンドロビン)にスケジュールされる.
+    ///
+    /// `None`の場合は無制限. なお`0`が指定された場合は`1`として扱われる.
+    ///
+    /// デフォルト値は`None`.
+    pub max_concurrent_syncs: Option<usize>,
+
+    /// リーダがフォロワーにスナップショットを送信する際の、一つのメッセージに含めるバイト数の上限.
+    ///
+    /// `Some`の場合には、スナップショットは`InstallSnapshotChunkCast`に分割されて送信される.
+    /// なお`0`が指定された場合は`1`として扱われる.
+    ///
+    /// `None`の場合は、スナップショット全体が一つの`InstallSnapshotCast`で送信される.
+    ///
+    /// デフォルト値は`None`.
+    pub snapshot_chunk_size: Option<usize>,
+
+    /// `true`の場合には、フォロワーがタイムアウトした際に、立候補する前にPreVoteを実施する.
+    ///
+    /// PreVoteでは`Term`を進めずに、過半数から投票を得られる見込みがあるかどうかを確認し、
+    /// 見込みがある場合にのみ、実際に立候補する.
+    /// これにより、ネットワーク分断から復帰したノードが、
+    /// 不要に`Term`を進めて正常なリーダを退任させてしまうことを防ぐことができる.
+    ///
+    /// 詳細は[Raftの博士論文](https://github.com/ongardie/dissertation)の「9.6 Preventing disruptions when a server rejoins the cluster」を参照のこと.
+    ///
+    /// デフォルト値は`false`.
+    pub pre_vote: bool,
+
+    /// フォロワーが`ReplicatedLog::follower_read_index`で読み込み地点を返すための、
+    /// 最新のコミット済みの`LogEntry::LeaseNoop`が発行されてからの経過時間の上限.
+    ///
+    /// 経過時間がこの値以内の場合には、フォロワーは、自身が把握しているコミット済み地点を返す.
+    /// `LeaseNoop`はリーダのハートビート毎に発行され、次のハートビートでフォロワーにコミットが伝わるので、
+    /// この値はハートビートの間隔の二倍よりも長くしておく必要がある.
+    ///
+    /// `None`の場合や、`lease_duration`が指定されていない場合には、フォロワーからの読み込みは無効となる.
+    ///
+    /// デフォルト値は`None`.
+    pub follower_read_staleness: Option<Duration>,
+
+    /// リーダが`LogEntry::LeaseNoop`を追加する際に使用する、リースの長さ.
+    ///
+    /// 指定された場合には、リーダはハートビートの度に、
+    /// 現在時刻にこの値を加えた時刻を期限とする`LogEntry::LeaseNoop`をログに追加する.
+    /// そのエントリがコミットされると、`ReplicatedLog::read_lease`が返す期限が延長される.
+    ///
+    /// `None`の場合には、`LogEntry::LeaseNoop`は追加されない.
+    ///
+    /// デフォルト値は`None`.
+    pub lease_duration: Option<Duration>,
+
+    /// `true`の場合には、クラスタ構成の変更がコミットされるまでの間、
+    /// `ReplicatedLog::propose_command`によるコマンドの提案を拒否する.
+    ///
+    /// 構成変更中のコマンドの追加を避けることで、構成変更を先に完了させることができる.
+    ///
+    /// デフォルト値は`false`.
+    pub reject_commands_during_config_change: bool,
+
+    /// リーダが保持可能な未コミット(未追記のものを含む)のエントリ数の上限.
+    ///
+    /// 提案を受け付けるとこの値を超えてしまう場合には、`ReplicatedLog::propose_command`は
+    /// `ErrorKind::Busy`を返すようになる.
+    /// 遅いフォロワーが追い付いてエントリがコミットされれば、再び提案を受け付けられるようになる.
+    ///
+    /// 大量の提案によって、リーダのログの終端がコミット済みの終端から際限なく離れてしまうことを防ぐ.
+    /// 構成変更の提案は制限の対象外.
+    ///
+    /// デフォルト値は`None`(i.e., 無制限).
+    pub max_uncommitted_entries: Option<usize>,
+
+    /// 提案可能なコマンド一つ当たりのバイト数の上限.
+    ///
+    /// この値を超えるコマンドが提案された場合には、`ReplicatedLog::propose_command`は
+    /// ローカルログを一切変更せずに`ErrorKind::InvalidInput`を返す.
+    ///
+    /// `max_append_bytes`などの一度に送信するエントリ群に対する制限とは独立して、
+    /// 単一のエントリの大きさを制限するためのもの
+    /// (`max_append_bytes`では、単体で上限を超えるエントリも、最低一つは送信されてしまう).
+    ///
+    /// デフォルト値は`None`(i.e., 無制限).
+    pub max_command_bytes: Option<usize>,
+
+    /// `true`の場合には、リーダは過半数との疎通が取れているかどうかを継続的に確認する.
+    ///
+    /// 投票権を有するメンバの過半数から、フォロワーのタイムアウト時間
+    /// (`TimeoutPolicy::follower`)以内に応答を受信できていない場合には、
+    /// リーダは`ReplicatedLog::propose_command`による提案を`ErrorKind::NotLeader`で拒否し、
+    /// 次のハートビートのタイミングでフォロワーに退任する.
+    ///
+    /// 過半数から分断されたリーダが、新しいリーダが選出されるまでの間、
+    /// コミットされる見込みのない提案を受け付け続けることを防ぐ.
+    ///
+    /// デフォルト値は`false`.
+    pub check_quorum: bool,
+
+    /// `true`の場合には、フォロワーのログがリーダのログと分岐していることを検出した際に、
+    /// 分岐した部分が全て未コミットであれば、ロールバックを経由せずに、リーダのエントリで直接上書きする.
+    ///
+    /// 通常は、ロールバック後にリーダからの再送を待つ必要があるが、
+    /// 上書きを行うことで、そのための往復を一つ省略することができる.
+    /// なお、コミット済みのエントリが上書きされることはない.
+    ///
+    /// デフォルト値は`false`.
+    pub overwrite_on_divergence: bool,
+
+    /// スナップショットをローカルログに保存する際に使用する圧縮方式.
+    ///
+    /// スナップショットは`Io::save_log_prefix`に渡される前に圧縮され、
+    /// ロード時には自動で展開されるので、利用者(e.g., `Event::SnapshotLoaded`)から見える内容は変わらない.
+    /// フォロワーへのスナップショットの送信も圧縮後のバイト列のまま行われ、
+    /// 受信側で展開された後に、受信側のこのオプションに従って圧縮し直される.
+    ///
+    /// 対応するフィーチャー(e.g., `lz4`)が無効な方式を指定した場合には、
+    /// スナップショットの保存が`ErrorKind::InvalidInput`で失敗する.
+    ///
+    /// デフォルト値は`Compression::None`.
+    pub snapshot_compression: Compression,
+
+    /// 選挙における役割毎のタイムアウト時間を決定するためのポリシー.
+    ///
+    /// 決定された時間は`Io::create_timeout_with`に渡される.
+    /// 候補者のタイムアウト時間に加算されるランダムな時間は、ノードIDと`random_seed`から導出される値を起点に決定されるので、
+    /// 同じ構成であれば、各ノードのタイムアウト時間の列は常に同じとなる.
+    ///
+    /// デフォルト値は`TimeoutPolicy::default()`.
+    pub timeout_policy: TimeoutPolicy,
+
+    /// 各ノードの選挙における優先度.
+    ///
+    /// 優先度の高いノードほど、リーダに選出されやすくなる(e.g., 特定のデータセンタにリーダを寄せたい場合に使用する).
+    ///
+    /// 自分よりも優先度の高い立候補可能なメンバが`N`個存在するノードは、
+    /// フォロワーおよび候補者のタイムアウト時間に`TimeoutPolicy::candidate_jitter`の`N`倍が加算され、
+    /// 自身の立候補を遅らせる(`TimeoutPolicy::timeout_with_rank`を参照).
+    /// また、自分よりも優先度の高い候補者からの投票依頼(PreVoteを含む)を受信した場合にも、
+    /// 自身のタイムアウトをリセットして、立候補を遅らせる.
+    ///
+    /// 優先度の高いノードが停止している場合でも、遅延の後に他のノードが立候補するので、
+    /// リーダが選出されなくなることはない.
+    ///
+    /// 含まれないノードの優先度は`0`として扱われる.
+    /// 全てのノードで同じ値を指定しておくこと.
+    ///
+    /// デフォルト値は空(i.e., 全てのノードの優先度が等しい).
+    pub election_priorities: BTreeMap<NodeId, u32>,
+
+    /// `true`の場合には、同じ`Term`で競合する候補者同士の間で、決定論的に片方が立候補を取り下げる.
+    ///
+    /// 候補者が、同じ`Term`で、自分と同じ終端位置のログを持つ別の候補者から投票依頼を受信した場合には、
+    /// `(election_priorities上の優先度, NodeId)`を比較して、値の小さい方が立候補を取り下げてフォロワーとなる
+    /// (投票は既に自分に対して行っているので、その`Term`内で相手に投票することはない).
+    /// これにより、次の`Term`では、取り下げなかった候補者が票を集めやすくなる.
+    ///
+    /// これは票割れ(split vote)の繰り返しを防ぐための活性(liveness)の補助であり、
+    /// 一つの`Term`で一つのノードにしか投票しないという安全性の規則は変わらない.
+    ///
+    /// デフォルト値は`false`(i.e., 票割れの解消はタイムアウトのジッタのみに任せる).
+    pub election_tie_break: bool,
+
+    /// このインスタンスが属するRaftグループの識別子.
+    ///
+    /// 送信メッセージのヘッダに設定され、受信時には`Io::try_recv_group_message`に渡される.
+    /// 一つの`Io`実装を複数のグループで共有する場合には、グループ毎に異なる値を指定すること
+    /// (同じグループに属する全てのノードでは、同じ値を指定する).
+    ///
+    /// デフォルト値は`GroupId::default()`(i.e., 単一グループ).
+    pub group_id: GroupId,
+
+    /// ログエントリのコミットの際に、過半数に加えて、常にエントリの保持が要求されるレプリカ群.
+    ///
+    /// 特定のホットスタンバイに、コミット済みのエントリが必ず存在することを保証したい場合に使用する.
+    /// ただし、同期レプリカが停止している間は、新しいエントリはコミットされなくなる.
+    /// 現在のクラスタ構成に含まれないノードは無視される.
+    ///
+    /// リーダのみが参照するので、全てのノードで同じ値を指定しておくこと.
+    ///
+    /// デフォルト値は空集合.
+    pub synchronous_replicas: BTreeSet<NodeId>,
+
+    /// クラスタの初期構成で、ウィットネスとして扱うメンバ群.
+    ///
+    /// ウィットネスは、リーダ選出やログのコミットの判定に参加するが、
+    /// リーダからは`Command`のペイロードを取り除いたエントリ群のみを受け取り、
+    /// 自身がリーダになることはない(詳細は`ClusterConfig::with_witnesses`を参照).
+    /// そのため、例えば「データを保持する二ノードと、ウィットネス一ノード」の構成でも、
+    /// データを保持するノードの一方が停止している間に、コミットを継続することができる.
+    ///
+    /// ウィットネス上では、`Event::Committed`で通知されるコマンドの内容は空となるので、
+    /// 利用者はそれを状態機械に適用してはならない.
+    ///
+    /// クラスタ構成に含まれないノードは無視される.
+    /// 全てのノードで同じ値を指定しておくこと.
+    ///
+    /// デフォルト値は空集合.
+    pub witnesses: BTreeSet<NodeId>,
+
+    /// `Event::SnapshotRequested`を発行するタイミングを決定するためのポリシー.
+    ///
+    /// デフォルト値は`SnapshotPolicy::Manual`.
+    pub snapshot_policy: SnapshotPolicy,
+
+    /// 初期クラスタ構成の`Joint`状態での合意値の算出方法.
+    ///
+    /// 以後の構成変更にも引き継がれるが、再起動時には、ストレージから復元された構成の値が優先される.
+    /// 安全性に関するトレードオフについては`JointQuorum`のドキュメントを参照のこと.
+    ///
+    /// デフォルト値は`JointQuorum::Both`.
+    pub joint_quorum: JointQuorum,
+
+    /// 前回の正常停止時に`ReplicatedLog::finish`で取得された履歴の要約.
+    ///
+    /// 指定された場合には、起動時のローカルログの読み込みの際に、エントリ群を走査して履歴を再構築する代わりに、
+    /// この要約から履歴が復元される.
+    /// 要約が読み込まれたスナップショットやログの終端と整合しない場合には、通常通りの再構築が行われる.
+    ///
+    /// デフォルト値は`None`.
+    pub restored_history: Option<LogHistorySummary>,
+
+    /// フォロワーがスナップショットのインストール中に、進捗が無いまま経過した場合に、
+    /// `Event::SnapshotInstallStalled`を発行するまでの時間.
+    ///
+    /// 断片の受信やローカルログへの保存の完了を進捗として扱う.
+    /// 一度発行された後は、次に進捗があるまでは再発行されない.
+    ///
+    /// デフォルト値は`None`(i.e., 停滞を検知しない).
+    pub snapshot_install_stall_timeout: Option<Duration>,
+
+    /// 構成変更の`CatchUp`状態で、新メンバ群のログが追い付くのを待つ時間の上限.
+    ///
+    /// リーダが`CatchUp`状態を観測してからこの時間が経過しても、
+    /// ログが追い付いていない新メンバが存在する場合には(e.g., 追加対象のノードが停止している)、
+    /// リーダは旧メンバ群のみからなる構成を提案して構成変更を中止し、`Event::ConfigChangeAborted`を発行する.
+    ///
+    /// 中止されるのは`CatchUp`状態の場合のみで、`Joint`状態に遷移した後の構成変更が中止されることはない.
+    ///
+    /// デフォルト値は`None`(i.e., 新メンバ群が追い付くまで待ち続ける).
+    pub catchup_timeout: Option<Duration>,
+
+    /// リーダがフォロワー群を走査する順番を決定するための戦略.
+    ///
+    /// 指定された場合には、ブロードキャストの送信順や、完了したログ同期用の読み込み処理の送信順が、
+    /// この戦略で決定される順番となる.
+    /// また`max_concurrent_syncs`で待機中のフォロワー群の中からは、順番が最も先のものから同期が開始される
+    /// (ラウンドロビンではなくなるので、順番が後のフォロワーの同期は、先のものが追い付くまで待たされる).
+    ///
+    /// 主に、テストで特定のスケジューリングを再現するために使用される.
+    ///
+    /// デフォルト値は`None`(i.e., `NodeId`の昇順に走査し、待機中のフォロワーは到着順に同期する).
+    pub follower_order: Option<FollowerOrder>,
+
+    /// ノードの挙動に影響を与える乱数列(e.g., 候補者のタイムアウト時間のばらつき)のシード.
+    ///
+    /// 乱数列の初期値は、このシードとノードIDを混ぜ合わせて決定されるので、
+    /// 同じシードであっても、ノード毎に異なる乱数列となる.
+    /// また同じシードとノードIDの組に対しては、常に同じ乱数列となるので、
+    /// シミュレーションテスト等で、選挙の経過を再現するために使用可能.
+    ///
+    /// デフォルト値は`None`(i.e., ノードIDのみから乱数列の初期値を決定する).
+    pub random_seed: Option<u64>,
+}
+
+/// `ReplicatedLog::snapshot_install_progress`が返すスナップショットのインストールの進捗.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotProgress {
+    /// インストール対象のスナップショットの終端位置.
+    pub tail: LogPosition,
+
+    /// 受信済み(保存中の場合には保存対象)のスナップショットのバイト数.
+    ///
+    /// 断片の受信中で、スナップショットが圧縮されている場合には、圧縮後のバイト数となる.
+    pub received_bytes: u64,
+
+    /// スナップショット全体のバイト数.
+    ///
+    /// 分割された断片の受信中で、まだ最後の断片を受信していない場合には`None`となる.
+    ///
+    /// `received_bytes`と同様に、断片の受信中は圧縮後のバイト数となる.
+    pub total_bytes: Option<u64>,
+
+    /// ローカルログへの保存中の場合には`true`、断片の受信中の場合には`false`.
+    pub saving: bool,
+}
+
+/// `ReplicatedLog::follower_progress`が返す、リーダから見たフォロワーのログ同期の進捗.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowerProgress {
+    /// フォロワーのノードID.
+    pub node: NodeId,
+
+    /// リーダが把握しているフォロワーのローカルログの終端.
+    pub log_tail: LogIndex,
+
+    /// リーダのローカルログの終端.
+    pub leader_tail: LogIndex,
+
+    /// フォロワーのログとの同期点が確定している場合には`true`.
+    ///
+    /// `false`の場合には、`log_tail`はまだ同期点の探索中の値となっている.
+    pub synced: bool,
+
+    /// 最近のフォロワーへのエントリの追記速度(エントリ数/秒).
+    ///
+    /// 現在の選挙期間内で、まだ二回以上`log_tail`が進んでいない場合には`None`となる.
+    pub append_rate: Option<f64>,
+}
+impl FollowerProgress {
+    /// フォロワーのログの、リーダのログに対する遅れ(エントリ数)を返す.
+    pub fn lag(&self) -> u64 {
+        self.leader_tail
+            .as_u64()
+            .saturating_sub(self.log_tail.as_u64())
+    }
+
+    /// 現在の追記速度が維持された場合に、フォロワーがリーダに追い付くまでの推定時間を返す.
+    ///
+    /// 既に追い付いている場合には`Duration::from_secs(0)`が返される.
+    /// 追記速度が不明な場合には`None`が返される.
+    pub fn eta(&self) -> Option<Duration> {
+        let lag = self.lag();
+        if lag == 0 {
+            return Some(Duration::from_secs(0));
+        }
+        match self.append_rate {
+            Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(lag as f64 / rate)),
+            _ => None,
+        }
+    }
+}
+
+/// `ReplicatedLog::election_stats`が返す、ローカルノードの選挙に関する統計情報.
+///
+/// 値はノードの起動時からの累計で、再起動した場合にはリセットされる.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ElectionStats {
+    /// 立候補した(i.e., `Candidate`状態に遷移した)回数.
+    pub elections_started: u64,
+
+    /// 立候補後に当選した(i.e., `Leader`状態に遷移した)回数.
+    pub elections_won: u64,
+
+    /// 立候補後に落選した(i.e., 当選せずに`Follower`状態に遷移したか、再度立候補した)回数.
+    pub elections_lost: u64,
+
+    /// 安定したリーダが存在した状態で終了した`Term`の数.
+    pub completed_terms: u64,
+
+    /// `completed_terms`の各`Term`で、リーダが安定して存在した期間の合計.
+    pub total_term_lifetime: Duration,
+}
+impl ElectionStats {
+    /// 一つの`Term`で、リーダが安定して存在した期間の平均を返す.
+    ///
+    /// 終了した`Term`が存在しない場合には`None`が返される.
+    pub fn average_term_lifetime(&self) -> Option<Duration> {
+        if self.completed_terms == 0 {
+            None
+        } else {
+            let secs = self.total_term_lifetime.as_secs_f64() / self.completed_terms as f64;
+            Some(Duration::from_secs_f64(secs))
+        }
+    }
+}
+
+/// `ReplicatedLog::status`が返すローカルノードの状態.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeStatus {
+    /// 現在の`Term`.
+    pub term: Term,
+
+    /// 現在の役割.
+    pub role: Role,
+
+    /// 認識している現在のリーダ(`ReplicatedLog::current_leader`を参照).
+    pub leader: Option<NodeId>,
+
+    /// ローカルログのコミット済みの終端.
+    pub committed_tail: LogPosition,
+
+    /// ローカルログの追記済みの終端.
+    pub appended_tail: LogPosition,
+}
+
 /// `ReplicatedLog`から発生するイベント一覧.
 #[derive(Debug, PartialEq, Eq)]
 #[allow(missing_docs)]
@@ -320,4 +1695,71 @@ pub enum Event {
     /// もし`new_head`の位置が、最新のコミット済み地点よりも
     /// 新しい場合には、これとは別に`SnapshotLoaded`イベントが発行される.
     SnapshotInstalled { new_head: LogPosition },
+
+    /// ローカルノードの`Term`が`old`から`new`に進んだ.
+    ///
+    /// `TermChanged`とは異なり、投票先のみが変わった場合には発行されない.
+    TermAdvanced { old: Term, new: Term },
+
+    /// 一連のログエントリがまとめてコミットされた.
+    ///
+    /// `range`は`(開始インデックス, 終了インデックス)`の半開区間で、
+    /// この区間に含まれる各エントリの`Committed`イベントの発行後に発行される.
+    /// 連続する`CommittedRange`の区間は、重複も隙間もなく隣接する.
+    CommittedRange { range: (LogIndex, LogIndex) },
+
+    /// コミット済みのログエントリ群が、まとめて消費済みとして記録された.
+    ///
+    /// `range`は`(開始インデックス, 終了インデックス)`の半開区間で、
+    /// 読み込まれたコミット済みエントリ群の`Committed`イベントの発行後に、一度の読み込み毎に一つ発行される.
+    /// 終了インデックスは`LogHistory::consumed_tail`の新しい値と一致し、
+    /// `LogHistory::committed_tail`を超えることはない.
+    Consumed { range: (LogIndex, LogIndex) },
+
+    /// `tail`を終端とするスナップショットのインストールが、
+    /// `ReplicatedLogOptions::snapshot_install_stall_timeout`の間、進捗していない.
+    ///
+    /// 進捗の詳細は`ReplicatedLog::snapshot_install_progress`で確認できる.
+    SnapshotInstallStalled { tail: LogPosition },
+
+    /// `ReplicatedLogOptions::snapshot_policy`に従い、スナップショットの取得が要求された.
+    ///
+    /// `ReplicatedLog`の利用者は、`up_to`までのコマンド群を適用した状態機械のスナップショットを取得して、
+    /// `ReplicatedLog::complete_snapshot`を呼び出すことが期待される.
+    /// スナップショットがインストールされるまでは、再度発行されることはない.
+    SnapshotRequested { up_to: LogIndex },
+
+    /// 立候補中に、`voter`から投票を拒否された.
+    ///
+    /// 票の割れや`Term`の食い違いの診断用で、`reason`は`voter`が拒否を決めた時点での理由を表す.
+    VoteRejected {
+        voter: NodeId,
+        reason: VoteRejectionReason,
+    },
+
+    /// `ReplicatedLogOptions::catchup_timeout`以内に新メンバ群のログが追い付かなかったため、
+    /// リーダが構成変更を中止した.
+    ///
+    /// `new_members`は中止された構成変更後のメンバ群で、`lagging`はその内でログが追い付いていなかったもの.
+    /// 旧メンバ群のみからなる構成がコミットされれば、クラスタは安定状態に戻る.
+    ConfigChangeAborted {
+        new_members: ClusterMembers,
+        lagging: ClusterMembers,
+    },
+
+    /// ローカルノードがリーダとして提案したコマンド`id`がコミットされた.
+    ///
+    /// `id`は`ReplicatedLog::propose_command`等が返したもので、提案と結果を対応付けるために使用される.
+    /// 対応するエントリの`Committed`イベントよりも先に発行される.
+    /// 提案したノードでのみ、コミット済みの終端が`id.index`を超えた時点で一度だけ発行される.
+    ///
+    /// コミット前にノードが再起動した場合や、スナップショットのインストールによってログが置き換えられた場合には発行されない.
+    ProposalCommitted { id: ProposalId },
+
+    /// ローカルノードがリーダとして提案したコマンド`id`が、コミットされる前にロールバックによって破棄された.
+    ///
+    /// このコマンドがコミットされることはないので、必要であれば、利用者は現在のリーダに対して再度提案する必要がある.
+    ///
+    /// ロールバック前にノードが再起動した場合や、スナップショットのインストールによってログが置き換えられた場合には発行されない.
+    ProposalRejected { id: ProposalId },
 }
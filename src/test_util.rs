@@ -52,7 +52,6 @@ pub mod tests {
     /// `TestIo` を操作するためのハンドル。
     #[derive(Clone)]
     pub struct TestIoHandle {
-        pub cluster: ClusterConfig,
         logs: Logs,
     }
 
@@ -97,7 +96,6 @@ pub mod tests {
     impl TestIo {
         pub fn handle(&self) -> TestIoHandle {
             TestIoHandle {
-                cluster: self.cluster.clone(),
                 logs: self.logs.clone(),
             }
         }
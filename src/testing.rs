@@ -0,0 +1,1822 @@
+//! 複数ノードを決定論的に動作させるためのテスト用の`Io`実装およびクラスタ.
+//!
+//! `MockIo`は全ての状態をメモリ上に保持し、保存・読み込み処理は即座に完了する.
+//! タイムアウトは時間経過では発火せず、`MockCluster::fire_timeout`等で明示的に発火させる.
+//! また`MockIo::set_loads_blocked`を使うことで、ログの読み込みを任意の時点まで保留させることも可能.
+//! 同様に`MockIo::set_saves_deferred`を使えば、ログの追記を、その処理がポーリングされるまで遅延させられる.
+//!
+//! メッセージは通常は即座に配送されるが、`MockNetwork::set_delay`を使うことで、
+//! 論理時刻に基づいた配送の遅延や、それによる到着順の入れ替わりを模擬することができる.
+//! `MockCluster::randomize`を使えば、シードに基づいて決定論的に配送順を撹拌することも可能.
+//!
+//! クレート外(e.g., 利用者のテスト)から使用する場合には、`testing`フィーチャーを有効にする必要がある.
+//!
+//! # Examples
+//!
+//! ```
+//! use raftlog::testing::MockCluster;
+//!
+//! let mut cluster = MockCluster::new(&["a", "b", "c"]);
+//! cluster.elect("a");
+//! cluster.propose("a", b"foo".to_vec()).unwrap();
+//! cluster.run();
+//! assert_eq!(cluster.log_suffix("c").tail(), cluster.log_suffix("a").tail());
+//! ```
+use futures::{Async, Future, Poll, Stream};
+use prometrics::metrics::MetricBuilder;
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use trackable::error::ErrorKindExt;
+
+use crate::cluster::ClusterMembers;
+use crate::election::{Ballot, Role};
+use crate::io::{Io, SaveBallotAndLog};
+use crate::log::{Log, LogIndex, LogPrefix, LogSuffix, ProposalId};
+use crate::memory::{MemoryResult, MemoryStorage};
+use crate::message::{GroupId, Message};
+use crate::node::NodeId;
+use crate::{Error, ErrorKind, Event, ReplicatedLog, ReplicatedLogOptions, Result};
+
+type MessageFilter = Box<dyn FnMut(&Message) -> bool + Send>;
+
+/// ノード間で共有されるメッセージ配送網.
+///
+/// メッセージは`(group_id, destination)`の組に基づいて、宛先のグループのノードに配送される.
+/// そのため、複数のRaftグループで、一つの配送網を共有することができる.
+/// 通信の遮断や遅延は、グループに関わらず、ノード単位で適用される.
+#[derive(Default)]
+pub struct MockNetwork {
+    mailboxes: BTreeMap<(GroupId, NodeId), VecDeque<Message>>,
+    banned: BTreeSet<(NodeId, NodeId)>,
+    sent: Vec<Message>,
+    drop_once: Vec<MessageFilter>,
+    loopback: bool,
+    now: u64,
+    delays: BTreeMap<NodeId, u64>,
+    delayed: Vec<DelayedMessage>,
+    shuffle_seed: Option<u64>,
+    undeliverable: usize,
+}
+impl fmt::Debug for MockNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MockNetwork")
+            .field("mailboxes", &self.mailboxes)
+            .field("banned", &self.banned)
+            .field("sent", &self.sent)
+            .field("drop_once", &self.drop_once.len())
+            .field("loopback", &self.loopback)
+            .field("now", &self.now)
+            .field("delays", &self.delays)
+            .field("delayed", &self.delayed)
+            .field("shuffle_seed", &self.shuffle_seed)
+            .field("undeliverable", &self.undeliverable)
+            .finish()
+    }
+}
+impl MockNetwork {
+    /// `filter`が`true`を返す最初のメッセージを、一度だけ配送せずに破棄する.
+    pub fn drop_once<F>(&mut self, filter: F)
+    where
+        F: FnMut(&Message) -> bool + Send + 'static,
+    {
+        self.drop_once.push(Box::new(filter));
+    }
+
+    /// 自ノード宛てのメッセージを、自ノードに配送するかどうかを設定する.
+    ///
+    /// `false`(デフォルト)の場合には、自ノード宛てのメッセージは破棄される.
+    pub fn set_loopback(&mut self, loopback: bool) {
+        self.loopback = loopback;
+    }
+
+    /// `to`宛てのメッセージの配送を、論理時刻で`delay`だけ遅延させる.
+    ///
+    /// 遅延させられたメッセージは`deliver_due`メソッドが呼ばれた際に配送される.
+    /// `delay`が`0`の場合には、即座に配送される(デフォルト).
+    pub fn set_delay(&mut self, to: &str, delay: u64) {
+        if delay == 0 {
+            self.delays.remove(&NodeId::from(to));
+        } else {
+            self.delays.insert(to.into(), delay);
+        }
+    }
+
+    /// 同じ時刻に配送されるメッセージ群の順番を、`seed`から生成した乱数でシャッフルするようにする.
+    ///
+    /// `None`の場合には、送信された順番で配送される(デフォルト).
+    pub fn set_shuffle_seed(&mut self, seed: Option<u64>) {
+        // xorshiftの状態は非ゼロである必要がある
+        self.shuffle_seed = seed.map(|s| s | 1);
+    }
+
+    /// `node`を配送網から切り離す.
+    ///
+    /// 全てのグループの`node`宛のメールボックスが、配送待ちのメッセージと共に破棄される.
+    /// 以降の`node`宛のメッセージは、パニックすることなく破棄され、`undeliverable_messages`に計上される.
+    /// 構成変更によってメンバが削除された直後のように、未知の宛先へ送信される状況を模擬するために使用される.
+    ///
+    /// なお、切り離されたノードの`MockIo`を再度生成した場合には、再び配送網に接続される.
+    pub fn disconnect(&mut self, node: &str) {
+        let node = NodeId::from(node);
+        self.mailboxes.retain(|(_, id), _| *id != node);
+    }
+
+    /// 宛先が未知(ないし切り離された)ノードだったために、破棄されたメッセージの数を返す.
+    pub fn undeliverable_messages(&self) -> usize {
+        self.undeliverable
+    }
+
+    /// 現在の論理時刻を返す.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// 論理時刻を`now`に進めて、配送期限に達した遅延メッセージ群を宛先に配送する.
+    pub fn deliver_due(&mut self, now: u64) {
+        self.now = cmp::max(self.now, now);
+        let now = self.now;
+        let (mut due, pending): (Vec<_>, Vec<_>) =
+            self.delayed.drain(..).partition(|m| m.deadline <= now);
+        self.delayed = pending;
+
+        // 期限の昇順で配送する (期限が同じものは、送信順ないしシャッフルされた順番)
+        due.sort_by_key(|m| m.deadline);
+        let mut start = 0;
+        while start < due.len() {
+            let deadline = due[start].deadline;
+            let end = start
+                + due[start..]
+                    .iter()
+                    .take_while(|m| m.deadline == deadline)
+                    .count();
+            if let Some(ref mut state) = self.shuffle_seed {
+                for i in (start + 1..end).rev() {
+                    *state ^= *state << 13;
+                    *state ^= *state >> 7;
+                    *state ^= *state << 17;
+                    let j = start + (*state % (i - start + 1) as u64) as usize;
+                    due.swap(i, j);
+                }
+            }
+            start = end;
+        }
+        for m in due {
+            self.deliver(m.message);
+        }
+    }
+
+    /// 配送期限に達していない遅延メッセージが存在するかどうかを返す.
+    pub fn has_delayed_messages(&self) -> bool {
+        !self.delayed.is_empty()
+    }
+
+    /// これまでに送信された(配送されなかったものも含む)メッセージ群を返す.
+    pub fn sent_messages(&self) -> &[Message] {
+        &self.sent
+    }
+
+    /// 送信済みメッセージの記録を破棄する.
+    pub fn clear_sent_messages(&mut self) {
+        self.sent.clear();
+    }
+
+    /// 配送待ちのメッセージが存在するかどうかを返す.
+    pub fn has_pending_messages(&self) -> bool {
+        self.mailboxes.values().any(|m| !m.is_empty())
+    }
+
+    /// グループ`group_id`のノード宛に、配送待ちのメッセージが存在するかどうかを返す.
+    pub fn has_pending_group_messages(&self, group_id: GroupId) -> bool {
+        self.mailboxes
+            .iter()
+            .any(|(k, m)| k.0 == group_id && !m.is_empty())
+    }
+
+    /// `message`を、宛先のノードに(送信記録を残さずに)直接配送する.
+    ///
+    /// 過去に送信されたメッセージの再送を模擬するために使用される.
+    pub fn replay(&mut self, message: Message) {
+        self.deliver(message);
+    }
+
+    fn send(&mut self, message: Message) {
+        let from = message.header().sender.clone();
+        let to = message.header().destination.clone();
+        self.sent.push(message.clone());
+        if from == to && !self.loopback {
+            return;
+        }
+        if self.banned.contains(&(from, to.clone())) {
+            return;
+        }
+        if let Some(i) = self.drop_once.iter_mut().position(|f| f(&message)) {
+            let _ = self.drop_once.remove(i);
+            return;
+        }
+        if let Some(&delay) = self.delays.get(&to) {
+            let deadline = self.now + delay;
+            self.delayed.push(DelayedMessage { deadline, message });
+            return;
+        }
+        self.deliver(message);
+    }
+
+    fn deliver(&mut self, message: Message) {
+        let header = message.header();
+        let to = (header.group_id, header.destination.clone());
+        if let Some(mailbox) = self.mailboxes.get_mut(&to) {
+            mailbox.push_back(message);
+        } else {
+            self.undeliverable += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DelayedMessage {
+    deadline: u64,
+    message: Message,
+}
+
+/// ノードの永続ストレージ.
+///
+/// ノードを再起動した場合でも内容は維持される.
+pub type MockStorage = MemoryStorage;
+
+#[derive(Debug)]
+struct MockTimer {
+    generation: u64,
+    expired: u64,
+
+    // この世代以前のタイムアウトは`cancel_timeout`によって取り消されている.
+    cancelled: u64,
+
+    // 最後に生成されたタイムアウトの(`TimeoutPolicy`に従って決定された)時間.
+    duration: Option<Duration>,
+}
+
+/// `MockIo`が生成するタイムアウト.
+///
+/// `MockCluster::fire_timeout`で発火させられるまでは完了しない.
+/// また`Io::cancel_timeout`で取り消された後は、発火させられても完了しない.
+#[derive(Debug)]
+pub struct MockTimeout {
+    timer: Arc<Mutex<MockTimer>>,
+    generation: u64,
+}
+impl Future for MockTimeout {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let timer = self.timer.lock().expect("Never fails");
+        if timer.cancelled < self.generation && self.generation <= timer.expired {
+            Ok(Async::Ready(()))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// `MockIo`が生成するログ読み込み処理.
+///
+/// 読み込み結果は生成時点で確定しているが、
+/// 読み込みが保留されている間は完了しない.
+#[derive(Debug)]
+pub struct MockLoadLog {
+    result: Option<Result<Log>>,
+    blocked: Arc<AtomicBool>,
+}
+impl Future for MockLoadLog {
+    type Item = Log;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.blocked.load(Ordering::SeqCst) {
+            return Ok(Async::NotReady);
+        }
+        let result = track_assert_some!(
+            self.result.take(),
+            ErrorKind::InconsistentState,
+            "MockLoadLog has already completed"
+        );
+        let log = track!(result)?;
+        Ok(Async::Ready(log))
+    }
+}
+
+/// `MockIo`が生成する、生成時点で結果が確定している非同期処理.
+pub type MockResult<T> = MemoryResult<T>;
+
+/// `MockIo`が生成するログ保存処理.
+///
+/// 通常は生成時点で保存が完了しているが、`MockIo::set_saves_deferred`が有効な場合には、
+/// 最初にポーリングされた時点でストレージに反映される.
+#[derive(Debug)]
+pub struct MockSaveLog {
+    deferred: Option<(Arc<Mutex<MockStorage>>, LogSuffix)>,
+    result: MockResult<()>,
+}
+impl MockSaveLog {
+    fn done(result: Result<()>) -> Self {
+        MockSaveLog {
+            deferred: None,
+            result: MockResult::new(result),
+        }
+    }
+    fn deferred(storage: Arc<Mutex<MockStorage>>, suffix: LogSuffix) -> Self {
+        MockSaveLog {
+            deferred: Some((storage, suffix)),
+            result: MockResult::new(Ok(())),
+        }
+    }
+}
+impl Future for MockSaveLog {
+    type Item = ();
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some((storage, suffix)) = self.deferred.take() {
+            let mut storage = storage.lock().expect("Never fails");
+            self.result = MockResult::new(track!(storage.save_log_suffix_owned(suffix)));
+        }
+        track!(self.result.poll())
+    }
+}
+
+/// `MockIo::load_script`で指定される、決められたステップで発生させる障害ないし回復.
+///
+/// ステップは`load_script`の呼び出し時点を`0`として、
+/// `MockCluster::step`によってノードが駆動される度に一つずつ進む.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaultEvent {
+    /// `step`以降にローカルノードが最初に送信するメッセージを破棄する.
+    DropMessage { step: u64 },
+
+    /// `step`以降の最初のログの保存処理を、`kind`のエラーで失敗させる.
+    FailSave { step: u64, kind: ErrorKind },
+
+    /// `step`の時点で、ローカルノードと`peers`との間の通信を遮断する.
+    Partition { step: u64, peers: Vec<NodeId> },
+
+    /// `step`の時点で、ローカルノードと`peers`との間の通信を回復する.
+    Heal { step: u64, peers: Vec<NodeId> },
+}
+impl FaultEvent {
+    fn step(&self) -> u64 {
+        match *self {
+            FaultEvent::DropMessage { step }
+            | FaultEvent::FailSave { step, .. }
+            | FaultEvent::Partition { step, .. }
+            | FaultEvent::Heal { step, .. } => step,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Intake {
+    quota: Option<usize>,
+    received: usize,
+    dropped: usize,
+}
+
+#[derive(Debug, Default)]
+struct FaultScript {
+    step: u64,
+    events: VecDeque<FaultEvent>,
+}
+
+/// テスト用の`Io`実装.
+#[derive(Debug)]
+pub struct MockIo {
+    node: NodeId,
+    group_id: GroupId,
+    network: Arc<Mutex<MockNetwork>>,
+    storage: Arc<Mutex<MockStorage>>,
+    timer: Arc<Mutex<MockTimer>>,
+    loads_blocked: Arc<AtomicBool>,
+    saves_deferred: Arc<AtomicBool>,
+    save_errors: Arc<Mutex<VecDeque<ErrorKind>>>,
+    duplication_factor: Arc<AtomicUsize>,
+    script: Arc<Mutex<FaultScript>>,
+    intake: Arc<Mutex<Intake>>,
+    loaded_entries: Arc<AtomicUsize>,
+}
+impl MockIo {
+    /// `network`に接続されたノード`node`用の`MockIo`インスタンスを生成する.
+    ///
+    /// ノードは`GroupId::default()`のグループに属する.
+    pub fn new(node: NodeId, network: Arc<Mutex<MockNetwork>>) -> Self {
+        Self::with_group(node, GroupId::default(), network)
+    }
+
+    /// `network`に接続された、グループ`group_id`のノード`node`用の`MockIo`インスタンスを生成する.
+    pub fn with_group(node: NodeId, group_id: GroupId, network: Arc<Mutex<MockNetwork>>) -> Self {
+        network
+            .lock()
+            .expect("Never fails")
+            .mailboxes
+            .entry((group_id, node.clone()))
+            .or_default();
+        MockIo {
+            node,
+            group_id,
+            network,
+            storage: Arc::default(),
+            timer: Arc::new(Mutex::new(MockTimer {
+                generation: 0,
+                expired: 0,
+                cancelled: 0,
+                duration: None,
+            })),
+            loads_blocked: Arc::default(),
+            saves_deferred: Arc::default(),
+            save_errors: Arc::default(),
+            duplication_factor: Arc::new(AtomicUsize::new(1)),
+            script: Arc::default(),
+            intake: Arc::default(),
+            loaded_entries: Arc::default(),
+        }
+    }
+
+    /// 同じノード上で、配送網を共有しつつ、グループ`group_id`を扱うための`MockIo`インスタンスを生成する.
+    ///
+    /// ストレージとタイマーは、グループ毎に独立したものが使用される.
+    pub fn for_group(&self, group_id: GroupId) -> Self {
+        Self::with_group(self.node.clone(), group_id, Arc::clone(&self.network))
+    }
+
+    /// ローカルノードのストレージを返す.
+    pub fn storage(&self) -> &Arc<Mutex<MockStorage>> {
+        &self.storage
+    }
+
+    /// `true`を指定した場合には、`false`が指定されるまでの間、ログの読み込みを完了させないようにする.
+    ///
+    /// 既に発行済みの読み込み処理にも適用される.
+    pub fn set_loads_blocked(&self, blocked: bool) {
+        self.loads_blocked.store(blocked, Ordering::SeqCst);
+    }
+
+    /// これまでのログの読み込みで、ストレージから読み出された(後半部分の)エントリの総数を返す.
+    pub fn loaded_entries(&self) -> usize {
+        self.loaded_entries.load(Ordering::SeqCst)
+    }
+
+    fn loaded(&self, result: Result<Log>) -> MockLoadLog {
+        if let Ok(Log::Suffix(ref suffix)) = result {
+            self.loaded_entries
+                .fetch_add(suffix.entries.len(), Ordering::SeqCst);
+        }
+        MockLoadLog {
+            result: Some(result),
+            blocked: Arc::clone(&self.loads_blocked),
+        }
+    }
+
+    /// `true`を指定した場合には、以降のログの追記処理を、それがポーリングされるまでストレージに反映させないようにする.
+    ///
+    /// 追記処理の完了を待たずにノードが破棄された場合に、追記内容が失われることを模擬するために使用される.
+    /// スナップショットの保存には適用されない.
+    pub fn set_saves_deferred(&self, deferred: bool) {
+        self.saves_deferred.store(deferred, Ordering::SeqCst);
+    }
+
+    /// 送信するメッセージのそれぞれを、`n`回ずつ配送網に送るようにする.
+    ///
+    /// メッセージの重複配送を模擬するために使用される.
+    /// デフォルト値は`1`で、`0`が指定された場合も`1`として扱われる.
+    pub fn set_duplication_factor(&self, n: usize) {
+        self.duplication_factor
+            .store(cmp::max(n, 1), Ordering::SeqCst);
+    }
+
+    /// 一ステップ(`MockCluster::step`)当たりに受信可能なメッセージ数の上限を設定する.
+    ///
+    /// 上限に達した後に受信しようとした場合には、その時点でメールボックス内にあるメッセージ群は全て破棄され、
+    /// 破棄された数は`dropped_messages`で取得可能となる.
+    /// ただし、送信元からのリンクが遮断されているメッセージは、上限の計算にも破棄数にも含まれない.
+    ///
+    /// 受信側の過負荷によるメッセージの取りこぼしを模擬するために使用される.
+    /// デフォルト値は`None`で、上限は設けられない.
+    pub fn set_intake_quota(&self, quota: Option<usize>) {
+        self.intake.lock().expect("Never fails").quota = quota;
+    }
+
+    /// `set_intake_quota`で設定された上限を超えたために破棄されたメッセージの数を返す.
+    pub fn dropped_messages(&self) -> usize {
+        self.intake.lock().expect("Never fails").dropped
+    }
+
+    /// 以降の`count`回分のログの保存処理(スナップショットの保存も含む)を、`kind`のエラーで失敗させる.
+    ///
+    /// 失敗した保存処理では、ストレージの内容は更新されない.
+    pub fn inject_save_errors(&self, kind: ErrorKind, count: usize) {
+        let mut errors = self.save_errors.lock().expect("Never fails");
+        errors.extend((0..count).map(|_| kind));
+    }
+
+    /// 障害ないし回復の発生手順を設定する.
+    ///
+    /// 既に設定済みの手順は破棄され、ステップは`0`に戻される.
+    /// 各イベントは、ステップが`FaultEvent`に指定の値に達した時点で適用される.
+    pub fn load_script(&mut self, mut script: Vec<FaultEvent>) {
+        script.sort_by_key(FaultEvent::step);
+        let mut current = self.script.lock().expect("Never fails");
+        current.step = 0;
+        current.events = script.into();
+    }
+
+    /// 未適用の`FaultEvent`が存在するかどうかを返す.
+    pub fn has_pending_faults(&self) -> bool {
+        !self.script.lock().expect("Never fails").events.is_empty()
+    }
+
+    /// ステップを一つ進めて、適用時期に達した`FaultEvent`群を適用する.
+    fn advance_script(&self) {
+        let mut script = self.script.lock().expect("Never fails");
+        script.step += 1;
+        while script
+            .events
+            .front()
+            .is_some_and(|e| e.step() <= script.step)
+        {
+            let event = script.events.pop_front().expect("Never fails");
+            self.apply_fault(event);
+        }
+    }
+
+    fn apply_fault(&self, event: FaultEvent) {
+        let mut network = self.network.lock().expect("Never fails");
+        match event {
+            FaultEvent::DropMessage { .. } => {
+                let node = self.node.clone();
+                network.drop_once(move |m| m.header().sender == node);
+            }
+            FaultEvent::FailSave { kind, .. } => {
+                drop(network);
+                self.inject_save_errors(kind, 1);
+            }
+            FaultEvent::Partition { peers, .. } => {
+                for peer in peers {
+                    network.banned.insert((self.node.clone(), peer.clone()));
+                    network.banned.insert((peer, self.node.clone()));
+                }
+            }
+            FaultEvent::Heal { peers, .. } => {
+                for peer in peers {
+                    network.banned.remove(&(self.node.clone(), peer.clone()));
+                    network.banned.remove(&(peer, self.node.clone()));
+                }
+            }
+        }
+    }
+
+    /// 現在のステップで受信したメッセージ数をリセットする.
+    fn reset_intake(&self) {
+        self.intake.lock().expect("Never fails").received = 0;
+    }
+
+    fn take_save_error(&self) -> Option<Error> {
+        let kind = self.save_errors.lock().expect("Never fails").pop_front()?;
+        Some(track!(kind.cause("Injected error")).into())
+    }
+}
+impl Io for MockIo {
+    type SaveBallot = MockResult<()>;
+    type LoadBallot = MockResult<Option<Ballot>>;
+    type SaveLog = MockSaveLog;
+    type LoadLog = MockLoadLog;
+    type Timeout = MockTimeout;
+
+    fn try_recv_message(&mut self) -> Result<Option<Message>> {
+        let group_id = self.group_id;
+        self.try_recv_group_message(group_id)
+    }
+
+    fn try_recv_group_message(&mut self, group_id: GroupId) -> Result<Option<Message>> {
+        let mut network = self.network.lock().expect("Never fails");
+        let mut intake = self.intake.lock().expect("Never fails");
+        let quota = match intake.quota {
+            None => {
+                return Ok(network
+                    .mailboxes
+                    .get_mut(&(group_id, self.node.clone()))
+                    .and_then(|m| m.pop_front()));
+            }
+            Some(quota) => quota,
+        };
+        let MockNetwork {
+            ref mut mailboxes,
+            ref banned,
+            ..
+        } = *network;
+        let mailbox = match mailboxes.get_mut(&(group_id, self.node.clone())) {
+            None => return Ok(None),
+            Some(mailbox) => mailbox,
+        };
+        while let Some(message) = mailbox.pop_front() {
+            let link = (message.header().sender.clone(), self.node.clone());
+            if banned.contains(&link) {
+                // 遮断されたリンク経由のメッセージは、受信負荷とは無関係に失われる
+                continue;
+            }
+            if intake.received < quota {
+                intake.received += 1;
+                return Ok(Some(message));
+            }
+            intake.dropped += 1;
+        }
+        Ok(None)
+    }
+
+    fn send_message(&mut self, message: Message) {
+        let mut network = self.network.lock().expect("Never fails");
+        for _ in 1..self.duplication_factor.load(Ordering::SeqCst) {
+            network.send(message.clone());
+        }
+        network.send(message);
+    }
+
+    fn save_ballot(&mut self, ballot: Ballot) -> Self::SaveBallot {
+        self.storage.lock().expect("Never fails").ballot = Some(ballot);
+        MockResult::new(Ok(()))
+    }
+
+    fn load_ballot(&mut self) -> Self::LoadBallot {
+        MockResult::new(Ok(self.storage.lock().expect("Never fails").ballot.clone()))
+    }
+
+    fn save_log_prefix(&mut self, prefix: LogPrefix) -> Self::SaveLog {
+        if let Some(e) = self.take_save_error() {
+            return MockSaveLog::done(Err(e));
+        }
+        self.storage
+            .lock()
+            .expect("Never fails")
+            .save_log_prefix(prefix);
+        MockSaveLog::done(Ok(()))
+    }
+
+    fn save_log_suffix(&mut self, suffix: &LogSuffix) -> Self::SaveLog {
+        if let Some(e) = self.take_save_error() {
+            return MockSaveLog::done(Err(e));
+        }
+        if self.saves_deferred.load(Ordering::SeqCst) {
+            return MockSaveLog::deferred(Arc::clone(&self.storage), suffix.clone());
+        }
+        let mut storage = self.storage.lock().expect("Never fails");
+        MockSaveLog::done(track!(storage.save_log_suffix(suffix)))
+    }
+
+    fn save_log_suffix_owned(&mut self, suffix: LogSuffix) -> Self::SaveLog {
+        if let Some(e) = self.take_save_error() {
+            return MockSaveLog::done(Err(e));
+        }
+        if self.saves_deferred.load(Ordering::SeqCst) {
+            return MockSaveLog::deferred(Arc::clone(&self.storage), suffix);
+        }
+        let mut storage = self.storage.lock().expect("Never fails");
+        MockSaveLog::done(track!(storage.save_log_suffix_owned(suffix)))
+    }
+
+    fn save_ballot_and_log(
+        &mut self,
+        ballot: Ballot,
+        suffix: &LogSuffix,
+    ) -> SaveBallotAndLog<Self> {
+        if let Some(e) = self.take_save_error() {
+            return SaveBallotAndLog::atomic(MockSaveLog::done(Err(e)));
+        }
+        let mut storage = self.storage.lock().expect("Never fails");
+        let result = track!(storage.save_log_suffix(suffix));
+        if result.is_ok() {
+            storage.ballot = Some(ballot);
+        }
+        SaveBallotAndLog::atomic(MockSaveLog::done(result))
+    }
+
+    fn load_log(&mut self, start: LogIndex, end: Option<LogIndex>) -> Self::LoadLog {
+        let result = track!(self
+            .storage
+            .lock()
+            .expect("Never fails")
+            .load_log(start, end));
+        self.loaded(result)
+    }
+
+    fn load_log_limited(&mut self, start: LogIndex, max_entries: usize) -> Self::LoadLog {
+        let result = track!(self
+            .storage
+            .lock()
+            .expect("Never fails")
+            .load_log_limited(start, max_entries));
+        self.loaded(result)
+    }
+
+    fn load_log_limited_bytes(
+        &mut self,
+        start: LogIndex,
+        max_entries: usize,
+        max_bytes: usize,
+    ) -> Self::LoadLog {
+        let result = track!(self
+            .storage
+            .lock()
+            .expect("Never fails")
+            .load_log_limited_bytes(start, max_entries, max_bytes));
+        self.loaded(result)
+    }
+
+    fn create_timeout(&mut self, _role: Role) -> Self::Timeout {
+        let mut timer = self.timer.lock().expect("Never fails");
+        timer.generation += 1;
+        timer.duration = None;
+        MockTimeout {
+            timer: Arc::clone(&self.timer),
+            generation: timer.generation,
+        }
+    }
+
+    fn cancel_timeout(&mut self) {
+        let mut timer = self.timer.lock().expect("Never fails");
+        timer.cancelled = timer.generation;
+    }
+    fn create_timeout_with(&mut self, role: Role, duration: Duration) -> Self::Timeout {
+        let timeout = self.create_timeout(role);
+        self.timer.lock().expect("Never fails").duration = Some(duration);
+        timeout
+    }
+}
+
+/// `MockIo`を使って構成された、テスト用のクラスタ.
+///
+/// 全てのノードは単一スレッド上で、ノードIDの順番に決定論的に駆動される.
+pub struct MockCluster {
+    members: ClusterMembers,
+    options: ReplicatedLogOptions,
+    network: Arc<Mutex<MockNetwork>>,
+    nodes: BTreeMap<NodeId, ReplicatedLog<MockIo>>,
+    timers: BTreeMap<NodeId, Arc<Mutex<MockTimer>>>,
+    events: BTreeMap<NodeId, Vec<Event>>,
+}
+impl MockCluster {
+    /// `members`から構成されるクラスタを生成する.
+    pub fn new(members: &[&str]) -> Self {
+        Self::with_options(members, ReplicatedLogOptions::default())
+    }
+
+    /// 全てのノードに`options`を適用したクラスタを生成する.
+    pub fn with_options(members: &[&str], options: ReplicatedLogOptions) -> Self {
+        Self::with_network(members, options, Arc::default())
+    }
+
+    /// 既存の配送網`network`を使用して、クラスタを生成する.
+    ///
+    /// `options.group_id`に異なる値を指定することで、複数のRaftグループを一つの配送網上で動かすことができる.
+    /// なお、各クラスタの`step`ないし`run`は、自身のグループのノードのみを駆動する.
+    pub fn with_network(
+        members: &[&str],
+        options: ReplicatedLogOptions,
+        network: Arc<Mutex<MockNetwork>>,
+    ) -> Self {
+        let mut cluster = MockCluster {
+            members: members.iter().map(|&m| NodeId::from(m)).collect(),
+            options,
+            network,
+            nodes: BTreeMap::new(),
+            timers: BTreeMap::new(),
+            events: BTreeMap::new(),
+        };
+        for &m in members {
+            let storage = Arc::default();
+            let options = cluster.options.clone();
+            cluster.start_node(m.into(), storage, options);
+        }
+        cluster
+    }
+
+    /// 現在のクラスタ構成で起動した新規ノード`id`を追加する.
+    ///
+    /// 追加したノードをクラスタに参加させるためには、別途構成変更を行う必要がある.
+    pub fn add_node(&mut self, id: &str) {
+        let options = self.options.clone();
+        self.start_node(id.into(), Arc::default(), options);
+        self.members.insert(id.into());
+    }
+
+    /// 指定のノードへの参照を返す.
+    pub fn node(&self, id: &str) -> &ReplicatedLog<MockIo> {
+        &self.nodes[&NodeId::from(id)]
+    }
+
+    /// 指定のノードへの破壊的な参照を返す.
+    pub fn node_mut(&mut self, id: &str) -> &mut ReplicatedLog<MockIo> {
+        self.nodes.get_mut(&NodeId::from(id)).expect("Unknown node")
+    }
+
+    /// 指定のノードがこれまでに発行したイベント群を返す.
+    pub fn events(&self, id: &str) -> &[Event] {
+        &self.events[&NodeId::from(id)]
+    }
+
+    /// ノード間のメッセージ配送網を返す.
+    pub fn network(&self) -> &Arc<Mutex<MockNetwork>> {
+        &self.network
+    }
+
+    /// 現在リーダとなっているノードのIDを返す.
+    ///
+    /// 複数のノードがリーダだと認識している場合には、最も新しい`Term`のものが返される.
+    pub fn leader(&self) -> Option<NodeId> {
+        self.nodes
+            .values()
+            .map(|n| n.local_node())
+            .filter(|n| n.role == Role::Leader)
+            .max_by_key(|n| n.ballot.term)
+            .map(|n| n.id.clone())
+    }
+
+    /// 指定のノードの現在のタイムアウトを発火させる.
+    pub fn fire_timeout(&mut self, id: &str) {
+        let mut timer = self.timers[&NodeId::from(id)].lock().expect("Never fails");
+        timer.expired = timer.generation;
+    }
+
+    /// 指定のノードの現在のタイムアウトの時間を返す.
+    ///
+    /// タイムアウトが既に発火済みの場合には`None`が返される.
+    pub fn timeout_duration(&self, id: &str) -> Option<Duration> {
+        let timer = self.timers[&NodeId::from(id)].lock().expect("Never fails");
+        if timer.expired < timer.generation {
+            timer.duration
+        } else {
+            None
+        }
+    }
+
+    /// `ids`の内で、現在のタイムアウトの時間が最も短いノードのタイムアウトを発火させる.
+    ///
+    /// 返り値は、タイムアウトを発火させたノードのID.
+    pub fn fire_earliest_timeout(&mut self, ids: &[&str]) -> Option<NodeId> {
+        let id = ids
+            .iter()
+            .filter_map(|&id| self.timeout_duration(id).map(|d| (d, id)))
+            .min()
+            .map(|(_, id)| id)?;
+        self.fire_timeout(id);
+        Some(NodeId::from(id))
+    }
+
+    /// `from`から`to`へのメッセージが配送されないようにする.
+    pub fn ban(&mut self, from: &str, to: &str) {
+        let mut network = self.network.lock().expect("Never fails");
+        network.banned.insert((from.into(), to.into()));
+    }
+
+    /// `node`を他の全てのノードから切り離す.
+    pub fn isolate(&mut self, node: &str) {
+        for m in self.members.clone() {
+            if m.as_str() != node {
+                self.ban(node, m.as_str());
+                self.ban(m.as_str(), node);
+            }
+        }
+    }
+
+    /// `nodes`に含まれるノード群と、それ以外のノード群との間の通信を遮断する.
+    pub fn partition(&mut self, nodes: &[&str]) {
+        for m in self.members.clone() {
+            if nodes.contains(&m.as_str()) {
+                continue;
+            }
+            for &n in nodes {
+                self.ban(n, m.as_str());
+                self.ban(m.as_str(), n);
+            }
+        }
+    }
+
+    /// 全ての通信制限を解除する.
+    pub fn heal(&mut self) {
+        self.network.lock().expect("Never fails").banned.clear();
+    }
+
+    /// `seed`に基づいて、メッセージの配送順を決定論的に撹拌するようにする.
+    ///
+    /// 全てのメッセージは配送網の遅延キューを経由するようになり、
+    /// 同じ時刻に配送されるメッセージ群の順番は、`seed`から生成した乱数でシャッフルされる.
+    pub fn randomize(&mut self, seed: u64) {
+        let mut network = self.network.lock().expect("Never fails");
+        for m in &self.members {
+            network.set_delay(m.as_str(), 1);
+        }
+        network.set_shuffle_seed(Some(seed));
+    }
+
+    /// `id`のノードに、タイムアウトを待たずに選挙を開始させる.
+    pub fn trigger_election(&mut self, id: &str) {
+        self.fire_timeout(id);
+    }
+
+    /// `id`のノードに`command`を提案させる.
+    pub fn propose(&mut self, id: &str, command: Vec<u8>) -> Result<ProposalId> {
+        track!(self.node_mut(id).propose_command(command))
+    }
+
+    /// `id`のノードの`MockIo`に、障害ないし回復の発生手順を設定する.
+    ///
+    /// 詳細は`MockIo::load_script`を参照のこと.
+    pub fn load_script(&mut self, id: &str, script: Vec<FaultEvent>) {
+        // 手順の設定は、ストレージの内容には影響を与えない
+        unsafe { self.node_mut(id).io_mut() }.load_script(script);
+    }
+
+    /// 全てのノードを一度ずつ駆動する.
+    ///
+    /// 駆動の前に、メッセージ配送網の論理時刻を一つ進めて、期限に達した遅延メッセージ群を配送する.
+    /// また各ノードの`MockIo`のステップを一つ進めて、適用時期に達した`FaultEvent`群を適用し、
+    /// `MockIo::set_intake_quota`用の受信数をリセットする.
+    /// また駆動の後には、全てのノードで`consumed_tail <= committed_tail <= appended_tail`が成立していることと、
+    /// ノード間でコミット済みのエントリが食い違っていないこと(`verify_committed_agreement`)を検証する.
+    ///
+    /// 何らかの進展(メッセージ送信ないしイベント発行)があった場合や、
+    /// 未適用の`FaultEvent`が残っている場合には`true`を返す.
+    pub fn step(&mut self) -> bool {
+        for node in self.nodes.values() {
+            node.io().advance_script();
+            node.io().reset_intake();
+        }
+        let sent_before = {
+            let mut network = self.network.lock().expect("Never fails");
+            let now = network.now + 1;
+            network.deliver_due(now);
+            network.sent.len()
+        };
+        let mut progressed = false;
+        for (id, node) in &mut self.nodes {
+            while let Async::Ready(event) = node.poll().expect("Node failed") {
+                let event = event.expect("Never fails");
+                self.events.get_mut(id).expect("Never fails").push(event);
+                progressed = true;
+            }
+            if let Err(e) = node.verify_safety_invariants() {
+                panic!(
+                    "Invariant violated: node={:?}, history={:?}, error={}",
+                    id,
+                    node.local_history(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = self.verify_committed_agreement() {
+            panic!("Committed logs diverged: {}", e);
+        }
+        let has_pending_faults = self.nodes.values().any(|n| n.io().has_pending_faults());
+        let network = self.network.lock().expect("Never fails");
+        progressed
+            || has_pending_faults
+            || network.sent.len() != sent_before
+            || network.has_pending_group_messages(self.options.group_id)
+            || network.has_delayed_messages()
+    }
+
+    /// 進展が無くなるまで、全てのノードを駆動し続ける.
+    pub fn run(&mut self) {
+        for _ in 0..10_000 {
+            if !self.step() {
+                return;
+            }
+        }
+        panic!("The cluster did not quiesce");
+    }
+
+    /// `id`のノードをリーダに選出させる.
+    pub fn elect(&mut self, id: &str) {
+        self.run();
+        self.fire_timeout(id);
+        self.run();
+        assert_eq!(self.leader(), Some(NodeId::from(id)));
+    }
+
+    /// `id`のノードを再起動する.
+    ///
+    /// ストレージの内容は維持されるが、メモリ上の状態やタイマー、発行済みのイベント群は破棄される.
+    pub fn restart(&mut self, id: &str) {
+        let options = self.options.clone();
+        self.restart_with_options(id, options);
+    }
+
+    /// `options`を指定して`id`のノードを再起動する.
+    ///
+    /// `options`は再起動されたノードにのみ適用される.
+    pub fn restart_with_options(&mut self, id: &str, options: ReplicatedLogOptions) {
+        let storage = Arc::clone(self.node(id).io().storage());
+        self.start_node(id.into(), storage, options);
+    }
+
+    /// `id`のノードをクラスタから取り外して返す.
+    ///
+    /// 取り外したノードを(再起動して)戻す場合には`restart_from_storage`を使用する.
+    pub fn take_node(&mut self, id: &str) -> ReplicatedLog<MockIo> {
+        let id = NodeId::from(id);
+        self.timers.remove(&id);
+        self.events.remove(&id);
+        self.nodes.remove(&id).expect("Unknown node")
+    }
+
+    /// `storage`の内容を引き継いで、`id`のノードを(再)起動する.
+    pub fn restart_from_storage(&mut self, id: &str, storage: Arc<Mutex<MockStorage>>) {
+        let options = self.options.clone();
+        self.start_node(id.into(), storage, options);
+    }
+
+    /// 指定のノードのローカルログの内容を返す.
+    pub fn log_suffix(&self, id: &str) -> LogSuffix {
+        let node = self.node(id);
+        let storage = node.io().storage().lock().expect("Never fails");
+        storage.suffix.clone()
+    }
+
+    /// 全てのノードのコミット済み領域が、互いに食い違っていないことを検証する.
+    ///
+    /// 各ノードのストレージ上のログの内で、コミット済みの範囲同士を比較し、
+    /// 同じインデックスに異なるエントリがコミットされているノードの組が存在しないかを確認する.
+    /// スナップショットに取り込まれて、既に破棄されている領域は比較の対象外となる.
+    ///
+    /// # Errors
+    ///
+    /// 食い違いが検出された場合には、
+    /// 該当ノードの組とインデックスを示すメッセージと共に`ErrorKind::InconsistentState`が返される.
+    pub fn verify_committed_agreement(&self) -> Result<()> {
+        let mut committed = Vec::new();
+        for id in self.nodes.keys() {
+            let suffix = self.log_suffix(id.as_str());
+            let end = cmp::min(
+                self.node(id.as_str())
+                    .local_history()
+                    .committed_tail()
+                    .index,
+                suffix.tail().index,
+            );
+            if suffix.head.index < end {
+                committed.push((id, track!(suffix.slice(suffix.head.index, end))?));
+            }
+        }
+        for (i, &(a, ref a_log)) in committed.iter().enumerate() {
+            for &(b, ref b_log) in &committed[i + 1..] {
+                if let Some(index) = a_log.first_divergence(b_log) {
+                    if index < cmp::min(a_log.tail().index, b_log.tail().index) {
+                        track_panic!(
+                            ErrorKind::InconsistentState,
+                            "Conflicting committed entries: nodes=({:?}, {:?}), index={:?}",
+                            a,
+                            b,
+                            index
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start_node(
+        &mut self,
+        id: NodeId,
+        storage: Arc<Mutex<MockStorage>>,
+        options: ReplicatedLogOptions,
+    ) {
+        let mut io = MockIo::with_group(id.clone(), options.group_id, Arc::clone(&self.network));
+        io.storage = storage;
+        let timer = Arc::clone(&io.timer);
+        let node = ReplicatedLog::with_options(
+            id.clone(),
+            self.members.clone(),
+            io,
+            &MetricBuilder::new(),
+            options,
+        )
+        .expect("Never fails");
+        self.nodes.insert(id.clone(), node);
+        self.timers.insert(id.clone(), timer);
+        self.events.insert(id, Vec::new());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cluster::ClusterConfig;
+    use crate::log::{LogEntry, LogPosition};
+    use crate::message::{MessageHeader, SequenceNumber, TimeoutNowCast};
+    use std::mem;
+
+    fn broadcast(io: &mut MockIo, members: &[&str]) {
+        let mut message: Message = TimeoutNowCast {
+            header: MessageHeader {
+                group_id: GroupId::default(),
+                sender: "a".into(),
+                destination: "".into(),
+                seq_no: SequenceNumber::new(0),
+                term: 0.into(),
+            },
+        }
+        .into();
+        for &m in members {
+            message.set_destination(&m.into());
+            io.send_message(message.clone());
+        }
+    }
+
+    #[test]
+    fn cancelled_timeout_never_fires() {
+        let mut io = MockIo::new("a".into(), Arc::default());
+        let mut old = io.create_timeout(Role::Follower);
+        io.cancel_timeout();
+
+        // 取り消し後に発火させても、古いタイムアウトは完了しない
+        let fire = |io: &MockIo| {
+            let mut timer = io.timer.lock().expect("Never fails");
+            timer.expired = timer.generation;
+        };
+        fire(&io);
+        assert_eq!(old.poll().ok(), Some(Async::NotReady));
+
+        let mut new = io.create_timeout(Role::Candidate);
+        fire(&io);
+        assert_eq!(new.poll().ok(), Some(Async::Ready(())));
+        assert_eq!(old.poll().ok(), Some(Async::NotReady));
+    }
+
+    #[test]
+    fn completed_futures_can_be_polled_again_without_panic() {
+        let mut io = MockIo::new("a".into(), Arc::default());
+        let ballot = Ballot {
+            term: 1.into(),
+            voted_for: "a".into(),
+        };
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries: vec![LogEntry::Noop { term: 1.into() }],
+        };
+
+        let mut save_ballot = io.save_ballot(ballot.clone());
+        assert_eq!(save_ballot.poll().ok(), Some(Async::Ready(())));
+        assert_eq!(
+            save_ballot.poll().map_err(|e| *e.kind()),
+            Err(ErrorKind::InconsistentState)
+        );
+
+        let mut load_ballot = io.load_ballot();
+        assert_eq!(load_ballot.poll().ok(), Some(Async::Ready(Some(ballot))));
+        assert!(load_ballot.poll().is_err());
+
+        let mut save_log = io.save_log_suffix(&suffix);
+        assert_eq!(save_log.poll().ok(), Some(Async::Ready(())));
+        assert!(save_log.poll().is_err());
+
+        let mut load_log = io.load_log(LogIndex::new(0), None);
+        assert!(load_log.poll().unwrap().is_ready());
+        assert_eq!(
+            load_log.poll().map_err(|e| *e.kind()).err(),
+            Some(ErrorKind::InconsistentState)
+        );
+
+        // 完了したタイムアウトは、何度ポーリングしても完了したままとなる
+        let mut timeout = io.create_timeout(Role::Follower);
+        {
+            let mut timer = io.timer.lock().expect("Never fails");
+            timer.expired = timer.generation;
+        }
+        assert_eq!(timeout.poll().ok(), Some(Async::Ready(())));
+        assert_eq!(timeout.poll().ok(), Some(Async::Ready(())));
+    }
+
+    #[test]
+    fn failed_combined_save_is_not_observable() {
+        let mut io = MockIo::new("a".into(), Arc::default());
+        let ballot = Ballot {
+            term: 1.into(),
+            voted_for: "a".into(),
+        };
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries: vec![LogEntry::Noop { term: 1.into() }],
+        };
+
+        io.inject_save_errors(ErrorKind::Other, 1);
+        assert!(io
+            .save_ballot_and_log(ballot.clone(), &suffix)
+            .wait()
+            .is_err());
+        {
+            let storage = io.storage().lock().unwrap();
+            assert_eq!(storage.ballot, None);
+            assert!(storage.suffix.entries.is_empty());
+        }
+
+        assert!(io
+            .save_ballot_and_log(ballot.clone(), &suffix)
+            .wait()
+            .is_ok());
+        let storage = io.storage().lock().unwrap();
+        assert_eq!(storage.ballot, Some(ballot));
+        assert_eq!(storage.suffix.tail(), suffix.tail());
+    }
+    #[test]
+    fn ballot_and_truncated_log_are_saved_together() {
+        let mut io = MockIo::new("a".into(), Arc::default());
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 2.into() },
+            ],
+        };
+        assert!(io.save_log_suffix(&suffix).wait().is_ok());
+
+        let ballot = Ballot {
+            term: 3.into(),
+            voted_for: "b".into(),
+        };
+        let new_tail = LogPosition {
+            prev_term: 1.into(),
+            index: 1.into(),
+        };
+
+        io.inject_save_errors(ErrorKind::Other, 1);
+        assert!(io
+            .save_ballot_and_truncate(ballot.clone(), new_tail)
+            .wait()
+            .is_err());
+        {
+            let storage = io.storage().lock().unwrap();
+            assert_eq!(storage.ballot, None);
+            assert_eq!(storage.suffix.tail(), suffix.tail());
+        }
+
+        assert!(io
+            .save_ballot_and_truncate(ballot.clone(), new_tail)
+            .wait()
+            .is_ok());
+        let storage = io.storage().lock().unwrap();
+        assert_eq!(storage.ballot, Some(ballot));
+        assert_eq!(storage.suffix.tail(), new_tail);
+    }
+
+    #[test]
+    fn truncation_with_mismatched_prev_term_is_rejected() {
+        let mut io = MockIo::new("a".into(), Arc::default());
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries: vec![
+                LogEntry::Noop { term: 1.into() },
+                LogEntry::Noop { term: 2.into() },
+            ],
+        };
+        assert!(io.save_log_suffix(&suffix).wait().is_ok());
+
+        let ballot = Ballot {
+            term: 3.into(),
+            voted_for: "b".into(),
+        };
+        let new_tail = LogPosition {
+            prev_term: 2.into(), // 正しくは`1`
+            index: 1.into(),
+        };
+        let error = io
+            .save_ballot_and_truncate(ballot, new_tail)
+            .wait()
+            .err()
+            .unwrap();
+        assert_eq!(*error.kind(), ErrorKind::InconsistentState);
+
+        let storage = io.storage().lock().unwrap();
+        assert_eq!(storage.ballot, None);
+        assert_eq!(storage.suffix.tail(), suffix.tail());
+    }
+
+    #[test]
+    fn self_messages_are_handled() {
+        let network = Arc::new(Mutex::new(MockNetwork::default()));
+        let mut a = MockIo::new("a".into(), Arc::clone(&network));
+        let mut b = MockIo::new("b".into(), Arc::clone(&network));
+
+        // デフォルトでは自ノード宛てのメッセージは破棄される
+        broadcast(&mut a, &["a", "b", "c"]);
+        assert!(a.try_recv_message().unwrap().is_none());
+        assert!(b.try_recv_message().unwrap().is_some());
+
+        network.lock().unwrap().set_loopback(true);
+        broadcast(&mut a, &["a", "b", "c"]);
+        assert!(a.try_recv_message().unwrap().is_some());
+        assert!(b.try_recv_message().unwrap().is_some());
+        assert_eq!(network.lock().unwrap().sent_messages().len(), 6);
+    }
+
+    #[test]
+    fn groups_share_one_network_without_cross_contamination() {
+        let network = Arc::new(Mutex::new(MockNetwork::default()));
+        let cluster = |group_id: u64| {
+            let options = ReplicatedLogOptions {
+                group_id: GroupId::new(group_id),
+                ..Default::default()
+            };
+            MockCluster::with_network(&["a", "b", "c"], options, Arc::clone(&network))
+        };
+        let mut one = cluster(1);
+        let mut two = cluster(2);
+        one.elect("a");
+        two.elect("b");
+        assert_eq!(one.leader(), Some("a".into()));
+        assert_eq!(two.leader(), Some("b".into()));
+
+        one.propose("a", b"one".to_vec()).unwrap();
+        two.propose("b", b"two".to_vec()).unwrap();
+        one.run();
+        two.run();
+
+        let commands = |cluster: &MockCluster, id: &str| {
+            cluster
+                .log_suffix(id)
+                .commands()
+                .map(|(_, c)| c.to_vec())
+                .collect::<Vec<_>>()
+        };
+        for id in &["a", "b", "c"] {
+            assert_eq!(commands(&one, id), [b"one".to_vec()]);
+            assert_eq!(commands(&two, id), [b"two".to_vec()]);
+        }
+
+        // 同じノード上の別グループ宛のメッセージは受信されない
+        let mut a = MockIo::new("a".into(), Arc::clone(&network));
+        let mut a3 = a.for_group(GroupId::new(3));
+        let mut b3 = MockIo::with_group("b".into(), GroupId::new(3), Arc::clone(&network));
+        let message: Message = TimeoutNowCast {
+            header: MessageHeader {
+                group_id: GroupId::new(3),
+                sender: "b".into(),
+                destination: "a".into(),
+                seq_no: SequenceNumber::new(0),
+                term: 0.into(),
+            },
+        }
+        .into();
+        b3.send_message(message.clone());
+        assert!(a.try_recv_message().unwrap().is_none());
+        assert!(a3.try_recv_message().unwrap().is_some());
+
+        // グループを指定すれば、既定のグループ用のインスタンスからでも受信できる
+        b3.send_message(message);
+        assert!(a.try_recv_group_message(GroupId::new(3)).unwrap().is_some());
+    }
+
+    #[test]
+    fn owned_suffix_is_stored_without_copy() {
+        let network = Arc::new(Mutex::new(MockNetwork::default()));
+        let mut io = MockIo::new("a".into(), network);
+
+        let mut entries = Vec::with_capacity(100);
+        entries.push(LogEntry::Command {
+            term: 1.into(),
+            command: vec![1; 1024].into(),
+        });
+        let ptr = entries.as_ptr();
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries,
+        };
+        io.save_log_suffix_owned(suffix).wait().unwrap();
+        {
+            let storage = io.storage().lock().unwrap();
+            assert_eq!(storage.suffix.entries.capacity(), 100);
+            assert_eq!(storage.suffix.entries.as_ptr(), ptr);
+        }
+
+        // 既存のログへの追記の場合はマージされる
+        let suffix = LogSuffix {
+            head: io.storage().lock().unwrap().suffix.tail(),
+            entries: vec![LogEntry::Noop { term: 2.into() }],
+        };
+        io.save_log_suffix_owned(suffix).wait().unwrap();
+        let storage = io.storage().lock().unwrap();
+        assert_eq!(storage.suffix.entries.len(), 2);
+        assert_eq!(storage.suffix.tail().prev_term, 2.into());
+    }
+
+    #[test]
+    fn limited_load_returns_prefix_of_range() {
+        let network = Arc::new(Mutex::new(MockNetwork::default()));
+        let mut io = MockIo::new("a".into(), network);
+        let entries = (0..5)
+            .map(|i| LogEntry::Command {
+                term: 1.into(),
+                command: vec![i].into(),
+            })
+            .collect::<Vec<_>>();
+        let suffix = LogSuffix {
+            head: Default::default(),
+            entries: entries.clone(),
+        };
+        io.save_log_suffix(&suffix).wait().unwrap();
+
+        let load = |io: &mut MockIo, start: u64, max_entries: usize| match io
+            .load_log_limited(start.into(), max_entries)
+            .wait()
+            .unwrap()
+        {
+            Log::Suffix(s) => s,
+            Log::Prefix(_) => panic!(),
+        };
+        let s = load(&mut io, 1, 2);
+        assert_eq!(s.head.index, 1.into());
+        assert_eq!(s.entries, &entries[1..3]);
+
+        // 末端を超える分は切り詰められる
+        let s = load(&mut io, 3, 10);
+        assert_eq!(s.entries, &entries[3..]);
+        let s = load(&mut io, 5, 10);
+        assert!(s.entries.is_empty());
+    }
+
+    #[test]
+    fn load_log_returns_snapshot_only_if_start_is_discarded() {
+        let network = Arc::new(Mutex::new(MockNetwork::default()));
+        let mut io = MockIo::new("a".into(), network);
+        let position = |index: u64| LogPosition {
+            prev_term: 1.into(),
+            index: index.into(),
+        };
+
+        // スナップショットは`[0, 10)`で、後半部分は`[5, 15)`のエントリを保持している
+        {
+            let mut storage = io.storage().lock().unwrap();
+            storage.prefix = Some(LogPrefix::new(
+                position(10),
+                ClusterConfig::new(BTreeSet::new()),
+                Vec::new(),
+            ));
+            storage.suffix = LogSuffix {
+                head: position(5),
+                entries: vec![LogEntry::Noop { term: 1.into() }; 10],
+            };
+        }
+        let mut load =
+            |start: u64, end: u64| io.load_log(start.into(), Some(end.into())).wait().unwrap();
+
+        // 範囲全体がスナップショットに含まれる
+        match load(2, 8) {
+            Log::Prefix(p) => assert!(p.covers(2.into(), 8.into())),
+            Log::Suffix(_) => panic!(),
+        }
+
+        // 範囲がスナップショットの境界を跨ぐ
+        match load(2, 12) {
+            Log::Prefix(p) => assert!(!p.covers(2.into(), 12.into())),
+            Log::Suffix(_) => panic!(),
+        }
+
+        // 範囲全体がスナップショットに含まれるが、後半部分にもエントリが残っている
+        match load(6, 8) {
+            Log::Suffix(s) => assert_eq!((s.head.index, s.tail().index), (6.into(), 8.into())),
+            Log::Prefix(_) => panic!(),
+        }
+
+        // 範囲全体が後半部分に含まれる
+        match load(11, 14) {
+            Log::Suffix(s) => assert_eq!((s.head.index, s.tail().index), (11.into(), 14.into())),
+            Log::Prefix(_) => panic!(),
+        }
+
+        // 不正な範囲は、スナップショット済み領域であってもエラーとなる
+        for &(start, end) in &[(4, 2), (8, 6)] {
+            let e = io
+                .load_log(start.into(), Some(end.into()))
+                .wait()
+                .unwrap_err();
+            assert_eq!(*e.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn delayed_messages_are_delivered_when_due() {
+        fn receive_all(seed: Option<u64>) -> Vec<u64> {
+            let network = Arc::new(Mutex::new(MockNetwork::default()));
+            network.lock().unwrap().set_delay("b", 3);
+            network.lock().unwrap().set_shuffle_seed(seed);
+            let mut b = MockIo::new("b".into(), Arc::clone(&network));
+            for i in 0..10 {
+                let header = MessageHeader {
+                    group_id: GroupId::default(),
+                    sender: "a".into(),
+                    destination: "b".into(),
+                    seq_no: SequenceNumber::new(i),
+                    term: 0.into(),
+                };
+                network
+                    .lock()
+                    .unwrap()
+                    .send(TimeoutNowCast { header }.into());
+            }
+
+            network.lock().unwrap().deliver_due(2);
+            assert!(b.try_recv_message().unwrap().is_none());
+            network.lock().unwrap().deliver_due(3);
+            assert!(!network.lock().unwrap().has_delayed_messages());
+            let mut received = Vec::new();
+            while let Some(m) = b.try_recv_message().unwrap() {
+                received.push(m.header().seq_no.as_u64());
+            }
+            received
+        }
+
+        // 同じ時刻に配送されるメッセージ群は、送信順に届く
+        let in_order = (0..10).collect::<Vec<_>>();
+        assert_eq!(receive_all(None), in_order);
+
+        // シャッフルされた順番は、シードが同じなら決定論的
+        let shuffled = receive_all(Some(12345));
+        assert_ne!(shuffled, in_order);
+        assert_eq!(receive_all(Some(12345)), shuffled);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, in_order);
+    }
+
+    #[test]
+    fn duplicated_messages_are_handled_idempotently() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        for id in &["a", "b", "c"] {
+            cluster.node(id).io().set_duplication_factor(3);
+        }
+        cluster.elect("a");
+
+        cluster.node_mut("a").propose_command(vec![1]).unwrap();
+        cluster.run();
+        cluster.fire_timeout("a");
+        cluster.run();
+
+        for id in &["a", "b", "c"] {
+            let log = cluster.log_suffix(id);
+            let commands = log
+                .entries
+                .iter()
+                .filter(|e| matches!(e, LogEntry::Command { .. }))
+                .count();
+            assert_eq!(commands, 1, "node={}", id);
+            assert_eq!(log.head.index, LogIndex::new(0));
+            assert_eq!(log.tail(), cluster.log_suffix("a").tail());
+
+            let committed = cluster.node(id).local_history().committed_tail();
+            assert_eq!(committed, log.tail(), "node={}", id);
+        }
+
+        // 実際に重複したメッセージが配送されている
+        let network = cluster.network().lock().unwrap();
+        let calls = network
+            .sent_messages()
+            .iter()
+            .filter(|m| matches!(m, Message::AppendEntriesCall(_)))
+            .map(|m| {
+                let h = m.header();
+                (h.destination.clone(), h.seq_no)
+            })
+            .collect::<Vec<_>>();
+        assert!(calls.windows(2).any(|w| w[0] == w[1]));
+    }
+
+    #[test]
+    fn randomized_cluster_converges() {
+        for seed in 0..20 {
+            let mut cluster = MockCluster::new(&["a", "b", "c"]);
+            cluster.randomize(seed);
+            cluster.run();
+            cluster.trigger_election("b");
+            cluster.run();
+            assert_eq!(cluster.leader(), Some(NodeId::from("b")), "seed={}", seed);
+
+            for i in 0..3 {
+                cluster.propose("b", vec![i]).unwrap();
+            }
+            cluster.run();
+            cluster.fire_timeout("b");
+            cluster.run();
+
+            let tail = cluster.log_suffix("b").tail();
+            for id in &["a", "b", "c"] {
+                let log = cluster.log_suffix(id);
+                assert_eq!(log.tail(), tail, "seed={}, node={}", seed, id);
+                let committed = cluster.node(id).local_history().committed_tail();
+                assert_eq!(committed, tail, "seed={}, node={}", seed, id);
+                let commands = cluster
+                    .events(id)
+                    .iter()
+                    .filter_map(|e| match e {
+                        Event::Committed {
+                            entry: LogEntry::Command { command, .. },
+                            ..
+                        } => Some(command[0]),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(commands, [0, 1, 2], "seed={}, node={}", seed, id);
+            }
+        }
+    }
+
+    #[test]
+    fn scripted_faults_are_reproducible() {
+        fn run_scenario() -> Vec<(String, mem::Discriminant<Message>)> {
+            let mut cluster = MockCluster::new(&["a", "b", "c"]);
+            cluster.elect("a");
+            cluster.propose("a", vec![0]).unwrap();
+            cluster.run();
+            cluster.network().lock().unwrap().clear_sent_messages();
+
+            // `c`はステップ1から3の間だけ分断され、`b`では保存が一度だけ一時的に失敗する
+            let peers = vec![NodeId::from("a"), NodeId::from("b")];
+            cluster.load_script(
+                "c",
+                vec![
+                    FaultEvent::Heal {
+                        step: 4,
+                        peers: peers.clone(),
+                    },
+                    FaultEvent::Partition { step: 1, peers },
+                ],
+            );
+            cluster.load_script(
+                "b",
+                vec![FaultEvent::FailSave {
+                    step: 1,
+                    kind: ErrorKind::Other,
+                }],
+            );
+            cluster.load_script("a", vec![FaultEvent::DropMessage { step: 2 }]);
+            for i in 1..3 {
+                cluster.propose("a", vec![i]).unwrap();
+            }
+
+            for _ in 0..3 {
+                cluster.step();
+            }
+            let tail = cluster.node("a").local_history().tail();
+            assert!(cluster.node("c").local_history().tail().index < tail.index);
+            assert_eq!(cluster.node("b").local_history().tail(), tail);
+
+            // 分断の回復後に、ハートビートによって`c`のログが修復される
+            cluster.run();
+            cluster.fire_timeout("a");
+            cluster.run();
+            assert!(!cluster.node("c").io().has_pending_faults());
+            for id in &["b", "c"] {
+                assert_eq!(cluster.log_suffix(id).tail(), tail);
+            }
+
+            // NOTE: `AppendEntriesReply::epoch`は起動毎に替わるので、比較対象から除外する
+            let network = cluster.network().lock().unwrap();
+            network
+                .sent_messages()
+                .iter()
+                .map(|m| (format!("{:?}", m.header()), mem::discriminant(m)))
+                .collect::<Vec<_>>()
+        }
+
+        assert_eq!(run_scenario(), run_scenario());
+    }
+
+    #[test]
+    fn messages_over_intake_quota_are_dropped() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        cluster.node("b").io().set_intake_quota(Some(2));
+
+        // 古い`Term`のメッセージ群を、一ステップの間に五つ届ける
+        let mut x = MockIo::new("x".into(), Arc::clone(cluster.network()));
+        for _ in 0..5 {
+            broadcast(&mut x, &["b"]);
+        }
+        cluster.step();
+        assert_eq!(cluster.node("b").io().dropped_messages(), 3);
+
+        // 上限内であれば、以降のメッセージは通常通り処理される
+        cluster.propose("a", b"after".to_vec()).unwrap();
+        cluster.run();
+        let commands = cluster
+            .log_suffix("b")
+            .commands()
+            .map(|(_, c)| c.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, [b"after".to_vec()]);
+        assert_eq!(cluster.node("b").io().dropped_messages(), 3);
+    }
+
+    #[test]
+    fn messages_to_disconnected_node_are_dropped() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+
+        // `c`を停止させて、配送網からも切り離す
+        cluster.take_node("c");
+        cluster.network().lock().unwrap().disconnect("c");
+        assert_eq!(
+            cluster.network().lock().unwrap().undeliverable_messages(),
+            0
+        );
+
+        // `c`宛のメッセージは破棄されるが、送信元の`a`は動作し続ける
+        let id = cluster.propose("a", b"after".to_vec()).unwrap();
+        cluster.run();
+        assert!(cluster.network().lock().unwrap().undeliverable_messages() > 0);
+        assert_eq!(cluster.leader(), Some("a".into()));
+        assert!(id.index < cluster.node("a").local_history().committed_tail().index);
+        let commands = cluster
+            .log_suffix("b")
+            .commands()
+            .map(|(_, c)| c.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(commands, [b"after".to_vec()]);
+    }
+
+    #[test]
+    fn heartbeats_reset_follower_timeout() {
+        let mut cluster = MockCluster::new(&["a", "b", "c"]);
+        cluster.elect("a");
+        let generation = |c: &MockCluster, id: &str| {
+            c.timers[&NodeId::from(id)]
+                .lock()
+                .expect("Never fails")
+                .generation
+        };
+        let b = generation(&cluster, "b");
+        let c = generation(&cluster, "c");
+
+        // `b`はハートビートを受信し続けるが、`c`は受信できない
+        cluster.isolate("c");
+        for _ in 0..3 {
+            cluster.node_mut("a").heartbeat().unwrap();
+            cluster.run();
+        }
+        assert!(b < generation(&cluster, "b"));
+        assert_eq!(c, generation(&cluster, "c"));
+
+        // 最後のハートビート以前に生成されたタイムアウトの期限が到来する
+        for &(id, generation) in &[("b", b), ("c", c)] {
+            let mut timer = cluster.timers[&NodeId::from(id)]
+                .lock()
+                .expect("Never fails");
+            timer.expired = generation;
+        }
+        cluster.run();
+        assert_eq!(cluster.node("b").local_node().role, Role::Follower);
+        assert_eq!(cluster.node("c").local_node().role, Role::Candidate);
+        assert_eq!(cluster.leader(), Some("a".into()));
+    }
+
+    #[test]
+    fn only_majority_side_commits_under_asymmetric_partition() {
+        let mut cluster = MockCluster::new(&["a", "b", "c", "d", "e"]);
+        cluster.elect("a");
+        let committed = |c: &MockCluster, id: &str| c.node(id).local_history().committed_tail();
+        let before = committed(&cluster, "a");
+
+        // `a`からは`b`にのみ送信可能で、`b`からの応答は`a`に届かない
+        for &m in &["c", "d", "e"] {
+            cluster.ban("a", m);
+            cluster.ban(m, "a");
+        }
+        cluster.ban("b", "a");
+
+        // 多数派側(`c`, `d`, `e`)で新しいリーダが選出される
+        for &m in &["d", "e"] {
+            cluster.fire_timeout(m);
+        }
+        cluster.run();
+        for _ in 0..2 {
+            cluster.fire_timeout("c");
+            cluster.run();
+        }
+        assert_eq!(cluster.node("c").local_node().role, Role::Leader);
+        assert_eq!(cluster.node("a").local_node().role, Role::Leader);
+
+        cluster.propose("a", b"stale".to_vec()).unwrap();
+        cluster.propose("c", b"fresh".to_vec()).unwrap();
+        cluster.run();
+        assert_eq!(committed(&cluster, "a"), before);
+        for &m in &["c", "d", "e"] {
+            assert!(committed(&cluster, m).index > before.index);
+        }
+
+        // 分断解消後は、古いリーダは退位し、全ノードのコミット済み領域は食い違わない
+        cluster.heal();
+        cluster.node_mut("c").heartbeat().unwrap();
+        cluster.run();
+        assert_eq!(cluster.node("a").local_node().role, Role::Follower);
+        assert_eq!(committed(&cluster, "a"), committed(&cluster, "c"));
+        cluster.verify_committed_agreement().unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+//! `raftlog::testing`が提供するテスト用のクラスタを、クレート外から利用するテスト.
+use futures::Future;
+use raftlog::election::Role;
+use raftlog::log::LogEntry;
+use raftlog::node::NodeId;
+use raftlog::testing::MockCluster;
+use raftlog::Event;
+
+#[test]
+fn leader_is_elected() {
+    let mut cluster = MockCluster::new(&["a", "b", "c"]);
+    cluster.elect("b");
+    assert_eq!(cluster.leader(), Some(NodeId::from("b")));
+    assert_eq!(cluster.node("b").local_node().role, Role::Leader);
+    for id in &["a", "c"] {
+        assert_eq!(cluster.node(id).local_node().role, Role::Follower);
+        assert_eq!(cluster.node(id).current_leader(), Some(NodeId::from("b")));
+    }
+
+    // 選出されたリーダに提案したコマンドは、全てのノードでコミットされる
+    // (フォロワーには、次のハートビートでコミット済みの地点が通知される)
+    cluster.propose("b", b"foo".to_vec()).unwrap();
+    cluster.run();
+    cluster.node_mut("b").heartbeat().unwrap();
+    cluster.run();
+    for id in &["a", "b", "c"] {
+        assert!(cluster.events(id).iter().any(|e| matches!(
+            e,
+            Event::Committed { entry: LogEntry::Command { command, .. }, .. }
+                if command[..] == b"foo"[..]
+        )));
+    }
+}
+
+#[test]
+fn status_reflects_commit_in_one_read() {
+    let mut cluster = MockCluster::new(&["a", "b", "c"]);
+    cluster.elect("a");
+    let before = cluster.node("a").status();
+
+    let proposal = cluster.propose("a", b"foo".to_vec()).unwrap();
+    cluster.run();
+    let status = cluster.node("a").status();
+    assert_eq!(status.role, Role::Leader);
+    assert_eq!(status.term, before.term);
+    assert_eq!(status.leader, Some(NodeId::from("a")));
+    assert!(status.committed_tail.index > proposal.index);
+    assert!(status.committed_tail.index > before.committed_tail.index);
+    assert_eq!(status.committed_tail, status.appended_tail);
+}
+
+#[test]
+fn pending_committed_entries_can_be_peeked() {
+    let mut cluster = MockCluster::new(&["a", "b", "c"]);
+    cluster.elect("a");
+
+    // コミット済みエントリの読み込み(消費)を保留させる
+    cluster.node("a").io().set_loads_blocked(true);
+    cluster.propose("a", b"foo".to_vec()).unwrap();
+    cluster.propose("a", b"bar".to_vec()).unwrap();
+    cluster.run();
+    let history = cluster.node("a").local_history();
+    let consumed_tail = history.consumed_tail();
+    assert!(consumed_tail.index < history.committed_tail().index);
+    cluster.node("a").io().set_loads_blocked(false);
+
+    // 先読みしても消費はされないので、何度でも同じ結果が返される
+    let first = cluster.node_mut("a").pending_committed().wait().unwrap();
+    let second = cluster.node_mut("a").pending_committed().wait().unwrap();
+    assert_eq!(first.head, consumed_tail);
+    assert_eq!(first.head, second.head);
+    assert_eq!(first.entries, second.entries);
+    let commands = first.commands().map(|(_, c)| c).collect::<Vec<_>>();
+    assert_eq!(commands, [&b"foo"[..], &b"bar"[..]]);
+    assert_eq!(
+        cluster.node("a").local_history().consumed_tail(),
+        consumed_tail
+    );
+
+    // 消費後は空になる
+    cluster.run();
+    let consumed_tail = cluster.node("a").local_history().consumed_tail();
+    assert_eq!(
+        consumed_tail,
+        cluster.node("a").local_history().committed_tail()
+    );
+    let pending = cluster.node_mut("a").pending_committed().wait().unwrap();
+    assert!(pending.is_empty());
+    assert_eq!(pending.head, consumed_tail);
+}